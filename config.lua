@@ -1,12 +1,27 @@
 -- Configuration settings via properties
 -- These will be set when config.lua is executed after core initialization
+-- Read from $XDG_CONFIG_HOME/eigenplayer/config.lua (~/.config/eigenplayer/config.lua);
+-- --config <path> picks another file
+-- Any setting can be overridden from the environment, e.g.
+-- EIGENPLAYER_AUDIO__DEFAULT_VOLUME=0.3 for audio.default_volume
 
 if core then
     -- Audio settings
-    core:set_property("ring_buffer_size", 88200)
-    core:set_property("default_volume", 0.1)
-    core:set_property("enable_eq", true)
-    core:set_property("eq_bands",{{1000, 1, 1, 1}})
+    core:set_property("audio.ring_buffer_size", 88200)
+    core:set_property("audio.default_volume", 0.1)
+    core:set_property("eq.enabled", true)
+    core:set_property("eq.bands",{{1000, 1, 1, 1}})
+
+    -- Output device by name, as listed by `devices` (default: the system's default),
+    -- and optionally the audio backend and sample format to open it with
+    -- config.audio = { backend = "alsa", device = "USB Audio", sample_format = "i16" }
+
+    -- Database file (default: $XDG_DATA_HOME/eigenplayer/playlists.db);
+    -- EIGENPLAYER_DB and --db take precedence
+    -- core:set_property("db.path", "~/music/eigenplayer.db")
+
+    -- REPL prompt, refreshed before each command:
+    -- core:set_property("ui.prompt", "{playing_icon} {track} [{pos}] {vol}> ")
 
     -- Add more config properties here as needed
 end
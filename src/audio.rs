@@ -1,40 +1,65 @@
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Device, Stream, StreamConfig, SupportedStreamConfig};
-use ringbuf::{HeapRb, traits::*};
+use cpal::{
+    Device, FromSample, Host, SampleFormat, SizedSample, Stream, StreamConfig,
+    SupportedStreamConfig,
+};
+use ringbuf::{HeapCons, HeapRb, traits::*};
 use std::fs::File;
 use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
 use symphonia::core::audio::SampleBuffer;
 use symphonia::core::codecs::DecoderOptions;
-use symphonia::core::formats::FormatOptions;
+use symphonia::core::formats::{FormatOptions, SeekMode, SeekTo};
 use symphonia::core::io::MediaSourceStream;
 use symphonia::core::meta::MetadataOptions;
 use symphonia::core::probe::Hint;
+use symphonia::core::units::Time;
 use tracing::*;
 
-use crate::eq::Eq;
+use crate::eq::{Eq, bands_from_config};
+
+/// Sample formats the output can be opened with, as `audio.sample_format` names them.
+pub const SAMPLE_FORMATS: [&str; 8] = ["f32", "f64", "i8", "i16", "i32", "u8", "u16", "u32"];
 
 pub struct AudioBackend {
+    /// Audio API output goes through (`alsa`, `jack`, ...); "" is the platform default.
+    backend: String,
     device: Device,
     config: StreamConfig,
+    sample_format: SampleFormat,
     stream: Option<Stream>,
     state: Arc<Mutex<AudioState>>,
     decoder_thread: Option<JoinHandle<()>>,
     ring_buffer_size: usize,
     eq: Arc<Mutex<Eq>>,
+    /// `[frequency, q, gain_db, type]` the EQ filters were built from, kept so they can be
+    /// rebuilt when the device's sample rate changes.
+    eq_bands: Vec<[f32; 4]>,
     producer_sleep_time: u64,
+    current_path: Option<String>,
+    duration: Option<f64>,
 }
 
 struct AudioState {
     playing: bool,
     volume: f32,
     stop_signal: bool,
+    /// The decoder reached the end of the file.
+    decoder_done: bool,
+    /// The output drained everything the decoder produced; cleared by `take_finished`.
+    finished: bool,
+    /// Interleaved samples sent to the device since the start of the track.
+    samples_played: u64,
 }
 
 // im only using ring buffer because thats the only resonable thing i could think of
 // not sure if I know what im doing but it works
 // also gives me more room to play with the audio without over/underruns
 impl AudioBackend {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        Self::with_ring_buffer_size(88200, 1.0, false, Vec::new(), 100)
+    }
+
     pub fn with_ring_buffer_size(
         ring_buffer_size: usize,
         default_volume: f32,
@@ -47,34 +72,59 @@ impl AudioBackend {
             .default_output_device()
             .ok_or("No output device available")?;
 
-        let config: SupportedStreamConfig = device.default_output_config()?.into();
+        let config: SupportedStreamConfig = device.default_output_config()?;
+        let sample_format = supported_format(config.sample_format());
 
         let state = Arc::new(Mutex::new(AudioState {
             playing: false,
             volume: default_volume,
             stop_signal: false,
+            decoder_done: false,
+            finished: false,
+            samples_played: 0,
         }));
 
         let eq = { Eq::from_config(eq_bands.clone(), enable_eq, config.sample_rate() as f32) };
+        let eq_bands = eq_bands.clone();
 
         let eq = Arc::new(Mutex::new(eq));
         Ok(Self {
+            backend: String::new(),
             device,
             config: config.into(),
+            sample_format,
             stream: None,
             state,
             decoder_thread: None,
             ring_buffer_size,
             eq,
-	    producer_sleep_time
+            eq_bands,
+	    producer_sleep_time,
+            current_path: None,
+            duration: None,
         })
     }
 
     pub fn load_track(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.load_track_at(path, 0.0)
+    }
+
+    /// Loads `path` and starts decoding `start` seconds in.
+    pub fn load_track_at(
+        &mut self,
+        path: &str,
+        start: f64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         println!("[Audio Backend] Loading track: {}", path);
 
         // kinda need to do this
         self.stop_decoder();
+        {
+            let mut state = self.state.lock().unwrap();
+            state.decoder_done = false;
+            state.finished = false;
+            state.samples_played = 0;
+        }
         let file = Box::new(File::open(path)?);
 
         // we let symphonia deal with the file
@@ -93,12 +143,42 @@ impl AudioBackend {
             &MetadataOptions::default(),
         )?;
 
-        let format = probed.format;
+        let mut format = probed.format;
         let track = format.default_track().ok_or("No default track found")?;
+        let track_id = track.id;
 
         let decoder = symphonia::default::get_codecs()
             .make(&track.codec_params, &DecoderOptions::default())?;
 
+        let params = track.codec_params.clone();
+        self.duration = match (params.n_frames, params.time_base) {
+            (Some(frames), Some(tb)) => {
+                let time = tb.calc_time(frames);
+                Some(time.seconds as f64 + time.frac)
+            }
+            _ => None,
+        };
+        self.current_path = Some(path.to_string());
+
+        if start > 0.0 {
+            let seeked = format.seek(
+                SeekMode::Coarse,
+                SeekTo::Time {
+                    time: Time::from(start),
+                    track_id: Some(track_id),
+                },
+            )?;
+            let reached = params
+                .time_base
+                .map(|tb| {
+                    let time = tb.calc_time(seeked.actual_ts);
+                    time.seconds as f64 + time.frac
+                })
+                .unwrap_or(start);
+            let per_second = self.config.sample_rate as f64 * self.config.channels as f64;
+            self.state.lock().unwrap().samples_played = (reached * per_second) as u64;
+        }
+
         // bridge between decoder thread and cpal callback
         // producer will write decoded samples
         // consumer will read and play
@@ -147,24 +227,54 @@ impl AudioBackend {
                 }
             }
 
+            {
+                // a stop request also ends the loop, but that's not the track finishing
+                let mut state = state.lock().unwrap();
+                state.decoder_done = !state.stop_signal;
+            }
             println!("[Audio Backend] Decoder thread finished");
         });
 
         self.decoder_thread = Some(decoder_thread);
 
-        let state_for_callback = Arc::clone(&self.state);
-        let consumer = Arc::new(Mutex::new(consumer));
+        let state = Arc::clone(&self.state);
         let eq = Arc::clone(&self.eq);
+        let stream = match self.sample_format {
+            SampleFormat::F64 => self.build_stream::<f64>(state, consumer, eq)?,
+            SampleFormat::I8 => self.build_stream::<i8>(state, consumer, eq)?,
+            SampleFormat::I16 => self.build_stream::<i16>(state, consumer, eq)?,
+            SampleFormat::I32 => self.build_stream::<i32>(state, consumer, eq)?,
+            SampleFormat::U8 => self.build_stream::<u8>(state, consumer, eq)?,
+            SampleFormat::U16 => self.build_stream::<u16>(state, consumer, eq)?,
+            SampleFormat::U32 => self.build_stream::<u32>(state, consumer, eq)?,
+            _ => self.build_stream::<f32>(state, consumer, eq)?,
+        };
 
-        let stream = self.device.build_output_stream(
+        stream.play()?;
+        self.stream = Some(stream);
+
+        info!("[Audio Backend] Track loaded, decoder thread started");
+
+        Ok(())
+    }
+
+    /// The output stream, pulling samples from `consumer` and writing them as `T`.
+    fn build_stream<T: SizedSample + FromSample<f32>>(
+        &self,
+        state: Arc<Mutex<AudioState>>,
+        consumer: HeapCons<f32>,
+        eq: Arc<Mutex<Eq>>,
+    ) -> Result<Stream, cpal::BuildStreamError> {
+        let consumer = Arc::new(Mutex::new(consumer));
+        self.device.build_output_stream(
             &self.config,
-            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                let state = state_for_callback.lock().unwrap();
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                let mut state = state.lock().unwrap();
                 let mut consumer = consumer.lock().unwrap();
                 let mut eq = eq.lock().unwrap();
                 if !state.playing {
                     for sample in data.iter_mut() {
-                        *sample = 0.0;
+                        *sample = T::EQUILIBRIUM;
                     }
                     return;
                 }
@@ -172,23 +282,26 @@ impl AudioBackend {
                 for sample in data.iter_mut() {
                     // consume and apply volume on the sample
                     // and apply eq
-                    let mut s = consumer.try_pop().unwrap_or(0.0);
+                    let mut s = match consumer.try_pop() {
+                        Some(s) => {
+                            state.samples_played += 1;
+                            s
+                        }
+                        None => 0.0,
+                    };
                     if eq.enabled {
                         s = eq.process(s);
                     }
-                    *sample = s * state.volume;
+                    *sample = T::from_sample(s * state.volume);
+                }
+
+                if state.decoder_done && consumer.is_empty() {
+                    state.finished = true;
                 }
             },
             |err| eprintln!("[Audio Backend] Stream error: {}", err),
             None,
-        )?;
-
-        stream.play()?;
-        self.stream = Some(stream);
-
-        info!("[Audio Backend] Track loaded, decoder thread started");
-
-        Ok(())
+        )
     }
 
     fn stop_decoder(&mut self) {
@@ -231,6 +344,156 @@ impl AudioBackend {
         state.volume = volume.clamp(0.0, 1.0);
     }
 
+    /// Replaces the EQ bands (`[frequency, q, gain_db, type]`) while playing.
+    pub fn set_eq_bands(&mut self, bands: Vec<[f32; 4]>) {
+        info!("[Audio Backend] Setting {} EQ bands", bands.len());
+        self.eq_bands = bands.clone();
+        let filters = bands_from_config(bands, self.config.sample_rate as f32);
+        self.eq.lock().unwrap().update_bands(filters);
+    }
+
+    pub fn set_eq_enabled(&mut self, enabled: bool) {
+        self.eq.lock().unwrap().set_enabled(enabled);
+    }
+
+    /// Names of the audio backends available on this system, e.g. `alsa` or `jack`.
+    pub fn backends() -> Vec<String> {
+        cpal::available_hosts()
+            .iter()
+            .map(|id| id.to_string())
+            .collect()
+    }
+
+    /// The host for `backend`, by a name from `backends` ("" for the platform default).
+    fn host(backend: &str) -> Result<Host, Box<dyn std::error::Error>> {
+        if backend.is_empty() {
+            return Ok(cpal::default_host());
+        }
+        let id = cpal::available_hosts()
+            .into_iter()
+            .find(|id| id.to_string().eq_ignore_ascii_case(backend))
+            .ok_or_else(|| {
+                format!(
+                    "No audio backend named '{}' (available: {})",
+                    backend,
+                    Self::backends().join(", ")
+                )
+            })?;
+        Ok(cpal::host_from_id(id)?)
+    }
+
+    /// Names of the output devices `backend` offers, in its order.
+    pub fn output_devices(backend: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut names = Vec::new();
+        for device in Self::host(backend)?.output_devices()? {
+            names.push(device.description()?.name().to_string());
+        }
+        Ok(names)
+    }
+
+    /// Name of `backend`'s default output device.
+    pub fn default_device(backend: &str) -> Option<String> {
+        Self::host(backend)
+            .ok()?
+            .default_output_device()?
+            .description()
+            .ok()
+            .map(|description| description.name().to_string())
+    }
+
+    /// Name of the device output currently goes to.
+    pub fn device_name(&self) -> Option<String> {
+        self.device
+            .description()
+            .ok()
+            .map(|description| description.name().to_string())
+    }
+
+    /// Reopens output on `device` ("" for the default) of `backend` ("" for the platform
+    /// default), writing samples as `sample_format` (one of `SAMPLE_FORMATS`, "" for
+    /// what the device prefers). A loaded track carries on where it was, keeping the
+    /// play/pause state. Nothing changes if the combination can't be opened.
+    pub fn set_output(
+        &mut self,
+        backend: &str,
+        device: &str,
+        sample_format: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let host = Self::host(backend)?;
+        let device = if device.is_empty() {
+            host.default_output_device()
+                .ok_or("No output device available")?
+        } else {
+            host.output_devices()?
+                .find(|found| {
+                    found
+                        .description()
+                        .is_ok_and(|description| description.name() == device)
+                })
+                .ok_or_else(|| format!("No output device named '{}'", device))?
+        };
+        let config = output_config(&device, sample_format)?;
+
+        info!(
+            "[Audio Backend] Switching output to '{}' ({}, {})",
+            device
+                .description()
+                .map(|description| description.name().to_string())
+                .unwrap_or_default(),
+            host.id(),
+            config.sample_format()
+        );
+        let position = self.position();
+        self.stop_decoder();
+        self.stream = None;
+        self.backend = backend.to_string();
+        self.device = device;
+        self.sample_format = supported_format(config.sample_format());
+        let config: StreamConfig = config.into();
+        if config.sample_rate != self.config.sample_rate {
+            let filters = bands_from_config(self.eq_bands.clone(), config.sample_rate as f32);
+            self.eq.lock().unwrap().update_bands(filters);
+        }
+        self.config = config;
+
+        match self.current_path.clone() {
+            Some(path) => self.load_track_at(&path, position),
+            None => Ok(()),
+        }
+    }
+
+    /// Samples buffered between decoder and output; used from the next loaded track on.
+    pub fn set_ring_buffer_size(&mut self, size: usize) {
+        self.ring_buffer_size = size;
+    }
+
+    /// Seconds of the current track that have been played.
+    pub fn position(&self) -> f64 {
+        let samples = self.state.lock().unwrap().samples_played;
+        samples as f64 / (self.config.sample_rate as f64 * self.config.channels as f64)
+    }
+
+    /// Length of the current track, when the container reports it.
+    pub fn duration(&self) -> Option<f64> {
+        self.duration
+    }
+
+    /// Jumps to `seconds` into the current track by restarting the decoder there. Keeps
+    /// the play/pause state.
+    pub fn seek(&mut self, seconds: f64) -> Result<(), Box<dyn std::error::Error>> {
+        let path = self.current_path.clone().ok_or("No track loaded")?;
+        self.load_track_at(&path, seconds.max(0.0))
+    }
+
+    /// Returns true once after the loaded track has played through to the end.
+    pub fn take_finished(&mut self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let finished = state.finished;
+        state.finished = false;
+        state.decoder_done &= !finished;
+        finished
+    }
+
     pub fn is_playing(&self) -> bool {
         let state = self.state.lock().unwrap();
         state.playing
@@ -243,6 +506,45 @@ impl Drop for AudioBackend {
     }
 }
 
+/// `format` if the output can write it, otherwise `f32`, which every backend converts.
+fn supported_format(format: SampleFormat) -> SampleFormat {
+    if SAMPLE_FORMATS.contains(&format.to_string().as_str()) {
+        format
+    } else {
+        SampleFormat::F32
+    }
+}
+
+/// The config to open `device` with: its default, or with `sample_format` its best
+/// config in that format, at the default sample rate when the device allows it.
+fn output_config(
+    device: &Device,
+    sample_format: &str,
+) -> Result<SupportedStreamConfig, Box<dyn std::error::Error>> {
+    let default = device.default_output_config()?;
+    if sample_format.is_empty() || default.sample_format().to_string() == sample_format {
+        return Ok(default);
+    }
+    if !SAMPLE_FORMATS.contains(&sample_format) {
+        return Err(format!(
+            "Unknown sample format '{}' (one of {})",
+            sample_format,
+            SAMPLE_FORMATS.join(", ")
+        )
+        .into());
+    }
+    device
+        .supported_output_configs()?
+        .filter(|range| range.sample_format().to_string() == sample_format)
+        .max_by_key(|range| range.channels() == default.channels())
+        .map(|range| {
+            range
+                .try_with_sample_rate(default.sample_rate())
+                .unwrap_or_else(|| range.with_max_sample_rate())
+        })
+        .ok_or_else(|| format!("The device can't play {} samples", sample_format).into())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,407 @@
+use crate::core::{Capability, Command, CommandCallback, Core, CoreError};
+use crate::params;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::Arc;
+
+type Validator = fn(&str) -> Result<(), String>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArgKind {
+    Required,
+    Optional,
+    /// Swallows every remaining parameter, joined with spaces (paths, playlist names).
+    Rest,
+}
+
+#[derive(Clone)]
+pub struct ArgSpec {
+    pub name: String,
+    pub type_name: &'static str,
+    pub kind: ArgKind,
+    validate: Validator,
+}
+
+/// Name, description and arguments of a command, used for parsing and for help output.
+/// `capability` is the access level a client needs to run it; it defaults to `Admin` so
+/// forgetting to tag a command never exposes it.
+#[derive(Clone, Default)]
+pub struct CommandUsage {
+    pub name: String,
+    pub description: String,
+    pub args: Vec<ArgSpec>,
+    /// Complete command lines shown by `help <command>`.
+    pub examples: Vec<String>,
+    pub capability: Capability,
+}
+
+impl CommandUsage {
+    /// One-line synopsis such as `volume <level>` or `add <track...>`.
+    pub fn synopsis(&self) -> String {
+        let mut out = self.name.clone();
+        for arg in &self.args {
+            let part = match arg.kind {
+                ArgKind::Required => format!(" <{}>", arg.name),
+                ArgKind::Optional => format!(" [{}]", arg.name),
+                ArgKind::Rest => format!(" <{}...>", arg.name),
+            };
+            out.push_str(&part);
+        }
+        out
+    }
+
+    /// The text of `help <command>`: synopsis, description, arguments and examples.
+    pub fn help(&self) -> Vec<String> {
+        let mut lines = vec![format!("Usage: {}", self.synopsis())];
+        if !self.description.is_empty() {
+            lines.push(self.description.clone());
+        }
+        if !self.args.is_empty() {
+            lines.push(String::new());
+            lines.push("Arguments:".to_string());
+            for arg in &self.args {
+                let kind = match arg.kind {
+                    ArgKind::Required => "",
+                    ArgKind::Optional => ", optional",
+                    ArgKind::Rest => ", rest of the line",
+                };
+                lines.push(format!("  {:<12} {}{}", arg.name, arg.type_name, kind));
+            }
+        }
+        if !self.examples.is_empty() {
+            lines.push(String::new());
+            lines.push("Examples:".to_string());
+            lines.extend(self.examples.iter().map(|example| format!("  {}", example)));
+        }
+        lines
+    }
+
+    fn invalid(&self, message: String) -> CoreError {
+        CoreError::InvalidArgument {
+            command: self.name.clone(),
+            message: format!("{} (usage: {})", message, self.synopsis()),
+        }
+    }
+
+    /// Checks the raw parameters against the declared arguments.
+    pub fn parse(&self, params: &[String]) -> Result<Args, CoreError> {
+        let mut values = HashMap::new();
+        let mut rest = params.iter();
+
+        for spec in &self.args {
+            let value = match spec.kind {
+                ArgKind::Rest => {
+                    let joined = rest.by_ref().cloned().collect::<Vec<_>>().join(" ");
+                    (!joined.is_empty()).then_some(joined)
+                }
+                _ => rest.next().cloned(),
+            };
+
+            match value {
+                Some(value) => {
+                    (spec.validate)(&value).map_err(|e| {
+                        self.invalid(format!(
+                            "invalid {} '{}' for <{}>: {}",
+                            spec.type_name, value, spec.name, e
+                        ))
+                    })?;
+                    values.insert(spec.name.clone(), value);
+                }
+                None if spec.kind == ArgKind::Optional => {}
+                None => return Err(self.invalid(format!("missing <{}>", spec.name))),
+            }
+        }
+
+        if rest.next().is_some() {
+            return Err(self.invalid("too many arguments".to_string()));
+        }
+
+        Ok(Args {
+            command: self.name.clone(),
+            values,
+        })
+    }
+}
+
+/// Validated command arguments, looked up by the names given to the builder.
+pub struct Args {
+    command: String,
+    values: HashMap<String, String>,
+}
+
+impl Args {
+    pub fn get<T: FromStr>(&self, name: &str) -> Result<T, CoreError> {
+        self.opt(name)?.ok_or_else(|| CoreError::InvalidArgument {
+            command: self.command.clone(),
+            message: format!("missing <{}>", name),
+        })
+    }
+
+    pub fn opt<T: FromStr>(&self, name: &str) -> Result<Option<T>, CoreError> {
+        match self.values.get(name) {
+            Some(raw) => raw
+                .parse()
+                .map(Some)
+                .map_err(|_| CoreError::InvalidArgument {
+                    command: self.command.clone(),
+                    message: format!("cannot parse <{}> from '{}'", name, raw),
+                }),
+            None => Ok(None),
+        }
+    }
+
+    pub fn str(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(|s| s.as_str())
+    }
+}
+
+/// Builds a `Command` whose parameters are parsed and validated before the body runs:
+///
+/// ```ignore
+/// CommandBuilder::new("volume")
+///     .description("Set the volume")
+///     .arg::<f32>("level")
+///     .run(|core, args| {
+///         let level: f32 = args.get("level")?;
+///         core.set_property("audio.volume", PropertyValue::Float(level))
+///     })
+/// ```
+pub struct CommandBuilder {
+    usage: CommandUsage,
+}
+
+impl CommandBuilder {
+    pub fn new(name: &str) -> Self {
+        Self {
+            usage: CommandUsage {
+                name: name.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.usage.description = description.to_string();
+        self
+    }
+
+    pub fn example(mut self, command_line: &str) -> Self {
+        self.usage.examples.push(command_line.to_string());
+        self
+    }
+
+    pub fn capability(mut self, capability: Capability) -> Self {
+        self.usage.capability = capability;
+        self
+    }
+
+    pub fn arg<T: FromStr>(self, name: &str) -> Self
+    where
+        T::Err: Display,
+    {
+        self.push_arg::<T>(name, ArgKind::Required)
+    }
+
+    pub fn optional_arg<T: FromStr>(self, name: &str) -> Self
+    where
+        T::Err: Display,
+    {
+        self.push_arg::<T>(name, ArgKind::Optional)
+    }
+
+    pub fn rest_arg(self, name: &str) -> Self {
+        self.push_arg::<String>(name, ArgKind::Rest)
+    }
+
+    fn push_arg<T: FromStr>(mut self, name: &str, kind: ArgKind) -> Self
+    where
+        T::Err: Display,
+    {
+        let full = std::any::type_name::<T>();
+        self.usage.args.push(ArgSpec {
+            name: name.to_string(),
+            type_name: full.rsplit("::").next().unwrap_or(full),
+            kind,
+            validate: |raw| raw.parse::<T>().map(|_| ()).map_err(|e| e.to_string()),
+        });
+        self
+    }
+
+    pub fn run<F>(self, body: F) -> Command
+    where
+        F: Fn(&mut Core, &Args) -> Result<(), CoreError> + Send + Sync + 'static,
+    {
+        let usage = self.usage.clone();
+        let execute: CommandCallback = Arc::new(move |params, core| {
+            let args = usage.parse(&params)?;
+            body(core, &args)
+        });
+        Command {
+            execute,
+            usage: self.usage,
+        }
+    }
+
+    /// Like `run`, but deserializes the parameters into `P` (see `params::from_args`).
+    /// Arguments not declared on the builder are filled in from `P`'s field names so
+    /// help output and structured callers still know the parameter order; `Option`
+    /// fields become optional arguments.
+    pub fn run_typed<P, F>(mut self, body: F) -> Command
+    where
+        P: DeserializeOwned,
+        F: Fn(&mut Core, P) -> Result<(), CoreError> + Send + Sync + 'static,
+    {
+        if self.usage.args.is_empty() {
+            let optional = params::optional_fields::<P>();
+            for field in params::field_names::<P>() {
+                let kind = if optional.contains(&field) {
+                    ArgKind::Optional
+                } else {
+                    ArgKind::Required
+                };
+                self = self.push_arg::<String>(field, kind);
+            }
+        }
+        let usage = self.usage.clone();
+        let execute: CommandCallback = Arc::new(move |raw, core| {
+            let params = params::from_args::<P>(&raw).map_err(|e| usage.invalid(e.0))?;
+            body(core, params)
+        });
+        Command {
+            execute,
+            usage: self.usage,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::PropertyValue;
+
+    fn volume_core() -> Core {
+        let mut core = Core::new();
+        core.add_property("volume", PropertyValue::Float(0.5));
+        core.register(
+            CommandBuilder::new("volume")
+                .arg::<f32>("level")
+                .run(|core, args| {
+                    let level: f32 = args.get("level")?;
+                    core.set_property("volume", PropertyValue::Float(level))
+                }),
+        );
+        core
+    }
+
+    #[test]
+    fn test_parses_typed_args() {
+        let mut core = volume_core();
+        core.execute_command("volume", vec!["0.25".to_string()])
+            .unwrap();
+        assert_eq!(core.get_float("volume"), Some(0.25));
+    }
+
+    #[test]
+    fn test_reports_bad_args() {
+        let mut core = volume_core();
+        let err = core
+            .execute_command("volume", vec!["loud".to_string()])
+            .unwrap_err();
+        assert!(err.to_string().contains("invalid f32 'loud' for <level>"));
+
+        let err = core.execute_command("volume", vec![]).unwrap_err();
+        assert!(err.to_string().contains("missing <level>"));
+        assert_eq!(core.get_float("volume"), Some(0.5));
+    }
+
+    #[test]
+    fn test_run_typed() {
+        #[derive(serde::Deserialize)]
+        struct Seek {
+            seconds: f32,
+            relative: Option<bool>,
+        }
+
+        let mut core = Core::new();
+        core.add_property("position", PropertyValue::Float(10.0));
+        let command = CommandBuilder::new("seek")
+            .capability(Capability::PlaybackControl)
+            .run_typed(|core, p: Seek| {
+                let base = match p.relative {
+                    Some(true) => core.get_float("position").unwrap_or(0.0),
+                    _ => 0.0,
+                };
+                core.set_property("position", PropertyValue::Float(base + p.seconds))
+            });
+        assert_eq!(command.usage.synopsis(), "seek <seconds> [relative]");
+        core.register(command);
+
+        core.execute_command("seek", vec!["5".to_string(), "true".to_string()])
+            .unwrap();
+        assert_eq!(core.get_float("position"), Some(15.0));
+
+        let err = core
+            .execute_command("seek", vec!["later".to_string()])
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("invalid f32 'later' for <seconds>")
+        );
+
+        let params = serde_json::json!({"seconds": 2});
+        let err = core
+            .execute_command_structured("seek", &params, Capability::ReadOnly)
+            .unwrap_err();
+        assert!(matches!(err, CoreError::PermissionDenied { .. }));
+        core.execute_command_structured("seek", &params, Capability::PlaybackControl)
+            .unwrap();
+        assert_eq!(core.get_float("position"), Some(2.0));
+    }
+
+    #[test]
+    fn test_rest_arg_joins_words() {
+        let usage = CommandBuilder::new("add")
+            .rest_arg("track")
+            .run(|_core, _args| Ok(()))
+            .usage;
+        let args = usage
+            .parse(&["My".to_string(), "Song.flac".to_string()])
+            .unwrap();
+        assert_eq!(args.str("track"), Some("My Song.flac"));
+        assert_eq!(usage.synopsis(), "add <track...>");
+    }
+
+    #[test]
+    fn test_help() {
+        let usage = CommandBuilder::new("move")
+            .description("Move an entry")
+            .arg::<usize>("from")
+            .optional_arg::<usize>("to")
+            .example("move 3 1")
+            .run(|_, _| Ok(()))
+            .usage;
+        assert_eq!(
+            usage.help(),
+            vec![
+                "Usage: move <from> [to]",
+                "Move an entry",
+                "",
+                "Arguments:",
+                "  from         usize",
+                "  to           usize, optional",
+                "",
+                "Examples:",
+                "  move 3 1",
+            ]
+        );
+
+        let mut core = Core::new();
+        core.add_command("bare", Command::new(Arc::new(|_, _| Ok(()))));
+        assert_eq!(
+            core.command_usage("bare").unwrap().help(),
+            vec!["Usage: bare"]
+        );
+    }
+}
@@ -1,118 +1,727 @@
+use crate::command_builder::CommandBuilder;
 use crate::core::*;
-use std::sync::Arc;
+use crate::paths::normalize_path;
+use serde::Deserialize;
 use tracing::*;
 
 fn play_command() -> Command {
-    Command {
-        execute: Arc::new(|params, core| {
-            if let Some(track) = params.get(0) {
-		info!("settting track");
-		info!("set state to playing");
-                core.set_property("playing", PropertyValue::Bool(true));
-		
-                core.set_property("current_track", PropertyValue::String(track.clone()));
-            }
-        }),
-    }
+    CommandBuilder::new("play")
+        .capability(Capability::PlaybackControl)
+        .description("Play a track")
+        .rest_arg("track")
+        .example("play ~/music/song.flac")
+        .example("play #2")
+        .run(|core, args| {
+            let track = normalize_path(&args.get::<String>("track")?);
+            info!("setting track and state to playing");
+            core.set_property("audio.playing", PropertyValue::Bool(true))?;
+            core.set_property("playlist.current", PropertyValue::String(track))
+        })
 }
 
 fn pause_command() -> Command {
-    Command {
-        execute: Arc::new(|_params, core| {
-            core.set_property("playing", PropertyValue::Bool(false));
-        }),
-    }
+    CommandBuilder::new("pause")
+        .capability(Capability::PlaybackControl)
+        .description("Pause playback")
+        .run(|core, _args| core.set_property("audio.playing", PropertyValue::Bool(false)))
+}
+
+fn toggle_command() -> Command {
+    CommandBuilder::new("toggle")
+        .capability(Capability::PlaybackControl)
+        .description("Pause if playing, play if paused")
+        .run(|core, _args| {
+            let playing = core.get_bool("audio.playing").unwrap_or(false);
+            core.set_property("audio.playing", PropertyValue::Bool(!playing))
+        })
+}
+
+#[derive(Deserialize)]
+struct VolumeParams {
+    level: String,
 }
 
 fn volume_command() -> Command {
-    Command {
-        execute: Arc::new(|params, core| {
-            if let Some(vol_str) = params.get(0) {
-                if let Ok(vol) = vol_str.parse::<f32>() {
-                    core.set_property("volume", PropertyValue::Float(vol.clamp(0.0, 1.0)));
-                }
-            }
-        }),
-    }
+    CommandBuilder::new("volume")
+        .capability(Capability::PlaybackControl)
+        .description("Set the volume between 0.0 and 1.0; +0.1/-0.1 change it from here")
+        .example("volume 0.5")
+        .example("volume -0.05")
+        .run_typed(|core, p: VolumeParams| {
+            let invalid = |message: String| CoreError::InvalidArgument {
+                command: "volume".to_string(),
+                message,
+            };
+            let level: f32 = p
+                .level
+                .parse()
+                .map_err(|_| invalid(format!("'{}' is not a volume", p.level)))?;
+            let level = if p.level.starts_with(['+', '-']) {
+                core.get_float("audio.volume").unwrap_or(1.0) + level
+            } else {
+                level
+            };
+            core.set_property("audio.volume", PropertyValue::Float(level.clamp(0.0, 1.0)))
+        })
 }
 
 fn add_command() -> Command {
-    Command {
-        execute: Arc::new(|params, core| {
-            if let Some(track) = params.get(0) {
-                if let Some(playlist) = core.get_string_list("playlist") {
-                    let mut new_playlist = playlist.clone();
-                    new_playlist.push(track.clone());
-                    core.set_property("playlist", PropertyValue::StringList(new_playlist));
-                }
-            }
-        }),
-    }
+    CommandBuilder::new("add")
+        .capability(Capability::LibraryModify)
+        .description("Append a track to the current playlist")
+        .rest_arg("track")
+        .run(|core, args| {
+            let track = normalize_path(&args.get::<String>("track")?);
+            let mut new_playlist = core
+                .get_string_list("playlist.tracks")
+                .cloned()
+                .unwrap_or_default();
+            new_playlist.push(track);
+            core.set_property("playlist.tracks", PropertyValue::StringList(new_playlist))
+        })
 }
 
 fn remove_command() -> Command {
-    Command {
-        execute: Arc::new(|params, core| {
-            if let Some(track) = params.get(0) {
-                if let Some(playlist) = core.get_string_list("playlist") {
-                    let new_playlist: Vec<String> =
-                        playlist.iter().filter(|t| *t != track).cloned().collect();
-
-                    core.set_property("playlist", PropertyValue::StringList(new_playlist));
-                }
+    CommandBuilder::new("remove")
+        .capability(Capability::LibraryModify)
+        .description("Remove a track from the current playlist")
+        .rest_arg("track")
+        .run(|core, args| {
+            let raw = args.get::<String>("track")?;
+            let track = normalize_path(&raw);
+            let new_playlist: Vec<String> = core
+                .get_string_list("playlist.tracks")
+                .map(|playlist| {
+                    playlist
+                        .iter()
+                        .filter(|t| **t != track && **t != raw)
+                        .cloned()
+                        .collect()
+                })
+                .unwrap_or_default();
+            core.set_property("playlist.tracks", PropertyValue::StringList(new_playlist))
+        })
+}
+
+fn move_command() -> Command {
+    CommandBuilder::new("move")
+        .capability(Capability::LibraryModify)
+        .description("Move playlist entry <from> to position <to>, counting from 1")
+        .arg::<usize>("from")
+        .arg::<usize>("to")
+        .example("move 5 1")
+        .run(|core, args| {
+            let (from, to): (usize, usize) = (args.get("from")?, args.get("to")?);
+            let mut playlist = core
+                .get_string_list("playlist.tracks")
+                .cloned()
+                .unwrap_or_default();
+            let len = playlist.len();
+            if !(1..=len).contains(&from) || !(1..=len).contains(&to) {
+                return Err(CoreError::InvalidArgument {
+                    command: "move".to_string(),
+                    message: format!("positions must be between 1 and {}", len),
+                });
             }
-        }),
+            // the current track is tracked by path, so it stays current wherever it ends up
+            let track = playlist.remove(from - 1);
+            playlist.insert(to - 1, track);
+            core.set_property("playlist.tracks", PropertyValue::StringList(playlist))
+        })
+}
+
+fn clear_command() -> Command {
+    CommandBuilder::new("clear")
+        .capability(Capability::LibraryModify)
+        .description("Stop playback and empty the current playlist")
+        .run(|core, _args| {
+            core.set_property("audio.playing", PropertyValue::Bool(false))?;
+            core.set_property(
+                "playlist.current",
+                PropertyValue::String("none".to_string()),
+            )?;
+            core.set_property("playlist.tracks", PropertyValue::StringList(Vec::new()))?;
+            // an empty queue isn't the saved playlist any more
+            core.set_property("playlist.name", PropertyValue::String(String::new()))
+        })
+}
+
+/// Moves `offset` entries away from the current track, if that entry exists; with
+/// `playlist.repeat` set to `all` it wraps around the ends instead. In shuffle mode the
+/// step goes through the shuffle order, see `shuffle_step`.
+fn step_track(core: &mut Core, offset: isize) -> Result<(), CoreError> {
+    let wrap = core
+        .get_string("playlist.repeat")
+        .is_some_and(|r| r == "all");
+    let target = match (
+        core.get_string("playlist.current"),
+        core.get_string_list("playlist.tracks"),
+    ) {
+        (Some(current), Some(playlist)) if core.get_bool("playlist.shuffle") == Some(true) => {
+            let played = core
+                .get_string_list("playlist.shuffle_played")
+                .cloned()
+                .unwrap_or_default();
+            let (played, target) = shuffle_step(played, current, playlist, offset, wrap);
+            core.set_property("playlist.shuffle_played", PropertyValue::StringList(played))?;
+            target
+        }
+        (Some(current), Some(playlist)) if wrap && !playlist.is_empty() => playlist
+            .iter()
+            .position(|t| t == current)
+            .map(|idx| (idx as isize + offset).rem_euclid(playlist.len() as isize))
+            .map(|idx| playlist[idx as usize].clone()),
+        (Some(current), Some(playlist)) => playlist
+            .iter()
+            .position(|t| t == current)
+            .and_then(|idx| idx.checked_add_signed(offset))
+            .and_then(|idx| playlist.get(idx))
+            .cloned(),
+        _ => None,
+    };
+
+    match target {
+        Some(track) => switch_track(core, track, offset > 0),
+        None => Ok(()),
     }
 }
 
-fn next_command() -> Command {
-    Command {
-        execute: Arc::new(|_params, core| {
-            if let (Some(current), Some(playlist)) = (
-                core.get_string("current_track"),
-                core.get_string_list("playlist"),
-            ) {
-                if let Some(idx) = playlist.iter().position(|t| t == current) {
-                    if idx + 1 < playlist.len() {
-                        core.set_property(
-                            "current_track",
-                            PropertyValue::String(playlist[idx + 1].clone()),
-                        );
-                        core.set_property("playing", PropertyValue::Bool(true));
-                    }
-                }
-            }
-        }),
+/// Starts playing `track`. Leaving a track that was still playing counts as a skip when
+/// `skipping` is set.
+fn switch_track(core: &mut Core, track: String, skipping: bool) -> Result<(), CoreError> {
+    if skipping
+        && core.get_bool("audio.playing") == Some(true)
+        && core.get_bool("audio.finished") != Some(true)
+        && let Some(current) = core.get_string("playlist.current").cloned()
+    {
+        core.emit_custom(TRACK_SKIPPED, PropertyValue::String(current));
+    }
+    core.set_property("playlist.current", PropertyValue::String(track))?;
+    core.set_property("audio.playing", PropertyValue::Bool(true))
+}
+
+/// One step through a shuffle round. `played` holds the round's tracks in the order they
+/// came on; stepping back walks it, stepping forward past its end draws a random track
+/// that hasn't played yet, so nothing repeats until every track has. A current track
+/// outside the round (picked by hand, or the playlist changed) starts a new one. Returns
+/// the updated round and the track to play. An exhausted round ends there (None), or
+/// with `wrap` starts over with a track other than the current one where possible.
+fn shuffle_step(
+    mut played: Vec<String>,
+    current: &str,
+    playlist: &[String],
+    offset: isize,
+    wrap: bool,
+) -> (Vec<String>, Option<String>) {
+    played.retain(|track| playlist.contains(track));
+    let index = match played.iter().position(|t| t == current) {
+        Some(index) => index,
+        None => {
+            played = vec![current.to_string()];
+            0
+        }
+    };
+
+    if let Some(track) = index
+        .checked_add_signed(offset)
+        .and_then(|idx| played.get(idx))
+    {
+        return (played.clone(), Some(track.clone()));
+    }
+    if offset < 0 {
+        return (played, None);
     }
+
+    let mut unplayed: Vec<&String> = playlist
+        .iter()
+        .filter(|track| !played.contains(track))
+        .collect();
+    if unplayed.is_empty() {
+        if !wrap || playlist.is_empty() {
+            return (played, None);
+        }
+        played.clear();
+        unplayed = playlist.iter().filter(|track| *track != current).collect();
+        if unplayed.is_empty() {
+            unplayed = playlist.iter().collect();
+        }
+    }
+    let track = unplayed[fastrand::usize(..unplayed.len())].clone();
+    played.push(track.clone());
+    (played, Some(track))
+}
+
+/// What happens when the current track has played to the end, following
+/// `playlist.repeat`: start it over, or move on like `next`.
+pub fn advance(core: &mut Core) -> Result<(), CoreError> {
+    if core
+        .get_string("playlist.repeat")
+        .is_some_and(|r| r == "one")
+    {
+        core.emit_custom(SEEK_REQUESTED, PropertyValue::Float(0.0));
+        core.set_property("audio.finished", PropertyValue::Bool(false))?;
+        return core.set_property("audio.position", PropertyValue::Float(0.0));
+    }
+    step_track(core, 1)
+}
+
+fn next_command() -> Command {
+    CommandBuilder::new("next")
+        .capability(Capability::PlaybackControl)
+        .description("Play the next track in the playlist")
+        .run(|core, _args| step_track(core, 1))
 }
 
 fn prev_command() -> Command {
-    Command {
-        execute: Arc::new(|_params, core| {
-            if let (Some(current), Some(playlist)) = (
-                core.get_string("current_track"),
-                core.get_string_list("playlist"),
-            ) {
-                if let Some(idx) = playlist.iter().position(|t| t == current) {
-                    if idx > 0 {
-                        core.set_property(
-                            "current_track",
-                            PropertyValue::String(playlist[idx - 1].clone()),
-                        );
-                        core.set_property("playing", PropertyValue::Bool(true));
-                    }
+    CommandBuilder::new("prev")
+        .capability(Capability::PlaybackControl)
+        .description("Play the previous track in the playlist")
+        .run(|core, _args| step_track(core, -1))
+}
+
+/// Where `seek` goes: `1:23` (or `83`, `1:02:03`) from the start of the track, `+10` or
+/// `-1:00` from the current position.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SeekTarget {
+    Absolute(f64),
+    Relative(f64),
+}
+
+pub fn parse_seek(text: &str) -> Option<SeekTarget> {
+    let (sign, time) = match text.chars().next()? {
+        '+' => (Some(1.0), &text[1..]),
+        '-' => (Some(-1.0), &text[1..]),
+        _ => (None, text),
+    };
+    let parts: Vec<&str> = time.split(':').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+
+    let mut seconds = 0.0;
+    for (i, part) in parts.iter().enumerate() {
+        if part.is_empty() || !part.chars().all(|c| c.is_ascii_digit() || c == '.') {
+            return None;
+        }
+        let value: f64 = part.parse().ok()?;
+        // minutes and seconds after a `:` stay below 60
+        if i > 0 && value >= 60.0 {
+            return None;
+        }
+        seconds = seconds * 60.0 + value;
+    }
+
+    Some(match sign {
+        Some(sign) => SeekTarget::Relative(sign * seconds),
+        None => SeekTarget::Absolute(seconds),
+    })
+}
+
+fn shuffle_command() -> Command {
+    CommandBuilder::new("shuffle")
+        .capability(Capability::PlaybackControl)
+        .description("Turn shuffle on or off; toggles without an argument")
+        .optional_arg::<String>("mode")
+        .example("shuffle")
+        .example("shuffle on")
+        .run(|core, args| {
+            let enabled = match args.str("mode") {
+                Some("on") => true,
+                Some("off") => false,
+                None => core.get_bool("playlist.shuffle") != Some(true),
+                Some(other) => {
+                    return Err(CoreError::InvalidArgument {
+                        command: "shuffle".to_string(),
+                        message: format!("expected 'on' or 'off', got '{}'", other),
+                    });
+                }
+            };
+            // a new round starts from whatever is playing now
+            let played = match core.get_string("playlist.current") {
+                Some(current) if enabled && current != "none" => vec![current.clone()],
+                _ => Vec::new(),
+            };
+            core.set_property("playlist.shuffle_played", PropertyValue::StringList(played))?;
+            core.set_property("playlist.shuffle", PropertyValue::Bool(enabled))
+        })
+}
+
+/// Repeat modes in the order `repeat` without an argument cycles through.
+pub const REPEAT_MODES: [&str; 3] = ["off", "all", "one"];
+
+fn repeat_command() -> Command {
+    CommandBuilder::new("repeat")
+        .capability(Capability::PlaybackControl)
+        .description("Set the repeat mode (off, one, all); cycles without an argument")
+        .optional_arg::<String>("mode")
+        .example("repeat one")
+        .run(|core, args| {
+            let mode = match args.str("mode") {
+                Some(mode) if REPEAT_MODES.contains(&mode) => mode.to_string(),
+                Some(other) => {
+                    return Err(CoreError::InvalidArgument {
+                        command: "repeat".to_string(),
+                        message: format!("expected off, one or all, got '{}'", other),
+                    });
+                }
+                None => {
+                    let current = core.get_string("playlist.repeat");
+                    let index = REPEAT_MODES
+                        .iter()
+                        .position(|mode| Some(*mode) == current.map(|c| c.as_str()))
+                        .map_or(0, |index| index + 1);
+                    REPEAT_MODES[index % REPEAT_MODES.len()].to_string()
                 }
+            };
+            core.set_property("playlist.repeat", PropertyValue::String(mode))
+        })
+}
+
+fn seek_command() -> Command {
+    CommandBuilder::new("seek")
+        .capability(Capability::PlaybackControl)
+        .description("Jump within the current track: 1:23, or +10/-30 from here")
+        .arg::<String>("position")
+        .example("seek 1:23")
+        .example("seek +10")
+        .example("seek -30")
+        .run(|core, args| {
+            let text = args.get::<String>("position")?;
+            let target = parse_seek(&text).ok_or_else(|| CoreError::InvalidArgument {
+                command: "seek".to_string(),
+                message: format!("invalid position '{}'", text),
+            })?;
+            if core
+                .get_string("playlist.current")
+                .is_none_or(|t| t == "none")
+            {
+                return Err(CoreError::CommandFailed {
+                    command: "seek".to_string(),
+                    message: "no track loaded".to_string(),
+                });
             }
-        }),
-    }
+
+            let mut seconds = match target {
+                SeekTarget::Absolute(seconds) => seconds,
+                SeekTarget::Relative(offset) => {
+                    core.get_float("audio.position").unwrap_or(0.0) as f64 + offset
+                }
+            }
+            .max(0.0);
+            let duration = core.get_float("audio.duration").unwrap_or(0.0) as f64;
+            if duration > 0.0 {
+                seconds = seconds.min(duration);
+            }
+
+            core.emit_custom(SEEK_REQUESTED, PropertyValue::Float(seconds as f32));
+            core.set_property("audio.position", PropertyValue::Float(seconds as f32))
+        })
+}
+
+fn goto_command() -> Command {
+    CommandBuilder::new("goto")
+        .capability(Capability::PlaybackControl)
+        .description("Play entry n of the current playlist, counting from 1")
+        .arg::<usize>("n")
+        .example("goto 3")
+        .run(|core, args| {
+            let n: usize = args.get("n")?;
+            let track = core
+                .get_string_list("playlist.tracks")
+                .and_then(|playlist| playlist.get(n.checked_sub(1)?))
+                .cloned()
+                .ok_or_else(|| CoreError::InvalidArgument {
+                    command: "goto".to_string(),
+                    message: format!("the playlist has no entry {}", n),
+                })?;
+            let skipping = core.get_string("playlist.current") != Some(&track);
+            switch_track(core, track, skipping)
+        })
+}
+
+fn undo_command() -> Command {
+    CommandBuilder::new("undo")
+        .description("Revert the last change to the player state")
+        .run(|core, _args| {
+            if !core.undo()? {
+                info!("nothing to undo");
+            }
+            Ok(())
+        })
+}
+
+fn redo_command() -> Command {
+    CommandBuilder::new("redo")
+        .description("Re-apply the last undone change")
+        .run(|core, _args| {
+            if !core.redo()? {
+                info!("nothing to redo");
+            }
+            Ok(())
+        })
+}
+
+fn reset_command() -> Command {
+    CommandBuilder::new("reset")
+        .description("Restore a property (or `all` of them) to its default value")
+        .arg::<String>("property")
+        .example("reset audio.volume")
+        .example("reset all")
+        .run(
+            |core, args| match args.get::<String>("property")?.as_str() {
+                "all" => core.reset_all(),
+                name => core.reset_property(name),
+            },
+        )
 }
 
 pub fn register_commands(core: &mut Core) {
-    core.add_command("play", play_command());
-    core.add_command("pause", pause_command());
-    core.add_command("volume", volume_command());
-    core.add_command("add", add_command());
-    core.add_command("remove", remove_command());
-    core.add_command("next", next_command());
-    core.add_command("prev", prev_command());
+    core.register(play_command());
+    core.register(pause_command());
+    core.register(toggle_command());
+    core.register(volume_command());
+    core.register(add_command());
+    core.register(remove_command());
+    core.register(move_command());
+    core.register(clear_command());
+    core.register(next_command());
+    core.register(prev_command());
+    core.register(goto_command());
+    core.register(seek_command());
+    core.register(shuffle_command());
+    core.register(repeat_command());
+    core.register(undo_command());
+    core.register(redo_command());
+    core.register(reset_command());
+    core.keys.bind_defaults();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::property::register_property;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_parse_seek() {
+        assert_eq!(parse_seek("1:23"), Some(SeekTarget::Absolute(83.0)));
+        assert_eq!(parse_seek("1:02:03"), Some(SeekTarget::Absolute(3723.0)));
+        assert_eq!(parse_seek("90"), Some(SeekTarget::Absolute(90.0)));
+        assert_eq!(parse_seek("+10"), Some(SeekTarget::Relative(10.0)));
+        assert_eq!(parse_seek("-1:30"), Some(SeekTarget::Relative(-90.0)));
+        assert_eq!(parse_seek("1:75"), None);
+        assert_eq!(parse_seek("+-5"), None);
+        assert_eq!(parse_seek("1::2"), None);
+        assert_eq!(parse_seek("later"), None);
+        assert_eq!(parse_seek(""), None);
+    }
+
+    #[test]
+    fn test_seek_command() {
+        let mut core = Core::new();
+        register_property(&mut core);
+        register_commands(&mut core);
+        let seek = |core: &mut Core, to: &str| core.execute_command("seek", vec![to.to_string()]);
+        assert!(seek(&mut core, "0:10").is_err());
+
+        let requested = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&requested);
+        core.subscribe_event(
+            Some(&format!("custom:{}", SEEK_REQUESTED)),
+            Arc::new(move |event, _core| {
+                if let EventType::Custom(_, PropertyValue::Float(at)) = event {
+                    log.lock().unwrap().push(*at);
+                }
+            }),
+        );
+        core.set_property("playlist.current", PropertyValue::String("a.flac".into()))
+            .unwrap();
+        core.set_property("audio.duration", PropertyValue::Float(120.0))
+            .unwrap();
+
+        seek(&mut core, "1:00").unwrap();
+        seek(&mut core, "+30").unwrap();
+        seek(&mut core, "-2:00").unwrap();
+        seek(&mut core, "+5:00").unwrap();
+        assert!(seek(&mut core, "soon").is_err());
+        assert_eq!(*requested.lock().unwrap(), vec![60.0, 90.0, 0.0, 120.0]);
+        assert_eq!(core.get_float("audio.position"), Some(120.0));
+    }
+
+    #[test]
+    fn test_shuffle() {
+        let mut core = Core::new();
+        register_property(&mut core);
+        register_commands(&mut core);
+        let tracks: Vec<String> = (1..=5).map(|n| format!("{}.flac", n)).collect();
+        core.set_property("playlist.tracks", PropertyValue::StringList(tracks.clone()))
+            .unwrap();
+        core.set_property("playlist.current", PropertyValue::String("3.flac".into()))
+            .unwrap();
+        core.execute_command("shuffle", vec![]).unwrap();
+        assert_eq!(core.get_bool("playlist.shuffle"), Some(true));
+
+        let current = |core: &Core| core.get_string("playlist.current").cloned().unwrap();
+        let mut order = vec![current(&core)];
+        for _ in 0..4 {
+            core.execute_command("next", vec![]).unwrap();
+            order.push(current(&core));
+        }
+        // every track once, and the round is over
+        let mut sorted = order.clone();
+        sorted.sort();
+        assert_eq!(sorted, tracks);
+        core.execute_command("next", vec![]).unwrap();
+        assert_eq!(current(&core), order[4]);
+
+        // prev walks back through the same order, next forward again
+        core.execute_command("prev", vec![]).unwrap();
+        core.execute_command("prev", vec![]).unwrap();
+        assert_eq!(current(&core), order[2]);
+        core.execute_command("next", vec![]).unwrap();
+        assert_eq!(current(&core), order[3]);
+        // the playlist itself keeps its order
+        assert_eq!(core.get_string_list("playlist.tracks"), Some(&tracks));
+
+        core.execute_command("shuffle", vec!["off".to_string()])
+            .unwrap();
+        assert!(
+            core.get_string_list("playlist.shuffle_played")
+                .unwrap()
+                .is_empty()
+        );
+        assert!(
+            core.execute_command("shuffle", vec!["maybe".to_string()])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_repeat() {
+        let mut core = Core::new();
+        register_property(&mut core);
+        register_commands(&mut core);
+        let tracks = vec!["1.flac".to_string(), "2.flac".to_string()];
+        core.set_property("playlist.tracks", PropertyValue::StringList(tracks))
+            .unwrap();
+        core.set_property("playlist.current", PropertyValue::String("2.flac".into()))
+            .unwrap();
+        let current = |core: &Core| core.get_string("playlist.current").cloned().unwrap();
+        let repeat = |core: &Core| core.get_string("playlist.repeat").cloned().unwrap();
+
+        // off: the last track ends and nothing follows
+        advance(&mut core).unwrap();
+        assert_eq!(current(&core), "2.flac");
+
+        core.execute_command("repeat", vec![]).unwrap();
+        assert_eq!(repeat(&core), "all");
+        advance(&mut core).unwrap();
+        assert_eq!(current(&core), "1.flac");
+        core.execute_command("prev", vec![]).unwrap();
+        assert_eq!(current(&core), "2.flac");
+
+        core.execute_command("repeat", vec![]).unwrap();
+        assert_eq!(repeat(&core), "one");
+        core.set_property("audio.position", PropertyValue::Float(200.0))
+            .unwrap();
+        advance(&mut core).unwrap();
+        assert_eq!(current(&core), "2.flac");
+        assert_eq!(core.get_float("audio.position"), Some(0.0));
+
+        core.execute_command("repeat", vec![]).unwrap();
+        assert_eq!(repeat(&core), "off");
+        assert!(
+            core.execute_command("repeat", vec!["twice".to_string()])
+                .is_err()
+        );
+
+        // shuffle with repeat all starts a new round instead of stopping
+        core.execute_command("repeat", vec!["all".to_string()])
+            .unwrap();
+        core.execute_command("shuffle", vec!["on".to_string()])
+            .unwrap();
+        for _ in 0..5 {
+            let before = current(&core);
+            core.execute_command("next", vec![]).unwrap();
+            assert_ne!(current(&core), before);
+        }
+    }
+
+    #[test]
+    fn test_goto() {
+        let mut core = Core::new();
+        register_property(&mut core);
+        register_commands(&mut core);
+        let tracks: Vec<String> = (1..=3).map(|n| format!("{}.flac", n)).collect();
+        core.set_property("playlist.tracks", PropertyValue::StringList(tracks))
+            .unwrap();
+        let goto = |core: &mut Core, n: &str| core.execute_command("goto", vec![n.to_string()]);
+
+        goto(&mut core, "3").unwrap();
+        assert_eq!(
+            core.get_string("playlist.current").map(|t| t.as_str()),
+            Some("3.flac")
+        );
+        assert_eq!(core.get_bool("audio.playing"), Some(true));
+        assert!(goto(&mut core, "0").is_err());
+        assert!(goto(&mut core, "4").is_err());
+        assert!(goto(&mut core, "two").is_err());
+    }
+
+    #[test]
+    fn test_move() {
+        let mut core = Core::new();
+        register_property(&mut core);
+        register_commands(&mut core);
+        let tracks: Vec<String> = ["a", "b", "c", "d"].map(String::from).to_vec();
+        core.set_property("playlist.tracks", PropertyValue::StringList(tracks))
+            .unwrap();
+        core.set_property("playlist.current", PropertyValue::String("b".into()))
+            .unwrap();
+        let move_entry = |core: &mut Core, from: &str, to: &str| {
+            core.execute_command("move", vec![from.to_string(), to.to_string()])
+        };
+
+        move_entry(&mut core, "1", "3").unwrap();
+        move_entry(&mut core, "4", "1").unwrap();
+        assert_eq!(
+            core.get_string_list("playlist.tracks").unwrap().join(""),
+            "dbca"
+        );
+        // next still follows the current track at its new place
+        core.execute_command("next", vec![]).unwrap();
+        assert_eq!(
+            core.get_string("playlist.current").map(|t| t.as_str()),
+            Some("c")
+        );
+        assert!(move_entry(&mut core, "0", "2").is_err());
+        assert!(move_entry(&mut core, "2", "5").is_err());
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut core = Core::new();
+        register_property(&mut core);
+        register_commands(&mut core);
+        core.set_property(
+            "playlist.tracks",
+            PropertyValue::StringList(vec!["a".into()]),
+        )
+        .unwrap();
+        core.set_property("playlist.name", PropertyValue::String("mix".into()))
+            .unwrap();
+        core.execute_command("goto", vec!["1".to_string()]).unwrap();
+
+        core.execute_command("clear", vec![]).unwrap();
+        assert!(core.get_string_list("playlist.tracks").unwrap().is_empty());
+        assert_eq!(core.get_bool("audio.playing"), Some(false));
+        assert_eq!(
+            core.get_string("playlist.current").map(|t| t.as_str()),
+            Some("none")
+        );
+        assert_eq!(
+            core.get_string("playlist.name").map(|t| t.as_str()),
+            Some("")
+        );
+    }
 }
@@ -0,0 +1,512 @@
+use crate::audio::SAMPLE_FORMATS;
+use crate::core::{Core, PropertyValue};
+use crate::eq::BAND_TYPES;
+use crate::style::{Role, escape_code};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// What a setting accepts. Checked when `config.lua` runs and by `config set`, so a typo
+/// is reported instead of quietly turning into the default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Bool,
+    /// Whole number in `min..=max`.
+    Int {
+        min: i32,
+        max: i32,
+    },
+    /// Number in `min..=max`; whole numbers are accepted too.
+    Float {
+        min: f32,
+        max: f32,
+    },
+    String,
+    /// One of the listed words.
+    Choice(&'static [&'static str]),
+    /// `""` (whatever the device prefers) or one of `audio::SAMPLE_FORMATS`.
+    SampleFormat,
+    /// `false` only: the feature isn't implemented yet, so `true` is refused instead of
+    /// being quietly ignored.
+    Unsupported,
+    /// A color `style::escape_code` understands, e.g. `bold cyan`.
+    Color,
+    /// `{frequency, q, gain_db, type}` bands, see `eq::BAND_TYPES`.
+    EqBands,
+}
+
+/// The settings a config may change, each one by `core:set_property` or by the same
+/// path in the `config` table (`config.audio.default_volume = 0.3`, see
+/// `lua::apply_config_settings`). Properties not listed here (and not a
+/// `ui.color.<role>`) are player state rather than configuration.
+pub const SETTINGS: &[(&str, Kind)] = &[
+    (
+        "audio.ring_buffer_size",
+        Kind::Int {
+            min: 1,
+            max: i32::MAX,
+        },
+    ),
+    ("audio.default_volume", Kind::Float { min: 0.0, max: 1.0 }),
+    (
+        "audio.producer_sleep_time",
+        Kind::Int {
+            min: 0,
+            max: i32::MAX,
+        },
+    ),
+    ("audio.backend", Kind::String),
+    ("audio.device", Kind::String),
+    ("audio.sample_format", Kind::SampleFormat),
+    ("audio.exclusive", Kind::Unsupported),
+    (
+        "audio.resume_min_duration",
+        Kind::Float {
+            min: 0.0,
+            max: f32::MAX,
+        },
+    ),
+    ("eq.enabled", Kind::Bool),
+    ("eq.bands", Kind::EqBands),
+    ("db.path", Kind::String),
+    (
+        "db.trash_days",
+        Kind::Int {
+            min: 0,
+            max: i32::MAX,
+        },
+    ),
+    ("scripts.sandbox", Kind::Bool),
+    ("ui.colors", Kind::Bool),
+    ("ui.prompt", Kind::String),
+    ("playlist.shuffle", Kind::Bool),
+    ("playlist.repeat", Kind::Choice(&["off", "one", "all"])),
+];
+
+/// The kind of the setting `name`, or `None` when it isn't one.
+pub fn kind_of(name: &str) -> Option<Kind> {
+    if let Some(role) = name.strip_prefix("ui.color.") {
+        return Role::from_name(role).map(|_| Kind::Color);
+    }
+    SETTINGS
+        .iter()
+        .find(|(setting, _)| *setting == name)
+        .map(|(_, kind)| *kind)
+}
+
+impl Kind {
+    /// What the setting must be, to finish "`name` must be ...".
+    pub fn describe(self) -> String {
+        match self {
+            Kind::Bool => "true or false".to_string(),
+            Kind::Int {
+                min: 1,
+                max: i32::MAX,
+            } => "a positive integer".to_string(),
+            Kind::Int {
+                min: 0,
+                max: i32::MAX,
+            } => "a non-negative integer".to_string(),
+            Kind::Int { min, max } => format!("an integer from {} to {}", min, max),
+            Kind::Float { min, max } if max == f32::MAX => {
+                format!("a number of at least {}", min)
+            }
+            Kind::Float { min, max } => format!("a number from {} to {}", min, max),
+            Kind::String => "a string".to_string(),
+            Kind::Choice(words) => {
+                let words: Vec<String> = words.iter().map(|w| format!("\"{}\"", w)).collect();
+                format!("one of {}", words.join(", "))
+            }
+            Kind::SampleFormat => {
+                let formats: Vec<String> = SAMPLE_FORMATS
+                    .iter()
+                    .map(|f| format!("\"{}\"", f))
+                    .collect();
+                format!("\"\" or one of {}", formats.join(", "))
+            }
+            Kind::Unsupported => "false; it isn't supported yet".to_string(),
+            Kind::Color => "a color like 'bold cyan'".to_string(),
+            Kind::EqBands => format!(
+                "a list of {{frequency, q, gain, type}} bands with type 0-{} ({})",
+                BAND_TYPES.len() - 1,
+                BAND_TYPES.join(", ")
+            ),
+        }
+    }
+
+    /// `value` as this kind wants it, converting numbers between integer and float
+    /// where that loses nothing.
+    fn accept(self, value: &PropertyValue) -> Option<PropertyValue> {
+        match (self, value) {
+            (Kind::Bool, PropertyValue::Bool(_)) => Some(value.clone()),
+            (Kind::Int { min, max }, PropertyValue::Int(n)) => {
+                (min..=max).contains(n).then(|| value.clone())
+            }
+            (Kind::Int { .. }, PropertyValue::Float(f)) if f.fract() == 0.0 => {
+                self.accept(&PropertyValue::Int(*f as i32))
+            }
+            (Kind::Float { min, max }, PropertyValue::Float(f)) => {
+                (min..=max).contains(f).then(|| value.clone())
+            }
+            (Kind::Float { .. }, PropertyValue::Int(n)) => {
+                self.accept(&PropertyValue::Float(*n as f32))
+            }
+            (Kind::String, PropertyValue::String(_)) => Some(value.clone()),
+            (Kind::Choice(words), PropertyValue::String(s)) => {
+                words.contains(&s.as_str()).then(|| value.clone())
+            }
+            (Kind::SampleFormat, PropertyValue::String(s)) => {
+                (s.is_empty() || SAMPLE_FORMATS.contains(&s.as_str())).then(|| value.clone())
+            }
+            (Kind::Unsupported, PropertyValue::Bool(false)) => Some(value.clone()),
+            (Kind::Color, PropertyValue::String(s)) => {
+                escape_code(s).is_some().then(|| value.clone())
+            }
+            (Kind::EqBands, PropertyValue::EqBandList(bands)) => bands
+                .iter()
+                .all(|[freq, q, _, band_type]| {
+                    *freq > 0.0
+                        && *q > 0.0
+                        && band_type.fract() == 0.0
+                        && (0.0..BAND_TYPES.len() as f32).contains(band_type)
+                })
+                .then(|| value.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Checks `value` for the setting `name`. Returns the value to store (numbers may be
+/// converted, see `Kind`) or a message like "audio.ring_buffer_size must be a positive
+/// integer (got 'large')". Names that aren't settings pass unchecked.
+pub fn check(name: &str, value: &PropertyValue) -> Result<PropertyValue, String> {
+    let Some(kind) = kind_of(name) else {
+        return Ok(value.clone());
+    };
+    kind.accept(value).ok_or_else(|| {
+        let got = match value {
+            PropertyValue::String(s) => s.clone(),
+            other => other.to_json().to_string(),
+        };
+        format!("{} must be {} (got '{}')", name, kind.describe(), got)
+    })
+}
+
+/// The config `--init-config` and `config init` write: every setting at its default,
+/// with what it does.
+pub const DEFAULT_CONFIG: &str = r#"-- eigenplayer configuration
+--
+-- Run at startup and again whenever this file is saved. Settings are properties, set
+-- with core:set_property or as the same path in the config table (config.eq = { enabled
+-- = true } works as well); `config` in the REPL lists them and `config set` tries one
+-- out for a session. Every value below is the default, so delete what you don't change.
+-- EIGENPLAYER_<SECTION>__<KEY> environment variables override what is set here, e.g.
+-- EIGENPLAYER_AUDIO__DEFAULT_VOLUME=0.3.
+
+if core then
+    -- Audio
+
+    -- volume (0 to 1) the player starts with
+    core:set_property("audio.default_volume", 0.5)
+    -- samples buffered between decoder and output; larger survives load spikes better
+    core:set_property("audio.ring_buffer_size", 88200)
+    -- milliseconds the decoder waits when the buffer is full (read at startup)
+    core:set_property("audio.producer_sleep_time", 100)
+    -- audio API to play through, e.g. "alsa" or "jack" (`devices` lists them); "" is
+    -- the platform default
+    core:set_property("audio.backend", "")
+    -- output device by name, as listed by `devices`; "" is the system default
+    core:set_property("audio.device", "")
+    -- samples written to the device: "f32", "f64", "i8", "i16", "i32", "u8", "u16" or
+    -- "u32"; "" is whatever the device prefers
+    core:set_property("audio.sample_format", "")
+    -- ask for the device to ourselves (read at startup; not supported by any backend
+    -- yet, so only false is accepted)
+    core:set_property("audio.exclusive", false)
+    -- tracks at least this long (seconds) resume where they were left; 0 disables
+    core:set_property("audio.resume_min_duration", 600.0)
+
+    -- Equalizer
+
+    core:set_property("eq.enabled", false)
+    -- bands are {frequency, q, gain_db, type} with type 0 lowshelf, 1 peak, 2 highshelf:
+    -- core:set_property("eq.bands", {{100, 0.7, 3, 0}, {1000, 1, -2, 1}})
+    core:set_property("eq.bands", {})
+
+    -- Playback
+
+    core:set_property("playlist.shuffle", false)
+    -- what happens when a track ends: "off", "one" (repeat it) or "all" (wrap around)
+    core:set_property("playlist.repeat", "off")
+
+    -- Database
+
+    -- database file; "" is $XDG_DATA_HOME/eigenplayer/playlists.db (read at startup)
+    core:set_property("db.path", "")
+    -- deleted playlists older than this many days are purged at startup
+    core:set_property("db.trash_days", 30)
+
+    -- Plugins
+
+    -- run plugins without os.execute, file writes or arbitrary require
+    core:set_property("scripts.sandbox", false)
+
+    -- Interface
+
+    -- prompt, refreshed before each command; {track}, {pos}, {vol} and {playing_icon}
+    -- are filled in, e.g. "{playing_icon} {track} [{pos}] {vol}> "
+    core:set_property("ui.prompt", "> ")
+    config.ui = {
+        -- false turns colors off; each role takes attributes (bold, dim, italic,
+        -- underline) and colors (red, bright_cyan, ...), or "none"
+        colors = {
+            header = "bold",
+            current = "bold green",
+            error = "red",
+            warning = "yellow",
+        },
+    }
+
+    -- Key bindings
+
+    -- used by `keys` (single-key mode); a key runs a command line or a Lua function,
+    -- e.g. m = function() core:set_property("audio.volume", 0.0) end
+    config.keys = {
+        space = "toggle",
+        n = "next",
+        p = "prev",
+        up = "volume +0.05",
+        down = "volume -0.05",
+        right = "seek +5",
+        left = "seek -5",
+    }
+end
+"#;
+
+/// Writes `DEFAULT_CONFIG` to `path`, creating its directory. Never replaces an existing
+/// file.
+pub fn write_default_config(path: &Path) -> io::Result<()> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = fs::File::options()
+        .write(true)
+        .create_new(true)
+        .open(path)?;
+    io::Write::write_all(&mut file, DEFAULT_CONFIG.as_bytes())
+}
+
+/// Prefix of the environment variables that override settings.
+pub const ENV_PREFIX: &str = "EIGENPLAYER_";
+
+/// The setting an environment variable overrides: `EIGENPLAYER_AUDIO__DEFAULT_VOLUME`
+/// is `audio.default_volume`, `__` separating the parts. Variables without a `__`, like
+/// `EIGENPLAYER_DB`, aren't overrides.
+pub fn env_setting_name(var: &str) -> Option<String> {
+    let rest = var.strip_prefix(ENV_PREFIX)?;
+    rest.contains("__")
+        .then(|| rest.to_lowercase().replace("__", "."))
+}
+
+/// Applies the overrides among `vars` (`(name, value)` pairs, e.g. `std::env::vars()`)
+/// on top of whatever the config set. Values are read like `config set` reads them.
+/// Returns a message per override that names no setting or doesn't fit it; those are
+/// skipped.
+pub fn apply_env_overrides(
+    core: &mut Core,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (var, text) in vars {
+        let Some(name) = env_setting_name(&var) else {
+            continue;
+        };
+        let current = match core.get_property(&name) {
+            Some(current) if kind_of(&name).is_some() => current,
+            _ => {
+                problems.push(format!("{}: no setting named {}", var, name));
+                continue;
+            }
+        };
+        let value = current
+            .parse_as(&text)
+            .map_err(|e| format!("{}: {}", var, e))
+            .and_then(|value| check(&name, &value));
+        match value {
+            Ok(value) => {
+                let _ = core.set_property(&name, value);
+            }
+            Err(problem) => problems.push(problem),
+        }
+    }
+    problems.sort();
+    problems
+}
+
+/// The current value of every setting `core` has.
+pub fn snapshot(core: &Core) -> HashMap<String, PropertyValue> {
+    core.properties_in("")
+        .into_iter()
+        .filter(|(name, _)| kind_of(name).is_some())
+        .map(|(name, value)| (name.to_string(), value.clone()))
+        .collect()
+}
+
+/// Stores converted every setting that only needs converting (an integer where a float
+/// belongs) after a config ran. Invalid values never get this far: `core:set_property`
+/// and the `config` table check them before writing.
+pub fn enforce(core: &mut Core) {
+    let converted: Vec<(String, PropertyValue)> = snapshot(core)
+        .into_iter()
+        .filter_map(|(name, value)| match check(&name, &value) {
+            Ok(checked) if checked != value => Some((name, checked)),
+            _ => None,
+        })
+        .collect();
+    for (name, value) in converted {
+        let _ = core.set_property(&name, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::DEFAULT_BINDINGS;
+    use crate::player::CoreBuilder;
+    use crate::property::register_property;
+
+    #[test]
+    fn test_check() {
+        assert_eq!(
+            check(
+                "audio.ring_buffer_size",
+                &PropertyValue::String("large".to_string())
+            ),
+            Err("audio.ring_buffer_size must be a positive integer (got 'large')".to_string())
+        );
+        assert!(check("audio.ring_buffer_size", &PropertyValue::Int(0)).is_err());
+        assert_eq!(
+            check("audio.default_volume", &PropertyValue::Int(1)),
+            Ok(PropertyValue::Float(1.0))
+        );
+        assert!(check("audio.default_volume", &PropertyValue::Float(1.5)).is_err());
+        assert!(
+            check(
+                "playlist.repeat",
+                &PropertyValue::String("once".to_string())
+            )
+            .is_err()
+        );
+        assert!(
+            check(
+                "ui.color.error",
+                &PropertyValue::String("bold red".to_string())
+            )
+            .is_ok()
+        );
+        assert!(
+            check(
+                "ui.color.error",
+                &PropertyValue::String("plaid".to_string())
+            )
+            .is_err()
+        );
+        assert!(
+            check(
+                "eq.bands",
+                &PropertyValue::EqBandList(vec![[1000.0, 1.0, 3.0, 1.0]])
+            )
+            .is_ok()
+        );
+        assert!(
+            check(
+                "eq.bands",
+                &PropertyValue::EqBandList(vec![[1000.0, 0.0, 3.0, 7.0]])
+            )
+            .is_err()
+        );
+        assert!(check("audio.sample_format", &PropertyValue::String(String::new())).is_ok());
+        assert!(
+            check(
+                "audio.sample_format",
+                &PropertyValue::String("i24".to_string())
+            )
+            .is_err()
+        );
+        assert_eq!(
+            check("audio.exclusive", &PropertyValue::Bool(true)),
+            Err("audio.exclusive must be false; it isn't supported yet (got 'true')".to_string())
+        );
+        // not a setting
+        assert!(check("audio.position", &PropertyValue::Bool(true)).is_ok());
+    }
+
+    #[test]
+    fn test_enforce() {
+        let mut core = Core::new();
+        register_property(&mut core);
+        core.set_property("audio.default_volume", PropertyValue::Int(0))
+            .unwrap();
+
+        enforce(&mut core);
+        assert_eq!(
+            core.get_property("audio.default_volume"),
+            Some(&PropertyValue::Float(0.0))
+        );
+        assert_eq!(core.get_int("audio.ring_buffer_size"), Some(88200));
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        assert_eq!(
+            env_setting_name("EIGENPLAYER_UI__COLOR__ERROR").as_deref(),
+            Some("ui.color.error")
+        );
+        assert_eq!(env_setting_name("EIGENPLAYER_DB"), None);
+        assert_eq!(env_setting_name("HOME"), None);
+
+        let mut core = Core::new();
+        register_property(&mut core);
+        let vars = [
+            ("EIGENPLAYER_AUDIO__DEFAULT_VOLUME", "0.3"),
+            ("EIGENPLAYER_EQ__ENABLED", "on"),
+            ("EIGENPLAYER_AUDIO__RING_BUFFER_SIZE", "large"),
+            ("EIGENPLAYER_AUDIO__POSITION", "10"),
+            ("EIGENPLAYER_DB", "/tmp/x.db"),
+        ]
+        .map(|(var, value)| (var.to_string(), value.to_string()));
+        let problems = apply_env_overrides(&mut core, vars);
+
+        assert_eq!(core.get_float("audio.default_volume"), Some(0.3));
+        assert_eq!(core.get_bool("eq.enabled"), Some(true));
+        assert_eq!(core.get_int("audio.ring_buffer_size"), Some(88200));
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].contains("no setting named audio.position"));
+        assert!(problems[1].contains("'large' is not a whole number"));
+    }
+
+    #[test]
+    fn test_default_config() {
+        let dir = std::env::temp_dir().join("eigenplayer_default_config_test");
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("nested/config.lua");
+        write_default_config(&path).unwrap();
+        let again = write_default_config(&path).unwrap_err();
+        assert_eq!(again.kind(), io::ErrorKind::AlreadyExists);
+
+        // every setting it writes is the default already
+        let (core, mut keys) = CoreBuilder::new().config(&path).build_with_config_keys();
+        let stock = CoreBuilder::new().build();
+        assert_eq!(
+            snapshot(&core.read().unwrap()),
+            snapshot(&stock.read().unwrap())
+        );
+        keys.sort();
+        let mut defaults: Vec<&str> = DEFAULT_BINDINGS.iter().map(|(key, _)| *key).collect();
+        defaults.sort();
+        assert_eq!(keys, defaults);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,7 +1,99 @@
-use std::collections::HashMap;
-use std::sync::Arc;
+use crate::command_builder::CommandUsage;
+use crate::keys::Keymap;
+use crate::scheduler::{Scheduler, TimerCallback, TimerId};
+use crate::undo::{Mutation, UndoHistory};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 use tracing::*;
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum CoreError {
+    UnknownProperty(String),
+    UnknownCommand(String),
+    CommandCancelled(String),
+    InvalidArgument {
+        command: String,
+        message: String,
+    },
+    PermissionDenied {
+        command: String,
+        required: Capability,
+    },
+    DispatchCycle(String),
+    /// The command ran but failed, e.g. a Lua command raised an error.
+    CommandFailed {
+        command: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for CoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoreError::UnknownProperty(name) => write!(f, "unknown property '{}'", name),
+            CoreError::UnknownCommand(name) => write!(f, "unknown command '{}'", name),
+            CoreError::CommandCancelled(name) => {
+                write!(f, "command '{}' was cancelled by middleware", name)
+            }
+            CoreError::InvalidArgument { command, message } => {
+                write!(f, "{}: {}", command, message)
+            }
+            CoreError::PermissionDenied { command, required } => {
+                write!(f, "command '{}' requires {} access", command, required)
+            }
+            CoreError::DispatchCycle(name) => write!(
+                f,
+                "property '{}' changed more than {} times in one dispatch, observers are looping",
+                name, MAX_CHANGES_PER_DISPATCH
+            ),
+            CoreError::CommandFailed { command, message } => {
+                write!(f, "command '{}' failed: {}", command, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CoreError {}
+
+/// What a client is allowed to do. Levels are ordered, so a client granted
+/// `LibraryModify` may also run `PlaybackControl` and `ReadOnly` commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Capability {
+    ReadOnly,
+    PlaybackControl,
+    LibraryModify,
+    #[default]
+    Admin,
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Capability::ReadOnly => "read-only",
+            Capability::PlaybackControl => "playback-control",
+            Capability::LibraryModify => "library-modify",
+            Capability::Admin => "admin",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::str::FromStr for Capability {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read-only" => Ok(Capability::ReadOnly),
+            "playback-control" => Ok(Capability::PlaybackControl),
+            "library-modify" => Ok(Capability::LibraryModify),
+            "admin" => Ok(Capability::Admin),
+            _ => Err(format!("unknown capability '{}'", s)),
+        }
+    }
+}
+
 // Property value types
 #[derive(Debug, Clone, PartialEq)]
 pub enum PropertyValue {
@@ -49,24 +141,145 @@ impl PropertyValue {
         }
     }
 
-    pub fn as_eq_band_list(&self) -> Option<&Vec<[f32 ;4]>> {
+    pub fn to_json(&self) -> serde_json::Value {
+        use serde_json::json;
+        match self {
+            PropertyValue::String(s) => json!(s),
+            PropertyValue::Bool(b) => json!(b),
+            PropertyValue::Float(f) => json!(f),
+            PropertyValue::Int(i) => json!(i),
+            PropertyValue::StringList(list) => json!(list),
+            PropertyValue::EqBandList(bands) => json!(bands),
+        }
+    }
+
+    pub fn as_eq_band_list(&self) -> Option<&Vec<[f32; 4]>> {
         match self {
             PropertyValue::EqBandList(list) => Some(list),
             _ => None,
         }
     }
+
+    /// Reads `text` as a value of the same type as `self`, for setting properties from
+    /// typed input: `on`/`off` work for booleans, lists are JSON arrays.
+    pub fn parse_as(&self, text: &str) -> Result<PropertyValue, String> {
+        let text = text.trim();
+        match self {
+            PropertyValue::String(_) => Ok(PropertyValue::String(text.to_string())),
+            PropertyValue::Bool(_) => match text.to_lowercase().as_str() {
+                "true" | "on" | "yes" | "1" => Ok(PropertyValue::Bool(true)),
+                "false" | "off" | "no" | "0" => Ok(PropertyValue::Bool(false)),
+                _ => Err(format!("'{}' is not true or false", text)),
+            },
+            PropertyValue::Float(_) => text
+                .parse()
+                .map(PropertyValue::Float)
+                .map_err(|_| format!("'{}' is not a number", text)),
+            PropertyValue::Int(_) => text
+                .parse()
+                .map(PropertyValue::Int)
+                .map_err(|_| format!("'{}' is not a whole number", text)),
+            PropertyValue::StringList(_) => serde_json::from_str(text)
+                .map(PropertyValue::StringList)
+                .map_err(|_| format!("'{}' is not a JSON list of strings", text)),
+            PropertyValue::EqBandList(_) => serde_json::from_str(text)
+                .map(PropertyValue::EqBandList)
+                .map_err(|_| {
+                    format!(
+                        "'{}' is not a JSON list of [freq, q, gain, type] bands",
+                        text
+                    )
+                }),
+        }
+    }
+}
+
+/// Property observer. It gets mutable access to the core and may set other properties or
+/// run commands; see `Core::set_property` for how nested changes are delivered.
+pub type PropertyCallback = Arc<dyn Fn(&PropertyValue, &mut Core) + Send + Sync>;
+
+/// How often an observer may be notified about changes to a property.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rate {
+    /// Every change is delivered as it happens.
+    Immediate,
+    /// At most one delivery per interval. Changes inside the window are coalesced and the
+    /// latest value is delivered once the window has passed.
+    Throttle(Duration),
+    /// Delivered once the value has stopped changing for the whole interval.
+    Debounce(Duration),
+    /// Delivered on the next `tick` with the latest value; the changes in between are
+    /// dropped. For observers too slow to run on every change, such as Lua scripts
+    /// watching `audio.position`.
+    Coalesce,
+}
+
+pub struct Subscriber {
+    pub callback: PropertyCallback,
+    pub rate: Rate,
+    last_fired: Option<Instant>,
+    pending: Option<(PropertyValue, Instant)>,
 }
 
-pub type PropertyCallback = Arc<dyn Fn(&PropertyValue, &Core) + Send + Sync>;
+impl Subscriber {
+    pub fn new(callback: PropertyCallback, rate: Rate) -> Self {
+        Self {
+            callback,
+            rate,
+            last_fired: None,
+            pending: None,
+        }
+    }
+
+    /// Records a new value and returns true if it should be delivered right away.
+    /// Otherwise the value is parked until `take_due` hands it out.
+    fn offer(&mut self, value: &PropertyValue, now: Instant) -> bool {
+        match self.rate {
+            Rate::Immediate => true,
+            Rate::Throttle(interval) => match self.last_fired {
+                Some(last) if now.duration_since(last) < interval => {
+                    self.pending = Some((value.clone(), last + interval));
+                    false
+                }
+                _ => {
+                    self.last_fired = Some(now);
+                    self.pending = None;
+                    true
+                }
+            },
+            Rate::Debounce(interval) => {
+                self.pending = Some((value.clone(), now + interval));
+                false
+            }
+            Rate::Coalesce => {
+                self.pending = Some((value.clone(), now));
+                false
+            }
+        }
+    }
+
+    fn take_due(&mut self, now: Instant) -> Option<PropertyValue> {
+        match &self.pending {
+            Some((_, due)) if *due <= now => {
+                self.last_fired = Some(now);
+                self.pending.take().map(|(value, _)| value)
+            }
+            _ => None,
+        }
+    }
+}
 
 pub struct Property {
     pub value: PropertyValue,
-    pub callbacks: Vec<PropertyCallback>,
+    /// The value the property was registered with, restored by `Core::reset_property`.
+    pub default: PropertyValue,
+    pub callbacks: Vec<Subscriber>,
 }
 
 impl Property {
     pub fn new(initial: PropertyValue) -> Self {
         Self {
+            default: initial.clone(),
             value: initial,
             callbacks: Vec::new(),
         }
@@ -81,27 +294,173 @@ impl Property {
     }
 
     pub fn subscribe(&mut self, callback: PropertyCallback) {
-        self.callbacks.push(callback);
+        self.subscribe_with_rate(callback, Rate::Immediate);
+    }
+
+    pub fn subscribe_with_rate(&mut self, callback: PropertyCallback, rate: Rate) {
+        self.callbacks.push(Subscriber::new(callback, rate));
     }
 }
 
-pub type CommandCallback = Arc<dyn Fn(Vec<String>, &mut Core) + Send + Sync>;
+pub type CommandCallback =
+    Arc<dyn Fn(Vec<String>, &mut Core) -> Result<(), CoreError> + Send + Sync>;
 
 pub struct Command {
     pub execute: CommandCallback,
+    pub usage: CommandUsage,
+}
+
+impl Command {
+    /// A command without argument metadata; see `CommandBuilder` for declared arguments.
+    pub fn new(execute: CommandCallback) -> Self {
+        Self {
+            execute,
+            usage: CommandUsage::default(),
+        }
+    }
 }
 
+/// Middleware run before every command. Returning `false` cancels the command, which is
+/// how permission checks or argument validation can veto it.
+pub type PreCommandHook = Arc<dyn Fn(&str, &[String], &Core) -> bool + Send + Sync>;
+
+/// Middleware run after every command that was not cancelled, with its execution time.
+pub type PostCommandHook = Arc<dyn Fn(&str, &[String], Duration, &Core) + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum EventType {
     PropertyChanged(String),
     CommandExecuted(String),
+    /// Published by plugins and subsystems through `Core::emit_custom`.
+    Custom(String, PropertyValue),
+}
+
+impl EventType {
+    /// Returns the `kind:name` key that subscription filters are matched against,
+    /// e.g. `property:volume`, `command:play` or `custom:scrobble_submitted`.
+    pub fn key(&self) -> String {
+        match self {
+            EventType::PropertyChanged(name) => format!("property:{}", name),
+            EventType::CommandExecuted(name) => format!("command:{}", name),
+            EventType::Custom(name, _) => format!("custom:{}", name),
+        }
+    }
+
+    /// Checks the event against a filter pattern where `*` matches any run of characters,
+    /// so `property:audio.*` or `command:*` select a whole family of events.
+    pub fn matches(&self, pattern: &str) -> bool {
+        wildcard_match(pattern, &self.key())
+    }
+}
+
+/// Custom event emitted when a track plays through to the end; the payload is its path.
+pub const TRACK_FINISHED: &str = "track_finished";
+/// Custom event emitted when `next` leaves a track that hadn't finished yet.
+pub const TRACK_SKIPPED: &str = "track_skipped";
+/// Custom event emitted when a saved playlist changed in the database; the payload is its
+/// name.
+pub const PLAYLIST_MODIFIED: &str = "playlist_modified";
+/// Custom event emitted when a saved playlist was renamed; the payload is `[old, new]`.
+pub const PLAYLIST_RENAMED: &str = "playlist_renamed";
+/// Custom event emitted when a saved playlist was moved to the trash.
+pub const PLAYLIST_DELETED: &str = "playlist_deleted";
+/// Custom event emitted when a play was written to the history; the payload is the track.
+pub const HISTORY_APPENDED: &str = "history_appended";
+/// Custom event carrying a message a script wants shown to the user, from `core:print`
+/// or `core:notify`; the payload is `[level, text]` with level `info`, `warn` or `error`.
+/// Frontends render it; nothing should print a script's output directly.
+pub const SCRIPT_MESSAGE: &str = "script_message";
+/// Custom event emitted after `config.lua` was rerun because it changed on disk; the
+/// payload lists the properties whose values changed.
+pub const CONFIG_RELOADED: &str = "config_reloaded";
+/// Custom event asking the audio backend to jump within the current track; the payload
+/// is the position in seconds. Emitted by the `seek` command.
+pub const SEEK_REQUESTED: &str = "seek_requested";
+
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            // let the last '*' swallow one more character and retry
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// One entry of the in-memory event log. `detail` holds the new property value or the
+/// command arguments and outcome.
+#[derive(Debug, Clone)]
+pub struct EventLogEntry {
+    pub at: SystemTime,
+    pub event: EventType,
+    pub detail: String,
+}
+
+const DEFAULT_EVENT_LOG_CAPACITY: usize = 256;
+
+pub type EventCallback = Arc<dyn Fn(&EventType, &mut Core) + Send + Sync>;
+
+pub struct EventSubscription {
+    pub filter: Option<String>,
+    pub callback: EventCallback,
+}
+
+/// Handle for sharing one `Core` between the REPL, the audio subscriptions, Lua and any
+/// other frontend. Lock it only for the duration of a single operation.
+pub type SharedCore = Arc<RwLock<Core>>;
+
+enum Notification {
+    Property {
+        value: PropertyValue,
+        callbacks: Vec<PropertyCallback>,
+    },
+    Event(EventType),
 }
 
-pub type EventCallback = Arc<dyn Fn(&EventType, &Core) + Send + Sync>;
+/// How many times one property may change while a single dispatch is draining before the
+/// change is treated as an observer feedback loop and rejected.
+const MAX_CHANGES_PER_DISPATCH: usize = 32;
 
 pub struct Core {
     pub properties: HashMap<String, Property>,
     pub commands: HashMap<String, Command>,
-    pub event_callbacks: Vec<EventCallback>,
+    pub event_callbacks: Vec<EventSubscription>,
+    pub pre_command_hooks: Vec<PreCommandHook>,
+    pub post_command_hooks: Vec<PostCommandHook>,
+    event_log: VecDeque<EventLogEntry>,
+    event_log_capacity: usize,
+    pub history: UndoHistory,
+    pub keys: Keymap,
+    scheduler: Scheduler,
+    dispatch_queue: VecDeque<Notification>,
+    dispatching: bool,
+    changes_this_dispatch: HashMap<String, usize>,
+}
+
+/// How often the driving thread should call `Core::tick`; this is the resolution of
+/// timers and rate-limited observers.
+pub const TICK_INTERVAL: Duration = Duration::from_millis(20);
+
+impl Default for Core {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Core {
@@ -110,50 +469,330 @@ impl Core {
             properties: HashMap::new(),
             commands: HashMap::new(),
             event_callbacks: Vec::new(),
+            pre_command_hooks: Vec::new(),
+            post_command_hooks: Vec::new(),
+            event_log: VecDeque::new(),
+            event_log_capacity: DEFAULT_EVENT_LOG_CAPACITY,
+            history: UndoHistory::default(),
+            keys: Keymap::default(),
+            scheduler: Scheduler::default(),
+            dispatch_queue: VecDeque::new(),
+            dispatching: false,
+            changes_this_dispatch: HashMap::new(),
         }
     }
 
+    pub fn into_shared(self) -> SharedCore {
+        Arc::new(RwLock::new(self))
+    }
+
     pub fn add_property(&mut self, name: &str, value: PropertyValue) {
         self.properties
             .insert(name.to_string(), Property::new(value));
     }
 
-    pub fn set_property(&mut self, name: &str, value: PropertyValue) {
+    /// Sets an existing property and notifies its observers. Unknown names are an error so
+    /// typos in scripts surface instead of being silently dropped; use `set_or_create` when
+    /// implicit creation is really wanted.
+    ///
+    /// Notifications go through a FIFO queue: property observers run in subscription order,
+    /// then event subscribers. If an observer sets another property, that change is applied
+    /// immediately but its notifications are queued behind the ones already pending, so
+    /// delivery is breadth-first rather than recursive. A property that keeps changing
+    /// within one dispatch is rejected with `CoreError::DispatchCycle`.
+    pub fn set_property(&mut self, name: &str, value: PropertyValue) -> Result<(), CoreError> {
         info!(
             "[set_property] Called for '{}' with value: {:?}",
             name, value
         );
 
-        let prop_callbacks = if let Some(prop) = self.properties.get_mut(name) {
-            prop.set(value.clone());
-            info!(
-                "[set_property] Found property, callbacks count: {}",
-                prop.callbacks.len()
-            );
-            prop.callbacks.clone()
-        } else {
+        if !self.properties.contains_key(name) {
             info!("[set_property] Property '{}' not found!", name);
-            return;
-        };
+            return Err(CoreError::UnknownProperty(name.to_string()));
+        }
+
+        if self.dispatching {
+            let count = self
+                .changes_this_dispatch
+                .entry(name.to_string())
+                .or_insert(0);
+            *count += 1;
+            if *count > MAX_CHANGES_PER_DISPATCH {
+                warn!("[set_property] Observer loop detected on '{}'", name);
+                return Err(CoreError::DispatchCycle(name.to_string()));
+            }
+        }
+
+        let prop = self.properties.get_mut(name).expect("checked above");
+        let old = std::mem::replace(&mut prop.value, value.clone());
+        info!(
+            "[set_property] Found property, callbacks count: {}",
+            prop.callbacks.len()
+        );
+        let now = Instant::now();
+        let prop_callbacks = prop
+            .callbacks
+            .iter_mut()
+            .filter_map(|sub| sub.offer(&value, now).then(|| Arc::clone(&sub.callback)))
+            .collect::<Vec<_>>();
+
+        self.history.record(Mutation {
+            name: name.to_string(),
+            old,
+            new: value.clone(),
+        });
 
         info!(
-            "[set_property] Running {} property callbacks",
+            "[set_property] Queueing {} property callbacks",
             prop_callbacks.len()
         );
 
-        for cb in &prop_callbacks {
-            cb(&value, self);
-        }
         let event = EventType::PropertyChanged(name.to_string());
-        for cb in &self.event_callbacks {
-            cb(&event, self);
+        self.record_event(&event, format!("{:?}", value));
+
+        self.dispatch_queue.push_back(Notification::Property {
+            value,
+            callbacks: prop_callbacks,
+        });
+        self.dispatch_queue.push_back(Notification::Event(event));
+        self.dispatch();
+        Ok(())
+    }
+
+    /// Drains the notification queue unless an outer call is already doing so.
+    fn dispatch(&mut self) {
+        if self.dispatching {
+            return;
+        }
+        self.dispatching = true;
+
+        while let Some(notification) = self.dispatch_queue.pop_front() {
+            match notification {
+                Notification::Property { value, callbacks } => {
+                    for cb in &callbacks {
+                        cb(&value, self);
+                    }
+                }
+                Notification::Event(event) => {
+                    let callbacks: Vec<EventCallback> = self
+                        .event_callbacks
+                        .iter()
+                        .filter(|sub| sub.filter.as_deref().is_none_or(|f| event.matches(f)))
+                        .map(|sub| Arc::clone(&sub.callback))
+                        .collect();
+                    for cb in &callbacks {
+                        cb(&event, self);
+                    }
+                }
+            }
+        }
+
+        self.changes_this_dispatch.clear();
+        self.dispatching = false;
+    }
+
+    /// Restores a property to the value it was registered with.
+    pub fn reset_property(&mut self, name: &str) -> Result<(), CoreError> {
+        let default = self
+            .properties
+            .get(name)
+            .map(|prop| prop.default.clone())
+            .ok_or_else(|| CoreError::UnknownProperty(name.to_string()))?;
+        if self.get_property(name) != Some(&default) {
+            self.set_property(name, default)?;
+        }
+        Ok(())
+    }
+
+    /// Restores every property to its registered default, as a single undo step.
+    pub fn reset_all(&mut self) -> Result<(), CoreError> {
+        let names: Vec<String> = self
+            .property_names_in("")
+            .into_iter()
+            .map(|name| name.to_string())
+            .collect();
+        self.history.begin_group();
+        let result = names.iter().try_for_each(|name| self.reset_property(name));
+        self.history.end_group();
+        result
+    }
+
+    /// Subscribes to a property with the given delivery rate.
+    pub fn subscribe_property(
+        &mut self,
+        name: &str,
+        rate: Rate,
+        callback: PropertyCallback,
+    ) -> Result<(), CoreError> {
+        match self.properties.get_mut(name) {
+            Some(prop) => {
+                prop.subscribe_with_rate(callback, rate);
+                Ok(())
+            }
+            None => Err(CoreError::UnknownProperty(name.to_string())),
+        }
+    }
+
+    /// Removes an observer added with `subscribe_property`, identified by its callback.
+    /// Returns false if it wasn't subscribed to `name`.
+    pub fn unsubscribe_property(&mut self, name: &str, callback: &PropertyCallback) -> bool {
+        let Some(prop) = self.properties.get_mut(name) else {
+            return false;
+        };
+        let before = prop.callbacks.len();
+        prop.callbacks
+            .retain(|sub| !Arc::ptr_eq(&sub.callback, callback));
+        prop.callbacks.len() != before
+    }
+
+    /// Runs `callback` once after `after` has elapsed.
+    pub fn schedule(&mut self, after: Duration, callback: TimerCallback) -> TimerId {
+        self.scheduler.add(after, None, callback)
+    }
+
+    /// Runs `callback` every `interval`, starting one interval from now.
+    pub fn schedule_repeating(&mut self, interval: Duration, callback: TimerCallback) -> TimerId {
+        self.scheduler.add(interval, Some(interval), callback)
+    }
+
+    pub fn cancel_timer(&mut self, id: TimerId) -> bool {
+        self.scheduler.cancel(id)
+    }
+
+    pub fn timer_pending(&self, id: TimerId) -> bool {
+        self.scheduler.is_pending(id)
+    }
+
+    /// Moves a float property in a straight line to `target` over `over`, a step every
+    /// tick, and returns the timer driving it so it can be cancelled. The steps are not
+    /// recorded for undo; a fade isn't something to step back through.
+    pub fn ramp_float(
+        &mut self,
+        name: &str,
+        target: f32,
+        over: Duration,
+    ) -> Result<TimerId, CoreError> {
+        let start = self
+            .get_float(name)
+            .ok_or_else(|| CoreError::UnknownProperty(name.to_string()))?;
+        let name = name.to_string();
+        let began = Instant::now();
+        let this: Arc<OnceLock<TimerId>> = Arc::default();
+        let own = Arc::clone(&this);
+        let id = self.schedule_repeating(
+            TICK_INTERVAL,
+            Arc::new(move |core| {
+                let progress = if over.is_zero() {
+                    1.0
+                } else {
+                    (began.elapsed().as_secs_f32() / over.as_secs_f32()).min(1.0)
+                };
+                core.history.replaying = true;
+                let _ = core.set_property(
+                    &name,
+                    PropertyValue::Float(start + (target - start) * progress),
+                );
+                core.history.replaying = false;
+                if progress >= 1.0
+                    && let Some(id) = own.get()
+                {
+                    core.cancel_timer(*id);
+                }
+            }),
+        );
+        let _ = this.set(id);
+        Ok(id)
+    }
+
+    /// True while any timer is waiting to fire, e.g. to keep ticking a headless core
+    /// until the work a script started is done.
+    pub fn has_pending_timers(&self) -> bool {
+        !self.scheduler.is_empty()
+    }
+
+    /// Fires due timers and delivers throttled and debounced notifications whose wait has
+    /// elapsed. Called every `TICK_INTERVAL` by whoever drives the core (the ticker thread
+    /// in `main`).
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        for timer in self.scheduler.take_due(now) {
+            timer(self);
+        }
+
+        for prop in self.properties.values_mut() {
+            for sub in prop.callbacks.iter_mut() {
+                if let Some(value) = sub.take_due(now) {
+                    self.dispatch_queue.push_back(Notification::Property {
+                        value,
+                        callbacks: vec![Arc::clone(&sub.callback)],
+                    });
+                }
+            }
+        }
+        self.dispatch();
+    }
+
+    /// Sets a property, registering it first if it does not exist yet.
+    pub fn set_or_create(&mut self, name: &str, value: PropertyValue) {
+        if !self.properties.contains_key(name) {
+            self.add_property(name, value.clone());
         }
+        // cannot fail, the property was registered above
+        let _ = self.set_property(name, value);
     }
 
     pub fn get_property(&self, name: &str) -> Option<&PropertyValue> {
         self.properties.get(name).map(|p| p.get())
     }
 
+    /// Lists the property names under a dotted namespace, sorted. `audio` matches
+    /// `audio.volume` and `audio.eq.bands` but not `audiobook`; an empty namespace lists
+    /// every property.
+    pub fn property_names_in(&self, namespace: &str) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .properties
+            .keys()
+            .map(|name| name.as_str())
+            .filter(|name| in_namespace(name, namespace))
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn properties_in(&self, namespace: &str) -> Vec<(&str, &PropertyValue)> {
+        self.property_names_in(namespace)
+            .into_iter()
+            .filter_map(|name| self.get_property(name).map(|value| (name, value)))
+            .collect()
+    }
+
+    /// Serializes every property into a JSON object nested by namespace, so
+    /// `audio.volume` ends up at `{"audio": {"volume": ...}}`.
+    pub fn snapshot(&self) -> serde_json::Value {
+        let mut root = serde_json::Map::new();
+        for (name, value) in self.properties_in("") {
+            let parts: Vec<&str> = name.split('.').collect();
+            if !insert_json_path(&mut root, &parts, value.to_json()) {
+                // a property already sits where a section would go; keep the full name
+                root.insert(name.to_string(), value.to_json());
+            }
+        }
+        serde_json::Value::Object(root)
+    }
+
+    /// Returns the top-level sections (`audio`, `eq`, `playlist`, ...) that currently own
+    /// at least one property.
+    pub fn namespaces(&self) -> Vec<&str> {
+        let mut sections: Vec<&str> = self
+            .properties
+            .keys()
+            .filter_map(|name| name.split_once('.').map(|(section, _)| section))
+            .collect();
+        sections.sort_unstable();
+        sections.dedup();
+        sections
+    }
+
     // Typed getters for convenience
     pub fn get_string(&self, name: &str) -> Option<&String> {
         self.get_property(name).and_then(|v| v.as_string())
@@ -175,31 +814,245 @@ impl Core {
         self.get_property(name).and_then(|v| v.as_string_list())
     }
 
-    pub fn add_command(&mut self, name: &str, command: Command) {
+    pub fn add_command(&mut self, name: &str, mut command: Command) {
+        if command.usage.name.is_empty() {
+            command.usage.name = name.to_string();
+        }
         self.commands.insert(name.to_string(), command);
     }
 
-    pub fn execute_command(&mut self, name: &str, params: Vec<String>) {
-        if let Some(cmd) = self.commands.get(name) {
-            let exec_fn = Arc::clone(&cmd.execute);
-            exec_fn(params, self);
+    /// What `name` takes and does, for help output.
+    pub fn command_usage(&self, name: &str) -> Option<&CommandUsage> {
+        self.commands.get(name).map(|command| &command.usage)
+    }
+
+    /// Adds a command built with `CommandBuilder` under its own name.
+    pub fn register(&mut self, command: Command) {
+        let name = command.usage.name.clone();
+        self.add_command(&name, command);
+    }
+
+    /// Runs a command with full access; this is what the local REPL and scripts use.
+    pub fn execute_command(&mut self, name: &str, params: Vec<String>) -> Result<(), CoreError> {
+        self.execute_command_as(name, params, Capability::Admin)
+    }
+
+    /// Runs a command with structured parameters, as sent by remote clients (JSON) or
+    /// Lua tables: arrays are positional, objects are matched to the declared argument
+    /// names. The same capability check as `execute_command_as` applies to `granted`.
+    pub fn execute_command_structured(
+        &mut self,
+        name: &str,
+        params: &serde_json::Value,
+        granted: Capability,
+    ) -> Result<(), CoreError> {
+        let order: Vec<String> = match self.commands.get(name) {
+            Some(cmd) => cmd.usage.args.iter().map(|a| a.name.clone()).collect(),
+            None => return Err(CoreError::UnknownCommand(name.to_string())),
+        };
+        let args = crate::params::structured_to_args(params, &order).map_err(|e| {
+            CoreError::InvalidArgument {
+                command: name.to_string(),
+                message: e.0,
+            }
+        })?;
+        self.execute_command_as(name, args, granted)
+    }
+
+    /// Runs a command on behalf of a client holding `granted`. Remote frontends should go
+    /// through here so a LAN client can't, say, delete playlists without being allowed to.
+    pub fn execute_command_as(
+        &mut self,
+        name: &str,
+        params: Vec<String>,
+        granted: Capability,
+    ) -> Result<(), CoreError> {
+        let exec_fn = match self.commands.get(name) {
+            Some(cmd) if cmd.usage.capability > granted => {
+                return Err(CoreError::PermissionDenied {
+                    command: name.to_string(),
+                    required: cmd.usage.capability,
+                });
+            }
+            Some(cmd) => Arc::clone(&cmd.execute),
+            None => return Err(CoreError::UnknownCommand(name.to_string())),
+        };
+
+        for hook in self.pre_command_hooks.clone() {
+            if !hook(name, &params, self) {
+                info!("[execute_command] '{}' cancelled by middleware", name);
+                return Err(CoreError::CommandCancelled(name.to_string()));
+            }
+        }
+
+        let started = Instant::now();
+        self.history.begin_group();
+        let result = exec_fn(params.clone(), self);
+        self.history.end_group();
+        let elapsed = started.elapsed();
+
+        for hook in self.post_command_hooks.clone() {
+            hook(name, &params, elapsed, self);
         }
 
         let event = EventType::CommandExecuted(name.to_string());
-        for cb in &self.event_callbacks {
-            cb(&event, self);
+        let outcome = match &result {
+            Ok(()) => "ok".to_string(),
+            Err(e) => format!("failed: {}", e),
+        };
+        self.record_event(&event, format!("{:?} {}", params, outcome));
+        self.emit(&event);
+        result
+    }
+
+    /// Reverts the most recent group of property changes. Returns false when there is
+    /// nothing left to undo.
+    pub fn undo(&mut self) -> Result<bool, CoreError> {
+        let Some(group) = self.history.pop_undo() else {
+            return Ok(false);
+        };
+        let result = self.replay(group.iter().rev().map(|m| (&m.name, &m.old)));
+        self.history.push_undone(group);
+        result.map(|_| true)
+    }
+
+    /// Re-applies the most recently undone group of changes.
+    pub fn redo(&mut self) -> Result<bool, CoreError> {
+        let Some(group) = self.history.pop_redo() else {
+            return Ok(false);
+        };
+        let result = self.replay(group.iter().map(|m| (&m.name, &m.new)));
+        self.history.push_redone(group);
+        result.map(|_| true)
+    }
+
+    fn replay<'a>(
+        &mut self,
+        changes: impl Iterator<Item = (&'a String, &'a PropertyValue)>,
+    ) -> Result<(), CoreError> {
+        self.history.replaying = true;
+        let result = changes
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .try_for_each(|(name, value)| self.set_property(&name, value));
+        self.history.replaying = false;
+        result
+    }
+
+    /// Publishes an application-defined event (e.g. `scrobble_submitted`) to subscribers
+    /// filtering on `custom:<name>` and records it in the event log.
+    pub fn emit_custom(&mut self, name: &str, payload: PropertyValue) {
+        let detail = format!("{:?}", payload);
+        let event = EventType::Custom(name.to_string(), payload);
+        self.record_event(&event, detail);
+        self.emit(&event);
+    }
+
+    fn record_event(&mut self, event: &EventType, detail: String) {
+        if self.event_log_capacity == 0 {
+            return;
+        }
+        while self.event_log.len() >= self.event_log_capacity {
+            self.event_log.pop_front();
         }
+        self.event_log.push_back(EventLogEntry {
+            at: SystemTime::now(),
+            event: event.clone(),
+            detail,
+        });
     }
 
-    pub fn subscribe_event(&mut self, callback: EventCallback) {
-        self.event_callbacks.push(callback);
+    /// Returns up to `limit` of the most recent log entries, oldest first.
+    pub fn recent_events(&self, limit: usize) -> Vec<&EventLogEntry> {
+        let skip = self.event_log.len().saturating_sub(limit);
+        self.event_log.iter().skip(skip).collect()
+    }
+
+    /// Changes how many entries the event log keeps; 0 disables logging.
+    pub fn set_event_log_capacity(&mut self, capacity: usize) {
+        self.event_log_capacity = capacity;
+        while self.event_log.len() > capacity {
+            self.event_log.pop_front();
+        }
+    }
+
+    pub fn add_pre_command_hook(&mut self, hook: PreCommandHook) {
+        self.pre_command_hooks.push(hook);
+    }
+
+    pub fn add_post_command_hook(&mut self, hook: PostCommandHook) {
+        self.post_command_hooks.push(hook);
+    }
+
+    /// Subscribes to core events. With a filter such as `property:audio.*` or `command:*`
+    /// the callback only sees matching events; `None` receives everything.
+    pub fn subscribe_event(&mut self, filter: Option<&str>, callback: EventCallback) {
+        self.event_callbacks.push(EventSubscription {
+            filter: filter.map(|f| f.to_string()),
+            callback,
+        });
+    }
+
+    fn emit(&mut self, event: &EventType) {
+        self.dispatch_queue
+            .push_back(Notification::Event(event.clone()));
+        self.dispatch();
+    }
+}
+
+fn insert_json_path(
+    node: &mut serde_json::Map<String, serde_json::Value>,
+    parts: &[&str],
+    value: serde_json::Value,
+) -> bool {
+    match parts {
+        [] => false,
+        [leaf] => {
+            node.insert(leaf.to_string(), value);
+            true
+        }
+        [section, rest @ ..] => match node
+            .entry(section.to_string())
+            .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()))
+        {
+            serde_json::Value::Object(child) => insert_json_path(child, rest, value),
+            _ => false,
+        },
     }
 }
 
+fn in_namespace(name: &str, namespace: &str) -> bool {
+    namespace.is_empty()
+        || name
+            .strip_prefix(namespace)
+            .is_some_and(|rest| rest.starts_with('.'))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_as() {
+        let bool_value = PropertyValue::Bool(false);
+        assert_eq!(bool_value.parse_as("On"), Ok(PropertyValue::Bool(true)));
+        assert!(bool_value.parse_as("maybe").is_err());
+        assert_eq!(
+            PropertyValue::Int(0).parse_as(" 42 "),
+            Ok(PropertyValue::Int(42))
+        );
+        assert!(PropertyValue::Int(0).parse_as("4.2").is_err());
+        assert_eq!(
+            PropertyValue::StringList(Vec::new()).parse_as(r#"["a", "b"]"#),
+            Ok(PropertyValue::StringList(vec!["a".into(), "b".into()]))
+        );
+        assert_eq!(
+            PropertyValue::EqBandList(Vec::new()).parse_as("[[100, 1, -3, 0]]"),
+            Ok(PropertyValue::EqBandList(vec![[100.0, 1.0, -3.0, 0.0]]))
+        );
+    }
+
     #[test]
     fn test_property_value_types() {
         let str_val = PropertyValue::String("test".to_string());
@@ -232,7 +1085,8 @@ mod tests {
         assert_eq!(core.get_float("volume"), Some(0.5));
         assert_eq!(core.get_string_list("playlist"), Some(&vec![]));
 
-        core.set_property("playing", PropertyValue::Bool(true));
+        core.set_property("playing", PropertyValue::Bool(true))
+            .unwrap();
         assert_eq!(core.get_bool("playing"), Some(true));
     }
 
@@ -250,7 +1104,8 @@ mod tests {
             }));
         }
 
-        core.set_property("test", PropertyValue::String("changed".to_string()));
+        core.set_property("test", PropertyValue::String("changed".to_string()))
+            .unwrap();
         assert!(*callback_triggered.lock().unwrap());
     }
 
@@ -261,16 +1116,496 @@ mod tests {
 
         core.add_command(
             "set_value",
-            Command {
-                execute: Arc::new(|params, core| {
-                    if let Some(val) = params.get(0) {
-                        core.set_property("value", PropertyValue::String(val.clone()));
-                    }
-                }),
-            },
+            Command::new(Arc::new(|params, core| {
+                if let Some(val) = params.first() {
+                    core.set_property("value", PropertyValue::String(val.clone()))?;
+                }
+                Ok(())
+            })),
         );
 
-        core.execute_command("set_value", vec!["new_value".to_string()]);
+        core.execute_command("set_value", vec!["new_value".to_string()])
+            .unwrap();
         assert_eq!(core.get_string("value"), Some(&"new_value".to_string()));
     }
+
+    #[test]
+    fn test_throttled_and_debounced_observers() {
+        let mut core = Core::new();
+        core.add_property("position", PropertyValue::Float(0.0));
+
+        let throttled = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let debounced = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let throttled_clone = Arc::clone(&throttled);
+        let debounced_clone = Arc::clone(&debounced);
+        core.subscribe_property(
+            "position",
+            Rate::Throttle(Duration::from_millis(30)),
+            Arc::new(move |value, _core| {
+                throttled_clone
+                    .lock()
+                    .unwrap()
+                    .push(value.as_float().unwrap());
+            }),
+        )
+        .unwrap();
+        core.subscribe_property(
+            "position",
+            Rate::Debounce(Duration::from_millis(30)),
+            Arc::new(move |value, _core| {
+                debounced_clone
+                    .lock()
+                    .unwrap()
+                    .push(value.as_float().unwrap());
+            }),
+        )
+        .unwrap();
+
+        for i in 1..=5 {
+            core.set_property("position", PropertyValue::Float(i as f32))
+                .unwrap();
+        }
+        core.tick();
+        assert_eq!(*throttled.lock().unwrap(), vec![1.0]);
+        assert!(debounced.lock().unwrap().is_empty());
+
+        std::thread::sleep(Duration::from_millis(40));
+        core.tick();
+        assert_eq!(*throttled.lock().unwrap(), vec![1.0, 5.0]);
+        assert_eq!(*debounced.lock().unwrap(), vec![5.0]);
+    }
+
+    #[test]
+    fn test_ramp_float() {
+        let mut core = Core::new();
+        core.add_property("volume", PropertyValue::Float(1.0));
+        assert!(core.ramp_float("missing", 0.0, Duration::ZERO).is_err());
+
+        let id = core
+            .ramp_float("volume", 0.0, Duration::from_millis(40))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        core.tick();
+        let halfway = core.get_float("volume").unwrap();
+        assert!(halfway < 1.0 && halfway > 0.0);
+
+        std::thread::sleep(Duration::from_millis(30));
+        core.tick();
+        assert_eq!(core.get_float("volume"), Some(0.0));
+        assert!(!core.timer_pending(id));
+        assert!(!core.history.can_undo());
+    }
+
+    #[test]
+    fn test_coalesced_observer() {
+        let mut core = Core::new();
+        core.add_property("position", PropertyValue::Float(0.0));
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen_clone = Arc::clone(&seen);
+        let callback: PropertyCallback = Arc::new(move |value, _core| {
+            seen_clone.lock().unwrap().push(value.as_float().unwrap());
+        });
+        core.subscribe_property("position", Rate::Coalesce, Arc::clone(&callback))
+            .unwrap();
+
+        for i in 1..=3 {
+            core.set_property("position", PropertyValue::Float(i as f32))
+                .unwrap();
+        }
+        assert!(seen.lock().unwrap().is_empty());
+        core.tick();
+        core.tick();
+        assert_eq!(*seen.lock().unwrap(), vec![3.0]);
+
+        assert!(core.unsubscribe_property("position", &callback));
+        assert!(!core.unsubscribe_property("position", &callback));
+        core.set_property("position", PropertyValue::Float(4.0))
+            .unwrap();
+        core.tick();
+        assert_eq!(*seen.lock().unwrap(), vec![3.0]);
+    }
+
+    #[test]
+    fn test_event_log_is_bounded() {
+        let mut core = Core::new();
+        core.add_property("volume", PropertyValue::Float(0.5));
+        core.set_event_log_capacity(3);
+
+        for i in 0..5 {
+            core.set_property("volume", PropertyValue::Float(i as f32 / 10.0))
+                .unwrap();
+        }
+        let _ = core.execute_command("missing", vec![]);
+
+        let log = core.recent_events(10);
+        assert_eq!(log.len(), 3);
+        assert_eq!(log[2].detail, "Float(0.4)");
+        assert_eq!(
+            core.recent_events(1)[0].event,
+            EventType::PropertyChanged("volume".to_string())
+        );
+    }
+
+    #[test]
+    fn test_undo_redo_groups_command_changes() {
+        let mut core = Core::new();
+        core.add_property("volume", PropertyValue::Float(0.5));
+        core.add_property("track", PropertyValue::String("a".to_string()));
+        core.add_property("position", PropertyValue::Float(0.0));
+        core.history.untrack("position");
+        core.add_command(
+            "jump",
+            Command::new(Arc::new(|_params, core| {
+                core.set_property("track", PropertyValue::String("b".to_string()))?;
+                core.set_property("volume", PropertyValue::Float(0.9))
+            })),
+        );
+
+        core.execute_command("jump", vec![]).unwrap();
+        core.set_property("position", PropertyValue::Float(12.0))
+            .unwrap();
+
+        assert!(core.undo().unwrap());
+        assert_eq!(core.get_float("volume"), Some(0.5));
+        assert_eq!(core.get_string("track"), Some(&"a".to_string()));
+        assert_eq!(core.get_float("position"), Some(12.0));
+        assert!(!core.undo().unwrap());
+
+        assert!(core.redo().unwrap());
+        assert_eq!(core.get_string("track"), Some(&"b".to_string()));
+        assert!(!core.history.can_redo());
+    }
+
+    #[test]
+    fn test_scheduled_timers() {
+        let mut core = Core::new();
+        core.add_property("count", PropertyValue::Int(0));
+        core.add_property("done", PropertyValue::Bool(false));
+
+        core.schedule(
+            Duration::from_millis(10),
+            Arc::new(|core| {
+                core.set_property("done", PropertyValue::Bool(true))
+                    .unwrap();
+            }),
+        );
+        let repeating = core.schedule_repeating(
+            Duration::from_millis(5),
+            Arc::new(|core| {
+                let count = core.get_int("count").unwrap();
+                core.set_property("count", PropertyValue::Int(count + 1))
+                    .unwrap();
+            }),
+        );
+
+        core.tick();
+        assert_eq!(core.get_bool("done"), Some(false));
+
+        std::thread::sleep(Duration::from_millis(15));
+        core.tick();
+        assert_eq!(core.get_bool("done"), Some(true));
+        assert_eq!(core.get_int("count"), Some(1));
+
+        assert!(core.cancel_timer(repeating));
+        std::thread::sleep(Duration::from_millis(10));
+        core.tick();
+        assert_eq!(core.get_int("count"), Some(1));
+        assert!(!core.timer_pending(repeating));
+    }
+
+    #[test]
+    fn test_snapshot_nests_namespaces() {
+        let mut core = Core::new();
+        core.add_property("audio.volume", PropertyValue::Float(0.5));
+        core.add_property("audio.playing", PropertyValue::Bool(true));
+        core.add_property(
+            "playlist.tracks",
+            PropertyValue::StringList(vec!["a.flac".to_string()]),
+        );
+
+        assert_eq!(
+            core.snapshot(),
+            serde_json::json!({
+                "audio": { "volume": 0.5, "playing": true },
+                "playlist": { "tracks": ["a.flac"] },
+            })
+        );
+    }
+
+    #[test]
+    fn test_reset_to_defaults() {
+        let mut core = Core::new();
+        core.add_property("volume", PropertyValue::Float(0.5));
+        core.add_property("eq.enabled", PropertyValue::Bool(false));
+
+        core.set_property("volume", PropertyValue::Float(0.9))
+            .unwrap();
+        core.set_property("eq.enabled", PropertyValue::Bool(true))
+            .unwrap();
+
+        core.reset_property("volume").unwrap();
+        assert_eq!(core.get_float("volume"), Some(0.5));
+        assert!(core.reset_property("missing").is_err());
+
+        core.set_property("volume", PropertyValue::Float(0.1))
+            .unwrap();
+        core.reset_all().unwrap();
+        assert_eq!(core.get_float("volume"), Some(0.5));
+        assert_eq!(core.get_bool("eq.enabled"), Some(false));
+
+        core.undo().unwrap();
+        assert_eq!(core.get_float("volume"), Some(0.1));
+        assert_eq!(core.get_bool("eq.enabled"), Some(true));
+    }
+
+    #[test]
+    fn test_property_namespaces() {
+        let mut core = Core::new();
+        core.add_property("audio.volume", PropertyValue::Float(0.5));
+        core.add_property("audio.playing", PropertyValue::Bool(false));
+        core.add_property("audiobook.chapter", PropertyValue::Int(1));
+        core.add_property("eq.enabled", PropertyValue::Bool(true));
+
+        assert_eq!(
+            core.property_names_in("audio"),
+            vec!["audio.playing", "audio.volume"]
+        );
+        assert_eq!(core.property_names_in("").len(), 4);
+        assert_eq!(core.namespaces(), vec!["audio", "audiobook", "eq"]);
+        assert_eq!(
+            core.properties_in("eq"),
+            vec![("eq.enabled", &PropertyValue::Bool(true))]
+        );
+    }
+
+    #[test]
+    fn test_set_unknown_property() {
+        let mut core = Core::new();
+        assert_eq!(
+            core.set_property("volumr", PropertyValue::Float(0.3)),
+            Err(CoreError::UnknownProperty("volumr".to_string()))
+        );
+        assert_eq!(core.get_property("volumr"), None);
+
+        core.set_or_create("volumr", PropertyValue::Float(0.3));
+        assert_eq!(core.get_float("volumr"), Some(0.3));
+    }
+
+    #[test]
+    fn test_nested_updates_are_queued_in_order() {
+        let mut core = Core::new();
+        core.add_property("a", PropertyValue::Int(0));
+        core.add_property("b", PropertyValue::Int(0));
+
+        let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let order_a = Arc::clone(&order);
+        let order_b = Arc::clone(&order);
+        core.subscribe_property(
+            "a",
+            Rate::Immediate,
+            Arc::new(move |value, core| {
+                order_a.lock().unwrap().push("a observer");
+                let a = value.as_int().unwrap();
+                core.set_property("b", PropertyValue::Int(a * 10)).unwrap();
+                // b is already updated, its observers run later
+                assert_eq!(core.get_int("b"), Some(a * 10));
+            }),
+        )
+        .unwrap();
+        core.subscribe_property(
+            "b",
+            Rate::Immediate,
+            Arc::new(move |_value, _core| order_b.lock().unwrap().push("b observer")),
+        )
+        .unwrap();
+        let order_events = Arc::clone(&order);
+        core.subscribe_event(
+            Some("property:a"),
+            Arc::new(move |_event, _core| order_events.lock().unwrap().push("a event")),
+        );
+
+        core.set_property("a", PropertyValue::Int(2)).unwrap();
+        assert_eq!(core.get_int("b"), Some(20));
+        assert_eq!(
+            *order.lock().unwrap(),
+            vec!["a observer", "a event", "b observer"]
+        );
+    }
+
+    #[test]
+    fn test_observer_cycles_are_stopped() {
+        let mut core = Core::new();
+        core.add_property("ping", PropertyValue::Int(0));
+        core.add_property("pong", PropertyValue::Int(0));
+        for (from, to) in [("ping", "pong"), ("pong", "ping")] {
+            core.subscribe_property(
+                from,
+                Rate::Immediate,
+                Arc::new(move |value, core| {
+                    let next = value.as_int().unwrap() + 1;
+                    let _ = core.set_property(to, PropertyValue::Int(next));
+                }),
+            )
+            .unwrap();
+        }
+
+        core.set_property("ping", PropertyValue::Int(1)).unwrap();
+        let total = core.get_int("ping").unwrap() + core.get_int("pong").unwrap();
+        assert!(total < 200, "cycle was not cut off: {}", total);
+    }
+
+    #[test]
+    fn test_command_middleware() {
+        let mut core = Core::new();
+        core.add_property("value", PropertyValue::String("initial".to_string()));
+        core.add_command(
+            "set_value",
+            Command::new(Arc::new(|params, core| {
+                if let Some(val) = params.first() {
+                    core.set_property("value", PropertyValue::String(val.clone()))?;
+                }
+                Ok(())
+            })),
+        );
+
+        let ran = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let ran_clone = Arc::clone(&ran);
+        core.add_pre_command_hook(Arc::new(|_name, params, _core| {
+            params.first().map(|p| p != "forbidden").unwrap_or(true)
+        }));
+        core.add_post_command_hook(Arc::new(move |name, params, _elapsed, _core| {
+            ran_clone
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", name, params.join(" ")));
+        }));
+
+        assert_eq!(
+            core.execute_command("set_value", vec!["forbidden".to_string()]),
+            Err(CoreError::CommandCancelled("set_value".to_string()))
+        );
+        assert_eq!(core.get_string("value"), Some(&"initial".to_string()));
+
+        core.execute_command("set_value", vec!["allowed".to_string()])
+            .unwrap();
+        assert_eq!(core.get_string("value"), Some(&"allowed".to_string()));
+        assert_eq!(*ran.lock().unwrap(), vec!["set_value allowed".to_string()]);
+    }
+
+    #[test]
+    fn test_command_capabilities() {
+        use crate::command_builder::CommandBuilder;
+
+        let mut core = Core::new();
+        core.add_property("volume", PropertyValue::Float(0.5));
+        core.register(
+            CommandBuilder::new("mute")
+                .capability(Capability::PlaybackControl)
+                .run(|core, _args| core.set_property("volume", PropertyValue::Float(0.0))),
+        );
+
+        assert_eq!(
+            core.execute_command_as("mute", vec![], Capability::ReadOnly),
+            Err(CoreError::PermissionDenied {
+                command: "mute".to_string(),
+                required: Capability::PlaybackControl,
+            })
+        );
+        assert_eq!(core.get_float("volume"), Some(0.5));
+
+        core.execute_command_as("mute", vec![], Capability::LibraryModify)
+            .unwrap();
+        assert_eq!(core.get_float("volume"), Some(0.0));
+        assert_eq!("read-only".parse(), Ok(Capability::ReadOnly));
+    }
+
+    #[test]
+    fn test_event_filters() {
+        let mut core = Core::new();
+        core.add_property("audio.volume", PropertyValue::Float(0.5));
+        core.add_property("playing", PropertyValue::Bool(false));
+
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen_clone = Arc::clone(&seen);
+        core.subscribe_event(
+            Some("property:audio.*"),
+            Arc::new(move |event, _core| {
+                seen_clone.lock().unwrap().push(event.key());
+            }),
+        );
+
+        core.set_property("playing", PropertyValue::Bool(true))
+            .unwrap();
+        core.set_property("audio.volume", PropertyValue::Float(0.2))
+            .unwrap();
+        assert!(core.execute_command("missing", vec![]).is_err());
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec!["property:audio.volume".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_shared_core_across_threads() {
+        let mut core = Core::new();
+        core.add_property("volume", PropertyValue::Float(0.5));
+        let shared = core.into_shared();
+
+        let writer = {
+            let shared = Arc::clone(&shared);
+            std::thread::spawn(move || {
+                shared
+                    .write()
+                    .unwrap()
+                    .set_property("volume", PropertyValue::Float(0.8))
+                    .unwrap();
+            })
+        };
+        writer.join().unwrap();
+
+        assert_eq!(shared.read().unwrap().get_float("volume"), Some(0.8));
+    }
+
+    #[test]
+    fn test_custom_events() {
+        let mut core = Core::new();
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen_clone = Arc::clone(&seen);
+        core.subscribe_event(
+            Some("custom:scrobble_*"),
+            Arc::new(move |event, _core| {
+                if let EventType::Custom(name, payload) = event {
+                    seen_clone
+                        .lock()
+                        .unwrap()
+                        .push((name.clone(), payload.clone()));
+                }
+            }),
+        );
+
+        core.emit_custom(
+            "scrobble_submitted",
+            PropertyValue::String("a.flac".to_string()),
+        );
+        core.emit_custom("lyrics_loaded", PropertyValue::Bool(true));
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![(
+                "scrobble_submitted".to_string(),
+                PropertyValue::String("a.flac".to_string())
+            )]
+        );
+        assert_eq!(core.recent_events(10).len(), 2);
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*", "command:play"));
+        assert!(wildcard_match("command:*", "command:play"));
+        assert!(wildcard_match("property:*.volume", "property:audio.volume"));
+        assert!(!wildcard_match("command:*", "property:volume"));
+        assert!(!wildcard_match("property:audio.*", "property:audio"));
+    }
 }
@@ -1,20 +1,196 @@
-use rusqlite::{Connection, Result, params};
+use crate::fingerprint;
+use crate::metadata::TrackMetadata;
+use crate::podcast::Episode;
+use rusqlite::{Connection, OptionalExtension, Result, params};
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Time window for the listening statistics queries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatsPeriod {
+    Week,
+    Month,
+    AllTime,
+}
+
+impl StatsPeriod {
+    /// SQLite date modifier for the start of the window; `None` means no lower bound.
+    fn modifier(self) -> Option<&'static str> {
+        match self {
+            StatsPeriod::Week => Some("-7 days"),
+            StatsPeriod::Month => Some("-1 month"),
+            StatsPeriod::AllTime => None,
+        }
+    }
+}
+
+/// Result of `Database::rename_playlist`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenameOutcome {
+    Renamed,
+    NotFound,
+    /// Another playlist already has the new name; nothing was changed.
+    NameTaken,
+}
+
+/// Result of `Database::copy_playlist`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CopyOutcome {
+    Copied,
+    NotFound,
+    /// A playlist with the target name exists; it was left alone.
+    NameTaken,
+}
+
+/// Result of `Database::undelete_playlist`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UndeleteOutcome {
+    Restored,
+    /// Nothing by that name is in the trash.
+    NotFound,
+    /// A playlist with that name was created since; the trashed one stays put.
+    NameTaken,
+}
+
+/// One row of `Database::playlist_tree`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaylistTreeEntry {
+    pub depth: usize,
+    /// Last path component.
+    pub name: String,
+    /// Full path, usable with `load`.
+    pub path: String,
+    pub is_folder: bool,
+}
+
+/// Result of `Database::check`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CheckReport {
+    /// Messages from `PRAGMA integrity_check`; empty when the file is healthy.
+    pub problems: Vec<String>,
+    /// playlist_tracks rows that pointed at a playlist which no longer exists.
+    pub orphans_removed: usize,
+}
+
+/// A track in a saved playlist with its per-playlist annotations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaylistEntry {
+    pub track: String,
+    pub note: Option<String>,
+    /// Entries are ordered by weight, then by position; everything starts at 0.
+    pub sort_weight: i64,
+}
+
+/// A `track_stats` row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackStats {
+    pub path: String,
+    pub play_count: u32,
+    pub skip_count: u32,
+    pub last_played: Option<String>,
+}
+
+/// Enough for every query the hot paths use without evicting each other.
+const STATEMENT_CACHE_CAPACITY: usize = 64;
+
+/// `tracks` columns in the order `track_from_row` reads them.
+const TRACK_COLUMNS: &str = "path, title, artist, album, track_number, duration, codec, genre";
+
+/// Name of the built-in playlist that always holds the liked tracks. It isn't stored in
+/// `playlists`; `get_playlist_tracks` answers it from the `favorites` table. No saved
+/// playlist can take the name, it would be hidden for good.
+pub const FAVORITES_PLAYLIST: &str = "favorites";
+
+/// How long a connection waits for another one's write lock before giving up with
+/// "database is locked".
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A change made through a `Database`, sent to the listener set with
+/// `set_event_sender` so in-memory views can follow the file without polling.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DbEvent {
+    /// Entries, notes or weights of a saved playlist changed, or it came back from the
+    /// trash. Liking a track modifies `favorites`.
+    PlaylistModified(String),
+    PlaylistRenamed {
+        old: String,
+        new: String,
+    },
+    /// The playlist was moved to the trash.
+    PlaylistDeleted(String),
+    /// A play of this track was added to the history.
+    HistoryAppended(String),
+}
+
+/// A connection that can be handed to other threads. Each thread that does real work
+/// (the writer, a server) should rather `connect()` its own, since WAL lets separate
+/// connections read while another writes.
+pub type SharedDatabase = Arc<Mutex<Database>>;
+
+/// What an EQ preset is linked to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EqTarget<'a> {
+    Track(&'a str),
+    Album(&'a str),
+}
 
 pub struct Database {
     conn: Connection,
+    events: Option<Sender<DbEvent>>,
 }
 
 impl Database {
     pub fn new(db_path: &str) -> Result<Self> {
-        let conn = Connection::open(db_path)?;
-        let db = Self { conn };
+        let db = Self::open(db_path)?;
         db.init_tables()?;
         Ok(db)
     }
 
+    fn open(db_path: &str) -> Result<Self> {
+        let conn = Connection::open(db_path)?;
+        // readers no longer block the writer (and vice versa), and a writer waits for
+        // another one instead of failing right away
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+        Ok(Self { conn, events: None })
+    }
+
+    /// Opens another connection to the same database file. It reports its changes to
+    /// the same listener. Fails for in-memory databases, which can't be shared between
+    /// connections.
+    pub fn connect(&self) -> Result<Self> {
+        let mut db = match self.conn.path() {
+            Some(path) if !path.is_empty() => Self::open(path)?,
+            _ => return Err(rusqlite::Error::InvalidPath("in-memory database".into())),
+        };
+        db.events = self.events.clone();
+        Ok(db)
+    }
+
+    /// Sends a `DbEvent` for every change made through this connection and the ones
+    /// `connect`ed from it afterwards.
+    pub fn set_event_sender(&mut self, events: Sender<DbEvent>) {
+        self.events = Some(events);
+    }
+
+    fn notify(&self, event: DbEvent) {
+        if let Some(events) = &self.events {
+            // nobody listening any more is fine
+            let _ = events.send(event);
+        }
+    }
+
+    pub fn into_shared(self) -> SharedDatabase {
+        Arc::new(Mutex::new(self))
+    }
+
     pub fn in_memory() -> Result<Self> {
         let conn = Connection::open_in_memory()?;
-        let db = Self { conn };
+        conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+        let db = Self { conn, events: None };
         db.init_tables()?;
         Ok(db)
     }
@@ -28,6 +204,20 @@ impl Database {
             [],
         )?;
 
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS folders (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                parent_id INTEGER,
+                FOREIGN KEY (parent_id) REFERENCES folders(id)
+            )",
+            [],
+        )?;
+        self.add_column_if_missing("playlists", "folder_id", "INTEGER REFERENCES folders(id)")?;
+        // where playback of the playlist was last left
+        self.add_column_if_missing("playlists", "last_track", "TEXT")?;
+        self.add_column_if_missing("playlists", "last_position", "REAL NOT NULL DEFAULT 0")?;
+
         self.conn.execute(
             "CREATE TABLE IF NOT EXISTS playlist_tracks (
                 id INTEGER PRIMARY KEY,
@@ -39,6 +229,18 @@ impl Database {
             [],
         )?;
 
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_playlist_tracks_playlist
+             ON playlist_tracks (playlist_id, position)",
+            [],
+        )?;
+        self.add_column_if_missing("playlist_tracks", "note", "TEXT")?;
+        self.add_column_if_missing(
+            "playlist_tracks",
+            "sort_weight",
+            "INTEGER NOT NULL DEFAULT 0",
+        )?;
+
         self.conn.execute(
             "CREATE TABLE IF NOT EXISTS play_history (
                 id INTEGER PRIMARY KEY,
@@ -48,182 +250,2537 @@ impl Database {
             [],
         )?;
 
-        Ok(())
-    }
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_play_history_played_at ON play_history (played_at)",
+            [],
+        )?;
 
-    pub fn create_playlist(&self, name: &str) -> Result<()> {
         self.conn.execute(
-            "INSERT OR IGNORE INTO playlists (name) VALUES (?1)",
-            params![name],
+            "CREATE TABLE IF NOT EXISTS queue (
+                position INTEGER PRIMARY KEY,
+                track_path TEXT NOT NULL
+            )",
+            [],
         )?;
-        Ok(())
-    }
 
-    pub fn delete_playlist(&self, name: &str) -> Result<()> {
-        let playlist_id: Option<i64> = self
-            .conn
-            .query_row(
-                "SELECT id FROM playlists WHERE name = ?1",
-                params![name],
-                |row| row.get(0),
-            )
-            .ok();
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS queue_state (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                current TEXT,
+                position REAL NOT NULL DEFAULT 0
+            )",
+            [],
+        )?;
 
-        if let Some(id) = playlist_id {
-            self.conn.execute(
-                "DELETE FROM playlist_tracks WHERE playlist_id = ?1",
-                params![id],
-            )?;
-            self.conn
-                .execute("DELETE FROM playlists WHERE id = ?1", params![id])?;
-        }
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS track_stats (
+                track_path TEXT PRIMARY KEY,
+                play_count INTEGER NOT NULL DEFAULT 0,
+                skip_count INTEGER NOT NULL DEFAULT 0,
+                last_played TIMESTAMP
+            )",
+            [],
+        )?;
 
-        Ok(())
-    }
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS resume_positions (
+                track_path TEXT PRIMARY KEY,
+                position REAL NOT NULL,
+                updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+            )",
+            [],
+        )?;
 
-    pub fn add_track_to_playlist(&self, playlist: &str, track: &str) -> Result<()> {
-        self.create_playlist(playlist)?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS feeds (
+                id INTEGER PRIMARY KEY,
+                url TEXT NOT NULL UNIQUE,
+                title TEXT NOT NULL,
+                added_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+            )",
+            [],
+        )?;
 
-        let playlist_id: i64 = self.conn.query_row(
-            "SELECT id FROM playlists WHERE name = ?1",
-            params![playlist],
-            |row| row.get(0),
+        // playback positions of episodes live in resume_positions, keyed by local_path
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS episodes (
+                id INTEGER PRIMARY KEY,
+                feed_id INTEGER NOT NULL,
+                guid TEXT NOT NULL,
+                title TEXT NOT NULL,
+                url TEXT NOT NULL,
+                published TEXT,
+                local_path TEXT,
+                listened INTEGER NOT NULL DEFAULT 0,
+                UNIQUE (feed_id, guid),
+                FOREIGN KEY (feed_id) REFERENCES feeds(id)
+            )",
+            [],
         )?;
 
-        let position: i64 = self.conn
-            .query_row(
-                "SELECT COALESCE(MAX(position), -1) + 1 FROM playlist_tracks WHERE playlist_id = ?1",
-                params![playlist_id],
-                |row| row.get(0),
-            )
-            .unwrap_or(0);
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS track_tags (
+                track_path TEXT NOT NULL,
+                tag TEXT NOT NULL,
+                PRIMARY KEY (track_path, tag)
+            )",
+            [],
+        )?;
 
         self.conn.execute(
-            "INSERT INTO playlist_tracks (playlist_id, track_path, position) VALUES (?1, ?2, ?3)",
-            params![playlist_id, track, position],
+            "CREATE TABLE IF NOT EXISTS tracks (
+                id INTEGER PRIMARY KEY,
+                path TEXT NOT NULL UNIQUE,
+                title TEXT,
+                artist TEXT,
+                album TEXT,
+                track_number INTEGER,
+                duration REAL,
+                codec TEXT,
+                added_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+            )",
+            [],
         )?;
+        self.add_column_if_missing("tracks", "genre", "TEXT")?;
 
-        Ok(())
-    }
+        // see fingerprint.rs for the format
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS track_fingerprints (
+                track_path TEXT PRIMARY KEY,
+                fingerprint BLOB NOT NULL
+            )",
+            [],
+        )?;
 
-    pub fn remove_track_from_playlist(&self, playlist: &str, track: &str) -> Result<()> {
-        let playlist_id: Option<i64> = self
-            .conn
-            .query_row(
-                "SELECT id FROM playlists WHERE name = ?1",
-                params![playlist],
-                |row| row.get(0),
-            )
-            .ok();
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS favorites (
+                track_path TEXT PRIMARY KEY,
+                liked_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+            )",
+            [],
+        )?;
 
-        if let Some(id) = playlist_id {
-            self.conn.execute(
-                "DELETE FROM playlist_tracks WHERE playlist_id = ?1 AND track_path = ?2",
-                params![id, track],
-            )?;
-        }
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS ratings (
+                track_path TEXT PRIMARY KEY,
+                rating INTEGER NOT NULL CHECK (rating BETWEEN 1 AND 5),
+                rated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+            )",
+            [],
+        )?;
 
-        Ok(())
-    }
+        // deleted playlists, kept until purged
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS trash_playlists (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                last_track TEXT,
+                last_position REAL NOT NULL DEFAULT 0,
+                deleted_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+            )",
+            [],
+        )?;
 
-    pub fn get_playlist_tracks(&self, playlist: &str) -> Result<Vec<String>> {
-        let playlist_id: Option<i64> = self
-            .conn
-            .query_row(
-                "SELECT id FROM playlists WHERE name = ?1",
-                params![playlist],
-                |row| row.get(0),
-            )
-            .ok();
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS trash_playlist_tracks (
+                trash_id INTEGER NOT NULL,
+                track_path TEXT NOT NULL,
+                position INTEGER,
+                note TEXT,
+                sort_weight INTEGER NOT NULL DEFAULT 0,
+                FOREIGN KEY (trash_id) REFERENCES trash_playlists(id)
+            )",
+            [],
+        )?;
 
-        if let Some(id) = playlist_id {
-            let mut stmt = self.conn.prepare(
-                "SELECT track_path FROM playlist_tracks WHERE playlist_id = ?1 ORDER BY position",
-            )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS eq_presets (
+                name TEXT PRIMARY KEY,
+                bands TEXT NOT NULL
+            )",
+            [],
+        )?;
 
-            let tracks = stmt
-                .query_map(params![id], |row| row.get(0))?
-                .collect::<Result<Vec<String>>>()?;
+        // `key` is a track path or an album name, depending on `kind`
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS eq_assignments (
+                kind TEXT NOT NULL CHECK (kind IN ('track', 'album')),
+                key TEXT NOT NULL,
+                preset TEXT NOT NULL,
+                PRIMARY KEY (kind, key)
+            )",
+            [],
+        )?;
 
-            Ok(tracks)
-        } else {
-            Ok(Vec::new())
-        }
+        Ok(())
     }
 
-    pub fn get_all_playlists(&self) -> Result<Vec<String>> {
+    /// Stores EQ bands (`[frequency, q, gain_db, type]`) under `name`, replacing any
+    /// preset of that name.
+    pub fn save_eq_preset(&self, name: &str, bands: &[[f32; 4]]) -> Result<()> {
+        let json = serde_json::to_string(bands).expect("bands serialize");
+        self.conn
+            .prepare_cached(
+                "INSERT INTO eq_presets (name, bands) VALUES (?1, ?2)
+                 ON CONFLICT(name) DO UPDATE SET bands = excluded.bands",
+            )?
+            .execute(params![name, json])?;
+        Ok(())
+    }
+
+    pub fn get_eq_preset(&self, name: &str) -> Result<Option<Vec<[f32; 4]>>> {
+        let json: Option<String> = self
+            .conn
+            .prepare_cached("SELECT bands FROM eq_presets WHERE name = ?1")?
+            .query_row(params![name], |row| row.get(0))
+            .optional()?;
+        Ok(json.and_then(|json| serde_json::from_str(&json).ok()))
+    }
+
+    pub fn eq_presets(&self) -> Result<Vec<String>> {
         let mut stmt = self
             .conn
-            .prepare("SELECT name FROM playlists ORDER BY name")?;
-        let playlists = stmt
+            .prepare_cached("SELECT name FROM eq_presets ORDER BY name")?;
+        let names = stmt
             .query_map([], |row| row.get(0))?
             .collect::<Result<Vec<String>>>()?;
-        Ok(playlists)
+        Ok(names)
     }
 
-    pub fn log_playback(&self, track: &str) -> Result<()> {
-        self.conn.execute(
-            "INSERT INTO play_history (track_path) VALUES (?1)",
-            params![track],
-        )?;
+    /// Links a preset to a track path or album name; `None` removes the link.
+    pub fn assign_eq_preset(&self, target: EqTarget, preset: Option<&str>) -> Result<()> {
+        let (kind, key) = match target {
+            EqTarget::Track(path) => ("track", path),
+            EqTarget::Album(album) => ("album", album),
+        };
+        match preset {
+            Some(preset) => self
+                .conn
+                .prepare_cached(
+                    "INSERT INTO eq_assignments (kind, key, preset) VALUES (?1, ?2, ?3)
+                     ON CONFLICT(kind, key) DO UPDATE SET preset = excluded.preset",
+                )?
+                .execute(params![kind, key, preset])?,
+            None => self
+                .conn
+                .prepare_cached("DELETE FROM eq_assignments WHERE kind = ?1 AND key = ?2")?
+                .execute(params![kind, key])?,
+        };
         Ok(())
     }
 
-    pub fn get_play_history(&self, limit: usize) -> Result<Vec<(String, String)>> {
-        let mut stmt = self
+    /// The preset linked to `track`, or else to its album, as `(name, bands)`.
+    pub fn preset_for_track(&self, track: &str) -> Result<Option<(String, Vec<[f32; 4]>)>> {
+        let name: Option<String> = self
             .conn
-            .prepare("SELECT track_path, played_at FROM play_history ORDER BY id DESC LIMIT ?1")?;
+            .prepare_cached(
+                "SELECT preset FROM eq_assignments
+                 WHERE (kind = 'track' AND key = ?1)
+                    OR (kind = 'album' AND key = (SELECT album FROM tracks WHERE path = ?1))
+                 ORDER BY kind = 'album'
+                 LIMIT 1",
+            )?
+            .query_row(params![track], |row| row.get(0))
+            .optional()?;
+        let Some(name) = name else {
+            return Ok(None);
+        };
+        Ok(self.get_eq_preset(&name)?.map(|bands| (name, bands)))
+    }
 
-        let history = stmt
-            .query_map(params![limit], |row| Ok((row.get(0)?, row.get(1)?)))?
-            .collect::<Result<Vec<(String, String)>>>()?;
+    pub fn set_fingerprint(&self, track: &str, fingerprint: &[u32]) -> Result<()> {
+        self.conn
+            .prepare_cached(
+                "INSERT INTO track_fingerprints (track_path, fingerprint) VALUES (?1, ?2)
+                 ON CONFLICT(track_path) DO UPDATE SET fingerprint = excluded.fingerprint",
+            )?
+            .execute(params![track, fingerprint::to_bytes(fingerprint)])?;
+        Ok(())
+    }
 
-        Ok(history)
+    /// Every stored fingerprint as `(path, fingerprint)`.
+    pub fn fingerprints(&self) -> Result<Vec<(String, Vec<u32>)>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT track_path, fingerprint FROM track_fingerprints")?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    fingerprint::from_bytes(&row.get::<_, Vec<u8>>(1)?),
+                ))
+            })?
+            .collect::<Result<Vec<(String, Vec<u32>)>>>()?;
+        Ok(rows)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Folds everything recorded about `from` (history, play/skip counts, tags, playlist
+    /// entries including trashed ones, queue, resume points, EQ preset) into `to` and
+    /// drops `from` from the library, for when a recording turns up under another path.
+    pub fn merge_track(&self, from: &str, to: &str) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO track_stats (track_path, play_count, skip_count, last_played)
+             SELECT ?2, play_count, skip_count, last_played FROM track_stats
+             WHERE track_path = ?1
+             ON CONFLICT(track_path) DO UPDATE SET
+                play_count = play_count + excluded.play_count,
+                skip_count = skip_count + excluded.skip_count,
+                last_played = MAX(COALESCE(last_played, ''), COALESCE(excluded.last_played, ''))",
+            params![from, to],
+        )?;
+        for (table, column) in [
+            ("play_history", "track_path"),
+            ("playlist_tracks", "track_path"),
+            ("queue", "track_path"),
+            ("queue_state", "current"),
+            ("track_tags", "track_path"),
+            ("resume_positions", "track_path"),
+            ("favorites", "track_path"),
+            ("ratings", "track_path"),
+            ("playlists", "last_track"),
+            ("trash_playlists", "last_track"),
+            ("trash_playlist_tracks", "track_path"),
+        ] {
+            // OR IGNORE: keep the existing row where `to` already has one
+            tx.execute(
+                &format!("UPDATE OR IGNORE {table} SET {column} = ?2 WHERE {column} = ?1"),
+                params![from, to],
+            )?;
+        }
+        // only track assignments are keyed by path
+        tx.execute(
+            "UPDATE OR IGNORE eq_assignments SET key = ?2 WHERE kind = 'track' AND key = ?1",
+            params![from, to],
+        )?;
+        tx.execute(
+            "DELETE FROM eq_assignments WHERE kind = 'track' AND key = ?1",
+            params![from],
+        )?;
+        for table in [
+            "track_stats",
+            "track_tags",
+            "resume_positions",
+            "track_fingerprints",
+            "favorites",
+            "ratings",
+        ] {
+            tx.execute(
+                &format!("DELETE FROM {table} WHERE track_path = ?1"),
+                params![from],
+            )?;
+        }
+        tx.execute("DELETE FROM tracks WHERE path = ?1", params![from])?;
+        tx.commit()
+    }
 
-    #[test]
-    fn test_database_creation() {
-        let db = Database::in_memory().unwrap();
-        assert!(db.get_all_playlists().unwrap().is_empty());
+    /// Inserts or refreshes the library entry for `meta.path`.
+    pub fn upsert_track(&self, meta: &TrackMetadata) -> Result<()> {
+        self.conn
+            .prepare_cached(
+                "INSERT INTO tracks
+                    (path, title, artist, album, track_number, duration, codec, genre)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                 ON CONFLICT(path) DO UPDATE SET
+                    title = excluded.title,
+                    artist = excluded.artist,
+                    album = excluded.album,
+                    track_number = excluded.track_number,
+                    duration = excluded.duration,
+                    codec = excluded.codec,
+                    genre = excluded.genre",
+            )?
+            .execute(params![
+                meta.path,
+                meta.title,
+                meta.artist,
+                meta.album,
+                meta.track_number,
+                meta.duration,
+                meta.codec,
+                meta.genre
+            ])?;
+        Ok(())
     }
 
-    #[test]
-    fn test_playlist_operations() {
-        let db = Database::in_memory().unwrap();
+    fn track_from_row(row: &rusqlite::Row) -> Result<TrackMetadata> {
+        Ok(TrackMetadata {
+            path: row.get(0)?,
+            title: row.get(1)?,
+            artist: row.get(2)?,
+            album: row.get(3)?,
+            track_number: row.get(4)?,
+            duration: row.get(5)?,
+            codec: row.get(6)?,
+            genre: row.get(7)?,
+        })
+    }
 
-        db.create_playlist("test").unwrap();
-        let playlists = db.get_all_playlists().unwrap();
-        assert_eq!(playlists, vec!["test"]);
+    pub fn get_track(&self, path: &str) -> Result<Option<TrackMetadata>> {
+        self.conn
+            .prepare_cached(&format!(
+                "SELECT {} FROM tracks WHERE path = ?1",
+                TRACK_COLUMNS
+            ))?
+            .query_row(params![path], Self::track_from_row)
+            .optional()
+    }
 
-        db.add_track_to_playlist("test", "track1.mp3").unwrap();
-        db.add_track_to_playlist("test", "track2.mp3").unwrap();
+    /// How a track should be shown in listings: "Artist – Title" when the library knows
+    /// it, otherwise the raw path.
+    pub fn display_name(&self, path: &str) -> String {
+        match self.get_track(path) {
+            Ok(Some(meta)) => meta.display_name(),
+            _ => path.to_string(),
+        }
+    }
 
-        let tracks = db.get_playlist_tracks("test").unwrap();
-        assert_eq!(tracks, vec!["track1.mp3", "track2.mp3"]);
+    /// Upgrades databases created before `column` existed.
+    fn add_column_if_missing(&self, table: &str, column: &str, decl: &str) -> Result<()> {
+        let exists: bool = self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2)",
+            params![table, column],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            self.conn.execute(
+                &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl),
+                [],
+            )?;
+        }
+        Ok(())
+    }
 
-        db.remove_track_from_playlist("test", "track1.mp3").unwrap();
-        let tracks = db.get_playlist_tracks("test").unwrap();
-        assert_eq!(tracks, vec!["track2.mp3"]);
+    /// Creates the folders along `path` (`a/b/c`) that don't exist yet and returns the id
+    /// of the last one, or `None` for an empty path (the top level).
+    pub fn create_folder(&self, path: &str) -> Result<Option<i64>> {
+        let mut parent: Option<i64> = None;
+        for name in path.split('/').filter(|s| !s.is_empty()) {
+            let existing: Option<i64> = self
+                .conn
+                .query_row(
+                    "SELECT id FROM folders WHERE name = ?1 AND parent_id IS ?2",
+                    params![name, parent],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            parent = Some(match existing {
+                Some(id) => id,
+                None => {
+                    self.conn
+                        .prepare_cached("INSERT INTO folders (name, parent_id) VALUES (?1, ?2)")?
+                        .execute(params![name, parent])?;
+                    self.conn.last_insert_rowid()
+                }
+            });
+        }
+        Ok(parent)
+    }
 
-        db.delete_playlist("test").unwrap();
-        assert!(db.get_all_playlists().unwrap().is_empty());
+    /// Playlists are stored under their full path (`rock/80s/best`); the folder part is
+    /// also recorded in the folders table so empty folders and the tree survive.
+    fn folder_of(&self, playlist: &str) -> Result<Option<i64>> {
+        match playlist.rsplit_once('/') {
+            Some((folder, _)) => self.create_folder(folder),
+            None => Ok(None),
+        }
     }
 
-    #[test]
-    fn test_play_history() {
-        let db = Database::in_memory().unwrap();
+    /// Row id of the playlist called `name`.
+    fn playlist_id(&self, name: &str) -> Result<Option<i64>> {
+        self.conn
+            .prepare_cached("SELECT id FROM playlists WHERE name = ?1")?
+            .query_row(params![name], |row| row.get(0))
+            .optional()
+    }
 
-        db.log_playback("song1.mp3").unwrap();
-        db.log_playback("song2.mp3").unwrap();
+    /// Whether `name` can't be given to a playlist: one has it, or it's reserved.
+    fn name_taken(&self, name: &str) -> Result<bool> {
+        Ok(name == FAVORITES_PLAYLIST || self.playlist_id(name)?.is_some())
+    }
 
-        let history = db.get_play_history(10).unwrap();
-        assert_eq!(history.len(), 2);
-        assert_eq!(history[0].0, "song2.mp3");
-        assert_eq!(history[1].0, "song1.mp3");
+    /// Creates an empty playlist unless it exists. Fails for `FAVORITES_PLAYLIST`, and so
+    /// do the writes that create their playlist on the way (adding, replacing).
+    pub fn create_playlist(&self, name: &str) -> Result<()> {
+        if name == FAVORITES_PLAYLIST {
+            return Err(rusqlite::Error::ToSqlConversionFailure(
+                format!("'{}' always holds the liked tracks; use like/unlike", name).into(),
+            ));
+        }
+        let folder_id = self.folder_of(name)?;
+        self.conn
+            .prepare_cached("INSERT OR IGNORE INTO playlists (name, folder_id) VALUES (?1, ?2)")?
+            .execute(params![name, folder_id])?;
+        Ok(())
+    }
+
+    /// Folders and playlists in display order, depth-first with names sorted.
+    pub fn playlist_tree(&self) -> Result<Vec<PlaylistTreeEntry>> {
+        let mut entries = Vec::new();
+        self.collect_tree(None, "", 0, &mut entries)?;
+        Ok(entries)
+    }
+
+    fn collect_tree(
+        &self,
+        folder: Option<i64>,
+        prefix: &str,
+        depth: usize,
+        out: &mut Vec<PlaylistTreeEntry>,
+    ) -> Result<()> {
+        let folders = {
+            let mut stmt = self.conn.prepare_cached(
+                "SELECT id, name FROM folders WHERE parent_id IS ?1 ORDER BY name",
+            )?;
+            stmt.query_map(params![folder], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>>>()?
+        };
+        for (id, name) in folders {
+            let path = format!("{}{}", prefix, name);
+            out.push(PlaylistTreeEntry {
+                depth,
+                name,
+                path: path.clone(),
+                is_folder: true,
+            });
+            self.collect_tree(Some(id), &format!("{}/", path), depth + 1, out)?;
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT name FROM playlists WHERE folder_id IS ?1 ORDER BY name")?;
+        let playlists = stmt
+            .query_map(params![folder], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>>>()?;
+        for path in playlists {
+            out.push(PlaylistTreeEntry {
+                depth,
+                name: path.rsplit('/').next().unwrap_or(&path).to_string(),
+                path,
+                is_folder: false,
+            });
+        }
+        Ok(())
+    }
+
+    /// Moves a playlist and its entries to the trash, from where `undelete_playlist` can
+    /// bring it back until `purge_trash` drops it. Returns false if there is no such
+    /// playlist.
+    pub fn delete_playlist(&self, name: &str) -> Result<bool> {
+        let playlist_id = self.playlist_id(name)?;
+
+        if let Some(id) = playlist_id {
+            let tx = self.conn.unchecked_transaction()?;
+            tx.execute(
+                "INSERT INTO trash_playlists (name, last_track, last_position)
+                 SELECT name, last_track, last_position FROM playlists WHERE id = ?1",
+                params![id],
+            )?;
+            let trash_id = tx.last_insert_rowid();
+            tx.execute(
+                "INSERT INTO trash_playlist_tracks
+                    (trash_id, track_path, position, note, sort_weight)
+                 SELECT ?2, track_path, position, note, sort_weight
+                 FROM playlist_tracks WHERE playlist_id = ?1",
+                params![id, trash_id],
+            )?;
+            tx.execute(
+                "DELETE FROM playlist_tracks WHERE playlist_id = ?1",
+                params![id],
+            )?;
+            tx.execute("DELETE FROM playlists WHERE id = ?1", params![id])?;
+            tx.commit()?;
+            self.notify(DbEvent::PlaylistDeleted(name.to_string()));
+        }
+
+        Ok(playlist_id.is_some())
+    }
+
+    /// Restores the most recently deleted playlist called `name`.
+    pub fn undelete_playlist(&self, name: &str) -> Result<UndeleteOutcome> {
+        let trash_id: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT id FROM trash_playlists WHERE name = ?1 ORDER BY id DESC LIMIT 1",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(trash_id) = trash_id else {
+            return Ok(UndeleteOutcome::NotFound);
+        };
+        if self.name_taken(name)? {
+            return Ok(UndeleteOutcome::NameTaken);
+        }
+
+        let tx = self.conn.unchecked_transaction()?;
+        let folder_id = self.folder_of(name)?;
+        tx.execute(
+            "INSERT INTO playlists (name, folder_id, last_track, last_position)
+             SELECT name, ?2, last_track, last_position FROM trash_playlists WHERE id = ?1",
+            params![trash_id, folder_id],
+        )?;
+        let playlist_id = tx.last_insert_rowid();
+        tx.execute(
+            "INSERT INTO playlist_tracks (playlist_id, track_path, position, note, sort_weight)
+             SELECT ?2, track_path, position, note, sort_weight
+             FROM trash_playlist_tracks WHERE trash_id = ?1",
+            params![trash_id, playlist_id],
+        )?;
+        tx.execute(
+            "DELETE FROM trash_playlist_tracks WHERE trash_id = ?1",
+            params![trash_id],
+        )?;
+        tx.execute(
+            "DELETE FROM trash_playlists WHERE id = ?1",
+            params![trash_id],
+        )?;
+        tx.commit()?;
+        self.notify(DbEvent::PlaylistModified(name.to_string()));
+        Ok(UndeleteOutcome::Restored)
+    }
+
+    /// Deleted playlists as `(name, deleted_at, track count)`, newest first.
+    pub fn trashed_playlists(&self) -> Result<Vec<(String, String, u32)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT t.name, t.deleted_at,
+                (SELECT COUNT(*) FROM trash_playlist_tracks WHERE trash_id = t.id)
+             FROM trash_playlists t ORDER BY t.id DESC",
+        )?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<Vec<(String, String, u32)>>>()?;
+        Ok(rows)
+    }
+
+    /// Permanently drops playlists that have been in the trash longer than `days` days
+    /// and returns how many went.
+    pub fn purge_trash(&self, days: u32) -> Result<usize> {
+        let cutoff = format!("-{} days", days);
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM trash_playlist_tracks WHERE trash_id IN
+                (SELECT id FROM trash_playlists WHERE deleted_at < datetime('now', ?1))",
+            params![cutoff],
+        )?;
+        let purged = tx.execute(
+            "DELETE FROM trash_playlists WHERE deleted_at < datetime('now', ?1)",
+            params![cutoff],
+        )?;
+        tx.commit()?;
+        Ok(purged)
+    }
+
+    /// Replaces the contents of `name` (creating it if needed) with `tracks`, in a single
+    /// transaction so a failed save leaves the old playlist intact.
+    pub fn replace_playlist(&self, name: &str, tracks: &[String]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        self.create_playlist(name)?;
+        let playlist_id = self
+            .playlist_id(name)?
+            .ok_or(rusqlite::Error::QueryReturnedNoRows)?;
+
+        // tracks that stay in the playlist keep their notes and weights
+        let mut annotations: HashMap<String, Vec<(Option<String>, i64)>> = HashMap::new();
+        {
+            let mut stmt = tx.prepare(
+                "SELECT track_path, note, sort_weight FROM playlist_tracks
+                 WHERE playlist_id = ?1 ORDER BY position DESC",
+            )?;
+            let rows = stmt.query_map(params![playlist_id], |row| {
+                Ok((row.get::<_, String>(0)?, row.get(1)?, row.get(2)?))
+            })?;
+            for row in rows {
+                let (track, note, weight) = row?;
+                annotations.entry(track).or_default().push((note, weight));
+            }
+        }
+
+        tx.execute(
+            "DELETE FROM playlist_tracks WHERE playlist_id = ?1",
+            params![playlist_id],
+        )?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO playlist_tracks (playlist_id, track_path, position, note, sort_weight)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for (position, track) in tracks.iter().enumerate() {
+                let (note, weight) = annotations
+                    .get_mut(track)
+                    .and_then(|saved| saved.pop())
+                    .unwrap_or((None, 0));
+                insert.execute(params![playlist_id, track, position as i64, note, weight])?;
+            }
+        }
+        tx.commit()?;
+        self.notify(DbEvent::PlaylistModified(name.to_string()));
+        Ok(())
+    }
+
+    /// Replaces the saved play queue, the running order restored at startup.
+    pub fn save_queue(&self, tracks: &[String]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM queue", [])?;
+        {
+            let mut insert =
+                tx.prepare("INSERT INTO queue (position, track_path) VALUES (?1, ?2)")?;
+            for (position, track) in tracks.iter().enumerate() {
+                insert.execute(params![position as i64, track])?;
+            }
+        }
+        tx.commit()
+    }
+
+    pub fn load_queue(&self) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT track_path FROM queue ORDER BY position")?;
+        let tracks = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>>>()?;
+        Ok(tracks)
+    }
+
+    /// Remembers the queue's current track; `position` is the offset into it in seconds.
+    pub fn set_queue_current(&self, track: Option<&str>, position: f64) -> Result<()> {
+        self.conn
+            .prepare_cached("INSERT INTO queue_state (id, current, position) VALUES (1, ?1, ?2)
+             ON CONFLICT(id) DO UPDATE SET current = excluded.current, position = excluded.position")?
+            .execute(params![track, position])?;
+        Ok(())
+    }
+
+    pub fn set_queue_position(&self, position: f64) -> Result<()> {
+        self.conn
+            .prepare_cached("UPDATE queue_state SET position = ?1 WHERE id = 1")?
+            .execute(params![position])?;
+        Ok(())
+    }
+
+    pub fn get_queue_current(&self) -> Result<Option<(String, f64)>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT current, position FROM queue_state WHERE id = 1",
+                [],
+                |row| Ok((row.get::<_, Option<String>>(0)?, row.get(1)?)),
+            )
+            .optional()?
+            .and_then(|(current, position)| current.map(|track| (track, position))))
+    }
+
+    /// Remembers the track and offset playback of a saved playlist was at. Does nothing
+    /// for playlists that don't exist.
+    pub fn save_playlist_position(&self, playlist: &str, track: &str, position: f64) -> Result<()> {
+        self.conn
+            .prepare_cached(
+                "UPDATE playlists SET last_track = ?2, last_position = ?3 WHERE name = ?1",
+            )?
+            .execute(params![playlist, track, position])?;
+        Ok(())
+    }
+
+    pub fn get_playlist_position(&self, playlist: &str) -> Result<Option<(String, f64)>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT last_track, last_position FROM playlists WHERE name = ?1",
+                params![playlist],
+                |row| Ok((row.get::<_, Option<String>>(0)?, row.get(1)?)),
+            )
+            .optional()?
+            .and_then(|(track, position)| track.map(|track| (track, position))))
+    }
+
+    /// Runs SQLite's integrity check and deletes playlist entries left behind by deleted
+    /// playlists.
+    pub fn check(&self) -> Result<CheckReport> {
+        let mut stmt = self.conn.prepare_cached("PRAGMA integrity_check")?;
+        let problems = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<String>>>()?
+            .into_iter()
+            .filter(|message| message != "ok")
+            .collect();
+
+        let orphans_removed = self.conn.execute(
+            "DELETE FROM playlist_tracks
+             WHERE playlist_id IS NULL OR playlist_id NOT IN (SELECT id FROM playlists)",
+            [],
+        )?;
+
+        Ok(CheckReport {
+            problems,
+            orphans_removed,
+        })
+    }
+
+    /// Size of the database in bytes, as SQLite sees it.
+    pub fn size_bytes(&self) -> Result<u64> {
+        self.conn.query_row(
+            "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+            [],
+            |row| row.get(0),
+        )
+    }
+
+    /// Rebuilds the database file and returns how many bytes that freed.
+    pub fn vacuum(&self) -> Result<u64> {
+        let before = self.size_bytes()?;
+        self.conn.execute("VACUUM", [])?;
+        Ok(before.saturating_sub(self.size_bytes()?))
+    }
+
+    /// Every distinct track path the database refers to whose file no longer exists.
+    pub fn missing_tracks(&self) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT path FROM tracks
+             UNION SELECT track_path FROM playlist_tracks
+             UNION SELECT track_path FROM queue
+             ORDER BY 1",
+        )?;
+        let paths = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<String>>>()?;
+        Ok(paths
+            .into_iter()
+            .filter(|path| !std::path::Path::new(path).exists())
+            .collect())
+    }
+
+    /// Rewrites every stored path under `old_prefix` to be under `new_prefix` instead, in
+    /// one transaction, and returns the number of rows changed. Prefixes match whole path
+    /// components, as in `paths::relocate_path`. Rows whose new path already has an entry
+    /// (library, stats, tags) are left as they were.
+    pub fn relocate(&self, old_prefix: &str, new_prefix: &str) -> Result<usize> {
+        const COLUMNS: &[(&str, &str)] = &[
+            ("tracks", "path"),
+            ("playlist_tracks", "track_path"),
+            ("play_history", "track_path"),
+            ("track_stats", "track_path"),
+            ("resume_positions", "track_path"),
+            ("track_tags", "track_path"),
+            ("queue", "track_path"),
+            ("queue_state", "current"),
+            ("episodes", "local_path"),
+            ("track_fingerprints", "track_path"),
+            ("eq_assignments", "key"),
+            ("favorites", "track_path"),
+            ("ratings", "track_path"),
+            ("playlists", "last_track"),
+            ("trash_playlists", "last_track"),
+            ("trash_playlist_tracks", "track_path"),
+        ];
+        let old_prefix = old_prefix.trim_end_matches('/');
+        let new_prefix = new_prefix.trim_end_matches('/');
+
+        let tx = self.conn.unchecked_transaction()?;
+        let mut changed = 0;
+        for (table, column) in COLUMNS {
+            changed += tx.execute(
+                &format!(
+                    "UPDATE OR IGNORE {table} SET {column} = ?2 || substr({column}, length(?1) + 1)
+                     WHERE {column} = ?1 OR substr({column}, 1, length(?1) + 1) = ?1 || '/'"
+                ),
+                params![old_prefix, new_prefix],
+            )?;
+        }
+        tx.commit()?;
+        Ok(changed)
+    }
+
+    pub fn rename_playlist(&self, old: &str, new: &str) -> Result<RenameOutcome> {
+        let exists = |name: &str| -> Result<bool> { Ok(self.playlist_id(name)?.is_some()) };
+
+        if !exists(old)? {
+            return Ok(RenameOutcome::NotFound);
+        }
+        if old != new && self.name_taken(new)? {
+            return Ok(RenameOutcome::NameTaken);
+        }
+
+        let folder_id = self.folder_of(new)?;
+        self.conn
+            .prepare_cached("UPDATE playlists SET name = ?2, folder_id = ?3 WHERE name = ?1")?
+            .execute(params![old, new, folder_id])?;
+        self.notify(DbEvent::PlaylistRenamed {
+            old: old.to_string(),
+            new: new.to_string(),
+        });
+        Ok(RenameOutcome::Renamed)
+    }
+
+    /// Saves the entries of `src`, notes and weights included, as a new playlist `dst`.
+    /// `src` may be `FAVORITES_PLAYLIST`, which gives a snapshot of the liked tracks.
+    pub fn copy_playlist(&self, src: &str, dst: &str) -> Result<CopyOutcome> {
+        if src == FAVORITES_PLAYLIST {
+            if self.name_taken(dst)? {
+                return Ok(CopyOutcome::NameTaken);
+            }
+            self.replace_playlist(dst, &self.get_playlist_tracks(src)?)?;
+            return Ok(CopyOutcome::Copied);
+        }
+        let Some(src_id) = self.playlist_id(src)? else {
+            return Ok(CopyOutcome::NotFound);
+        };
+        if self.name_taken(dst)? {
+            return Ok(CopyOutcome::NameTaken);
+        }
+
+        let tx = self.conn.unchecked_transaction()?;
+        self.create_playlist(dst)?;
+        let dst_id = self
+            .playlist_id(dst)?
+            .ok_or(rusqlite::Error::QueryReturnedNoRows)?;
+        tx.execute(
+            "INSERT INTO playlist_tracks (playlist_id, track_path, position, note, sort_weight)
+             SELECT ?2, track_path, position, note, sort_weight FROM playlist_tracks
+             WHERE playlist_id = ?1",
+            params![src_id, dst_id],
+        )?;
+        tx.commit()?;
+        self.notify(DbEvent::PlaylistModified(dst.to_string()));
+        Ok(CopyOutcome::Copied)
+    }
+
+    pub fn add_track_to_playlist(&self, playlist: &str, track: &str) -> Result<()> {
+        self.create_playlist(playlist)?;
+
+        let playlist_id = self
+            .playlist_id(playlist)?
+            .ok_or(rusqlite::Error::QueryReturnedNoRows)?;
+
+        let position: i64 = self
+            .conn
+            .prepare_cached(
+                "SELECT COALESCE(MAX(position), -1) + 1 FROM playlist_tracks WHERE playlist_id = ?1",
+            )?
+            .query_row(params![playlist_id], |row| row.get(0))
+            .unwrap_or(0);
+
+        self.conn
+            .prepare_cached("INSERT INTO playlist_tracks (playlist_id, track_path, position) VALUES (?1, ?2, ?3)")?
+            .execute(params![playlist_id, track, position])?;
+
+        self.notify(DbEvent::PlaylistModified(playlist.to_string()));
+        Ok(())
+    }
+
+    pub fn remove_track_from_playlist(&self, playlist: &str, track: &str) -> Result<()> {
+        let playlist_id = self.playlist_id(playlist)?;
+
+        if let Some(id) = playlist_id {
+            self.conn
+                .prepare_cached(
+                    "DELETE FROM playlist_tracks WHERE playlist_id = ?1 AND track_path = ?2",
+                )?
+                .execute(params![id, track])?;
+            self.notify(DbEvent::PlaylistModified(playlist.to_string()));
+        }
+
+        Ok(())
+    }
+
+    pub fn get_playlist_tracks(&self, playlist: &str) -> Result<Vec<String>> {
+        if playlist == FAVORITES_PLAYLIST {
+            return self.favorites();
+        }
+        let playlist_id = self.playlist_id(playlist)?;
+
+        if let Some(id) = playlist_id {
+            let mut stmt = self.conn.prepare_cached(
+                "SELECT track_path FROM playlist_tracks WHERE playlist_id = ?1
+                 ORDER BY sort_weight, position",
+            )?;
+
+            let tracks = stmt
+                .query_map(params![id], |row| row.get(0))?
+                .collect::<Result<Vec<String>>>()?;
+
+            Ok(tracks)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Like `get_playlist_tracks`, with each entry's note and sort weight.
+    pub fn get_playlist_entries(&self, playlist: &str) -> Result<Vec<PlaylistEntry>> {
+        if playlist == FAVORITES_PLAYLIST {
+            let entries = self.favorites()?.into_iter().map(|track| PlaylistEntry {
+                track,
+                note: None,
+                sort_weight: 0,
+            });
+            return Ok(entries.collect());
+        }
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT pt.track_path, pt.note, pt.sort_weight FROM playlist_tracks pt
+             JOIN playlists p ON p.id = pt.playlist_id
+             WHERE p.name = ?1
+             ORDER BY pt.sort_weight, pt.position",
+        )?;
+        let entries = stmt
+            .query_map(params![playlist], |row| {
+                Ok(PlaylistEntry {
+                    track: row.get(0)?,
+                    note: row.get(1)?,
+                    sort_weight: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<PlaylistEntry>>>()?;
+        Ok(entries)
+    }
+
+    /// Row id of the `index`th (0-based) entry of a playlist, in display order.
+    fn playlist_entry_id(&self, playlist: &str, index: usize) -> Result<Option<i64>> {
+        self.conn
+            .query_row(
+                "SELECT pt.id FROM playlist_tracks pt
+                 JOIN playlists p ON p.id = pt.playlist_id
+                 WHERE p.name = ?1
+                 ORDER BY pt.sort_weight, pt.position
+                 LIMIT 1 OFFSET ?2",
+                params![playlist, index as i64],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    /// Sets or clears (`None`) the note on a playlist entry. Returns false if the playlist
+    /// has no entry at `index`.
+    pub fn set_entry_note(&self, playlist: &str, index: usize, note: Option<&str>) -> Result<bool> {
+        let Some(id) = self.playlist_entry_id(playlist, index)? else {
+            return Ok(false);
+        };
+        self.conn
+            .prepare_cached("UPDATE playlist_tracks SET note = ?2 WHERE id = ?1")?
+            .execute(params![id, note])?;
+        self.notify(DbEvent::PlaylistModified(playlist.to_string()));
+        Ok(true)
+    }
+
+    /// Moves the `from`th (0-based) entry of a playlist to index `to`, keeping its note.
+    /// It takes the sort weight of the entry it lands next to, so the weights still agree
+    /// with the order. Returns false if either index is out of range.
+    pub fn move_playlist_entry(&self, playlist: &str, from: usize, to: usize) -> Result<bool> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut entries = {
+            let mut stmt = tx.prepare_cached(
+                "SELECT pt.id, pt.sort_weight FROM playlist_tracks pt
+                 JOIN playlists p ON p.id = pt.playlist_id
+                 WHERE p.name = ?1
+                 ORDER BY pt.sort_weight, pt.position",
+            )?;
+            stmt.query_map(params![playlist], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<Vec<(i64, i64)>>>()?
+        };
+        if from >= entries.len() || to >= entries.len() {
+            return Ok(false);
+        }
+        let weight = entries[to].1;
+        let (id, _) = entries.remove(from);
+        entries.insert(to, (id, weight));
+        {
+            let mut update = tx.prepare_cached(
+                "UPDATE playlist_tracks SET position = ?2, sort_weight = ?3 WHERE id = ?1",
+            )?;
+            for (position, (id, weight)) in entries.iter().enumerate() {
+                update.execute(params![id, position as i64, weight])?;
+            }
+        }
+        tx.commit()?;
+        self.notify(DbEvent::PlaylistModified(playlist.to_string()));
+        Ok(true)
+    }
+
+    /// Sets the sort weight of a playlist entry. Returns false if the playlist has no
+    /// entry at `index`.
+    pub fn set_entry_weight(&self, playlist: &str, index: usize, weight: i64) -> Result<bool> {
+        let Some(id) = self.playlist_entry_id(playlist, index)? else {
+            return Ok(false);
+        };
+        self.conn
+            .prepare_cached("UPDATE playlist_tracks SET sort_weight = ?2 WHERE id = ?1")?
+            .execute(params![id, weight])?;
+        self.notify(DbEvent::PlaylistModified(playlist.to_string()));
+        Ok(true)
+    }
+
+    /// Flags or unflags a track as liked.
+    pub fn set_favorite(&self, track: &str, liked: bool) -> Result<()> {
+        if liked {
+            self.conn
+                .prepare_cached("INSERT OR IGNORE INTO favorites (track_path) VALUES (?1)")?
+                .execute(params![track])?;
+        } else {
+            self.conn
+                .prepare_cached("DELETE FROM favorites WHERE track_path = ?1")?
+                .execute(params![track])?;
+        }
+        self.notify(DbEvent::PlaylistModified(FAVORITES_PLAYLIST.to_string()));
+        Ok(())
+    }
+
+    pub fn is_favorite(&self, track: &str) -> Result<bool> {
+        self.conn
+            .prepare_cached("SELECT EXISTS(SELECT 1 FROM favorites WHERE track_path = ?1)")?
+            .query_row(params![track], |row| row.get(0))
+    }
+
+    /// Rates a track from 1 to 5 stars; None removes the rating.
+    pub fn set_rating(&self, track: &str, rating: Option<u8>) -> Result<()> {
+        match rating {
+            Some(rating) => self
+                .conn
+                .prepare_cached(
+                    "INSERT INTO ratings (track_path, rating) VALUES (?1, ?2)
+                     ON CONFLICT(track_path) DO UPDATE SET
+                         rating = excluded.rating, rated_at = CURRENT_TIMESTAMP",
+                )?
+                .execute(params![track, rating])?,
+            None => self
+                .conn
+                .prepare_cached("DELETE FROM ratings WHERE track_path = ?1")?
+                .execute(params![track])?,
+        };
+        Ok(())
+    }
+
+    pub fn get_rating(&self, track: &str) -> Result<Option<u8>> {
+        self.conn
+            .prepare_cached("SELECT rating FROM ratings WHERE track_path = ?1")?
+            .query_row(params![track], |row| row.get(0))
+            .optional()
+    }
+
+    /// Liked tracks in the order they were liked; also what the `favorites` playlist
+    /// contains.
+    pub fn favorites(&self) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT track_path FROM favorites ORDER BY liked_at, rowid")?;
+        let tracks = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>>>()?;
+        Ok(tracks)
+    }
+
+    pub fn get_all_playlists(&self) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT name FROM playlists ORDER BY name")?;
+        let playlists = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>>>()?;
+        Ok(playlists)
+    }
+
+    pub fn log_playback(&self, track: &str) -> Result<()> {
+        self.conn
+            .prepare_cached("INSERT INTO play_history (track_path) VALUES (?1)")?
+            .execute(params![track])?;
+        self.notify(DbEvent::HistoryAppended(track.to_string()));
+        Ok(())
+    }
+
+    /// Counts a play-through of `track` and adds it to the play history.
+    pub fn record_play_completion(&self, track: &str) -> Result<()> {
+        self.conn
+            .prepare_cached(
+                "INSERT INTO track_stats (track_path, play_count, last_played)
+             VALUES (?1, 1, CURRENT_TIMESTAMP)
+             ON CONFLICT(track_path) DO UPDATE SET
+                play_count = play_count + 1,
+                last_played = CURRENT_TIMESTAMP",
+            )?
+            .execute(params![track])?;
+        self.log_playback(track)
+    }
+
+    pub fn record_skip(&self, track: &str) -> Result<()> {
+        self.conn
+            .prepare_cached(
+                "INSERT INTO track_stats (track_path, skip_count) VALUES (?1, 1)
+             ON CONFLICT(track_path) DO UPDATE SET skip_count = skip_count + 1",
+            )?
+            .execute(params![track])?;
+        Ok(())
+    }
+
+    /// Returns `(play_count, skip_count)` for a track, zero if it was never played.
+    pub fn get_track_stats(&self, track: &str) -> Result<(u32, u32)> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT play_count, skip_count FROM track_stats WHERE track_path = ?1",
+                params![track],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?
+            .unwrap_or((0, 0)))
+    }
+
+    /// Tracks with the most completed plays, as `(path, play_count)`.
+    pub fn most_played(&self, limit: usize) -> Result<Vec<(String, u32)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT track_path, play_count FROM track_stats WHERE play_count > 0
+             ORDER BY play_count DESC, track_path LIMIT ?1",
+        )?;
+        let rows = stmt
+            .query_map(params![limit], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, u32)>>>()?;
+        Ok(rows)
+    }
+
+    /// Tracks skipped most often, as `(path, skip_count)`.
+    pub fn most_skipped(&self, limit: usize) -> Result<Vec<(String, u32)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT track_path, skip_count FROM track_stats WHERE skip_count > 0
+             ORDER BY skip_count DESC, track_path LIMIT ?1",
+        )?;
+        let rows = stmt
+            .query_map(params![limit], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, u32)>>>()?;
+        Ok(rows)
+    }
+
+    /// Adds (or retitles) a feed subscription and returns its id.
+    pub fn add_feed(&self, url: &str, title: &str) -> Result<i64> {
+        self.conn
+            .prepare_cached(
+                "INSERT INTO feeds (url, title) VALUES (?1, ?2)
+             ON CONFLICT(url) DO UPDATE SET title = excluded.title",
+            )?
+            .execute(params![url, title])?;
+        self.conn
+            .query_row("SELECT id FROM feeds WHERE url = ?1", params![url], |row| {
+                row.get(0)
+            })
+    }
+
+    /// Subscribed feeds as `(id, url, title)`.
+    pub fn get_feeds(&self) -> Result<Vec<(i64, String, String)>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT id, url, title FROM feeds ORDER BY title")?;
+        let feeds = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<Vec<(i64, String, String)>>>()?;
+        Ok(feeds)
+    }
+
+    /// Feed titles with their number of unlistened episodes.
+    pub fn get_feed_summaries(&self) -> Result<Vec<(String, u32)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT f.title, COUNT(e.id) FROM feeds f
+             LEFT JOIN episodes e ON e.feed_id = f.id AND e.listened = 0
+             GROUP BY f.id ORDER BY f.title",
+        )?;
+        let feeds = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, u32)>>>()?;
+        Ok(feeds)
+    }
+
+    /// Records an episode and returns its id, or `None` if the feed already had it.
+    pub fn add_episode(&self, feed_id: i64, episode: &Episode) -> Result<Option<i64>> {
+        let inserted = self
+            .conn
+            .prepare_cached(
+                "INSERT OR IGNORE INTO episodes (feed_id, guid, title, url, published)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?
+            .execute(params![
+                feed_id,
+                episode.guid,
+                episode.title,
+                episode.url,
+                episode.published
+            ])?;
+        Ok((inserted > 0).then(|| self.conn.last_insert_rowid()))
+    }
+
+    /// Records the episode if it's new and returns its id while it still has to be
+    /// downloaded: no local file yet and not marked listened. A download that failed is
+    /// thus tried again by the next refresh.
+    pub fn episode_to_download(&self, feed_id: i64, episode: &Episode) -> Result<Option<i64>> {
+        self.add_episode(feed_id, episode)?;
+        self.conn
+            .prepare_cached(
+                "SELECT id FROM episodes
+                 WHERE feed_id = ?1 AND guid = ?2 AND local_path IS NULL AND listened = 0",
+            )?
+            .query_row(params![feed_id, episode.guid], |row| row.get(0))
+            .optional()
+    }
+
+    pub fn set_episode_path(&self, id: i64, path: &str) -> Result<()> {
+        self.conn
+            .prepare_cached("UPDATE episodes SET local_path = ?2 WHERE id = ?1")?
+            .execute(params![id, path])?;
+        Ok(())
+    }
+
+    pub fn set_episode_listened(&self, id: i64) -> Result<()> {
+        self.conn
+            .prepare_cached("UPDATE episodes SET listened = 1 WHERE id = ?1")?
+            .execute(params![id])?;
+        Ok(())
+    }
+
+    /// Marks the episode downloaded to `path` as listened; a no-op for other tracks.
+    pub fn mark_episode_finished(&self, path: &str) -> Result<()> {
+        self.conn
+            .prepare_cached("UPDATE episodes SET listened = 1 WHERE local_path = ?1")?
+            .execute(params![path])?;
+        Ok(())
+    }
+
+    pub fn add_tag(&self, track: &str, tag: &str) -> Result<()> {
+        self.conn
+            .prepare_cached("INSERT OR IGNORE INTO track_tags (track_path, tag) VALUES (?1, ?2)")?
+            .execute(params![track, tag])?;
+        Ok(())
+    }
+
+    /// Returns whether the track had the tag.
+    pub fn remove_tag(&self, track: &str, tag: &str) -> Result<bool> {
+        let removed = self
+            .conn
+            .prepare_cached("DELETE FROM track_tags WHERE track_path = ?1 AND tag = ?2")?
+            .execute(params![track, tag])?;
+        Ok(removed > 0)
+    }
+
+    pub fn get_track_tags(&self, track: &str) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT tag FROM track_tags WHERE track_path = ?1 ORDER BY tag")?;
+        let tags = stmt
+            .query_map(params![track], |row| row.get(0))?
+            .collect::<Result<Vec<String>>>()?;
+        Ok(tags)
+    }
+
+    /// Every tag in use, with the number of tracks carrying it.
+    pub fn get_all_tags(&self) -> Result<Vec<(String, u32)>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT tag, COUNT(*) FROM track_tags GROUP BY tag ORDER BY tag")?;
+        let tags = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, u32)>>>()?;
+        Ok(tags)
+    }
+
+    pub fn get_tracks_with_tag(&self, tag: &str) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT track_path FROM track_tags WHERE tag = ?1 ORDER BY track_path",
+        )?;
+        let tracks = stmt
+            .query_map(params![tag], |row| row.get(0))?
+            .collect::<Result<Vec<String>>>()?;
+        Ok(tracks)
+    }
+
+    pub fn save_resume_position(&self, track: &str, position: f64) -> Result<()> {
+        self.conn
+            .prepare_cached(
+                "INSERT INTO resume_positions (track_path, position) VALUES (?1, ?2)
+             ON CONFLICT(track_path) DO UPDATE SET
+                position = excluded.position,
+                updated_at = CURRENT_TIMESTAMP",
+            )?
+            .execute(params![track, position])?;
+        Ok(())
+    }
+
+    pub fn get_resume_position(&self, track: &str) -> Result<Option<f64>> {
+        self.conn
+            .query_row(
+                "SELECT position FROM resume_positions WHERE track_path = ?1",
+                params![track],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    pub fn clear_resume_position(&self, track: &str) -> Result<()> {
+        self.conn
+            .prepare_cached("DELETE FROM resume_positions WHERE track_path = ?1")?
+            .execute(params![track])?;
+        Ok(())
+    }
+
+    /// Seconds spent listening to completed plays in `period`. Plays of tracks whose
+    /// duration isn't in the library don't count.
+    pub fn total_listening_time(&self, period: StatsPeriod) -> Result<f64> {
+        self.conn.query_row(
+            "SELECT COALESCE(SUM(t.duration), 0) FROM play_history h
+             JOIN tracks t ON t.path = h.track_path
+             WHERE h.played_at >= COALESCE(datetime('now', ?1), '')",
+            params![period.modifier()],
+            |row| row.get(0),
+        )
+    }
+
+    /// Number of library tracks and their combined duration in seconds.
+    pub fn library_summary(&self) -> Result<(u32, f64)> {
+        self.conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(duration), 0) FROM tracks",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+    }
+
+    /// Most played tracks in `period`, as `(path, plays)`.
+    pub fn top_tracks(&self, period: StatsPeriod, limit: usize) -> Result<Vec<(String, u32)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT track_path, COUNT(*) AS plays FROM play_history
+             WHERE played_at >= COALESCE(datetime('now', ?1), '')
+             GROUP BY track_path ORDER BY plays DESC, track_path LIMIT ?2",
+        )?;
+        let rows = stmt
+            .query_map(params![period.modifier(), limit], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<Result<Vec<(String, u32)>>>()?;
+        Ok(rows)
+    }
+
+    /// Most played artists in `period`, as `(artist, plays)`, from tracks with an artist
+    /// tag in the library.
+    pub fn top_artists(&self, period: StatsPeriod, limit: usize) -> Result<Vec<(String, u32)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT t.artist, COUNT(*) AS plays FROM play_history h
+             JOIN tracks t ON t.path = h.track_path
+             WHERE t.artist IS NOT NULL
+               AND h.played_at >= COALESCE(datetime('now', ?1), '')
+             GROUP BY t.artist ORDER BY plays DESC, t.artist LIMIT ?2",
+        )?;
+        let rows = stmt
+            .query_map(params![period.modifier(), limit], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<Result<Vec<(String, u32)>>>()?;
+        Ok(rows)
+    }
+
+    /// Library tracks, newest first.
+    pub fn recently_added(&self, limit: usize) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT path FROM tracks ORDER BY added_at DESC, id DESC LIMIT ?1")?;
+        let rows = stmt
+            .query_map(params![limit], |row| row.get(0))?
+            .collect::<Result<Vec<String>>>()?;
+        Ok(rows)
+    }
+
+    /// Distinct tracks from the play history, most recently played first, with the time
+    /// of their last play.
+    pub fn recently_played(&self, limit: usize) -> Result<Vec<(String, String)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT track_path, MAX(played_at) FROM play_history
+             GROUP BY track_path ORDER BY MAX(id) DESC LIMIT ?1",
+        )?;
+        let rows = stmt
+            .query_map(params![limit], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, String)>>>()?;
+        Ok(rows)
+    }
+
+    /// The whole library, ordered by path.
+    pub fn all_tracks(&self) -> Result<Vec<TrackMetadata>> {
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT {} FROM tracks ORDER BY path",
+            TRACK_COLUMNS
+        ))?;
+        let tracks = stmt
+            .query_map([], Self::track_from_row)?
+            .collect::<Result<Vec<TrackMetadata>>>()?;
+        Ok(tracks)
+    }
+
+    /// Artists in the library with their number of tracks, by name.
+    pub fn artists(&self) -> Result<Vec<(String, u32)>> {
+        self.group_counts("artist", None)
+    }
+
+    /// Albums with their number of tracks, by name, optionally only one artist's.
+    pub fn albums(&self, artist: Option<&str>) -> Result<Vec<(String, u32)>> {
+        self.group_counts("album", artist.map(|a| ("artist", a)))
+    }
+
+    pub fn genres(&self) -> Result<Vec<(String, u32)>> {
+        self.group_counts("genre", None)
+    }
+
+    /// `column` values of the library with their track counts, skipping untagged
+    /// tracks. `column` is always one of ours, never user input.
+    fn group_counts(
+        &self,
+        column: &str,
+        filter: Option<(&str, &str)>,
+    ) -> Result<Vec<(String, u32)>> {
+        let (condition, value) = match filter {
+            Some((col, value)) => (format!("AND {} = ?1", col), Some(value)),
+            None => (String::new(), None),
+        };
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT {column}, COUNT(*) FROM tracks WHERE {column} IS NOT NULL {condition}
+             GROUP BY {column} ORDER BY {column} COLLATE NOCASE"
+        ))?;
+        let map = |row: &rusqlite::Row| Ok((row.get(0)?, row.get(1)?));
+        match value {
+            Some(value) => stmt.query_map(params![value], map)?.collect(),
+            None => stmt.query_map([], map)?.collect(),
+        }
+    }
+
+    /// Tracks of an album in track order.
+    pub fn tracks(&self, album: &str) -> Result<Vec<TrackMetadata>> {
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT {} FROM tracks WHERE album = ?1
+             ORDER BY track_number IS NULL, track_number, path",
+            TRACK_COLUMNS
+        ))?;
+        let tracks = stmt
+            .query_map(params![album], Self::track_from_row)?
+            .collect::<Result<Vec<TrackMetadata>>>()?;
+        Ok(tracks)
+    }
+
+    /// Library tracks whose title, artist, album or path contains `query`, ignoring
+    /// (ASCII) case.
+    pub fn search_tracks(&self, query: &str, limit: usize) -> Result<Vec<TrackMetadata>> {
+        let escaped = query
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT {} FROM tracks
+             WHERE title LIKE ?1 ESCAPE '\\' OR artist LIKE ?1 ESCAPE '\\'
+                OR album LIKE ?1 ESCAPE '\\' OR path LIKE ?1 ESCAPE '\\'
+             ORDER BY artist, album, track_number, path LIMIT ?2",
+            TRACK_COLUMNS
+        ))?;
+        let tracks = stmt
+            .query_map(
+                params![format!("%{}%", escaped), limit as i64],
+                Self::track_from_row,
+            )?
+            .collect::<Result<Vec<TrackMetadata>>>()?;
+        Ok(tracks)
+    }
+
+    pub fn tracks_with_genre(&self, genre: &str) -> Result<Vec<TrackMetadata>> {
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT {} FROM tracks WHERE genre = ?1 ORDER BY artist, album, track_number, path",
+            TRACK_COLUMNS
+        ))?;
+        let tracks = stmt
+            .query_map(params![genre], Self::track_from_row)?
+            .collect::<Result<Vec<TrackMetadata>>>()?;
+        Ok(tracks)
+    }
+
+    pub fn all_track_stats(&self) -> Result<Vec<TrackStats>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT track_path, play_count, skip_count, last_played
+             FROM track_stats ORDER BY track_path",
+        )?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(TrackStats {
+                    path: row.get(0)?,
+                    play_count: row.get(1)?,
+                    skip_count: row.get(2)?,
+                    last_played: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<TrackStats>>>()?;
+        Ok(rows)
+    }
+
+    /// Every tag assignment as `(path, tag)`.
+    pub fn all_track_tags(&self) -> Result<Vec<(String, String)>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT track_path, tag FROM track_tags ORDER BY track_path, tag")?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, String)>>>()?;
+        Ok(rows)
+    }
+
+    /// Every rating as `(path, stars, rated_at)`.
+    pub fn all_ratings(&self) -> Result<Vec<(String, u8, String)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT track_path, rating, rated_at FROM ratings ORDER BY track_path",
+        )?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<Vec<(String, u8, String)>>>()?;
+        Ok(rows)
+    }
+
+    /// Every liked track as `(path, liked_at)`, in the order they were liked.
+    pub fn all_favorites(&self) -> Result<Vec<(String, String)>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT track_path, liked_at FROM favorites ORDER BY rowid")?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, String)>>>()?;
+        Ok(rows)
+    }
+
+    /// The complete play history, oldest first.
+    pub fn full_play_history(&self) -> Result<Vec<(String, String)>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT track_path, played_at FROM play_history ORDER BY id")?;
+        let history = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, String)>>>()?;
+        Ok(history)
+    }
+
+    /// Play history grouped by track, most played first, as `(path, plays, last_played)`.
+    pub fn history_by_track(&self, limit: usize) -> Result<Vec<(String, u32, String)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT track_path, COUNT(*) AS plays, MAX(played_at) FROM play_history
+             GROUP BY track_path ORDER BY plays DESC, MAX(played_at) DESC LIMIT ?1",
+        )?;
+        let rows = stmt
+            .query_map(params![limit], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .collect::<Result<Vec<(String, u32, String)>>>()?;
+        Ok(rows)
+    }
+
+    /// Play counts per day (`YYYY-MM-DD`, UTC), newest day first.
+    pub fn history_by_day(&self, limit: usize) -> Result<Vec<(String, u32)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT date(played_at) AS day, COUNT(*) FROM play_history
+             GROUP BY day ORDER BY day DESC LIMIT ?1",
+        )?;
+        let rows = stmt
+            .query_map(params![limit], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, u32)>>>()?;
+        Ok(rows)
+    }
+
+    pub fn get_play_history(&self, limit: usize) -> Result<Vec<(String, String)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT track_path, played_at FROM play_history ORDER BY id DESC LIMIT ?1",
+        )?;
+
+        let history = stmt
+            .query_map(params![limit], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, String)>>>()?;
+
+        Ok(history)
+    }
+
+    /// Plays newest first as `(index, path, played_at)`, skipping `offset` matches. Index 1
+    /// is the latest play; indexes count the whole history, so a filtered entry keeps the
+    /// number `play_history_entry` knows it by. `search` matches the path, title or
+    /// artist, ignoring (ASCII) case.
+    pub fn play_history_page(
+        &self,
+        search: Option<&str>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<(usize, String, String)>> {
+        let pattern = search.map(|query| {
+            let escaped = query
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            format!("%{}%", escaped)
+        });
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT n, track_path, played_at FROM (
+                 SELECT ROW_NUMBER() OVER (ORDER BY h.id DESC) AS n, h.track_path,
+                        h.played_at, t.title, t.artist
+                 FROM play_history h LEFT JOIN tracks t ON t.path = h.track_path
+             )
+             WHERE ?1 IS NULL OR track_path LIKE ?1 ESCAPE '\\'
+                OR title LIKE ?1 ESCAPE '\\' OR artist LIKE ?1 ESCAPE '\\'
+             ORDER BY n LIMIT ?2 OFFSET ?3",
+        )?;
+        let rows = stmt
+            .query_map(params![pattern, limit as i64, offset as i64], |row| {
+                Ok((row.get::<_, i64>(0)? as usize, row.get(1)?, row.get(2)?))
+            })?
+            .collect::<Result<Vec<(usize, String, String)>>>()?;
+        Ok(rows)
+    }
+
+    /// Track of the `index`th most recent play (1 is the latest).
+    pub fn play_history_entry(&self, index: usize) -> Result<Option<String>> {
+        let Some(offset) = index.checked_sub(1) else {
+            return Ok(None);
+        };
+        self.conn
+            .query_row(
+                "SELECT track_path FROM play_history ORDER BY id DESC LIMIT 1 OFFSET ?1",
+                params![offset as i64],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_database_creation() {
+        let db = Database::in_memory().unwrap();
+        assert!(db.get_all_playlists().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_playlist_operations() {
+        let db = Database::in_memory().unwrap();
+
+        db.create_playlist("test").unwrap();
+        let playlists = db.get_all_playlists().unwrap();
+        assert_eq!(playlists, vec!["test"]);
+
+        db.add_track_to_playlist("test", "track1.mp3").unwrap();
+        db.add_track_to_playlist("test", "track2.mp3").unwrap();
+
+        let tracks = db.get_playlist_tracks("test").unwrap();
+        assert_eq!(tracks, vec!["track1.mp3", "track2.mp3"]);
+
+        db.remove_track_from_playlist("test", "track1.mp3").unwrap();
+        let tracks = db.get_playlist_tracks("test").unwrap();
+        assert_eq!(tracks, vec!["track2.mp3"]);
+
+        db.delete_playlist("test").unwrap();
+        assert!(db.get_all_playlists().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_playlist_trash() {
+        let db = Database::in_memory().unwrap();
+        db.add_track_to_playlist("sets/friday", "a.mp3").unwrap();
+        db.add_track_to_playlist("sets/friday", "b.mp3").unwrap();
+        db.set_entry_note("sets/friday", 1, Some("closer")).unwrap();
+
+        db.delete_playlist("sets/friday").unwrap();
+        assert_eq!(db.get_playlist_tracks("sets/friday").unwrap().len(), 0);
+        let trash = db.trashed_playlists().unwrap();
+        assert_eq!(trash.len(), 1);
+        assert_eq!((trash[0].0.as_str(), trash[0].2), ("sets/friday", 2));
+
+        db.create_playlist("sets/friday").unwrap();
+        assert_eq!(
+            db.undelete_playlist("sets/friday").unwrap(),
+            UndeleteOutcome::NameTaken
+        );
+        db.delete_playlist("sets/friday").unwrap();
+        // the empty one is the newest in the trash now
+        db.conn
+            .execute("DELETE FROM trash_playlists WHERE id = 2", [])
+            .unwrap();
+
+        assert_eq!(
+            db.undelete_playlist("sets/friday").unwrap(),
+            UndeleteOutcome::Restored
+        );
+        let entries = db.get_playlist_entries("sets/friday").unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].note.as_deref(), Some("closer"));
+        assert_eq!(
+            db.undelete_playlist("sets/friday").unwrap(),
+            UndeleteOutcome::NotFound
+        );
+
+        db.delete_playlist("sets/friday").unwrap();
+        assert_eq!(db.purge_trash(30).unwrap(), 0);
+        db.conn
+            .execute(
+                "UPDATE trash_playlists SET deleted_at = datetime('now', '-31 days')",
+                [],
+            )
+            .unwrap();
+        assert_eq!(db.purge_trash(30).unwrap(), 1);
+        assert!(db.trashed_playlists().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_track_metadata() {
+        let db = Database::in_memory().unwrap();
+        assert_eq!(db.display_name("a.mp3"), "a.mp3");
+
+        let mut meta = TrackMetadata {
+            path: "a.mp3".to_string(),
+            title: Some("Song".to_string()),
+            duration: Some(180.5),
+            ..Default::default()
+        };
+        db.upsert_track(&meta).unwrap();
+        assert_eq!(db.display_name("a.mp3"), "Song");
+
+        meta.artist = Some("Band".to_string());
+        db.upsert_track(&meta).unwrap();
+        assert_eq!(db.get_track("a.mp3").unwrap(), Some(meta));
+        assert_eq!(db.display_name("a.mp3"), "Band – Song");
+    }
+
+    #[test]
+    fn test_play_and_skip_counts() {
+        let db = Database::in_memory().unwrap();
+        assert_eq!(db.get_track_stats("a.mp3").unwrap(), (0, 0));
+
+        db.record_play_completion("a.mp3").unwrap();
+        db.record_play_completion("a.mp3").unwrap();
+        db.record_skip("a.mp3").unwrap();
+        db.record_skip("b.mp3").unwrap();
+
+        assert_eq!(db.get_track_stats("a.mp3").unwrap(), (2, 1));
+        assert_eq!(db.most_played(10).unwrap(), vec![("a.mp3".to_string(), 2)]);
+        assert_eq!(db.most_skipped(1).unwrap().len(), 1);
+        assert_eq!(db.get_play_history(10).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_podcast_episodes() {
+        let db = Database::in_memory().unwrap();
+        let feed = db.add_feed("https://example.com/rss", "Show").unwrap();
+        assert_eq!(
+            db.add_feed("https://example.com/rss", "Show!").unwrap(),
+            feed
+        );
+
+        let episode = Episode {
+            guid: "ep-1".to_string(),
+            title: "Pilot".to_string(),
+            url: "https://example.com/1.mp3".to_string(),
+            published: None,
+        };
+        let id = db.add_episode(feed, &episode).unwrap().unwrap();
+        assert_eq!(db.add_episode(feed, &episode).unwrap(), None);
+        assert_eq!(
+            db.get_feed_summaries().unwrap(),
+            vec![("Show!".to_string(), 1)]
+        );
+
+        db.set_episode_path(id, "/cache/Pilot.mp3").unwrap();
+        db.mark_episode_finished("/cache/Pilot.mp3").unwrap();
+        assert_eq!(
+            db.get_feed_summaries().unwrap(),
+            vec![("Show!".to_string(), 0)]
+        );
+    }
+
+    #[test]
+    fn test_track_tags() {
+        let db = Database::in_memory().unwrap();
+        db.add_tag("b.mp3", "workout").unwrap();
+        db.add_tag("a.mp3", "workout").unwrap();
+        db.add_tag("a.mp3", "2024 favorites").unwrap();
+        db.add_tag("a.mp3", "workout").unwrap();
+
+        assert_eq!(
+            db.get_track_tags("a.mp3").unwrap(),
+            vec!["2024 favorites", "workout"]
+        );
+        assert_eq!(
+            db.get_tracks_with_tag("workout").unwrap(),
+            vec!["a.mp3", "b.mp3"]
+        );
+        assert_eq!(
+            db.get_all_tags().unwrap(),
+            vec![
+                ("2024 favorites".to_string(), 1),
+                ("workout".to_string(), 2)
+            ]
+        );
+
+        assert!(db.remove_tag("a.mp3", "workout").unwrap());
+        assert!(!db.remove_tag("a.mp3", "workout").unwrap());
+        assert_eq!(db.get_tracks_with_tag("workout").unwrap(), vec!["b.mp3"]);
+    }
+
+    #[test]
+    fn test_resume_positions() {
+        let db = Database::in_memory().unwrap();
+        assert_eq!(db.get_resume_position("mix.flac").unwrap(), None);
+
+        db.save_resume_position("mix.flac", 120.0).unwrap();
+        db.save_resume_position("mix.flac", 240.5).unwrap();
+        assert_eq!(db.get_resume_position("mix.flac").unwrap(), Some(240.5));
+
+        db.clear_resume_position("mix.flac").unwrap();
+        assert_eq!(db.get_resume_position("mix.flac").unwrap(), None);
+    }
+
+    #[test]
+    fn test_listening_stats() {
+        let db = Database::in_memory().unwrap();
+        for (path, artist) in [("a.mp3", "Band"), ("b.mp3", "Band"), ("c.mp3", "Solo")] {
+            db.upsert_track(&TrackMetadata {
+                path: path.to_string(),
+                artist: Some(artist.to_string()),
+                duration: Some(100.0),
+                ..Default::default()
+            })
+            .unwrap();
+        }
+        db.record_play_completion("a.mp3").unwrap();
+        db.record_play_completion("a.mp3").unwrap();
+        db.record_play_completion("b.mp3").unwrap();
+        db.record_play_completion("c.mp3").unwrap();
+        db.record_play_completion("unknown.mp3").unwrap();
+        db.conn
+            .execute(
+                "UPDATE play_history SET played_at = datetime('now', '-20 days')
+                 WHERE track_path = 'c.mp3'",
+                [],
+            )
+            .unwrap();
+
+        assert_eq!(db.library_summary().unwrap(), (3, 300.0));
+        assert_eq!(
+            db.total_listening_time(StatsPeriod::AllTime).unwrap(),
+            400.0
+        );
+        assert_eq!(db.total_listening_time(StatsPeriod::Week).unwrap(), 300.0);
+        assert_eq!(
+            db.top_tracks(StatsPeriod::Week, 1).unwrap(),
+            vec![("a.mp3".to_string(), 2)]
+        );
+        assert_eq!(
+            db.top_artists(StatsPeriod::Month, 5).unwrap(),
+            vec![("Band".to_string(), 3), ("Solo".to_string(), 1)]
+        );
+        assert_eq!(
+            db.top_artists(StatsPeriod::Week, 5).unwrap(),
+            vec![("Band".to_string(), 3)]
+        );
+    }
+
+    #[test]
+    fn test_recent_views() {
+        let db = Database::in_memory().unwrap();
+        for path in ["old.mp3", "new.mp3"] {
+            db.upsert_track(&TrackMetadata {
+                path: path.to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+        }
+        assert_eq!(db.recently_added(1).unwrap(), vec!["new.mp3"]);
+
+        db.log_playback("a.mp3").unwrap();
+        db.log_playback("b.mp3").unwrap();
+        db.log_playback("a.mp3").unwrap();
+        let played: Vec<String> = db
+            .recently_played(10)
+            .unwrap()
+            .into_iter()
+            .map(|(track, _)| track)
+            .collect();
+        assert_eq!(played, vec!["a.mp3", "b.mp3"]);
+    }
+
+    #[test]
+    fn test_replace_playlist() {
+        let db = Database::in_memory().unwrap();
+        let tracks = vec!["a.mp3".to_string(), "b.mp3".to_string()];
+        db.replace_playlist("mix", &tracks).unwrap();
+        db.replace_playlist("mix", &tracks).unwrap();
+        assert_eq!(db.get_playlist_tracks("mix").unwrap(), tracks);
+
+        db.replace_playlist("mix", &[]).unwrap();
+        assert!(db.get_playlist_tracks("mix").unwrap().is_empty());
+        assert_eq!(db.get_all_playlists().unwrap(), vec!["mix"]);
+    }
+
+    #[test]
+    fn test_playlist_folders() {
+        let db = Database::in_memory().unwrap();
+        db.add_track_to_playlist("rock/80s/best", "a.mp3").unwrap();
+        db.create_playlist("rock/live").unwrap();
+        db.create_playlist("chill").unwrap();
+        db.create_folder("empty").unwrap();
+
+        assert_eq!(
+            db.get_playlist_tracks("rock/80s/best").unwrap(),
+            vec!["a.mp3"]
+        );
+
+        let tree: Vec<(usize, String, bool)> = db
+            .playlist_tree()
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.depth, e.path, e.is_folder))
+            .collect();
+        assert_eq!(
+            tree,
+            vec![
+                (0, "empty".to_string(), true),
+                (0, "rock".to_string(), true),
+                (1, "rock/80s".to_string(), true),
+                (2, "rock/80s/best".to_string(), false),
+                (1, "rock/live".to_string(), false),
+                (0, "chill".to_string(), false),
+            ]
+        );
+
+        db.rename_playlist("rock/live", "live").unwrap();
+        let top_level: Vec<String> = db
+            .playlist_tree()
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.depth == 0 && !e.is_folder)
+            .map(|e| e.path)
+            .collect();
+        assert_eq!(top_level, vec!["chill", "live"]);
+    }
+
+    #[test]
+    fn test_persistent_queue() {
+        let db = Database::in_memory().unwrap();
+        assert!(db.load_queue().unwrap().is_empty());
+        assert_eq!(db.get_queue_current().unwrap(), None);
+
+        let tracks = vec!["b.mp3".to_string(), "a.mp3".to_string()];
+        db.save_queue(&tracks).unwrap();
+        db.save_queue(&tracks).unwrap();
+        assert_eq!(db.load_queue().unwrap(), tracks);
+
+        db.set_queue_current(Some("a.mp3"), 0.0).unwrap();
+        db.set_queue_position(42.5).unwrap();
+        assert_eq!(
+            db.get_queue_current().unwrap(),
+            Some(("a.mp3".to_string(), 42.5))
+        );
+        db.set_queue_current(None, 0.0).unwrap();
+        assert_eq!(db.get_queue_current().unwrap(), None);
+    }
+
+    #[test]
+    fn test_playlist_position() {
+        let db = Database::in_memory().unwrap();
+        db.add_track_to_playlist("mix", "a.mp3").unwrap();
+        assert_eq!(db.get_playlist_position("mix").unwrap(), None);
+
+        db.save_playlist_position("mix", "a.mp3", 42.5).unwrap();
+        assert_eq!(
+            db.get_playlist_position("mix").unwrap(),
+            Some(("a.mp3".to_string(), 42.5))
+        );
+
+        db.save_playlist_position("nope", "a.mp3", 1.0).unwrap();
+        assert_eq!(db.get_playlist_position("nope").unwrap(), None);
+    }
+
+    #[test]
+    fn test_merge_track() {
+        let db = Database::in_memory().unwrap();
+        db.record_play_completion("old.mp3").unwrap();
+        db.record_skip("old.mp3").unwrap();
+        db.record_play_completion("new.flac").unwrap();
+        db.add_tag("old.mp3", "calm").unwrap();
+        db.add_track_to_playlist("mix", "old.mp3").unwrap();
+        db.set_fingerprint("old.mp3", &[1, 2]).unwrap();
+        db.save_playlist_position("mix", "old.mp3", 12.0).unwrap();
+        db.save_eq_preset("warm", &[[100.0, 0.7, 3.0, 0.0]])
+            .unwrap();
+        db.assign_eq_preset(EqTarget::Track("old.mp3"), Some("warm"))
+            .unwrap();
+        db.add_track_to_playlist("gone", "old.mp3").unwrap();
+        db.delete_playlist("gone").unwrap();
+
+        db.merge_track("old.mp3", "new.flac").unwrap();
+        assert_eq!(db.get_track_stats("new.flac").unwrap(), (2, 1));
+        assert_eq!(db.get_track_stats("old.mp3").unwrap(), (0, 0));
+        assert_eq!(db.get_track_tags("new.flac").unwrap(), vec!["calm"]);
+        assert_eq!(db.get_playlist_tracks("mix").unwrap(), vec!["new.flac"]);
+        assert_eq!(db.history_by_track(10).unwrap()[0].1, 2);
+        assert!(db.fingerprints().unwrap().is_empty());
+        assert_eq!(
+            db.get_playlist_position("mix").unwrap(),
+            Some(("new.flac".to_string(), 12.0))
+        );
+        assert_eq!(
+            db.preset_for_track("new.flac")
+                .unwrap()
+                .map(|(name, _)| name),
+            Some("warm".to_string())
+        );
+        assert!(db.preset_for_track("old.mp3").unwrap().is_none());
+        db.undelete_playlist("gone").unwrap();
+        assert_eq!(db.get_playlist_tracks("gone").unwrap(), vec!["new.flac"]);
+    }
+
+    #[test]
+    fn test_browse_library() {
+        let db = Database::in_memory().unwrap();
+        for (path, artist, album, number, genre) in [
+            ("/m/2.mp3", "Band", "First", Some(2), "Rock"),
+            ("/m/1.mp3", "Band", "First", Some(1), "Rock"),
+            ("/m/3.mp3", "Band", "Second", None, "Jazz"),
+            ("/m/4.mp3", "another", "Third", Some(1), "Rock"),
+        ] {
+            db.upsert_track(&TrackMetadata {
+                path: path.to_string(),
+                artist: Some(artist.to_string()),
+                album: Some(album.to_string()),
+                track_number: number,
+                genre: Some(genre.to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        }
+        db.upsert_track(&TrackMetadata {
+            path: "/m/untagged.mp3".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            db.artists().unwrap(),
+            vec![("another".to_string(), 1), ("Band".to_string(), 3)]
+        );
+        assert_eq!(
+            db.albums(Some("Band")).unwrap(),
+            vec![("First".to_string(), 2), ("Second".to_string(), 1)]
+        );
+        assert_eq!(db.albums(None).unwrap().len(), 3);
+        assert_eq!(
+            db.genres().unwrap(),
+            vec![("Jazz".to_string(), 1), ("Rock".to_string(), 3)]
+        );
+
+        let first: Vec<String> = db
+            .tracks("First")
+            .unwrap()
+            .into_iter()
+            .map(|t| t.path)
+            .collect();
+        assert_eq!(first, vec!["/m/1.mp3", "/m/2.mp3"]);
+        assert_eq!(db.tracks_with_genre("Jazz").unwrap()[0].path, "/m/3.mp3");
+
+        assert_eq!(db.search_tracks("band", 10).unwrap().len(), 3);
+        assert_eq!(db.search_tracks("band", 2).unwrap().len(), 2);
+        assert_eq!(db.search_tracks("untagged", 10).unwrap().len(), 1);
+        // LIKE wildcards in the query are taken literally
+        assert!(db.search_tracks("%", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_eq_presets() {
+        let db = Database::in_memory().unwrap();
+        let bass = [[100.0, 0.7, 6.0, 0.0]];
+        db.save_eq_preset("bass", &bass).unwrap();
+        db.save_eq_preset("flat", &[]).unwrap();
+        assert_eq!(db.eq_presets().unwrap(), vec!["bass", "flat"]);
+        assert_eq!(db.get_eq_preset("bass").unwrap(), Some(bass.to_vec()));
+
+        db.upsert_track(&TrackMetadata {
+            path: "/m/a.mp3".to_string(),
+            album: Some("Thin".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(db.preset_for_track("/m/a.mp3").unwrap(), None);
+
+        db.assign_eq_preset(EqTarget::Album("Thin"), Some("bass"))
+            .unwrap();
+        assert_eq!(
+            db.preset_for_track("/m/a.mp3").unwrap(),
+            Some(("bass".to_string(), bass.to_vec()))
+        );
+
+        // a track's own preset wins over its album's
+        db.assign_eq_preset(EqTarget::Track("/m/a.mp3"), Some("flat"))
+            .unwrap();
+        assert_eq!(db.preset_for_track("/m/a.mp3").unwrap().unwrap().0, "flat");
+        db.assign_eq_preset(EqTarget::Track("/m/a.mp3"), None)
+            .unwrap();
+        assert_eq!(db.preset_for_track("/m/a.mp3").unwrap().unwrap().0, "bass");
+    }
+
+    #[test]
+    fn test_ratings() {
+        let db = Database::in_memory().unwrap();
+        assert_eq!(db.get_rating("a.mp3").unwrap(), None);
+        db.set_rating("a.mp3", Some(4)).unwrap();
+        db.set_rating("a.mp3", Some(2)).unwrap();
+        assert_eq!(db.get_rating("a.mp3").unwrap(), Some(2));
+        assert!(db.set_rating("a.mp3", Some(6)).is_err());
+        db.set_rating("a.mp3", None).unwrap();
+        assert_eq!(db.get_rating("a.mp3").unwrap(), None);
+    }
+
+    #[test]
+    fn test_favorites() {
+        let db = Database::in_memory().unwrap();
+        db.set_favorite("b.mp3", true).unwrap();
+        db.set_favorite("a.mp3", true).unwrap();
+        db.set_favorite("a.mp3", true).unwrap();
+        assert!(db.is_favorite("a.mp3").unwrap());
+        assert_eq!(
+            db.get_playlist_tracks(FAVORITES_PLAYLIST).unwrap(),
+            vec!["b.mp3", "a.mp3"]
+        );
+
+        db.set_favorite("b.mp3", false).unwrap();
+        assert!(!db.is_favorite("b.mp3").unwrap());
+        assert_eq!(
+            db.get_playlist_entries(FAVORITES_PLAYLIST).unwrap().len(),
+            1
+        );
+
+        // the name stays reserved for the liked tracks
+        let err = db
+            .add_track_to_playlist(FAVORITES_PLAYLIST, "c.mp3")
+            .unwrap_err();
+        assert!(err.to_string().contains("always holds the liked tracks"));
+        assert!(db.replace_playlist(FAVORITES_PLAYLIST, &[]).is_err());
+        db.create_playlist("mix").unwrap();
+        assert_eq!(
+            db.rename_playlist("mix", FAVORITES_PLAYLIST).unwrap(),
+            RenameOutcome::NameTaken
+        );
+        assert_eq!(
+            db.copy_playlist("mix", FAVORITES_PLAYLIST).unwrap(),
+            CopyOutcome::NameTaken
+        );
+        assert_eq!(
+            db.copy_playlist(FAVORITES_PLAYLIST, "liked").unwrap(),
+            CopyOutcome::Copied
+        );
+        assert_eq!(db.get_playlist_tracks("liked").unwrap(), vec!["a.mp3"]);
+        assert_eq!(
+            db.get_playlist_tracks(FAVORITES_PLAYLIST).unwrap(),
+            vec!["a.mp3"]
+        );
+    }
+
+    #[test]
+    fn test_change_events() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut db = Database::in_memory().unwrap();
+        db.set_event_sender(tx);
+
+        db.replace_playlist("mix", &["a.mp3".to_string()]).unwrap();
+        db.rename_playlist("mix", "road").unwrap();
+        db.set_favorite("a.mp3", true).unwrap();
+        db.record_play_completion("a.mp3").unwrap();
+        db.delete_playlist("road").unwrap();
+        // nothing to delete, nothing to report
+        db.delete_playlist("road").unwrap();
+
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            vec![
+                DbEvent::PlaylistModified("mix".to_string()),
+                DbEvent::PlaylistRenamed {
+                    old: "mix".to_string(),
+                    new: "road".to_string(),
+                },
+                DbEvent::PlaylistModified(FAVORITES_PLAYLIST.to_string()),
+                DbEvent::HistoryAppended("a.mp3".to_string()),
+                DbEvent::PlaylistDeleted("road".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_relocate() {
+        let db = Database::in_memory().unwrap();
+        db.add_track_to_playlist("mix", "/mnt/old/a.mp3").unwrap();
+        db.add_track_to_playlist("mix", "/home/b.mp3").unwrap();
+        db.record_play_completion("/mnt/old/a.mp3").unwrap();
+        db.add_tag("/mnt/old/a.mp3", "mellow").unwrap();
+        db.save_playlist_position("mix", "/mnt/old/a.mp3", 12.0)
+            .unwrap();
+        db.add_track_to_playlist("mix", "/mnt/older/c.mp3").unwrap();
+
+        assert!(
+            db.missing_tracks()
+                .unwrap()
+                .contains(&"/mnt/old/a.mp3".to_string())
+        );
+
+        // playlist row, history row, stats row, tag row and resume point
+        assert_eq!(db.relocate("/mnt/old/", "/media/new/").unwrap(), 5);
+        assert_eq!(
+            db.get_playlist_tracks("mix").unwrap(),
+            vec!["/media/new/a.mp3", "/home/b.mp3", "/mnt/older/c.mp3"]
+        );
+        assert_eq!(
+            db.get_playlist_position("mix").unwrap(),
+            Some(("/media/new/a.mp3".to_string(), 12.0))
+        );
+        assert_eq!(db.get_track_stats("/media/new/a.mp3").unwrap(), (1, 0));
+        assert_eq!(
+            db.get_tracks_with_tag("mellow").unwrap(),
+            vec!["/media/new/a.mp3"]
+        );
+    }
+
+    #[test]
+    fn test_move_playlist_entry() {
+        let db = Database::in_memory().unwrap();
+        let tracks: Vec<String> = ["a", "b", "c"].iter().map(|t| t.to_string()).collect();
+        db.replace_playlist("set", &tracks).unwrap();
+        // `c` is pinned to the top by its weight
+        assert!(db.set_entry_weight("set", 2, -1).unwrap());
+        assert_eq!(db.get_playlist_tracks("set").unwrap(), vec!["c", "a", "b"]);
+
+        assert!(db.move_playlist_entry("set", 2, 0).unwrap());
+        assert_eq!(db.get_playlist_tracks("set").unwrap(), vec!["b", "c", "a"]);
+        assert!(db.move_playlist_entry("set", 0, 2).unwrap());
+        assert_eq!(db.get_playlist_tracks("set").unwrap(), vec!["c", "a", "b"]);
+        assert!(!db.move_playlist_entry("set", 3, 0).unwrap());
+    }
+
+    #[test]
+    fn test_rename_playlist() {
+        let db = Database::in_memory().unwrap();
+        db.add_track_to_playlist("old", "a.mp3").unwrap();
+        db.create_playlist("taken").unwrap();
+
+        assert_eq!(
+            db.rename_playlist("missing", "x").unwrap(),
+            RenameOutcome::NotFound
+        );
+        assert_eq!(
+            db.rename_playlist("old", "taken").unwrap(),
+            RenameOutcome::NameTaken
+        );
+        assert_eq!(
+            db.rename_playlist("old", "new").unwrap(),
+            RenameOutcome::Renamed
+        );
+        assert_eq!(db.get_playlist_tracks("new").unwrap(), vec!["a.mp3"]);
+        assert_eq!(db.get_all_playlists().unwrap(), vec!["new", "taken"]);
+    }
+
+    #[test]
+    fn test_copy_playlist() {
+        let db = Database::in_memory().unwrap();
+        db.add_track_to_playlist("src", "a.mp3").unwrap();
+        db.add_track_to_playlist("src", "b.mp3").unwrap();
+        db.set_entry_note("src", 1, Some("live")).unwrap();
+
+        assert_eq!(
+            db.copy_playlist("missing", "x").unwrap(),
+            CopyOutcome::NotFound
+        );
+        assert_eq!(
+            db.copy_playlist("src", "src").unwrap(),
+            CopyOutcome::NameTaken
+        );
+        assert_eq!(db.copy_playlist("src", "dst").unwrap(), CopyOutcome::Copied);
+        db.add_track_to_playlist("dst", "c.mp3").unwrap();
+
+        assert_eq!(
+            db.get_playlist_tracks("src").unwrap(),
+            vec!["a.mp3", "b.mp3"]
+        );
+        let entries = db.get_playlist_entries("dst").unwrap();
+        let tracks: Vec<&str> = entries.iter().map(|e| e.track.as_str()).collect();
+        assert_eq!(tracks, vec!["a.mp3", "b.mp3", "c.mp3"]);
+        assert_eq!(entries[1].note.as_deref(), Some("live"));
+    }
+
+    #[test]
+    fn test_check_and_vacuum() {
+        let db = Database::in_memory().unwrap();
+        db.add_track_to_playlist("keep", "a.mp3").unwrap();
+        db.add_track_to_playlist("gone", "b.mp3").unwrap();
+        // orphans come from files written before foreign keys were enforced
+        db.conn
+            .execute_batch(
+                "PRAGMA foreign_keys = OFF;
+                 DELETE FROM playlists WHERE name = 'gone';
+                 PRAGMA foreign_keys = ON;",
+            )
+            .unwrap();
+
+        let report = db.check().unwrap();
+        assert!(report.problems.is_empty());
+        assert_eq!(report.orphans_removed, 1);
+        assert_eq!(db.get_playlist_tracks("keep").unwrap(), vec!["a.mp3"]);
+        assert_eq!(db.check().unwrap().orphans_removed, 0);
+
+        db.vacuum().unwrap();
+        assert!(db.size_bytes().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_playlist_annotations() {
+        let db = Database::in_memory().unwrap();
+        for track in ["a.mp3", "b.mp3", "c.mp3"] {
+            db.add_track_to_playlist("set", track).unwrap();
+        }
+
+        assert!(
+            db.set_entry_note("set", 1, Some("drop after intro"))
+                .unwrap()
+        );
+        assert!(db.set_entry_weight("set", 2, -1).unwrap());
+        assert!(!db.set_entry_weight("set", 3, 5).unwrap());
+        assert_eq!(
+            db.get_playlist_tracks("set").unwrap(),
+            vec!["c.mp3", "a.mp3", "b.mp3"]
+        );
+
+        // saving the queue over the playlist keeps annotations of tracks still in it
+        db.replace_playlist("set", &["b.mp3".to_string(), "d.mp3".to_string()])
+            .unwrap();
+        let entries = db.get_playlist_entries("set").unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].note.as_deref(), Some("drop after intro"));
+        assert_eq!(entries[1].note, None);
+
+        assert!(db.set_entry_note("set", 0, None).unwrap());
+        assert_eq!(db.get_playlist_entries("set").unwrap()[0].note, None);
+    }
+
+    #[test]
+    fn test_concurrent_connections() {
+        let path = std::env::temp_dir().join("eigenplayer_wal_test.db");
+        let _ = std::fs::remove_file(&path);
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+        let mode: String = db
+            .conn
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(mode, "wal");
+
+        let shared = db.connect().unwrap().into_shared();
+        let handle = {
+            let shared = Arc::clone(&shared);
+            std::thread::spawn(move || {
+                for i in 0..20 {
+                    let db = shared.lock().unwrap();
+                    db.add_track_to_playlist("mix", &format!("{}.mp3", i))
+                        .unwrap();
+                }
+            })
+        };
+        // reading on another connection while the thread writes
+        for _ in 0..20 {
+            db.get_playlist_tracks("mix").unwrap();
+        }
+        handle.join().unwrap();
+        assert_eq!(db.get_playlist_tracks("mix").unwrap().len(), 20);
+
+        assert!(Database::in_memory().unwrap().connect().is_err());
+        drop((db, shared));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    #[test]
+    fn test_history_aggregation() {
+        let db = Database::in_memory().unwrap();
+        for (track, at) in [
+            ("a.mp3", "2024-03-01 10:00:00"),
+            ("b.mp3", "2024-03-01 11:00:00"),
+            ("a.mp3", "2024-03-02 09:00:00"),
+        ] {
+            db.conn
+                .execute(
+                    "INSERT INTO play_history (track_path, played_at) VALUES (?1, ?2)",
+                    params![track, at],
+                )
+                .unwrap();
+        }
+
+        assert_eq!(
+            db.history_by_track(10).unwrap(),
+            vec![
+                ("a.mp3".to_string(), 2, "2024-03-02 09:00:00".to_string()),
+                ("b.mp3".to_string(), 1, "2024-03-01 11:00:00".to_string()),
+            ]
+        );
+        assert_eq!(
+            db.history_by_day(10).unwrap(),
+            vec![("2024-03-02".to_string(), 1), ("2024-03-01".to_string(), 2)]
+        );
+        assert_eq!(db.history_by_day(1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_playlist_id_and_indexes() {
+        let db = Database::in_memory().unwrap();
+        assert_eq!(db.playlist_id("mix").unwrap(), None);
+        db.add_track_to_playlist("mix", "a.mp3").unwrap();
+        assert!(db.playlist_id("mix").unwrap().is_some());
+
+        let plan: String = db
+            .conn
+            .query_row(
+                "EXPLAIN QUERY PLAN SELECT track_path FROM playlist_tracks
+                 WHERE playlist_id = 1 ORDER BY position",
+                [],
+                |row| row.get(3),
+            )
+            .unwrap();
+        assert!(plan.contains("idx_playlist_tracks_playlist"), "{}", plan);
+    }
+
+    #[test]
+    fn test_play_history() {
+        let db = Database::in_memory().unwrap();
+
+        db.log_playback("song1.mp3").unwrap();
+        db.log_playback("song2.mp3").unwrap();
+
+        let history = db.get_play_history(10).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].0, "song2.mp3");
+        assert_eq!(history[1].0, "song1.mp3");
+    }
+
+    #[test]
+    fn test_play_history_page() {
+        let db = Database::in_memory().unwrap();
+        db.upsert_track(&TrackMetadata {
+            path: "b.mp3".to_string(),
+            artist: Some("Some_Band".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        for path in ["a.mp3", "b.mp3", "c.mp3", "b.mp3"] {
+            db.log_playback(path).unwrap();
+        }
+
+        let paths = |rows: Vec<(usize, String, String)>| -> Vec<(usize, String)> {
+            rows.into_iter().map(|(n, path, _)| (n, path)).collect()
+        };
+        assert_eq!(
+            paths(db.play_history_page(None, 1, 2).unwrap()),
+            vec![(2, "c.mp3".to_string()), (3, "b.mp3".to_string())]
+        );
+        // filtered entries keep their place in the whole history
+        assert_eq!(
+            paths(db.play_history_page(Some("some_band"), 0, 10).unwrap()),
+            vec![(1, "b.mp3".to_string()), (3, "b.mp3".to_string())]
+        );
+        // `_` is literal, not a wildcard matching the `.` in a.mp3
+        assert!(db.play_history_page(Some("a_m"), 0, 10).unwrap().is_empty());
+        assert_eq!(db.play_history_entry(4).unwrap(), Some("a.mp3".to_string()));
+        assert_eq!(db.play_history_entry(5).unwrap(), None);
+        assert_eq!(db.play_history_entry(0).unwrap(), None);
     }
 }
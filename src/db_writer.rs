@@ -0,0 +1,168 @@
+use crate::db::Database;
+use crate::metadata::TrackMetadata;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use tracing::*;
+
+/// A database write that can be done in the background.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DbWrite {
+    RecordPlayCompletion(String),
+    RecordSkip(String),
+    MarkEpisodeFinished(String),
+    LogPlayback(String),
+    SaveResumePosition {
+        track: String,
+        position: f64,
+    },
+    ClearResumePosition(String),
+    AddTrackToPlaylist {
+        playlist: String,
+        track: String,
+    },
+    RemoveTrackFromPlaylist {
+        playlist: String,
+        track: String,
+    },
+    ReplacePlaylist {
+        name: String,
+        tracks: Vec<String>,
+    },
+    /// Indexes are 0-based, see `Database::move_playlist_entry`.
+    MovePlaylistEntry {
+        playlist: String,
+        from: usize,
+        to: usize,
+    },
+    UpsertTrack(TrackMetadata),
+    SaveQueue(Vec<String>),
+    SetQueueCurrent(Option<String>),
+    SetQueuePosition(f64),
+    SetFavorite {
+        track: String,
+        liked: bool,
+    },
+    SetRating {
+        track: String,
+        rating: Option<u8>,
+    },
+    SavePlaylistPosition {
+        playlist: String,
+        track: String,
+        position: f64,
+    },
+}
+
+enum Job {
+    Write(DbWrite),
+    Flush(Sender<()>),
+}
+
+/// Handle to a thread that owns its own connection and applies `DbWrite`s in order, so
+/// SQLite fsyncs never block the REPL or property observers. Clones share the thread;
+/// it exits once every handle is dropped and the queue is drained.
+#[derive(Clone)]
+pub struct DbWriter {
+    tx: Sender<Job>,
+}
+
+impl DbWriter {
+    pub fn spawn(db: Database) -> Self {
+        let (tx, rx) = mpsc::channel::<Job>();
+        thread::spawn(move || {
+            for job in rx {
+                match job {
+                    Job::Write(write) => {
+                        if let Err(e) = apply(&db, &write) {
+                            warn!("[Database] Background write {:?} failed: {}", write, e);
+                        }
+                    }
+                    Job::Flush(done) => {
+                        let _ = done.send(());
+                    }
+                }
+            }
+        });
+        Self { tx }
+    }
+
+    /// Queues a write; failures are logged by the writer thread.
+    pub fn send(&self, write: DbWrite) {
+        if self.tx.send(Job::Write(write)).is_err() {
+            warn!("[Database] Writer thread is gone, dropping write");
+        }
+    }
+
+    /// Blocks until every write queued before this call has been applied.
+    pub fn flush(&self) {
+        let (done_tx, done_rx) = mpsc::channel();
+        if self.tx.send(Job::Flush(done_tx)).is_ok() {
+            let _ = done_rx.recv();
+        }
+    }
+}
+
+fn apply(db: &Database, write: &DbWrite) -> rusqlite::Result<()> {
+    match write {
+        DbWrite::RecordPlayCompletion(track) => db.record_play_completion(track),
+        DbWrite::RecordSkip(track) => db.record_skip(track),
+        DbWrite::MarkEpisodeFinished(path) => db.mark_episode_finished(path),
+        DbWrite::LogPlayback(track) => db.log_playback(track),
+        DbWrite::SaveResumePosition { track, position } => {
+            db.save_resume_position(track, *position)
+        }
+        DbWrite::ClearResumePosition(track) => db.clear_resume_position(track),
+        DbWrite::AddTrackToPlaylist { playlist, track } => {
+            db.add_track_to_playlist(playlist, track)
+        }
+        DbWrite::RemoveTrackFromPlaylist { playlist, track } => {
+            db.remove_track_from_playlist(playlist, track)
+        }
+        DbWrite::ReplacePlaylist { name, tracks } => db.replace_playlist(name, tracks),
+        DbWrite::MovePlaylistEntry { playlist, from, to } => {
+            db.move_playlist_entry(playlist, *from, *to).map(|_| ())
+        }
+        DbWrite::UpsertTrack(meta) => db.upsert_track(meta),
+        DbWrite::SaveQueue(tracks) => db.save_queue(tracks),
+        DbWrite::SetQueueCurrent(track) => db.set_queue_current(track.as_deref(), 0.0),
+        DbWrite::SetQueuePosition(position) => db.set_queue_position(*position),
+        DbWrite::SetFavorite { track, liked } => db.set_favorite(track, *liked),
+        DbWrite::SetRating { track, rating } => db.set_rating(track, *rating),
+        DbWrite::SavePlaylistPosition {
+            playlist,
+            track,
+            position,
+        } => db.save_playlist_position(playlist, track, *position),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_writes_apply_in_order() {
+        let path = std::env::temp_dir().join("eigenplayer_db_writer_test.db");
+        let _ = std::fs::remove_file(&path);
+        let path_str = path.to_str().unwrap();
+
+        let writer = DbWriter::spawn(Database::new(path_str).unwrap());
+        writer.send(DbWrite::AddTrackToPlaylist {
+            playlist: "mix".to_string(),
+            track: "a.mp3".to_string(),
+        });
+        writer.send(DbWrite::ReplacePlaylist {
+            name: "mix".to_string(),
+            tracks: vec!["b.mp3".to_string()],
+        });
+        writer.send(DbWrite::RecordSkip("b.mp3".to_string()));
+        writer.flush();
+
+        let reader = Database::new(path_str).unwrap();
+        assert_eq!(reader.get_playlist_tracks("mix").unwrap(), vec!["b.mp3"]);
+        assert_eq!(reader.get_track_stats("b.mp3").unwrap(), (0, 1));
+
+        drop(writer);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -70,23 +70,48 @@ impl Eq {
     }
 
     pub fn from_config(eq_bands: Vec<[f32; 4]>, enabled: bool, sample_rate: f32) -> Self {
-        let bands: Vec<Biquad> = eq_bands
-            .into_iter()
-            .map(|band| {
-                let f0 = band[0];
-                let q = band[1];
-                let gain_db = band[2];
-                let band_type = band[3] as u8;
-                let (b0, b1, b2, a1, a2) =
-                    biquad_coefficients(f0, q, gain_db, band_type, sample_rate);
-                Biquad::new(b0, b1, b2, a1, a2)
-            })
-            .collect();
-
-        Self { bands, enabled }
+        Self {
+            bands: bands_from_config(eq_bands, sample_rate),
+            enabled,
+        }
     }
 }
 
+/// Names of the band types, indexed by the type number in a band definition.
+pub const BAND_TYPES: [&str; 3] = ["lowshelf", "peak", "highshelf"];
+
+/// `lowshelf`, `peak` or `highshelf` for the type slot of a band; unknown types pass
+/// audio through unchanged.
+pub fn band_type_name(band_type: f32) -> &'static str {
+    BAND_TYPES
+        .get(band_type as usize)
+        .copied()
+        .unwrap_or("passthrough")
+}
+
+/// The type number for a name in `BAND_TYPES`.
+pub fn parse_band_type(name: &str) -> Option<f32> {
+    let index = BAND_TYPES
+        .iter()
+        .position(|t| t.eq_ignore_ascii_case(name))?;
+    Some(index as f32)
+}
+
+/// Builds the filters for `[frequency, q, gain_db, type]` band definitions.
+pub fn bands_from_config(eq_bands: Vec<[f32; 4]>, sample_rate: f32) -> Vec<Biquad> {
+    eq_bands
+        .into_iter()
+        .map(|band| {
+            let f0 = band[0];
+            let q = band[1];
+            let gain_db = band[2];
+            let band_type = band[3] as u8;
+            let (b0, b1, b2, a1, a2) = biquad_coefficients(f0, q, gain_db, band_type, sample_rate);
+            Biquad::new(b0, b1, b2, a1, a2)
+        })
+        .collect()
+}
+
 fn biquad_coefficients(
     f0: f32,
     q: f32,
@@ -136,3 +161,16 @@ fn biquad_coefficients(
         _ => (1.0, 0.0, 0.0, 0.0, 0.0),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_band_types() {
+        assert_eq!(band_type_name(2.0), "highshelf");
+        assert_eq!(band_type_name(7.0), "passthrough");
+        assert_eq!(parse_band_type("Peak"), Some(1.0));
+        assert_eq!(parse_band_type("notch"), None);
+    }
+}
@@ -0,0 +1,373 @@
+//! Dumps the database to plain files for analysis or migration.
+//!
+//! The JSON format (`format_version` 2) is a single object:
+//!
+//! ```json
+//! {
+//!   "format_version": 2,
+//!   "playlists": [{"name": "a/b", "tracks": [{"path": "...", "note": null, "sort_weight": 0}]}],
+//!   "history":   [{"path": "...", "played_at": "2024-01-02 10:00:00"}],
+//!   "stats":     [{"path": "...", "play_count": 3, "skip_count": 1, "last_played": "..."}],
+//!   "tracks":    [{"path": "...", "title": "...", "artist": "...", "album": "...",
+//!                  "track_number": 1, "duration": 215.3, "codec": "mp3", "genre": "..."}],
+//!   "tags":      [{"path": "...", "tag": "..."}],
+//!   "ratings":   [{"path": "...", "rating": 4, "rated_at": "..."}],
+//!   "favorites": [{"path": "...", "liked_at": "..."}]
+//! }
+//! ```
+//!
+//! Version 2 added `ratings` and `favorites`.
+//!
+//! Timestamps are SQLite's `YYYY-MM-DD HH:MM:SS` in UTC, durations are seconds, and
+//! missing metadata is `null`. The CSV export writes one file per section into a
+//! directory (`playlists.csv` has a `playlist` and `position` column in place of the
+//! nesting), with a header row and the same column names.
+
+use crate::db::Database;
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const FORMAT_VERSION: u32 = 2;
+
+#[derive(Debug, Serialize)]
+pub struct Export {
+    pub format_version: u32,
+    pub playlists: Vec<PlaylistExport>,
+    pub history: Vec<HistoryExport>,
+    pub stats: Vec<StatsExport>,
+    pub tracks: Vec<TrackExport>,
+    pub tags: Vec<TagExport>,
+    pub ratings: Vec<RatingExport>,
+    pub favorites: Vec<FavoriteExport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlaylistExport {
+    pub name: String,
+    pub tracks: Vec<PlaylistTrackExport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlaylistTrackExport {
+    pub path: String,
+    pub note: Option<String>,
+    pub sort_weight: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryExport {
+    pub path: String,
+    pub played_at: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsExport {
+    pub path: String,
+    pub play_count: u32,
+    pub skip_count: u32,
+    pub last_played: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrackExport {
+    pub path: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    pub duration: Option<f64>,
+    pub codec: Option<String>,
+    pub genre: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagExport {
+    pub path: String,
+    pub tag: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RatingExport {
+    pub path: String,
+    pub rating: u8,
+    pub rated_at: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FavoriteExport {
+    pub path: String,
+    pub liked_at: String,
+}
+
+/// Reads everything worth exporting out of `db`.
+pub fn collect(db: &Database) -> rusqlite::Result<Export> {
+    let mut playlists = Vec::new();
+    for name in db.get_all_playlists()? {
+        let tracks = db
+            .get_playlist_entries(&name)?
+            .into_iter()
+            .map(|entry| PlaylistTrackExport {
+                path: entry.track,
+                note: entry.note,
+                sort_weight: entry.sort_weight,
+            })
+            .collect();
+        playlists.push(PlaylistExport { name, tracks });
+    }
+
+    Ok(Export {
+        format_version: FORMAT_VERSION,
+        playlists,
+        history: db
+            .full_play_history()?
+            .into_iter()
+            .map(|(path, played_at)| HistoryExport { path, played_at })
+            .collect(),
+        stats: db
+            .all_track_stats()?
+            .into_iter()
+            .map(|stats| StatsExport {
+                path: stats.path,
+                play_count: stats.play_count,
+                skip_count: stats.skip_count,
+                last_played: stats.last_played,
+            })
+            .collect(),
+        tracks: db
+            .all_tracks()?
+            .into_iter()
+            .map(|meta| TrackExport {
+                path: meta.path,
+                title: meta.title,
+                artist: meta.artist,
+                album: meta.album,
+                track_number: meta.track_number,
+                duration: meta.duration,
+                codec: meta.codec,
+                genre: meta.genre,
+            })
+            .collect(),
+        tags: db
+            .all_track_tags()?
+            .into_iter()
+            .map(|(path, tag)| TagExport { path, tag })
+            .collect(),
+        ratings: db
+            .all_ratings()?
+            .into_iter()
+            .map(|(path, rating, rated_at)| RatingExport {
+                path,
+                rating,
+                rated_at,
+            })
+            .collect(),
+        favorites: db
+            .all_favorites()?
+            .into_iter()
+            .map(|(path, liked_at)| FavoriteExport { path, liked_at })
+            .collect(),
+    })
+}
+
+pub fn to_json(export: &Export) -> String {
+    serde_json::to_string_pretty(export).unwrap_or_default()
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_table(header: &[&str], rows: impl Iterator<Item = Vec<String>>) -> String {
+    let mut out = header.join(",");
+    out.push('\n');
+    for row in rows {
+        let fields: Vec<String> = row.iter().map(|f| csv_field(f)).collect();
+        out.push_str(&fields.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+fn opt<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(|v| v.to_string()).unwrap_or_default()
+}
+
+/// One CSV document per section, as `(file name, contents)`.
+pub fn to_csv(export: &Export) -> Vec<(&'static str, String)> {
+    let playlist_rows = export.playlists.iter().flat_map(|playlist| {
+        playlist.tracks.iter().enumerate().map(|(i, track)| {
+            vec![
+                playlist.name.clone(),
+                i.to_string(),
+                track.path.clone(),
+                opt(&track.note),
+                track.sort_weight.to_string(),
+            ]
+        })
+    });
+
+    vec![
+        (
+            "playlists.csv",
+            csv_table(
+                &["playlist", "position", "path", "note", "sort_weight"],
+                playlist_rows,
+            ),
+        ),
+        (
+            "history.csv",
+            csv_table(
+                &["path", "played_at"],
+                export
+                    .history
+                    .iter()
+                    .map(|h| vec![h.path.clone(), h.played_at.clone()]),
+            ),
+        ),
+        (
+            "stats.csv",
+            csv_table(
+                &["path", "play_count", "skip_count", "last_played"],
+                export.stats.iter().map(|s| {
+                    vec![
+                        s.path.clone(),
+                        s.play_count.to_string(),
+                        s.skip_count.to_string(),
+                        opt(&s.last_played),
+                    ]
+                }),
+            ),
+        ),
+        (
+            "tracks.csv",
+            csv_table(
+                &[
+                    "path",
+                    "title",
+                    "artist",
+                    "album",
+                    "track_number",
+                    "duration",
+                    "codec",
+                    "genre",
+                ],
+                export.tracks.iter().map(|t| {
+                    vec![
+                        t.path.clone(),
+                        opt(&t.title),
+                        opt(&t.artist),
+                        opt(&t.album),
+                        opt(&t.track_number),
+                        opt(&t.duration),
+                        opt(&t.codec),
+                        opt(&t.genre),
+                    ]
+                }),
+            ),
+        ),
+        (
+            "tags.csv",
+            csv_table(
+                &["path", "tag"],
+                export
+                    .tags
+                    .iter()
+                    .map(|t| vec![t.path.clone(), t.tag.clone()]),
+            ),
+        ),
+        (
+            "ratings.csv",
+            csv_table(
+                &["path", "rating", "rated_at"],
+                export
+                    .ratings
+                    .iter()
+                    .map(|r| vec![r.path.clone(), r.rating.to_string(), r.rated_at.clone()]),
+            ),
+        ),
+        (
+            "favorites.csv",
+            csv_table(
+                &["path", "liked_at"],
+                export
+                    .favorites
+                    .iter()
+                    .map(|f| vec![f.path.clone(), f.liked_at.clone()]),
+            ),
+        ),
+    ]
+}
+
+/// Writes the export to `path`: a directory of CSV files when `csv` is set, otherwise a
+/// JSON file.
+pub fn write_export(export: &Export, path: &Path, csv: bool) -> io::Result<()> {
+    if csv {
+        fs::create_dir_all(path)?;
+        for (file, contents) in to_csv(export) {
+            fs::write(path.join(file), contents)?;
+        }
+        Ok(())
+    } else {
+        fs::write(path, to_json(export))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::TrackMetadata;
+
+    #[test]
+    fn test_export() {
+        let db = Database::in_memory().unwrap();
+        db.add_track_to_playlist("mix", "/music/a.mp3").unwrap();
+        db.set_entry_note("mix", 0, Some("opener, \"loud\""))
+            .unwrap();
+        db.record_play_completion("/music/a.mp3").unwrap();
+        db.add_tag("/music/a.mp3", "rock").unwrap();
+        db.set_rating("/music/a.mp3", Some(4)).unwrap();
+        db.set_favorite("/music/b.mp3", true).unwrap();
+        db.upsert_track(&TrackMetadata {
+            path: "/music/a.mp3".to_string(),
+            title: Some("Song".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let export = collect(&db).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&to_json(&export)).unwrap();
+        assert_eq!(json["format_version"], 2);
+        assert_eq!(json["playlists"][0]["name"], "mix");
+        assert_eq!(
+            json["playlists"][0]["tracks"][0]["note"],
+            "opener, \"loud\""
+        );
+        assert_eq!(json["history"].as_array().unwrap().len(), 1);
+        assert_eq!(json["stats"][0]["play_count"], 1);
+        assert_eq!(json["tracks"][0]["title"], "Song");
+        assert_eq!(json["tracks"][0]["artist"], serde_json::Value::Null);
+        assert_eq!(json["tags"][0]["tag"], "rock");
+        assert_eq!(json["ratings"][0]["path"], "/music/a.mp3");
+        assert_eq!(json["ratings"][0]["rating"], 4);
+        assert_eq!(json["favorites"][0]["path"], "/music/b.mp3");
+        assert!(json["favorites"][0]["liked_at"].is_string());
+
+        let csv = to_csv(&export);
+        assert_eq!(
+            csv[0].1,
+            "playlist,position,path,note,sort_weight\nmix,0,/music/a.mp3,\"opener, \"\"loud\"\"\",0\n"
+        );
+        let ratings = &csv
+            .iter()
+            .find(|(file, _)| *file == "ratings.csv")
+            .unwrap()
+            .1;
+        assert!(ratings.starts_with("path,rating,rated_at\n/music/a.mp3,4,"));
+    }
+}
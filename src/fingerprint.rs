@@ -0,0 +1,227 @@
+use std::fs::File;
+use std::path::Path;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Length of one analysis frame, in seconds.
+const FRAME_SECONDS: f64 = 0.1;
+
+/// Only the start of a track is analysed; enough to tell recordings apart.
+const MAX_SECONDS: f64 = 30.0;
+
+/// Fingerprints at least this similar are treated as the same recording.
+pub const MATCH_THRESHOLD: f64 = 0.9;
+
+/// Loudness and brightness of one frame of the mono downmix.
+#[derive(Debug, Clone, Copy, Default)]
+struct Frame {
+    energy: f32,
+    zero_crossings: f32,
+}
+
+/// Decodes the first 30 seconds of `path` and reduces them to a bit string, in the
+/// spirit of chromaprint: for each pair of neighbouring 100 ms frames, one bit says
+/// whether it got louder and one whether it got brighter (more zero crossings).
+/// Only the direction of change is kept, so volume, bitrate and codec don't matter.
+pub fn fingerprint(path: &str) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+    let file = Box::new(File::open(path)?);
+    let mss = MediaSourceStream::new(file, Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = Path::new(path).extension() {
+        hint.with_extension(ext.to_str().unwrap_or(""));
+    }
+
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut format = probed.format;
+    let track = format.default_track().ok_or("No default track found")?;
+    let track_id = track.id;
+    let rate = track
+        .codec_params
+        .sample_rate
+        .ok_or("Unknown sample rate")?;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let frame_len = ((rate as f64 * FRAME_SECONDS) as usize).max(1);
+    let max_frames = (MAX_SECONDS / FRAME_SECONDS) as usize;
+    let mut frames = Vec::with_capacity(max_frames);
+    let mut current = Frame::default();
+    let mut filled = 0;
+    let mut last = 0.0f32;
+
+    while frames.len() < max_frames {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(_) => break,
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(_) => continue,
+        };
+
+        let spec = *decoded.spec();
+        let channels = spec.channels.count().max(1);
+        let mut buf = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buf.copy_interleaved_ref(decoded);
+
+        for chunk in buf.samples().chunks(channels) {
+            let sample = chunk.iter().sum::<f32>() / channels as f32;
+            current.energy += sample * sample;
+            if (sample >= 0.0) != (last >= 0.0) {
+                current.zero_crossings += 1.0;
+            }
+            last = sample;
+            filled += 1;
+            if filled == frame_len {
+                frames.push(current);
+                current = Frame::default();
+                filled = 0;
+                if frames.len() == max_frames {
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(pack(&frames))
+}
+
+fn pack(frames: &[Frame]) -> Vec<u32> {
+    let bits = frames.windows(2).flat_map(|pair| {
+        [
+            pair[1].energy > pair[0].energy,
+            pair[1].zero_crossings > pair[0].zero_crossings,
+        ]
+    });
+
+    let mut words = Vec::new();
+    for (i, bit) in bits.enumerate() {
+        if i % 32 == 0 {
+            words.push(0);
+        }
+        if bit {
+            *words.last_mut().expect("pushed above") |= 1 << (i % 32);
+        }
+    }
+    words
+}
+
+/// Fraction of matching bits out of the longer fingerprint. Whatever only one of them
+/// covers counts as differing, so a short clip doesn't match the start of a full track.
+/// 0 when either is empty.
+pub fn similarity(a: &[u32], b: &[u32]) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    let len = a.len().max(b.len());
+    let overhang = len - a.len().min(b.len());
+    let differing: u32 = a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum();
+    1.0 - (differing as usize + overhang * 32) as f64 / (len * 32) as f64
+}
+
+/// Silence and very short files produce (nearly) all-zero fingerprints that would match
+/// each other, so they are not used for matching.
+pub fn is_distinctive(fp: &[u32]) -> bool {
+    let bits = fp.len() * 32;
+    let set: u32 = fp.iter().map(|word| word.count_ones()).sum();
+    fp.len() >= 2 && set as usize * 10 >= bits
+}
+
+/// Fingerprints are stored as little-endian bytes.
+pub fn to_bytes(fp: &[u32]) -> Vec<u8> {
+    fp.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+pub fn from_bytes(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(4)
+        .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata;
+
+    /// `seconds` of 8 kHz mono wav of a tone whose loudness and pitch wander, scaled by
+    /// `gain`.
+    fn write_tone(path: &Path, seconds: f32, gain: f32, seed: u32) {
+        let rate = 8000u32;
+        let samples: Vec<i16> = (0..(rate as f32 * seconds) as u32)
+            .map(|i| {
+                let t = i as f32 / rate as f32;
+                let step = (i / 800 + seed * 1000).wrapping_mul(2_654_435_761) >> 16;
+                let loudness = 0.2 + (step % 7) as f32 / 10.0;
+                let pitch = 200.0 + (step % 5) as f32 * 150.0;
+                (gain * loudness * (t * pitch * std::f32::consts::TAU).sin() * 20000.0) as i16
+            })
+            .collect();
+
+        metadata::write_wav(path, &samples);
+    }
+
+    #[test]
+    fn test_fingerprint_matching() {
+        let dir = std::env::temp_dir();
+        let original = dir.join("eigenplayer_fp_a.wav");
+        let quieter = dir.join("eigenplayer_fp_b.wav");
+        let other = dir.join("eigenplayer_fp_c.wav");
+        write_tone(&original, 4.0, 1.0, 1);
+        write_tone(&quieter, 4.0, 0.5, 1);
+        write_tone(&other, 4.0, 1.0, 99);
+
+        let a = fingerprint(original.to_str().unwrap()).unwrap();
+        let b = fingerprint(quieter.to_str().unwrap()).unwrap();
+        let c = fingerprint(other.to_str().unwrap()).unwrap();
+
+        assert!(is_distinctive(&a));
+        assert!(similarity(&a, &b) >= MATCH_THRESHOLD);
+        assert!(similarity(&a, &c) < MATCH_THRESHOLD);
+        assert_eq!(from_bytes(&to_bytes(&a)), a);
+
+        for path in [original, quieter, other] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_silence_is_not_distinctive() {
+        assert!(!is_distinctive(&pack(&[Frame::default(); 100])));
+        assert_eq!(similarity(&[], &[1]), 0.0);
+    }
+
+    #[test]
+    fn test_prefix_clip_does_not_match() {
+        let dir = std::env::temp_dir();
+        let full = dir.join("eigenplayer_fp_full.wav");
+        let clip = dir.join("eigenplayer_fp_clip.wav");
+        write_tone(&full, 8.0, 1.0, 1);
+        // 33 frames: exactly two words, all of them equal to the full track's first two
+        write_tone(&clip, 3.3, 1.0, 1);
+
+        let a = fingerprint(full.to_str().unwrap()).unwrap();
+        let b = fingerprint(clip.to_str().unwrap()).unwrap();
+        assert!(is_distinctive(&b));
+        assert_eq!(b[..], a[..b.len()]);
+        assert!(similarity(&a, &b) < MATCH_THRESHOLD);
+        assert_eq!(similarity(&a, &b), similarity(&b, &a));
+
+        for path in [full, clip] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
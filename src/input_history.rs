@@ -0,0 +1,121 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// How many lines are kept; older ones are dropped when the file is loaded.
+const MAX_ENTRIES: usize = 1000;
+
+/// Lines typed at the REPL prompt, kept across sessions in a plain text file (one line
+/// per entry, oldest first) and recalled with `!!` and `!n`.
+#[derive(Debug, Default)]
+pub struct InputHistory {
+    entries: Vec<String>,
+    /// Where new entries are appended; None keeps them for this session only.
+    path: Option<PathBuf>,
+}
+
+impl InputHistory {
+    /// Reads the history in `path` (a missing file is an empty history) and appends new
+    /// entries to it from then on.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut entries: Vec<String> = match fs::read_to_string(path) {
+            Ok(text) => text.lines().map(str::to_string).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        if entries.len() > MAX_ENTRIES {
+            entries.drain(..entries.len() - MAX_ENTRIES);
+            fs::write(path, entries.join("\n") + "\n")?;
+        }
+        Ok(Self {
+            entries,
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    /// Records a line, unless it's blank or repeats the one before it.
+    pub fn push(&mut self, line: &str) -> io::Result<()> {
+        let line = line.trim();
+        if line.is_empty() || self.entries.last().is_some_and(|last| last == line) {
+            return Ok(());
+        }
+        self.entries.push(line.to_string());
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = fs::File::options().create(true).append(true).open(path)?;
+        writeln!(file, "{}", line)
+    }
+
+    /// Every entry, oldest first; `!n` counts from 1 in this order.
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// Replaces a leading `!!` (the last line) or `!n` (entry n) with the line it
+    /// recalls, keeping whatever follows: `!! --resume` reruns the last command with
+    /// one more argument. Ok(None) when `input` doesn't start with either.
+    pub fn expand(&self, input: &str) -> Result<Option<String>, String> {
+        let (entry, rest) = if let Some(rest) = input.strip_prefix("!!") {
+            let last = self.entries.last().ok_or("history is empty")?;
+            (last, rest)
+        } else if let Some(tail) = input.strip_prefix('!') {
+            let digits = tail.len() - tail.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            if digits == 0 {
+                return Ok(None);
+            }
+            let (number, rest) = tail.split_at(digits);
+            let entry = number
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| n.checked_sub(1))
+                .and_then(|index| self.entries.get(index))
+                .ok_or_else(|| format!("!{}: no such history entry", number))?;
+            (entry, rest)
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(format!("{}{}", entry, rest)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let mut history = InputHistory::default();
+        assert_eq!(history.expand("!!"), Err("history is empty".to_string()));
+        history.push("load road trip").unwrap();
+        history.push("status").unwrap();
+        history.push("status").unwrap();
+        assert_eq!(history.entries().len(), 2);
+
+        assert_eq!(history.expand("!!").unwrap().as_deref(), Some("status"));
+        assert_eq!(
+            history.expand("!1 --resume").unwrap().as_deref(),
+            Some("load road trip --resume")
+        );
+        assert!(history.expand("!9").is_err());
+        assert_eq!(history.expand("!x"), Ok(None));
+        assert_eq!(history.expand("play"), Ok(None));
+    }
+
+    #[test]
+    fn test_persists() {
+        let path = std::env::temp_dir().join("eigenplayer_input_history_test");
+        let _ = fs::remove_file(&path);
+
+        let mut history = InputHistory::load(&path).unwrap();
+        history.push("play ~/a.flac").unwrap();
+        history.push("volume 0.5").unwrap();
+        let reloaded = InputHistory::load(&path).unwrap();
+        assert_eq!(reloaded.entries(), ["play ~/a.flac", "volume 0.5"]);
+
+        fs::remove_file(path).unwrap();
+    }
+}
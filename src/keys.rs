@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+
+/// Keys with a name longer than one character, as written in bindings.
+const NAMED_KEYS: &[&str] = &[
+    "space",
+    "enter",
+    "tab",
+    "esc",
+    "backspace",
+    "delete",
+    "insert",
+    "up",
+    "down",
+    "left",
+    "right",
+    "home",
+    "end",
+    "pgup",
+    "pgdown",
+];
+
+/// Brings a key or chord into the form bindings are stored under: modifiers first, in
+/// the order `ctrl`, `alt`, `shift`, then the key, joined with `+` (`Ctrl+Shift+Up` is
+/// `ctrl+shift+up`). Single characters are kept as typed, so `N` and `n` are different
+/// keys; `+` on its own is written `+` or e.g. `ctrl++`. None if it isn't a key.
+pub fn normalize_key(key: &str) -> Option<String> {
+    let (modifiers, name) = if key == "+" {
+        ("", "+")
+    } else if let Some(modifiers) = key.strip_suffix("++") {
+        (modifiers, "+")
+    } else {
+        key.rsplit_once('+').unwrap_or(("", key))
+    };
+
+    let name = match name {
+        " " => "space".to_string(),
+        _ if name.chars().count() == 1 => name.to_string(),
+        _ => {
+            let lower = name.to_lowercase();
+            let function_key = lower
+                .strip_prefix('f')
+                .and_then(|n| n.parse::<u8>().ok())
+                .is_some_and(|n| (1..=12).contains(&n));
+            match lower.as_str() {
+                "escape" => "esc".to_string(),
+                "return" => "enter".to_string(),
+                _ if function_key || NAMED_KEYS.contains(&lower.as_str()) => lower,
+                _ => return None,
+            }
+        }
+    };
+
+    let mut held = [false; 3];
+    if !modifiers.is_empty() {
+        for modifier in modifiers.split('+') {
+            match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => held[0] = true,
+                "alt" | "meta" => held[1] = true,
+                "shift" => held[2] = true,
+                _ => return None,
+            }
+        }
+    }
+
+    let mut normalized = String::new();
+    for (held, modifier) in held.iter().zip(["ctrl+", "alt+", "shift+"]) {
+        if *held {
+            normalized.push_str(modifier);
+        }
+    }
+    normalized.push_str(&name);
+    Some(normalized)
+}
+
+/// Bindings every player starts with, unless config.lua bound the key to something else.
+pub const DEFAULT_BINDINGS: &[(&str, &str)] = &[
+    ("space", "toggle"),
+    ("n", "next"),
+    ("p", "prev"),
+    ("up", "volume +0.05"),
+    ("down", "volume -0.05"),
+    ("right", "seek +5"),
+    ("left", "seek -5"),
+];
+
+/// What each key does in the single-key input layer: a command line, run as if typed.
+/// Lua functions bound to a key are registered as a command first (see
+/// `core:bind_key`), so a binding is always plain text.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Keymap {
+    bindings: BTreeMap<String, String>,
+}
+
+impl Keymap {
+    /// Binds `key` to `command`, replacing what it did before. Returns the normalized key,
+    /// or None (and changes nothing) if `key` isn't one.
+    pub fn bind(&mut self, key: &str, command: &str) -> Option<String> {
+        let key = normalize_key(key)?;
+        self.bindings.insert(key.clone(), command.to_string());
+        Some(key)
+    }
+
+    /// Adds the `DEFAULT_BINDINGS` for keys that aren't bound yet.
+    pub fn bind_defaults(&mut self) {
+        for (key, command) in DEFAULT_BINDINGS {
+            if self.lookup(key).is_none() {
+                self.bind(key, command);
+            }
+        }
+    }
+
+    /// Removes the binding of `key`, returning the command it had.
+    pub fn unbind(&mut self, key: &str) -> Option<String> {
+        self.bindings.remove(&normalize_key(key)?)
+    }
+
+    pub fn lookup(&self, key: &str) -> Option<&str> {
+        self.bindings
+            .get(&normalize_key(key)?)
+            .map(|command| command.as_str())
+    }
+
+    /// Every binding as `(key, command)`, sorted by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.bindings
+            .iter()
+            .map(|(key, command)| (key.as_str(), command.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_key() {
+        assert_eq!(
+            normalize_key("Shift+Ctrl+Up").as_deref(),
+            Some("ctrl+shift+up")
+        );
+        assert_eq!(normalize_key(" ").as_deref(), Some("space"));
+        assert_eq!(normalize_key("N").as_deref(), Some("N"));
+        assert_eq!(normalize_key("ctrl++").as_deref(), Some("ctrl++"));
+        assert_eq!(normalize_key("+").as_deref(), Some("+"));
+        assert_eq!(normalize_key("Escape").as_deref(), Some("esc"));
+        assert_eq!(normalize_key("F12").as_deref(), Some("f12"));
+        assert_eq!(normalize_key("f13"), None);
+        assert_eq!(normalize_key("hyper+q"), None);
+        assert_eq!(normalize_key("spacebar"), None);
+        assert_eq!(normalize_key(""), None);
+    }
+
+    #[test]
+    fn test_keymap() {
+        let mut keys = Keymap::default();
+        assert_eq!(keys.bind("Ctrl+q", "quit").as_deref(), Some("ctrl+q"));
+        assert!(keys.bind("bogus", "quit").is_none());
+        keys.bind("space", "pause");
+        keys.bind(" ", "play");
+        assert_eq!(keys.lookup("control+q"), Some("quit"));
+        assert_eq!(keys.lookup("space"), Some("play"));
+        assert_eq!(
+            keys.iter().collect::<Vec<_>>(),
+            vec![("ctrl+q", "quit"), ("space", "play")]
+        );
+        assert_eq!(keys.unbind("ctrl+q").as_deref(), Some("quit"));
+        assert_eq!(keys.lookup("ctrl+q"), None);
+
+        keys.bind_defaults();
+        assert_eq!(keys.lookup("space"), Some("play"));
+        assert_eq!(keys.lookup("Up"), Some("volume +0.05"));
+    }
+}
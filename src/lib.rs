@@ -1,10 +1,31 @@
 pub mod audio;
+pub mod command_builder;
 pub mod commands;
+pub mod config;
 pub mod core;
 pub mod db;
+pub mod db_writer;
 pub mod eq;
+pub mod export;
+pub mod fingerprint;
+pub mod input_history;
+pub mod keys;
 pub mod lua;
+pub mod metadata;
+pub mod params;
+pub mod paths;
+pub mod player;
+pub mod plugin;
+pub mod podcast;
 pub mod property;
 pub mod repl;
+pub mod scan;
+pub mod scheduler;
+pub mod style;
+pub mod terminal;
+pub mod tokenize;
+pub mod undo;
+pub mod watch;
+pub mod xspf;
 
 pub use core::*;
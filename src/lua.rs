@@ -1,6 +1,20 @@
-use crate::core::{Core, PropertyValue};
-use mlua::{Lua, Result, UserData, UserDataMethods, Value};
-use std::sync::{Arc, Mutex};
+use crate::config;
+use crate::core::{
+    Capability, Command, Core, CoreError, PropertyCallback, PropertyValue, Rate, SCRIPT_MESSAGE,
+    SharedCore, TICK_INTERVAL,
+};
+use crate::db::{Database, SharedDatabase, StatsPeriod};
+use crate::keys::normalize_key;
+use crate::metadata::{TrackMetadata, probe_file};
+use crate::scheduler::{TimerCallback, TimerId};
+use crate::style::{Role, escape_code};
+use mlua::{
+    AnyUserData, FromLua, Function, Lua, LuaSerdeExt, MultiValue, ObjectLike, Result, Table,
+    UserData, UserDataMethods, Value,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::Duration;
 use tracing::*;
 
 /// Parses a Lua table into a Vec<String>, expecting an array-like table with string values.
@@ -23,11 +37,11 @@ fn parse_eq_band_list(table: &mlua::Table) -> Result<Vec<[f32; 4]>> {
         let (_, val) = pair?;
         if let Value::Table(band_table) = val {
             let mut band = [0.0f32; 4];
-            for i in 0..4 {
+            for (i, slot) in band.iter_mut().enumerate() {
                 if let Ok(val) = band_table.get::<f64>(i + 1) {
-                    band[i] = val as f32;
+                    *slot = val as f32;
                 } else {
-                    band[i] = 0.0;
+                    *slot = 0.0;
                 }
             }
             bands.push(band);
@@ -36,6 +50,46 @@ fn parse_eq_band_list(table: &mlua::Table) -> Result<Vec<[f32; 4]>> {
     Ok(bands)
 }
 
+/// Converts command parameters passed from Lua into JSON for
+/// `Core::execute_command_structured`: sequences stay positional, tables with string keys
+/// become named parameters.
+fn params_to_json(value: Value) -> Result<serde_json::Value> {
+    let scalar = |value: Value| -> Result<serde_json::Value> {
+        match value {
+            Value::String(s) => Ok(serde_json::Value::from(s.to_str()?.to_string())),
+            Value::Boolean(b) => Ok(serde_json::Value::from(b)),
+            Value::Integer(n) => Ok(serde_json::Value::from(n)),
+            Value::Number(n) => Ok(serde_json::Value::from(n)),
+            other => Err(mlua::Error::RuntimeError(format!(
+                "unsupported command parameter: {:?}",
+                other
+            ))),
+        }
+    };
+
+    match value {
+        Value::Nil => Ok(serde_json::Value::Null),
+        Value::Table(t) => {
+            let positional = t.raw_len();
+            let mut named = serde_json::Map::new();
+            for pair in t.clone().pairs::<Value, Value>() {
+                if let (Value::String(key), val) = pair? {
+                    named.insert(key.to_str()?.to_string(), scalar(val)?);
+                }
+            }
+            if named.is_empty() {
+                (1..=positional)
+                    .map(|i| scalar(t.raw_get(i)?))
+                    .collect::<Result<Vec<_>>>()
+                    .map(serde_json::Value::Array)
+            } else {
+                Ok(serde_json::Value::Object(named))
+            }
+        }
+        other => scalar(other),
+    }
+}
+
 /// Converts a Lua Value to a PropertyValue based on the property name.
 /// For table values, dispatches to specific parsers based on the name.
 /// Fails loudly for unsupported table property names.
@@ -47,10 +101,10 @@ fn value_to_property(name: &str, value: Value) -> Result<PropertyValue> {
         Value::Number(n) => Ok(PropertyValue::Float(n as f32)),
 	Value::Integer(n) => Ok(PropertyValue::Int(n as i32)),
         Value::Table(ref t) => match name {
-            "playlist" => Ok(PropertyValue::StringList(parse_string_list(t)?)),
-            "eq_bands" => Ok(PropertyValue::EqBandList(parse_eq_band_list(t)?)),
+            "playlist.tracks" => Ok(PropertyValue::StringList(parse_string_list(t)?)),
+            "eq.bands" => Ok(PropertyValue::EqBandList(parse_eq_band_list(t)?)),
             _ => Err(mlua::Error::RuntimeError(format!(
-                "Unsupported table property: '{}'. Supported table properties are: playlist, eq_bands",
+                "Unsupported table property: '{}'. Supported table properties are: playlist.tracks, eq.bands",
                 name
             ))),
         },
@@ -60,78 +114,438 @@ fn value_to_property(name: &str, value: Value) -> Result<PropertyValue> {
     }
 }
 
-pub struct LuaCore(pub Arc<Mutex<Core>>);
+/// Registry key of the core that Lua code currently running on behalf of the core itself
+/// should use; see `with_core`.
+const ACTIVE_CORE: &str = "eigenplayer.active_core";
+
+/// Runs `f` with `core` as the core every `core` handle in `lua` talks to. The core calls
+/// into Lua (commands, timers) while its lock is held, so going through the shared
+/// handle there would deadlock; any code that enters Lua with the core locked goes
+/// through here.
+pub fn with_core<R>(lua: &Lua, core: &mut Core, f: impl FnOnce() -> Result<R>) -> Result<R> {
+    lua.scope(|scope| {
+        let active = scope.create_any_userdata_ref_mut(core)?;
+        let previous: Value = lua.named_registry_value(ACTIVE_CORE)?;
+        lua.set_named_registry_value(ACTIVE_CORE, &active)?;
+        let result = f();
+        lua.set_named_registry_value(ACTIVE_CORE, previous)?;
+        result
+    })
+}
+
+/// Timers, commands, key bindings and observers registered while `PluginHost` loads a
+/// plugin, so unloading it can take them away again. Present as Lua app data only during
+/// the load.
+#[derive(Default)]
+pub struct Registrations {
+    pub timers: Vec<TimerId>,
+    pub commands: Vec<String>,
+    pub keys: Vec<String>,
+    pub observers: Vec<(String, PropertyCallback)>,
+}
+
+fn record(lua: &Lua, f: impl FnOnce(&mut Registrations)) {
+    if let Some(mut registrations) = lua.app_data_mut::<Registrations>() {
+        f(&mut registrations);
+    }
+}
+
+/// Wraps a Lua function as a scheduler callback. Errors are logged; an interval keeps
+/// running after one.
+fn lua_timer(lua: &Lua, callback: Function) -> TimerCallback {
+    let weak = lua.weak();
+    Arc::new(move |core| {
+        let Some(lua) = weak.try_upgrade() else {
+            return;
+        };
+        if let Err(e) = with_core(&lua, core, || callback.call::<()>(())) {
+            warn!("[Lua] Timer callback failed: {}", e);
+        }
+    })
+}
+
+fn property_to_value(lua: &Lua, value: &PropertyValue) -> Result<Value> {
+    match value {
+        PropertyValue::String(s) => Ok(Value::String(lua.create_string(s)?)),
+        PropertyValue::Bool(b) => Ok(Value::Boolean(*b)),
+        PropertyValue::Float(f) => Ok(Value::Number(*f as f64)),
+        PropertyValue::Int(i) => Ok(Value::Integer(*i as i64)),
+        PropertyValue::StringList(list) => {
+            let table = lua.create_table()?;
+            for (i, item) in list.iter().enumerate() {
+                table.set(i + 1, item.clone())?;
+            }
+            Ok(Value::Table(table))
+        }
+        PropertyValue::EqBandList(bands) => {
+            let table = lua.create_table()?;
+            for (i, band) in bands.iter().enumerate() {
+                let band_table = lua.create_table()?;
+                for (j, &val) in band.iter().enumerate() {
+                    band_table.set(j + 1, val as f64)?;
+                }
+                table.set(i + 1, band_table)?;
+            }
+            Ok(Value::Table(table))
+        }
+    }
+}
+
+/// The timer of the fade `core:fade_volume` last started.
+struct VolumeFade(Option<TimerId>);
+
+type ObserverFn = dyn Fn(&PropertyValue, &mut Core) + Send + Sync;
+
+/// Observers added with `core:observe`, by the id handed to the script.
+#[derive(Default)]
+struct Observers {
+    next_id: u64,
+    active: HashMap<u64, (String, PropertyCallback)>,
+}
+
+/// Version of the scripting surface (`core`, `db`, `http`, `json`, ...), bumped whenever
+/// something is added or changes. Scripts check it with `require_api`.
+pub const API_VERSION: u32 = 2;
+
+/// Bindings that are deprecated keep working, with a warning, until this version.
+const COMPAT_UNTIL: u32 = 3;
+
+/// Names of the deprecated bindings a Lua state has already warned about.
+#[derive(Default)]
+struct DeprecationWarnings(HashSet<&'static str>);
+
+/// The compat shim: logs, once per Lua state, that `old` is on its way out.
+fn deprecated(lua: &Lua, old: &'static str, new: &str) {
+    if lua.app_data_ref::<DeprecationWarnings>().is_none() {
+        lua.set_app_data(DeprecationWarnings::default());
+    }
+    let first = lua
+        .app_data_mut::<DeprecationWarnings>()
+        .is_some_and(|mut warned| warned.0.insert(old));
+    if first {
+        warn!(
+            "[Lua] {} is deprecated and goes away in API version {}, use {}",
+            old, COMPAT_UNTIL, new
+        );
+    }
+}
+
+/// The `eigen` global: `eigen.api_version`, `eigen.version` (the player's) and
+/// `eigen.require_api(min)`, also available as plain `require_api`. A plugin written
+/// against a newer API than the player has fails right at the top instead of halfway
+/// through:
+///
+/// ```lua
+/// require_api(2)
+/// ```
+pub fn register_eigen(lua: &Lua) -> Result<()> {
+    let eigen = lua.create_table()?;
+    eigen.set("api_version", API_VERSION)?;
+    eigen.set("version", env!("CARGO_PKG_VERSION"))?;
+    let require_api = lua.create_function(|_, min: u32| {
+        if min > API_VERSION {
+            return Err(mlua::Error::RuntimeError(format!(
+                "this script needs scripting API version {}, the player provides {}",
+                min, API_VERSION
+            )));
+        }
+        Ok(API_VERSION)
+    })?;
+    eigen.set("require_api", &require_api)?;
+    lua.globals().set("require_api", require_api)?;
+    lua.globals().set("eigen", eigen)
+}
+
+pub struct LuaCore(pub SharedCore);
+
+/// Runs `f` on the core behind the `core` global, for helpers that aren't its methods.
+fn write_core<R>(lua: &Lua, f: impl FnOnce(&mut Core) -> R) -> Result<R> {
+    let core: AnyUserData = lua.globals().get("core")?;
+    let core = core.borrow::<LuaCore>()?;
+    core.write(lua, f)
+}
+
+impl LuaCore {
+    fn read<R>(&self, lua: &Lua, f: impl FnOnce(&Core) -> R) -> Result<R> {
+        match lua.named_registry_value::<Option<AnyUserData>>(ACTIVE_CORE)? {
+            Some(active) => active.borrow_scoped::<Core, R>(f),
+            None => Ok(f(&self.0.read().unwrap())),
+        }
+    }
+
+    fn write<R>(&self, lua: &Lua, f: impl FnOnce(&mut Core) -> R) -> Result<R> {
+        match lua.named_registry_value::<Option<AnyUserData>>(ACTIVE_CORE)? {
+            Some(active) => active.borrow_mut_scoped::<Core, R>(f),
+            None => Ok(f(&mut self.0.write().unwrap())),
+        }
+    }
+
+    /// Subscribes `observer` and remembers it for `unobserve` and plugin unloading;
+    /// returns the id scripts get.
+    fn add_observer(
+        &self,
+        lua: &Lua,
+        name: String,
+        rate: Rate,
+        observer: PropertyCallback,
+    ) -> Result<u64> {
+        self.write(lua, |core| {
+            core.subscribe_property(&name, rate, Arc::clone(&observer))
+        })?
+        .map_err(mlua::Error::external)?;
+        record(lua, |registrations| {
+            registrations
+                .observers
+                .push((name.clone(), Arc::clone(&observer)))
+        });
+        if lua.app_data_ref::<Observers>().is_none() {
+            lua.set_app_data(Observers::default());
+        }
+        let mut observers = lua.app_data_mut::<Observers>().expect("set above");
+        observers.next_id += 1;
+        let id = observers.next_id;
+        observers.active.insert(id, (name, observer));
+        Ok(id)
+    }
+}
 
 impl UserData for LuaCore {
     fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
-        methods.add_method_mut(
+        methods.add_method(
             "execute_command",
-            |_, lua_core: &mut LuaCore, (name, params): (String, Vec<String>)| {
-                let mut core = lua_core.0.lock().unwrap();
-                core.execute_command(&name, params);
-                Ok(())
+            |lua, lua_core: &LuaCore, (name, params): (String, Value)| {
+                let params = params_to_json(params)?;
+                lua_core
+                    .write(lua, |core| {
+                        core.execute_command_structured(&name, &params, Capability::Admin)
+                    })?
+                    .map_err(mlua::Error::external)
             },
         );
 
-        methods.add_method_mut(
+        methods.add_method(
             "set_property",
-            |_lua, lua_core: &mut LuaCore, (name, value): (String, Value)| {
-                let mut core = lua_core.0.lock().unwrap();
+            |lua, lua_core: &LuaCore, (name, value): (String, Value)| {
                 let prop_value = value_to_property(&name, value)?;
-                core.set_property(&name, prop_value);
-                Ok(())
+                let prop_value =
+                    config::check(&name, &prop_value).map_err(mlua::Error::RuntimeError)?;
+                lua_core
+                    .write(lua, |core| core.set_property(&name, prop_value))?
+                    .map_err(mlua::Error::external)
             },
         );
 
-        methods.add_method("get_property", |lua, lua_core: &LuaCore, name: String| {
-            let core = lua_core.0.lock().unwrap();
-            match core.get_property(&name) {
-                Some(PropertyValue::String(s)) => Ok(Value::String(lua.create_string(s)?)),
-                Some(PropertyValue::Bool(b)) => Ok(Value::Boolean(*b)),
-                Some(PropertyValue::Float(f)) => Ok(Value::Number(*f as f64)),
-		Some(PropertyValue::Int(i)) => Ok(Value::Integer(*i as i64)),
-                Some(PropertyValue::StringList(list)) => {
-                    let table = lua.create_table()?;
-                    for (i, item) in list.iter().enumerate() {
-                        table.set(i + 1, item.clone())?;
+        methods.add_method(
+            "set_or_create",
+            |lua, lua_core: &LuaCore, (name, value): (String, Value)| {
+                let prop_value = value_to_property(&name, value)?;
+                let prop_value =
+                    config::check(&name, &prop_value).map_err(mlua::Error::RuntimeError)?;
+                lua_core.write(lua, |core| core.set_or_create(&name, prop_value))
+            },
+        );
+
+        methods.add_method(
+            "get_property",
+            |lua, lua_core: &LuaCore, name: String| match lua_core
+                .read(lua, |core| core.get_property(&name).cloned())?
+            {
+                Some(value) => property_to_value(lua, &value),
+                None => Ok(Value::Nil),
+            },
+        );
+
+        // core:observe("audio.position", function(value, name) ... end); changes reaching
+        // a Lua observer are coalesced to one call per tick with the latest value, so a
+        // slow script can't hold up the core. Pass "immediate" to see every change. Returns
+        // an id for `unobserve`.
+        methods.add_method(
+            "observe",
+            |lua,
+             lua_core: &LuaCore,
+             (name, callback, mode): (String, Function, Option<String>)| {
+                let rate = match mode.as_deref() {
+                    None | Some("coalesce") => Rate::Coalesce,
+                    Some("immediate") => Rate::Immediate,
+                    Some(other) => {
+                        return Err(mlua::Error::RuntimeError(format!(
+                            "unknown observe mode '{}', expected coalesce or immediate",
+                            other
+                        )));
+                    }
+                };
+                let weak = lua.weak();
+                let property = name.clone();
+                let observer: PropertyCallback = Arc::new(move |value, core| {
+                    let Some(lua) = weak.try_upgrade() else {
+                        return;
+                    };
+                    let called = with_core(&lua, core, || {
+                        callback.call::<()>((property_to_value(&lua, value)?, property.as_str()))
+                    });
+                    if let Err(e) = called {
+                        warn!("[Lua] Observer of '{}' failed: {}", property, e);
                     }
-                    Ok(Value::Table(table))
+                });
+                lua_core.add_observer(lua, name, rate, observer)
+            },
+        );
+
+        // seconds into the current track, as last reported by the audio backend
+        methods.add_method("get_position", |lua, lua_core: &LuaCore, ()| {
+            lua_core.read(lua, |core| core.get_float("audio.position").unwrap_or(0.0))
+        });
+
+        // length of the current track in seconds, nil when it isn't known
+        methods.add_method("get_duration", |lua, lua_core: &LuaCore, ()| {
+            lua_core.read(lua, |core| {
+                core.get_float("audio.duration")
+                    .filter(|duration| *duration > 0.0)
+            })
+        });
+
+        // core:on_position(30000, function(position, track) ... end) calls back every
+        // `ms` while something is playing, not while paused. Returns the timer id.
+        methods.add_method(
+            "on_position",
+            |lua, lua_core: &LuaCore, (ms, callback): (u64, Function)| {
+                if ms == 0 {
+                    return Err(mlua::Error::RuntimeError(
+                        "on_position needs an interval above 0 ms".to_string(),
+                    ));
                 }
-                Some(PropertyValue::EqBandList(bands)) => {
-                    let table = lua.create_table()?;
-                    for (i, band) in bands.iter().enumerate() {
-                        let band_table = lua.create_table()?;
-                        for (j, &val) in band.iter().enumerate() {
-                            band_table.set(j + 1, val as f64)?;
-                        }
-                        table.set(i + 1, band_table)?;
+                let weak = lua.weak();
+                let timer: TimerCallback = Arc::new(move |core| {
+                    if core.get_bool("audio.playing") != Some(true) {
+                        return;
+                    }
+                    let Some(lua) = weak.try_upgrade() else {
+                        return;
+                    };
+                    let position = core.get_float("audio.position").unwrap_or(0.0);
+                    let track = core.get_string("playlist.current").cloned();
+                    if let Err(e) = with_core(&lua, core, || callback.call::<()>((position, track)))
+                    {
+                        warn!("[Lua] on_position callback failed: {}", e);
                     }
-                    Ok(Value::Table(table))
+                });
+                let id = lua_core.write(lua, |core| {
+                    core.schedule_repeating(Duration::from_millis(ms), timer)
+                })?;
+                record(lua, |registrations| registrations.timers.push(id));
+                Ok(id.as_u64())
+            },
+        );
+
+        // fades `audio.volume` to `target` over `ms`, replacing a fade still running; the
+        // fade is not an undo step. Returns the timer id, for `cancel_timer`.
+        methods.add_method(
+            "fade_volume",
+            |lua, lua_core: &LuaCore, (target, ms): (f32, u64)| {
+                let previous = lua.app_data_ref::<VolumeFade>().and_then(|fade| fade.0);
+                let id = lua_core.write(lua, |core| {
+                    if let Some(previous) = previous {
+                        core.cancel_timer(previous);
+                    }
+                    core.ramp_float(
+                        "audio.volume",
+                        target.clamp(0.0, 1.0),
+                        Duration::from_millis(ms),
+                    )
+                })?;
+                let id = id.map_err(mlua::Error::external)?;
+                record(lua, |registrations| registrations.timers.push(id));
+                lua.set_app_data(VolumeFade(Some(id)));
+                Ok(id.as_u64())
+            },
+        );
+
+        // calls `fn` once the current track has played to `position` seconds (or was
+        // seeked past it); forgotten when another track starts first. Returns an observer
+        // id, for `unobserve`.
+        methods.add_method(
+            "schedule_at",
+            |lua, lua_core: &LuaCore, (position, callback): (f32, Function)| {
+                let track =
+                    lua_core.read(lua, |core| core.get_string("playlist.current").cloned())?;
+                let weak = lua.weak();
+                let this: Arc<OnceLock<Weak<ObserverFn>>> = Arc::default();
+                let own = Arc::clone(&this);
+                let observer: PropertyCallback = Arc::new(move |value, core| {
+                    let reached = value.as_float().is_some_and(|at| at >= position);
+                    let same_track = core.get_string("playlist.current") == track.as_ref();
+                    if same_track && !reached {
+                        return;
+                    }
+                    if let Some(me) = own.get().and_then(Weak::upgrade) {
+                        core.unsubscribe_property("audio.position", &me);
+                    }
+                    let Some(lua) = weak.try_upgrade().filter(|_| same_track) else {
+                        return;
+                    };
+                    if let Err(e) = with_core(&lua, core, || callback.call::<()>(())) {
+                        warn!("[Lua] schedule_at callback failed: {}", e);
+                    }
+                });
+                let _ = this.set(Arc::downgrade(&observer));
+                lua_core.add_observer(lua, "audio.position".to_string(), Rate::Immediate, observer)
+            },
+        );
+
+        methods.add_method("unobserve", |lua, lua_core: &LuaCore, id: u64| {
+            let removed = lua
+                .app_data_mut::<Observers>()
+                .and_then(|mut observers| observers.active.remove(&id));
+            match removed {
+                Some((name, observer)) => {
+                    lua_core.write(lua, |core| core.unsubscribe_property(&name, &observer))
                 }
-                None => Ok(Value::Nil),
+                None => Ok(false),
             }
         });
 
-        methods.add_method("get_string", |_, lua_core: &LuaCore, name: String| {
-            let core = lua_core.0.lock().unwrap();
-            Ok(core.get_string(&name).cloned())
+        methods.add_method(
+            "emit",
+            |lua, lua_core: &LuaCore, (name, value): (String, Value)| {
+                let payload = match value {
+                    Value::Nil => PropertyValue::String(String::new()),
+                    Value::Table(ref t) => PropertyValue::StringList(parse_string_list(t)?),
+                    other => value_to_property(&name, other)?,
+                };
+                lua_core.write(lua, |core| core.emit_custom(&name, payload))
+            },
+        );
+
+        methods.add_method(
+            "list_properties",
+            |lua, lua_core: &LuaCore, namespace: Option<String>| {
+                lua_core.read(lua, |core| {
+                    core.property_names_in(namespace.as_deref().unwrap_or(""))
+                        .into_iter()
+                        .map(|name| name.to_string())
+                        .collect::<Vec<String>>()
+                })
+            },
+        );
+
+        methods.add_method("get_string", |lua, lua_core: &LuaCore, name: String| {
+            lua_core.read(lua, |core| core.get_string(&name).cloned())
         });
 
-        methods.add_method("get_bool", |_, lua_core: &LuaCore, name: String| {
-            let core = lua_core.0.lock().unwrap();
-            Ok(core.get_bool(&name))
+        methods.add_method("get_bool", |lua, lua_core: &LuaCore, name: String| {
+            lua_core.read(lua, |core| core.get_bool(&name))
         });
 
-        methods.add_method("get_float", |_, lua_core: &LuaCore, name: String| {
-            let core = lua_core.0.lock().unwrap();
-            Ok(core.get_float(&name))
+        methods.add_method("get_float", |lua, lua_core: &LuaCore, name: String| {
+            lua_core.read(lua, |core| core.get_float(&name))
         });
 
         methods.add_method(
             "get_string_list",
             |lua, lua_core: &LuaCore, name: String| {
-                let core = lua_core.0.lock().unwrap();
-                match core.get_string_list(&name) {
+                deprecated(lua, "core:get_string_list", "core:get_property");
+                let list = lua_core.read(lua, |core| core.get_string_list(&name).cloned())?;
+                match list {
                     Some(list) => {
                         let table = lua.create_table()?;
                         for (i, item) in list.iter().enumerate() {
@@ -143,15 +557,1407 @@ impl UserData for LuaCore {
                 }
             },
         );
+
+        // core:register_command("lyrics", function(args) ... end); the function gets the
+        // arguments as a list of strings and fails the command by raising an error
+        methods.add_method(
+            "register_command",
+            |lua, lua_core: &LuaCore, (name, callback): (String, Function)| {
+                let weak = lua.weak();
+                let command_name = name.clone();
+                let command = Command::new(Arc::new(move |args, core| {
+                    let failed = |message: String| CoreError::CommandFailed {
+                        command: command_name.clone(),
+                        message,
+                    };
+                    let lua = weak
+                        .try_upgrade()
+                        .ok_or_else(|| failed("the Lua state is gone".to_string()))?;
+                    with_core(&lua, core, || callback.call::<()>(args))
+                        .map_err(|e| failed(e.to_string()))
+                }));
+                lua_core.write(lua, |core| core.add_command(&name, command))?;
+                record(lua, |registrations| registrations.commands.push(name));
+                Ok(())
+            },
+        );
+
+        // timers fire from `Core::tick`; both return an id for `cancel_timer`
+        methods.add_method(
+            "add_timeout",
+            |lua, lua_core: &LuaCore, (ms, callback): (u64, Function)| {
+                let callback = lua_timer(lua, callback);
+                let id = lua_core.write(lua, |core| {
+                    core.schedule(Duration::from_millis(ms), callback)
+                })?;
+                record(lua, |registrations| registrations.timers.push(id));
+                Ok(id.as_u64())
+            },
+        );
+
+        methods.add_method(
+            "add_interval",
+            |lua, lua_core: &LuaCore, (ms, callback): (u64, Function)| {
+                if ms == 0 {
+                    return Err(mlua::Error::RuntimeError(
+                        "add_interval needs an interval above 0 ms".to_string(),
+                    ));
+                }
+                let callback = lua_timer(lua, callback);
+                let id = lua_core.write(lua, |core| {
+                    core.schedule_repeating(Duration::from_millis(ms), callback)
+                })?;
+                record(lua, |registrations| registrations.timers.push(id));
+                Ok(id.as_u64())
+            },
+        );
+
+        methods.add_method("cancel_timer", |lua, lua_core: &LuaCore, id: u64| {
+            lua_core.write(lua, |core| core.cancel_timer(TimerId::from_u64(id)))
+        });
+
+        // tags, duration and stream details of any audio file, or nil and an error message
+        methods.add_method("get_metadata", |lua, _: &LuaCore, path: String| {
+            let (meta, info) = match probe_file(&path) {
+                Ok(probed) => probed,
+                Err(e) => return Ok((Value::Nil, Some(e.to_string()))),
+            };
+            let bitrate = info.bitrate(meta.duration);
+            let table = track_to_table(lua, meta)?;
+            table.set("sample_rate", info.sample_rate)?;
+            table.set("channels", info.channels)?;
+            table.set("bits_per_sample", info.bits_per_sample)?;
+            table.set("file_size", info.file_size)?;
+            table.set("bitrate", bitrate)?;
+            let tags = lua.create_table()?;
+            for (key, value) in info.tags {
+                tags.set(key, value)?;
+            }
+            table.set("tags", tags)?;
+            Ok((Value::Table(table), None))
+        });
+
+        // messages for the user go through the frontend (see SCRIPT_MESSAGE) so they don't
+        // garble the prompt the way print() does
+        methods.add_method("print", |lua, lua_core: &LuaCore, text: String| {
+            let payload = PropertyValue::StringList(vec!["info".to_string(), text]);
+            lua_core.write(lua, |core| core.emit_custom(SCRIPT_MESSAGE, payload))
+        });
+
+        methods.add_method(
+            "notify",
+            |lua, lua_core: &LuaCore, (level, text): (String, String)| {
+                if !matches!(level.as_str(), "info" | "warn" | "error") {
+                    return Err(mlua::Error::RuntimeError(format!(
+                        "unknown level '{}', expected info, warn or error",
+                        level
+                    )));
+                }
+                let payload = PropertyValue::StringList(vec![level, text]);
+                lua_core.write(lua, |core| core.emit_custom(SCRIPT_MESSAGE, payload))
+            },
+        );
+
+        // core:bind_key("ctrl+n", "next") or core:bind_key("m", function() ... end); a
+        // function becomes the command `key:<key>`
+        methods.add_method(
+            "bind_key",
+            |lua, lua_core: &LuaCore, (key, action): (String, Value)| {
+                let key = normalize_key(&key)
+                    .ok_or_else(|| mlua::Error::RuntimeError(format!("'{}' is not a key", key)))?;
+                let command_line = match action {
+                    Value::String(s) => s.to_str()?.to_string(),
+                    Value::Function(callback) => {
+                        let name = format!("key:{}", key);
+                        // holds the state strongly: config.lua's interpreter is dropped once
+                        // the file has run, the binding has to keep it alive
+                        let state = lua.clone();
+                        let command_name = name.clone();
+                        let command = Command::new(Arc::new(move |_args, core| {
+                            with_core(&state, core, || callback.call::<()>(())).map_err(|e| {
+                                CoreError::CommandFailed {
+                                    command: command_name.clone(),
+                                    message: e.to_string(),
+                                }
+                            })
+                        }));
+                        lua_core.write(lua, |core| core.add_command(&name, command))?;
+                        record(lua, |registrations| {
+                            registrations.commands.push(name.clone())
+                        });
+                        name
+                    }
+                    other => {
+                        return Err(mlua::Error::RuntimeError(format!(
+                            "key '{}' must be bound to a command or a function, not a {}",
+                            key,
+                            other.type_name()
+                        )));
+                    }
+                };
+                lua_core.write(lua, |core| core.keys.bind(&key, &command_line))?;
+                record(lua, |registrations| registrations.keys.push(key));
+                Ok(())
+            },
+        );
+
+        methods.add_method("unbind_key", |lua, lua_core: &LuaCore, key: String| {
+            lua_core.write(lua, |core| core.keys.unbind(&key).is_some())
+        });
+    }
+}
+
+/// A library track as a Lua table; missing tags are left out.
+fn track_to_table(lua: &Lua, track: TrackMetadata) -> Result<Table> {
+    let table = lua.create_table()?;
+    table.set("display_name", track.display_name())?;
+    table.set("path", track.path)?;
+    table.set("title", track.title)?;
+    table.set("artist", track.artist)?;
+    table.set("album", track.album)?;
+    table.set("track_number", track.track_number)?;
+    table.set("duration", track.duration)?;
+    table.set("codec", track.codec)?;
+    table.set("genre", track.genre)?;
+    Ok(table)
+}
+
+fn parse_period(period: Option<&str>) -> Result<StatsPeriod> {
+    match period.unwrap_or("all") {
+        "week" => Ok(StatsPeriod::Week),
+        "month" => Ok(StatsPeriod::Month),
+        "all" => Ok(StatsPeriod::AllTime),
+        other => Err(mlua::Error::RuntimeError(format!(
+            "unknown period '{}', expected week, month or all",
+            other
+        ))),
+    }
+}
+
+/// The `db` global: the library, saved playlists and play history.
+///
+/// ```lua
+/// for _, t in ipairs(db:top_tracks("week", 10)) do print(t.plays, t.path) end
+/// db:save_playlist("weekly top", { "/music/a.mp3" })
+/// ```
+pub struct LuaDb(pub SharedDatabase);
+
+impl LuaDb {
+    fn with<R>(&self, f: impl FnOnce(&Database) -> rusqlite::Result<R>) -> Result<R> {
+        f(&self.0.lock().unwrap()).map_err(mlua::Error::external)
+    }
+}
+
+impl UserData for LuaDb {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method(
+            "search",
+            |lua, db: &LuaDb, (query, limit): (String, Option<usize>)| {
+                let tracks = db.with(|db| db.search_tracks(&query, limit.unwrap_or(50)))?;
+                tracks
+                    .into_iter()
+                    .map(|track| track_to_table(lua, track))
+                    .collect::<Result<Vec<_>>>()
+            },
+        );
+
+        methods.add_method("get_track", |lua, db: &LuaDb, path: String| {
+            match db.with(|db| db.get_track(&path))? {
+                Some(track) => Ok(Some(track_to_table(lua, track)?)),
+                None => Ok(None),
+            }
+        });
+
+        methods.add_method("playlists", |_, db: &LuaDb, ()| {
+            db.with(|db| db.get_all_playlists())
+        });
+
+        methods.add_method("get_playlist", |_, db: &LuaDb, name: String| {
+            db.with(|db| db.get_playlist_tracks(&name))
+        });
+
+        methods.add_method(
+            "save_playlist",
+            |_, db: &LuaDb, (name, tracks): (String, Vec<String>)| {
+                db.with(|db| db.replace_playlist(&name, &tracks))
+            },
+        );
+
+        methods.add_method(
+            "add_to_playlist",
+            |_, db: &LuaDb, (name, track): (String, String)| {
+                db.with(|db| db.add_track_to_playlist(&name, &track))
+            },
+        );
+
+        methods.add_method(
+            "remove_from_playlist",
+            |_, db: &LuaDb, (name, track): (String, String)| {
+                db.with(|db| db.remove_track_from_playlist(&name, &track))
+            },
+        );
+
+        // newest first, as { track = path, played_at = "YYYY-MM-DD HH:MM:SS" }
+        methods.add_method("history", |lua, db: &LuaDb, limit: Option<usize>| {
+            let rows = db.with(|db| db.get_play_history(limit.unwrap_or(50)))?;
+            rows.into_iter()
+                .map(|(track, played_at)| {
+                    let row = lua.create_table()?;
+                    row.set("track", track)?;
+                    row.set("played_at", played_at)?;
+                    Ok(row)
+                })
+                .collect::<Result<Vec<_>>>()
+        });
+
+        // period is "week", "month" or "all" (the default)
+        methods.add_method(
+            "top_tracks",
+            |lua, db: &LuaDb, (period, limit): (Option<String>, Option<usize>)| {
+                let period = parse_period(period.as_deref())?;
+                let rows = db.with(|db| db.top_tracks(period, limit.unwrap_or(10)))?;
+                rows.into_iter()
+                    .map(|(track, plays)| {
+                        let row = lua.create_table()?;
+                        row.set("track", track)?;
+                        row.set("plays", plays)?;
+                        Ok(row)
+                    })
+                    .collect::<Result<Vec<_>>>()
+            },
+        );
     }
 }
 
-pub fn init_lua(core: Arc<Mutex<Core>>) -> Result<Lua> {
+/// Makes `db` available to scripts as the `db` global.
+pub fn register_db(lua: &Lua, db: SharedDatabase) -> Result<()> {
+    lua.globals().set("db", LuaDb(db))
+}
+
+/// How long a Lua HTTP request may take, connecting included.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+type HttpOutcome = std::result::Result<(u16, Vec<(String, String)>, String), String>;
+
+/// Runs a request on its own thread, then calls `callback(response, nil)` or
+/// `callback(nil, error)` from the core's tick, like any other entry into Lua; the
+/// request thread never touches the core or the Lua state itself. HTTP error statuses
+/// are responses, not errors.
+fn spawn_request(
+    lua: &Lua,
+    request: ureq::Request,
+    body: Option<String>,
+    callback: Function,
+) -> Result<()> {
+    let slot: Arc<Mutex<Option<HttpOutcome>>> = Arc::default();
+    let reply = Arc::clone(&slot);
+    std::thread::spawn(move || {
+        let result = match body {
+            Some(body) => request.send_string(&body),
+            None => request.call(),
+        };
+        let outcome = match result {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => {
+                let status = response.status();
+                let headers: Vec<(String, String)> = response
+                    .headers_names()
+                    .into_iter()
+                    .filter_map(|name| {
+                        let value = response.header(&name)?.to_string();
+                        Some((name.to_lowercase(), value))
+                    })
+                    .collect();
+                response
+                    .into_string()
+                    .map(|body| (status, headers, body))
+                    .map_err(|e| e.to_string())
+            }
+            Err(e) => Err(e.to_string()),
+        };
+        *reply.lock().unwrap() = Some(outcome);
+    });
+
+    let weak = lua.weak();
+    let poll_id: Arc<OnceLock<TimerId>> = Arc::default();
+    let own_id = Arc::clone(&poll_id);
+    let poll: TimerCallback = Arc::new(move |core| {
+        let Some(outcome) = slot.lock().unwrap().take() else {
+            return;
+        };
+        if let Some(id) = own_id.get() {
+            core.cancel_timer(*id);
+        }
+        let Some(lua) = weak.try_upgrade() else {
+            return;
+        };
+        let called = with_core(&lua, core, || match outcome {
+            Ok((status, headers, body)) => {
+                let response = lua.create_table()?;
+                response.set("status", status)?;
+                response.set("body", body)?;
+                response.set("headers", lua.create_table_from(headers)?)?;
+                callback.call::<()>((response, Value::Nil))
+            }
+            Err(e) => callback.call::<()>((Value::Nil, e)),
+        });
+        if let Err(e) = called {
+            warn!("[Lua] HTTP callback failed: {}", e);
+        }
+    });
+    let id = write_core(lua, |core| core.schedule_repeating(TICK_INTERVAL, poll))?;
+    let _ = poll_id.set(id);
+    Ok(())
+}
+
+fn build_request(
+    agent: &ureq::Agent,
+    method: &str,
+    url: &str,
+    headers: Option<Table>,
+) -> Result<ureq::Request> {
+    let mut request = agent.request(method, url);
+    if let Some(headers) = headers {
+        for pair in headers.pairs::<String, String>() {
+            let (name, value) = pair?;
+            request = request.set(&name, &value);
+        }
+    }
+    Ok(request)
+}
+
+/// The `http` global. Requests never block the script; the callback runs once the
+/// response is in. `headers` is an optional table of request headers.
+///
+/// ```lua
+/// http.get("https://example.org/lyrics?q=song", function(res, err)
+///     if res and res.status == 200 then print(res.body) end
+/// end)
+/// http.post(url, body, callback, { ["content-type"] = "application/json" })
+/// ```
+pub fn register_http(lua: &Lua) -> Result<()> {
+    let agent = ureq::AgentBuilder::new().timeout(HTTP_TIMEOUT).build();
+    let http = lua.create_table()?;
+    let get_agent = agent.clone();
+    http.set(
+        "get",
+        lua.create_function(
+            move |lua, (url, callback, headers): (String, Function, Option<Table>)| {
+                let request = build_request(&get_agent, "GET", &url, headers)?;
+                spawn_request(lua, request, None, callback)
+            },
+        )?,
+    )?;
+    http.set(
+        "post",
+        lua.create_function(
+            move |lua,
+                  (url, body, callback, headers): (String, String, Function, Option<Table>)| {
+                let request = build_request(&agent, "POST", &url, headers)?;
+                spawn_request(lua, request, Some(body), callback)
+            },
+        )?,
+    )?;
+    lua.globals().set("http", http)
+}
+
+/// The `json` global. Tables that are sequences encode as arrays, others as objects;
+/// decoded arrays keep an array marker so `{}` round-trips as `[]`, and JSON null is
+/// `json.null`.
+///
+/// ```lua
+/// local state = json.decode(res.body)
+/// print(json.encode({ track = path, plays = 3 }, true)) -- pretty-printed
+/// ```
+pub fn register_json(lua: &Lua) -> Result<()> {
+    let json = lua.create_table()?;
+    json.set(
+        "encode",
+        lua.create_function(|_, (value, pretty): (Value, Option<bool>)| {
+            let encoded = if pretty.unwrap_or(false) {
+                serde_json::to_string_pretty(&value)
+            } else {
+                serde_json::to_string(&value)
+            };
+            encoded.map_err(mlua::Error::external)
+        })?,
+    )?;
+    json.set(
+        "decode",
+        lua.create_function(|lua, text: String| {
+            let parsed: serde_json::Value =
+                serde_json::from_str(&text).map_err(mlua::Error::external)?;
+            lua.to_value(&parsed)
+        })?,
+    )?;
+    json.set("null", lua.null())?;
+    lua.globals().set("json", json)
+}
+
+/// The `async` global, written in Lua on top of timers, observers and `http`: inside
+/// `async.run(fn, ...)`, `fn` runs as a coroutine that can wait without blocking anything,
+/// and the core's tick resumes it.
+///
+/// ```lua
+/// async.run(function()
+///     core:execute_command("play", { "/music/a.mp3" })
+///     async.wait_for("audio.finished", true)
+///     local res, err = async.get("https://example.org/scrobble?t=a")
+///     async.sleep(500)
+/// end)
+/// ```
+///
+/// `wait_for(name, value_or_predicate, timeout_ms)` returns the matching value, or nil
+/// and "timeout". An error in a task is reported where it was resumed from.
+const ASYNC_LUA: &str = r#"
+local core, http = core, http
+local create, resume, running, yield =
+    coroutine.create, coroutine.resume, coroutine.running, coroutine.yield
+local async = {}
+
+local function step(task, ...)
+    local ok, err = resume(task, ...)
+    if not ok then
+        error(err, 0)
+    end
+end
+
+local function current()
+    local task, main = running()
+    if main then
+        error("can only wait inside async.run", 3)
+    end
+    return task
+end
+
+function async.run(fn, ...)
+    step(create(fn), ...)
+end
+
+function async.sleep(ms)
+    local task = current()
+    core:add_timeout(ms, function() step(task) end)
+    yield()
+end
+
+function async.wait_for(name, expected, timeout_ms)
+    local matches = expected
+    if type(expected) ~= "function" then
+        matches = function(value) return value == expected end
+    end
+    local value = core:get_property(name)
+    if matches(value) then
+        return value
+    end
+
+    local task = current()
+    local observer, timer
+    local done = false
+    local function finish(...)
+        if done then return end
+        done = true
+        core:unobserve(observer)
+        if timer then core:cancel_timer(timer) end
+        step(task, ...)
+    end
+    observer = core:observe(name, function(new)
+        if matches(new) then finish(new) end
+    end, "immediate")
+    if timeout_ms then
+        timer = core:add_timeout(timeout_ms, function() finish(nil, "timeout") end)
+    end
+    return yield()
+end
+
+function async.get(url, headers)
+    local task = current()
+    http.get(url, function(res, err) step(task, res, err) end, headers)
+    return yield()
+end
+
+function async.post(url, body, headers)
+    local task = current()
+    http.post(url, body, function(res, err) step(task, res, err) end, headers)
+    return yield()
+end
+
+return async
+"#;
+
+pub fn register_async(lua: &Lua) -> Result<()> {
+    let async_table: Table = lua.load(ASYNC_LUA).set_name("=async").call(())?;
+    lua.globals().set("async", async_table)
+}
+
+pub fn init_lua(core: SharedCore) -> Result<Lua> {
     let lua = Lua::new();
     lua.globals().set("core", LuaCore(core))?;
+    lua.globals().set("config", lua.create_table()?)?;
+    register_http(&lua)?;
+    register_json(&lua)?;
+    register_eigen(&lua)?;
+    register_async(&lua)?;
     Ok(lua)
 }
 
+/// Typed access to the `config` table a config script fills in, by dotted path, so
+/// readers don't walk the Lua globals themselves:
+///
+/// ```ignore
+/// let config = ConfigTable::from_globals(&lua)?;
+/// let bands: Option<Vec<[f32; 4]>> = config.get_path("audio.eq.bands")?;
+/// ```
+///
+/// Missing entries (or a missing `config`) read as `None`; entries of the wrong type are
+/// errors naming the path.
+pub struct ConfigTable {
+    table: Option<Table>,
+}
+
+impl ConfigTable {
+    pub fn from_globals(lua: &Lua) -> Result<Self> {
+        Ok(Self {
+            table: lua.globals().get::<Option<Table>>("config")?,
+        })
+    }
+
+    /// The value at `path`, e.g. `ui.colors`, converted to `T`.
+    pub fn get_path<T: FromLua>(&self, path: &str) -> Result<Option<T>> {
+        let Some(mut table) = self.table.clone() else {
+            return Ok(None);
+        };
+        let mut walked = 0;
+        let mut parts = path.split('.').peekable();
+        while let Some(part) = parts.next() {
+            walked += part.len();
+            if parts.peek().is_none() {
+                return table
+                    .get::<Option<T>>(part)
+                    .map_err(|e| mlua::Error::RuntimeError(format!("config.{}: {}", path, e)));
+            }
+            table = match table.get::<Value>(part)? {
+                Value::Nil => return Ok(None),
+                Value::Table(inner) => {
+                    walked += 1;
+                    inner
+                }
+                other => {
+                    return Err(mlua::Error::RuntimeError(format!(
+                        "config.{} should be a table, not a {}",
+                        &path[..walked],
+                        other.type_name()
+                    )));
+                }
+            };
+        }
+        Ok(None)
+    }
+
+    pub fn get_nested_string(&self, path: &str) -> Result<Option<String>> {
+        self.get_path(path)
+    }
+
+    pub fn get_nested_bool(&self, path: &str) -> Result<Option<bool>> {
+        self.get_path(path)
+    }
+
+    pub fn get_nested_float(&self, path: &str) -> Result<Option<f64>> {
+        self.get_path(path)
+    }
+
+    pub fn get_nested_usize(&self, path: &str) -> Result<Option<usize>> {
+        self.get_path(path)
+    }
+
+    pub fn get_nested_table(&self, path: &str) -> Result<Option<Table>> {
+        self.get_path(path)
+    }
+
+    /// The array at `path`, empty when there is none.
+    pub fn get_nested_array<T: FromLua>(&self, path: &str) -> Result<Vec<T>> {
+        Ok(self.get_path::<Vec<T>>(path)?.unwrap_or_default())
+    }
+}
+
+/// Binds what a config script put in its `config.keys` table, each entry as if passed
+/// to `core:bind_key`:
+///
+/// ```lua
+/// config.keys = {
+///     ["ctrl+n"] = "next",
+///     ["+"] = "volume 1.0",
+///     m = function() core:set_property("audio.volume", 0.0) end,
+/// }
+/// ```
+///
+/// Entries that fail are logged and skipped. Returns the keys bound, normalized.
+pub fn bind_config_keys(lua: &Lua) -> Result<Vec<String>> {
+    let Some(keys) = ConfigTable::from_globals(lua)?.get_nested_table("keys")? else {
+        return Ok(Vec::new());
+    };
+    let core: AnyUserData = lua.globals().get("core")?;
+    let mut bound = Vec::new();
+    for pair in keys.pairs::<String, Value>() {
+        let (key, action) = pair?;
+        match core.call_method::<()>("bind_key", (key.as_str(), action)) {
+            Ok(()) => bound.extend(normalize_key(&key)),
+            Err(e) => warn!("[Config] Invalid key binding '{}': {}", key, e),
+        }
+    }
+    Ok(bound)
+}
+
+/// Applies `config.ui.colors`: `false` turns REPL colors off, a table sets the color of
+/// each role (see `style::Role`). Entries that don't make sense are logged and skipped.
+///
+/// ```lua
+/// config.ui = { colors = { current = "bold cyan", error = "bright_red" } }
+/// ```
+pub fn apply_config_colors(lua: &Lua) -> Result<()> {
+    let colors = ConfigTable::from_globals(lua)?.get_path::<Value>("ui.colors")?;
+    match colors.unwrap_or(Value::Nil) {
+        Value::Nil => Ok(()),
+        Value::Boolean(enabled) => write_core(lua, |core| {
+            core.set_property("ui.colors", PropertyValue::Bool(enabled))
+        })?
+        .map_err(mlua::Error::external),
+        Value::Table(theme) => {
+            for pair in theme.pairs::<String, Value>() {
+                let (name, color) = pair?;
+                let (Some(role), Value::String(color)) = (Role::from_name(&name), color) else {
+                    warn!("[Config] Unknown color setting ui.colors.{}", name);
+                    continue;
+                };
+                let color = color.to_str()?.to_string();
+                if escape_code(&color).is_none() {
+                    warn!("[Config] Unknown color '{}' for {}", color, name);
+                    continue;
+                }
+                write_core(lua, |core| {
+                    core.set_property(&role.property(), PropertyValue::String(color))
+                })?
+                .map_err(mlua::Error::external)?;
+            }
+            Ok(())
+        }
+        _ => {
+            warn!("[Config] ui.colors should be a table or a boolean");
+            Ok(())
+        }
+    }
+}
+
+/// Mirrors the settings a config script put in its `config` table into their
+/// properties: `config.audio.default_volume = 0.3` does what
+/// `core:set_property("audio.default_volume", 0.3)` does, for every setting in
+/// `config::SETTINGS`. Each value is checked (`config::check`) before it is stored;
+/// invalid ones are logged and skipped. Returns how many were set.
+pub fn apply_config_settings(lua: &Lua) -> Result<usize> {
+    let config = ConfigTable::from_globals(lua)?;
+    let mut applied = 0;
+    for (name, _) in config::SETTINGS {
+        // `config.ui.colors` may also be a theme table; `apply_config_colors` owns it
+        if *name == "ui.colors" {
+            continue;
+        }
+        let Some(value) = config.get_path::<Value>(name)? else {
+            continue;
+        };
+        let value = match value_to_property(name, value) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("[Config] config.{}: {}", name, e);
+                continue;
+            }
+        };
+        let value = match config::check(name, &value) {
+            Ok(value) => value,
+            Err(problem) => {
+                warn!("[Config] {}", problem);
+                continue;
+            }
+        };
+        write_core(lua, |core| core.set_property(name, value))?.map_err(mlua::Error::external)?;
+        applied += 1;
+    }
+    Ok(applied)
+}
+
 pub fn run_script(lua: &Lua, script: &str) -> Result<()> {
     lua.load(script).exec()
 }
+
+/// Evaluates interactive input: as an expression if it is one (so `1 + 1` shows 2),
+/// otherwise as statements.
+pub fn eval(lua: &Lua, code: &str) -> Result<MultiValue> {
+    let expression = lua
+        .load(format!("return {}", code))
+        .set_name("=repl")
+        .into_function();
+    match expression {
+        Ok(function) => function.call(()),
+        Err(_) => lua.load(code).set_name("=repl").eval(),
+    }
+}
+
+/// True if `error` only means the chunk isn't finished yet, e.g. an open `function`.
+pub fn is_incomplete(error: &mlua::Error) -> bool {
+    matches!(
+        error,
+        mlua::Error::SyntaxError {
+            incomplete_input: true,
+            ..
+        }
+    )
+}
+
+/// Renders a value for the REPL. Tables are shown up to two levels deep, sequences in
+/// order and other keys sorted.
+pub fn format_value(value: &Value) -> String {
+    format_nested(value, 2)
+}
+
+fn format_nested(value: &Value, depth: usize) -> String {
+    match value {
+        Value::Nil => "nil".to_string(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Integer(i) => i.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => format!("{:?}", s.to_string_lossy()),
+        Value::Table(t) if depth > 0 => {
+            let len = t.raw_len();
+            let mut items: Vec<String> = (1..=len)
+                .map(|i| format_nested(&t.raw_get(i).unwrap_or(Value::Nil), depth - 1))
+                .collect();
+            let mut named: Vec<String> = t
+                .clone()
+                .pairs::<Value, Value>()
+                .filter_map(|pair| pair.ok())
+                .filter(
+                    |(key, _)| !matches!(key, Value::Integer(i) if *i >= 1 && *i as usize <= len),
+                )
+                .map(|(key, val)| {
+                    let key = match &key {
+                        Value::String(s) => s.to_string_lossy(),
+                        other => format!("[{}]", format_nested(other, 0)),
+                    };
+                    format!("{} = {}", key, format_nested(&val, depth - 1))
+                })
+                .collect();
+            named.sort();
+            items.extend(named);
+            format!("{{{}}}", items.join(", "))
+        }
+        other => format!("<{}>", other.type_name()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_command() {
+        let core = Core::new().into_shared();
+        let lua = init_lua(core.clone()).unwrap();
+        run_script(
+            &lua,
+            r#"
+            core:set_or_create("greeting", "")
+            core:register_command("greet", function(args)
+                if #args == 0 then error("who?") end
+                core:set_property("greeting", "hello " .. table.concat(args, " "))
+            end)
+            "#,
+        )
+        .unwrap();
+
+        let mut core = core.write().unwrap();
+        core.execute_command("greet", vec!["big".to_string(), "world".to_string()])
+            .unwrap();
+        assert_eq!(
+            core.get_string("greeting").cloned(),
+            Some("hello big world".to_string())
+        );
+        assert!(matches!(
+            core.execute_command("greet", vec![]),
+            Err(CoreError::CommandFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_eval_and_format() {
+        let lua = init_lua(Core::new().into_shared()).unwrap();
+        let shown = |code: &str| {
+            eval(&lua, code)
+                .unwrap()
+                .iter()
+                .map(format_value)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(shown("1 + 1"), vec!["2"]);
+        assert!(shown("x = 'set'").is_empty());
+        assert_eq!(shown("x, nil"), vec!["\"set\"", "nil"]);
+        assert_eq!(
+            shown("{ 'a', { 1 }, name = 'mix', [true] = false }"),
+            vec!["{\"a\", {1}, [true] = false, name = \"mix\"}"]
+        );
+        assert!(is_incomplete(&eval(&lua, "function f()").unwrap_err()));
+        assert!(!is_incomplete(&eval(&lua, "1 +* 2").unwrap_err()));
+    }
+
+    #[test]
+    fn test_db_api() {
+        let lua = init_lua(Core::new().into_shared()).unwrap();
+        let db = Database::in_memory().unwrap();
+        db.upsert_track(&TrackMetadata {
+            path: "/m/a.mp3".to_string(),
+            title: Some("Song".to_string()),
+            artist: Some("Band".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        db.record_play_completion("/m/a.mp3").unwrap();
+        register_db(&lua, db.into_shared()).unwrap();
+
+        run_script(
+            &lua,
+            r#"
+            local found = db:search("band")
+            assert(#found == 1 and found[1].display_name == "Band – Song")
+            assert(found[1].album == nil)
+
+            local top = db:top_tracks("week", 5)
+            db:save_playlist("top", { top[1].track })
+            db:add_to_playlist("top", "/m/b.mp3")
+            local tracks = db:get_playlist("top")
+            assert(#tracks == 2 and tracks[1] == "/m/a.mp3")
+            assert(db:history(1)[1].track == "/m/a.mp3")
+            assert(db:playlists()[1] == "top")
+            assert(not pcall(db.top_tracks, db, "decade"))
+            "#,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_timers() {
+        let core = Core::new().into_shared();
+        let lua = init_lua(core.clone()).unwrap();
+        run_script(
+            &lua,
+            r#"
+            core:set_or_create("ticks", 0)
+            core:set_or_create("done", false)
+            core:add_timeout(0, function() core:set_property("done", true) end)
+            local id
+            id = core:add_interval(1, function()
+                local ticks = core:get_property("ticks") + 1
+                core:set_property("ticks", ticks)
+                if ticks == 2 then core:cancel_timer(id) end
+            end)
+            "#,
+        )
+        .unwrap();
+
+        for _ in 0..5 {
+            std::thread::sleep(Duration::from_millis(2));
+            core.write().unwrap().tick();
+        }
+        let core = core.read().unwrap();
+        assert_eq!(core.get_bool("done"), Some(true));
+        assert_eq!(core.get_int("ticks"), Some(2));
+    }
+
+    #[test]
+    fn test_config_keys() {
+        let core = Core::new().into_shared();
+        let lua = init_lua(core.clone()).unwrap();
+        run_script(
+            &lua,
+            r#"
+            core:set_or_create("muted", false)
+            config.keys = {
+                ["Ctrl+n"] = "next",
+                m = function() core:set_property("muted", true) end,
+                ["not a key"] = "pause",
+                x = 42,
+            }
+            "#,
+        )
+        .unwrap();
+        let mut bound = bind_config_keys(&lua).unwrap();
+        bound.sort();
+        assert_eq!(bound, vec!["ctrl+n", "m"]);
+        drop(lua);
+
+        let mut core = core.write().unwrap();
+        assert_eq!(core.keys.lookup("ctrl+n"), Some("next"));
+        assert_eq!(core.keys.lookup("m"), Some("key:m"));
+        // the function outlives the script's interpreter
+        core.execute_command("key:m", vec![]).unwrap();
+        assert_eq!(core.get_bool("muted"), Some(true));
+    }
+
+    #[test]
+    fn test_http() {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // headers and body may arrive in separate reads
+            let mut request = String::new();
+            let mut chunk = [0u8; 1024];
+            let body = loop {
+                let read = stream.read(&mut chunk).unwrap();
+                request.push_str(&String::from_utf8_lossy(&chunk[..read]));
+                match request.split_once("\r\n\r\n") {
+                    Some((_, body)) if body.len() >= 4 || read == 0 => break body.to_string(),
+                    _ if read == 0 => break String::new(),
+                    _ => {}
+                }
+            };
+            let response = format!(
+                "HTTP/1.1 201 Created\r\nX-Echo: yes\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+
+        let core = Core::new().into_shared();
+        let lua = init_lua(core.clone()).unwrap();
+        lua.globals().set("port", port).unwrap();
+        run_script(
+            &lua,
+            r#"
+            core:set_or_create("reply", "")
+            core:set_or_create("failure", "")
+            http.post("http://127.0.0.1:" .. port .. "/", "ping", function(res, err)
+                assert(err == nil and res.status == 201 and res.headers["x-echo"] == "yes")
+                core:set_property("reply", res.body)
+            end, { ["content-type"] = "text/plain" })
+            http.get("http://invalid.invalid/", function(res, err)
+                assert(res == nil)
+                core:set_property("failure", err)
+            end)
+            "#,
+        )
+        .unwrap();
+
+        for _ in 0..500 {
+            {
+                let mut core = core.write().unwrap();
+                core.tick();
+                if core.get_string("reply").is_some_and(|r| !r.is_empty())
+                    && core.get_string("failure").is_some_and(|f| !f.is_empty())
+                {
+                    break;
+                }
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let core = core.read().unwrap();
+        assert_eq!(core.get_string("reply").cloned(), Some("ping".to_string()));
+        assert!(!core.get_string("failure").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_json() {
+        let lua = init_lua(Core::new().into_shared()).unwrap();
+        run_script(
+            &lua,
+            r#"
+            local value = json.decode('{"name": "mix", "tracks": ["a", "b"], "empty": [], "gone": null}')
+            assert(value.name == "mix" and #value.tracks == 2 and value.gone == json.null)
+            assert(json.encode(value.empty) == "[]")
+            assert(json.encode({ 1, 2, 3 }) == "[1,2,3]")
+            assert(json.encode({ plays = 3 }) == '{"plays":3}')
+            assert(json.decode(json.encode(value)).tracks[2] == "b")
+            assert(not pcall(json.decode, "{oops"))
+            assert(not pcall(json.encode, { f = print }))
+            "#,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_script_messages() {
+        let core = Core::new().into_shared();
+        let shown = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = shown.clone();
+        core.write().unwrap().subscribe_event(
+            Some(&format!("custom:{}", SCRIPT_MESSAGE)),
+            Arc::new(move |event, _core| {
+                if let crate::core::EventType::Custom(_, PropertyValue::StringList(message)) = event
+                {
+                    sink.lock().unwrap().push(message.join(": "));
+                }
+            }),
+        );
+
+        let lua = init_lua(core).unwrap();
+        run_script(
+            &lua,
+            r#"
+            core:print("now playing")
+            core:notify("warn", "lyrics not found")
+            assert(not pcall(core.notify, core, "shout", "hey"))
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            *shown.lock().unwrap(),
+            vec!["info: now playing", "warn: lyrics not found"]
+        );
+    }
+
+    #[test]
+    fn test_observe() {
+        let core = Core::new().into_shared();
+        core.write()
+            .unwrap()
+            .add_property("audio.position", PropertyValue::Float(0.0));
+        let lua = init_lua(core.clone()).unwrap();
+        run_script(
+            &lua,
+            r#"
+            seen = {}
+            core:observe("audio.position", function(value, name)
+                table.insert(seen, name .. "=" .. value)
+            end)
+            every = 0
+            core:observe("audio.position", function() every = every + 1 end, "immediate")
+            assert(not pcall(core.observe, core, "no.such", function() end))
+            "#,
+        )
+        .unwrap();
+
+        {
+            let mut core = core.write().unwrap();
+            for i in 1..=3 {
+                core.set_property("audio.position", PropertyValue::Float(i as f32))
+                    .unwrap();
+            }
+            core.tick();
+        }
+        let seen: Vec<String> = lua.globals().get("seen").unwrap();
+        assert_eq!(seen, vec!["audio.position=3.0"]);
+        assert_eq!(lua.globals().get::<i64>("every").unwrap(), 3);
+    }
+
+    #[test]
+    fn test_api_version() {
+        let core = Core::new().into_shared();
+        core.write().unwrap().add_property(
+            "playlist.tracks",
+            PropertyValue::StringList(vec!["a".into()]),
+        );
+        let lua = init_lua(core).unwrap();
+        run_script(
+            &lua,
+            r#"
+            assert(eigen.api_version >= 2 and require_api(1) == eigen.api_version)
+            local ok, err = pcall(require_api, eigen.api_version + 1)
+            assert(not ok and tostring(err):find("needs scripting API version"))
+            -- deprecated, but still there
+            assert(core:get_string_list("playlist.tracks")[1] == "a")
+            "#,
+        )
+        .unwrap();
+        assert!(
+            lua.app_data_ref::<DeprecationWarnings>()
+                .unwrap()
+                .0
+                .contains("core:get_string_list")
+        );
+    }
+
+    #[test]
+    fn test_async() {
+        let core = Core::new().into_shared();
+        core.write()
+            .unwrap()
+            .add_property("audio.finished", PropertyValue::Bool(false));
+        let lua = init_lua(core.clone()).unwrap();
+        run_script(
+            &lua,
+            r#"
+            steps = {}
+            async.run(function(label)
+                table.insert(steps, label)
+                async.sleep(1)
+                table.insert(steps, "slept")
+                local finished = async.wait_for("audio.finished", true)
+                table.insert(steps, "finished=" .. tostring(finished))
+                local value, err = async.wait_for("audio.finished", false, 1)
+                table.insert(steps, tostring(value) .. " " .. err)
+            end, "started")
+            assert(not pcall(async.sleep, 1))
+            "#,
+        )
+        .unwrap();
+        let steps = || lua.globals().get::<Vec<String>>("steps").unwrap();
+        assert_eq!(steps(), vec!["started"]);
+
+        std::thread::sleep(Duration::from_millis(5));
+        core.write().unwrap().tick();
+        assert_eq!(steps(), vec!["started", "slept"]);
+
+        core.write()
+            .unwrap()
+            .set_property("audio.finished", PropertyValue::Bool(true))
+            .unwrap();
+        assert_eq!(steps().len(), 3);
+        assert_eq!(steps()[2], "finished=true");
+
+        std::thread::sleep(Duration::from_millis(5));
+        core.write().unwrap().tick();
+        assert_eq!(steps()[3], "nil timeout");
+        // nothing is left observing once the waits are over
+        let core = core.read().unwrap();
+        assert_eq!(core.properties["audio.finished"].callbacks.len(), 0);
+        assert!(!core.has_pending_timers());
+    }
+
+    #[test]
+    fn test_schedule_at() {
+        let core = Core::new().into_shared();
+        {
+            let mut core = core.write().unwrap();
+            core.add_property("playlist.current", PropertyValue::String("a".into()));
+            core.add_property("audio.position", PropertyValue::Float(0.0));
+            core.add_property("audio.volume", PropertyValue::Float(1.0));
+        }
+        let lua = init_lua(core.clone()).unwrap();
+        run_script(
+            &lua,
+            r#"
+            skipped = 0
+            core:schedule_at(5, function() skipped = skipped + 1 end)
+            core:schedule_at(50, function() skipped = skipped + 10 end)
+            core:fade_volume(0.5, 1000)
+            core:fade_volume(0, 0)
+            "#,
+        )
+        .unwrap();
+
+        let mut core = core.write().unwrap();
+        let seek = |core: &mut Core, at: f32| {
+            core.set_property("audio.position", PropertyValue::Float(at))
+                .unwrap()
+        };
+        seek(&mut core, 3.0);
+        assert_eq!(lua.globals().get::<i64>("skipped").unwrap(), 0);
+        seek(&mut core, 6.0);
+        seek(&mut core, 7.0);
+        assert_eq!(lua.globals().get::<i64>("skipped").unwrap(), 1);
+        // the other one goes with the track change
+        core.set_property("playlist.current", PropertyValue::String("b".into()))
+            .unwrap();
+        seek(&mut core, 60.0);
+        assert_eq!(lua.globals().get::<i64>("skipped").unwrap(), 1);
+        assert_eq!(core.properties["audio.position"].callbacks.len(), 0);
+
+        std::thread::sleep(Duration::from_millis(25));
+        core.tick();
+        assert_eq!(core.get_float("audio.volume"), Some(0.0));
+        assert!(!core.has_pending_timers());
+    }
+
+    #[test]
+    fn test_position_api() {
+        let core = Core::new().into_shared();
+        {
+            let mut core = core.write().unwrap();
+            core.add_property("audio.playing", PropertyValue::Bool(false));
+            core.add_property("playlist.current", PropertyValue::String("a".into()));
+            core.add_property("audio.position", PropertyValue::Float(12.5));
+            core.add_property("audio.duration", PropertyValue::Float(0.0));
+        }
+        let lua = init_lua(core.clone()).unwrap();
+        run_script(
+            &lua,
+            r#"
+            assert(core:get_position() == 12.5 and core:get_duration() == nil)
+            marks = {}
+            core:on_position(1, function(position, track)
+                table.insert(marks, track .. "@" .. position)
+            end)
+            "#,
+        )
+        .unwrap();
+
+        std::thread::sleep(Duration::from_millis(2));
+        core.write().unwrap().tick();
+        assert!(
+            lua.globals()
+                .get::<Vec<String>>("marks")
+                .unwrap()
+                .is_empty()
+        );
+
+        core.write()
+            .unwrap()
+            .set_property("audio.playing", PropertyValue::Bool(true))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        core.write().unwrap().tick();
+        assert_eq!(
+            lua.globals().get::<Vec<String>>("marks").unwrap(),
+            vec!["a@12.5"]
+        );
+    }
+
+    #[test]
+    fn test_config_colors() {
+        let mut core = Core::new();
+        crate::property::register_property(&mut core);
+        let core = core.into_shared();
+        let lua = init_lua(core.clone()).unwrap();
+        run_script(
+            &lua,
+            r#"config.ui = { colors = { current = "bold cyan", error = "plaid", title = "red" } }"#,
+        )
+        .unwrap();
+        apply_config_colors(&lua).unwrap();
+        {
+            let core = core.read().unwrap();
+            assert_eq!(
+                core.get_string("ui.color.current").map(|c| c.as_str()),
+                Some("bold cyan")
+            );
+            assert_eq!(
+                core.get_string("ui.color.error").map(|c| c.as_str()),
+                Some("red")
+            );
+        }
+
+        run_script(&lua, "config.ui.colors = false").unwrap();
+        apply_config_colors(&lua).unwrap();
+        assert_eq!(core.read().unwrap().get_bool("ui.colors"), Some(false));
+    }
+
+    #[test]
+    fn test_config_table() {
+        let lua = Lua::new();
+        let empty = ConfigTable::from_globals(&lua).unwrap();
+        assert_eq!(empty.get_nested_bool("ui.colors").unwrap(), None);
+
+        lua.load(
+            r#"
+            config = {
+                ui = { prompt = "> ", colors = false },
+                audio = { volume = 0.5, buffer = 4096, eq = { bands = { {1000, 1, 3, 1} } } },
+                tags = { "a", "b" },
+            }
+            "#,
+        )
+        .exec()
+        .unwrap();
+        let config = ConfigTable::from_globals(&lua).unwrap();
+        assert_eq!(
+            config.get_nested_string("ui.prompt").unwrap(),
+            Some("> ".to_string())
+        );
+        assert_eq!(config.get_nested_bool("ui.colors").unwrap(), Some(false));
+        assert_eq!(config.get_nested_float("audio.volume").unwrap(), Some(0.5));
+        assert_eq!(config.get_nested_usize("audio.buffer").unwrap(), Some(4096));
+        assert_eq!(
+            config.get_path::<Vec<[f32; 4]>>("audio.eq.bands").unwrap(),
+            Some(vec![[1000.0, 1.0, 3.0, 1.0]])
+        );
+        assert_eq!(
+            config.get_nested_array::<String>("tags").unwrap(),
+            vec!["a", "b"]
+        );
+        assert!(
+            config
+                .get_nested_array::<String>("missing")
+                .unwrap()
+                .is_empty()
+        );
+        assert!(config.get_nested_table("audio.eq").unwrap().is_some());
+        assert_eq!(config.get_nested_bool("audio.missing.flag").unwrap(), None);
+
+        let wrong = config.get_nested_usize("ui.prompt").unwrap_err();
+        assert!(wrong.to_string().contains("config.ui.prompt"));
+        let not_table = config.get_nested_bool("ui.prompt.x").unwrap_err();
+        assert!(
+            not_table
+                .to_string()
+                .contains("config.ui.prompt should be a table")
+        );
+    }
+
+    #[test]
+    fn test_config_settings() {
+        let mut core = Core::new();
+        crate::property::register_property(&mut core);
+        let core = core.into_shared();
+        let lua = init_lua(core.clone()).unwrap();
+        run_script(
+            &lua,
+            r#"
+            config.audio = { default_volume = 0.3, device = print, ring_buffer_size = "large" }
+            config.eq = { enabled = true, bands = { {1000, 1, 3, 1} } }
+            config.playlist = { ["repeat"] = "all" }
+            config.ui = { colors = { error = "bold red" } }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(apply_config_settings(&lua).unwrap(), 4);
+
+        let core = core.read().unwrap();
+        assert_eq!(core.get_float("audio.default_volume"), Some(0.3));
+        assert_eq!(core.get_bool("eq.enabled"), Some(true));
+        assert_eq!(
+            core.get_property("eq.bands"),
+            Some(&PropertyValue::EqBandList(vec![[1000.0, 1.0, 3.0, 1.0]]))
+        );
+        assert_eq!(
+            core.get_string("playlist.repeat").map(|s| s.as_str()),
+            Some("all")
+        );
+        // a function isn't a device name
+        assert_eq!(
+            core.get_string("audio.device").map(|s| s.as_str()),
+            Some("")
+        );
+        assert_eq!(core.get_int("audio.ring_buffer_size"), Some(88200));
+    }
+
+    #[test]
+    fn test_set_property_checks_settings() {
+        let mut core = Core::new();
+        crate::property::register_property(&mut core);
+        let core = core.into_shared();
+        let lua = init_lua(core.clone()).unwrap();
+        run_script(
+            &lua,
+            r#"
+            seen = 0
+            core:observe("audio.device", function() seen = seen + 1 end, "immediate")
+            ok, err = pcall(core.set_property, core, "audio.device", 42)
+            assert(not ok)
+            assert(tostring(err):find("audio.device must be", 1, true))
+            assert(not pcall(core.set_or_create, core, "audio.ring_buffer_size", 0))
+            core:set_property("audio.default_volume", 1)
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(lua.globals().get::<i64>("seen").unwrap(), 0);
+        let core = core.read().unwrap();
+        assert_eq!(
+            core.get_string("audio.device").map(|s| s.as_str()),
+            Some("")
+        );
+        assert_eq!(core.get_int("audio.ring_buffer_size"), Some(88200));
+        assert_eq!(
+            core.get_property("audio.default_volume"),
+            Some(&PropertyValue::Float(1.0))
+        );
+    }
+}
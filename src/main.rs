@@ -1,12 +1,8 @@
-use eigenplayer::audio::AudioBackend;
-use eigenplayer::commands::*;
-use eigenplayer::core::*;
-use eigenplayer::db::Database;
-use eigenplayer::lua::{init_lua, run_script};
-use eigenplayer::property::*;
-use eigenplayer::repl::Repl;
-use ringbuf::producer;
-use std::sync::{Arc, Mutex};
+use eigenplayer::config::write_default_config;
+use eigenplayer::paths::{config_path, resolve_config_path};
+use eigenplayer::player::Player;
+use std::io::IsTerminal;
+use std::path::PathBuf;
 use tracing::*;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -22,147 +18,73 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with_line_number(true)
         .init();
 
-    let core = Arc::new(Mutex::new(Core::new()));
-
-    // Register default properties
-    {
-        let mut core_lock = core.lock().unwrap();
-        register_property(&mut *core_lock);
-    }
-
-    // Load and execute config.lua to set config properties
-    match std::fs::read_to_string("config.lua") {
-        Ok(script) => match init_lua(Arc::clone(&core)) {
-            Ok(lua) => match run_script(&lua, &script) {
-                Ok(_) => info!("[Config] Successfully loaded and executed config.lua"),
-                Err(e) => warn!("[Config] Failed to execute config.lua: {}", e),
+    let mut builder = Player::builder();
+    let mut config: Option<PathBuf> = None;
+    let mut init_config = false;
+    let mut args = std::env::args().skip(1);
+    let mut command = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => builder = builder.json_output(true),
+            "--config" => match args.next() {
+                Some(path) => config = Some(path.into()),
+                None => return Err("--config needs a path".into()),
             },
-            Err(e) => warn!("[Config] Failed to initialize Lua for config: {}", e),
-        },
-        Err(_) => {
-            warn!("[Config] config.lua not found, using default configuration");
-        }
-    }
-
-    // Now get the values from properties
-    let (default_volume, ring_buffer_size, enable_eq, eq_bands, producer_sleep_time) = {
-        let core_lock = core.lock().unwrap();
-        let default_volume = core_lock.get_float("default_volume").unwrap_or(0.5);
-        let ring_buffer_size = core_lock.get_float("ring_buffer_size").unwrap_or(88200.0) as usize;
-        let enable_eq = core_lock.get_bool("enable_eq").unwrap_or(false);
-        let eq_bands = core_lock
-            .get_property("eq_bands")
-            .and_then(|v| v.as_eq_band_list())
-            .cloned()
-            .unwrap_or_default();
-	let producer_sleep_time = core_lock.get_int("producer_sleep_time").unwrap_or(100);
-        (default_volume, ring_buffer_size, enable_eq, eq_bands, producer_sleep_time)
-    };
-
-    let db = Database::new("playlists.db")?;
-    info!("[Database] Initialized playlists.db");
-
-    if let Ok(tracks) = db.get_playlist_tracks("default") {
-        if !tracks.is_empty() {
-            {
-                let mut core_lock = core.lock().unwrap();
-                core_lock.set_property("playlist", PropertyValue::StringList(tracks.clone()));
+            "--init-config" => init_config = true,
+            "--db" => match args.next() {
+                Some(path) => builder = builder.db_path(path),
+                None => return Err("--db needs a path".into()),
+            },
+            // everything after the script belongs to it
+            "--script" => match args.next() {
+                Some(path) => {
+                    let script_args: Vec<String> = args.collect();
+                    return builder
+                        .config(resolve_config_path(config.as_deref()))
+                        .run_script(path.as_ref(), &script_args);
+                }
+                None => return Err("--script needs a file".into()),
+            },
+            // `eigenplayer play song.flac`: run one REPL command instead of the REPL
+            other if !other.starts_with('-') => {
+                command.push(arg);
+                command.extend(args.by_ref());
             }
-            info!(
-                "[Database] Loaded default playlist with {} tracks",
-                tracks.len()
-            );
+            other => return Err(format!("unknown argument '{}'", other).into()),
         }
     }
 
-    let audio_backend = Arc::new(Mutex::new(AudioBackend::with_ring_buffer_size(
-        ring_buffer_size,
-        default_volume,
-        enable_eq,
-        eq_bands,
-	producer_sleep_time as u64,
-    )?));
-
-    println!(
-        "[Audio] Initialized audio backend with {} prebuffer packets",
-        ring_buffer_size
-    );
-
-    let audio_for_track = Arc::clone(&audio_backend);
-    {
-        let mut core_lock = core.lock().unwrap();
-        if let Some(prop) = core_lock.properties.get_mut("current_track") {
-            prop.subscribe(Arc::new(move |value, _core| {
-                if let Some(track) = value.as_string() {
-                    if track != "none" {
-                        info!("[Audio] Loading track: {}", track);
-                        let mut audio = audio_for_track.lock().unwrap();
-                        if let Err(e) = audio.load_track(track) {
-                            warn!("[Audio] Failed to load track: {}", e);
-                        }
-                    }
-                }
-            }));
-        }
+    if init_config {
+        let path = config.unwrap_or_else(config_path);
+        write_default_config(&path)
+            .map_err(|e| format!("can't write {}: {}", path.display(), e))?;
+        println!("Wrote {}", path.display());
+        return Ok(());
     }
 
-    let audio_for_playing = Arc::clone(&audio_backend);
-    {
-        let mut core_lock = core.lock().unwrap();
-        if let Some(prop) = core_lock.properties.get_mut("playing") {
-            prop.subscribe(Arc::new(move |value, _core| {
-                if let Some(playing) = value.as_bool() {
-                    let mut audio = audio_for_playing.lock().unwrap();
-                    if playing {
-                        if let Err(e) = audio.play() {
-                            warn!("[Audio] Failed to start playback: {}", e);
-                        }
-                    } else {
-                        audio.pause();
-                    }
-                }
-            }));
-        }
-    }
+    let player = builder
+        .config(resolve_config_path(config.as_deref()))
+        .build()?;
+    player.spawn_ticker();
 
-    let audio_for_volume = Arc::clone(&audio_backend);
-    {
-        let mut core_lock = core.lock().unwrap();
-        if let Some(prop) = core_lock.properties.get_mut("volume") {
-            prop.subscribe(Arc::new(move |value, _core| {
-                if let Some(vol) = value.as_float() {
-                    let mut audio = audio_for_volume.lock().unwrap();
-                    audio.set_volume(vol);
-                }
-            }));
+    // there's no daemon to hand the command to yet, so it runs in this process
+    if !command.is_empty() {
+        if !player.run_once(&command)? {
+            std::process::exit(1);
         }
+        return Ok(());
     }
 
-    {
-        let mut core_lock = core.lock().unwrap();
-        register_commands(&mut *core_lock);
-    }
+    player.spawn_watcher();
 
-    {
-        let mut core_lock = core.lock().unwrap();
-        core_lock.subscribe_event(Arc::new(|event, _core| match event {
-            EventType::PropertyChanged(name) => {
-                if name != "playing" {
-                    info!("[Core] Property '{}' changed", name);
-                }
-            }
-            EventType::CommandExecuted(name) => {
-                info!("[Core] Command '{}' executed", name);
-            }
-        }));
+    let interactive = std::io::stdin().is_terminal();
+    if interactive {
+        println!("\nInitialization complete!\n");
     }
 
-    println!("\nInitialization complete!\n");
-
-    let mut repl = Repl::new(db);
-    {
-        let mut core_lock = core.lock().unwrap();
-        repl.run(&mut *core_lock)?;
+    // typos at the prompt are shrugged off; a failing line in a piped script is not
+    if !player.run_repl()? && !interactive {
+        std::process::exit(1);
     }
 
     Ok(())
@@ -0,0 +1,210 @@
+use std::fs::File;
+use std::path::Path;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey};
+use symphonia::core::probe::Hint;
+
+/// Tags and stream details of an audio file, as stored in the `tracks` table.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackMetadata {
+    pub path: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    /// Length in seconds, when the container reports it.
+    pub duration: Option<f64>,
+    pub codec: Option<String>,
+    pub genre: Option<String>,
+}
+
+impl TrackMetadata {
+    /// "Artist – Title" when both tags are known, otherwise the title or the file name.
+    pub fn display_name(&self) -> String {
+        match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => format!("{} – {}", artist, title),
+            (None, Some(title)) => title.clone(),
+            _ => Path::new(&self.path)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| self.path.clone()),
+        }
+    }
+
+    fn apply_tags(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            let value = tag.value.to_string();
+            match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => self.title = Some(value),
+                Some(StandardTagKey::Artist) => self.artist = Some(value),
+                Some(StandardTagKey::AlbumArtist) if self.artist.is_none() => {
+                    self.artist = Some(value)
+                }
+                Some(StandardTagKey::Album) => self.album = Some(value),
+                Some(StandardTagKey::Genre) => self.genre = Some(value),
+                Some(StandardTagKey::TrackNumber) => {
+                    // "3/12" style numbers are common in ID3
+                    self.track_number = value.split('/').next().and_then(|n| n.trim().parse().ok())
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Technical details of a file that the library doesn't keep.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamInfo {
+    pub sample_rate: Option<u32>,
+    pub channels: Option<usize>,
+    pub bits_per_sample: Option<u32>,
+    pub file_size: u64,
+    /// Every tag under the key it has in the file (`TIT2`, `TITLE`, ...), container tags
+    /// first.
+    pub tags: Vec<(String, String)>,
+}
+
+impl StreamInfo {
+    /// Average bitrate in kbit/s over the whole file, tags and all.
+    pub fn bitrate(&self, duration: Option<f64>) -> Option<f64> {
+        duration
+            .filter(|d| *d > 0.0)
+            .map(|d| self.file_size as f64 * 8.0 / d / 1000.0)
+    }
+
+    fn collect_tags(&mut self, revision: &MetadataRevision) {
+        self.tags.extend(
+            revision
+                .tags()
+                .iter()
+                .map(|tag| (tag.key.clone(), tag.value.to_string())),
+        );
+    }
+}
+
+/// Probes `path` with symphonia and reads its tags, duration and codec.
+pub fn read_metadata(path: &str) -> Result<TrackMetadata, Box<dyn std::error::Error>> {
+    probe_file(path).map(|(meta, _)| meta)
+}
+
+/// `read_metadata` plus the stream details and raw tags.
+pub fn probe_file(path: &str) -> Result<(TrackMetadata, StreamInfo), Box<dyn std::error::Error>> {
+    let file = File::open(path)?;
+    let mut info = StreamInfo {
+        file_size: file.metadata()?.len(),
+        ..Default::default()
+    };
+    let file = Box::new(file);
+    let mss = MediaSourceStream::new(file, Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = Path::new(path).extension() {
+        hint.with_extension(ext.to_str().unwrap_or(""));
+    }
+
+    let mut probed = symphonia::default::get_probe().format(
+        &hint,
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+
+    let mut meta = TrackMetadata {
+        path: path.to_string(),
+        ..Default::default()
+    };
+
+    // container-level tags (e.g. ID3v2 ahead of an mp3) first, then in-stream tags
+    if let Some(mut container) = probed.metadata.get()
+        && let Some(revision) = container.skip_to_latest()
+    {
+        meta.apply_tags(revision);
+        info.collect_tags(revision);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        meta.apply_tags(revision);
+        info.collect_tags(revision);
+    }
+
+    let track = probed
+        .format
+        .default_track()
+        .ok_or("No default track found")?;
+    let params = &track.codec_params;
+
+    meta.codec = symphonia::default::get_codecs()
+        .get_codec(params.codec)
+        .map(|codec| codec.short_name.to_string());
+
+    meta.duration = match (params.n_frames, params.time_base, params.sample_rate) {
+        (Some(frames), Some(tb), _) => {
+            let time = tb.calc_time(frames);
+            Some(time.seconds as f64 + time.frac)
+        }
+        (Some(frames), None, Some(rate)) => Some(frames as f64 / rate as f64),
+        _ => None,
+    };
+    info.sample_rate = params.sample_rate;
+    info.channels = params.channels.map(|channels| channels.count());
+    info.bits_per_sample = params.bits_per_sample;
+
+    Ok((meta, info))
+}
+
+/// Writes `samples` as an 8 kHz mono 16-bit wav, for tests that need a real audio file.
+#[cfg(test)]
+pub(crate) fn write_wav(path: &Path, samples: &[i16]) {
+    let rate = 8000u32;
+    let data_len = samples.len() as u32 * 2;
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    for field in [16u32, 0x0001_0001, rate, rate * 2, 0x0010_0002] {
+        bytes.extend_from_slice(&field.to_le_bytes());
+    }
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+    std::fs::write(path, bytes).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_wav_metadata() {
+        let path = std::env::temp_dir().join("eigenplayer_metadata_test.wav");
+        write_wav(&path, &[0; 16000]);
+
+        let meta = read_metadata(path.to_str().unwrap()).unwrap();
+        assert_eq!(meta.codec.as_deref(), Some("pcm_s16le"));
+        assert!((meta.duration.unwrap() - 2.0).abs() < 0.01);
+        assert_eq!(meta.display_name(), "eigenplayer_metadata_test.wav");
+
+        let (_, info) = probe_file(path.to_str().unwrap()).unwrap();
+        assert_eq!(info.sample_rate, Some(8000));
+        assert_eq!(info.channels, Some(1));
+        assert_eq!(info.bits_per_sample, Some(16));
+        assert_eq!(info.file_size, 44 + 32000);
+        let bitrate = info.bitrate(meta.duration).unwrap();
+        assert!((bitrate - 128.176).abs() < 0.1);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_display_name() {
+        let meta = TrackMetadata {
+            path: "/music/a.flac".to_string(),
+            title: Some("Song".to_string()),
+            artist: Some("Band".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(meta.display_name(), "Band – Song");
+    }
+}
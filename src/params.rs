@@ -0,0 +1,400 @@
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, Visitor};
+use serde::forward_to_deserialize_any;
+use std::fmt;
+
+/// Error produced while turning raw command arguments into a parameter struct.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamError(pub String);
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+impl de::Error for ParamError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ParamError(msg.to_string())
+    }
+}
+
+/// Deserializes positional arguments into `T`, matching them to the struct fields in
+/// declaration order. Missing trailing `Option` fields become `None`, and surplus
+/// arguments are joined with spaces into the last field so paths with spaces survive.
+pub fn from_args<T: DeserializeOwned>(args: &[String]) -> Result<T, ParamError> {
+    T::deserialize(ArgsDeserializer { args })
+}
+
+/// Returns the field names of a parameter struct, in declaration order.
+pub fn field_names<T: DeserializeOwned>() -> Vec<&'static str> {
+    let mut names = Vec::new();
+    let _ = T::deserialize(FieldProbe { names: &mut names });
+    names
+}
+
+/// Returns the fields of a parameter struct that are `Option`s and so may be left out.
+pub fn optional_fields<T: DeserializeOwned>() -> Vec<&'static str> {
+    field_names::<T>()
+        .into_iter()
+        .filter(|&field| {
+            let mut optional = false;
+            let _ = T::deserialize(OptionProbe {
+                field: Some(field),
+                optional: &mut optional,
+            });
+            optional
+        })
+        .collect()
+}
+
+struct ArgsDeserializer<'a> {
+    args: &'a [String],
+}
+
+impl<'de> de::Deserializer<'de> for ArgsDeserializer<'_> {
+    type Error = ParamError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, ParamError> {
+        Err(ParamError(
+            "command parameters must be a struct with named fields".to_string(),
+        ))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ParamError> {
+        if self.args.len() > fields.len() && fields.is_empty() {
+            return Err(ParamError("this command takes no arguments".to_string()));
+        }
+
+        let mut values: Vec<String> = self.args.iter().take(fields.len()).cloned().collect();
+        if self.args.len() > fields.len()
+            && let Some(last) = values.last_mut()
+        {
+            for extra in &self.args[fields.len()..] {
+                last.push(' ');
+                last.push_str(extra);
+            }
+        }
+
+        visitor.visit_map(ArgsMap {
+            fields,
+            values,
+            index: 0,
+        })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map enum
+        identifier ignored_any
+    }
+}
+
+struct ArgsMap {
+    fields: &'static [&'static str],
+    values: Vec<String>,
+    index: usize,
+}
+
+impl<'de> MapAccess<'de> for ArgsMap {
+    type Error = ParamError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, ParamError> {
+        if self.index >= self.values.len() {
+            return Ok(None);
+        }
+        let field = self.fields[self.index];
+        seed.deserialize(field.into_deserializer()).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, ParamError> {
+        let field = self.fields[self.index];
+        let value = std::mem::take(&mut self.values[self.index]);
+        self.index += 1;
+        seed.deserialize(ArgValue { field, value })
+    }
+}
+
+/// A single argument; numbers and booleans are parsed from the string on demand.
+struct ArgValue {
+    field: &'static str,
+    value: String,
+}
+
+impl ArgValue {
+    fn parse<T: std::str::FromStr>(&self, kind: &str) -> Result<T, ParamError> {
+        self.value.parse().map_err(|_| {
+            ParamError(format!(
+                "invalid {} '{}' for <{}>",
+                kind, self.value, self.field
+            ))
+        })
+    }
+}
+
+macro_rules! parse_arg {
+    ($($method:ident => $visit:ident: $ty:ty),* $(,)?) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParamError> {
+                visitor.$visit(self.parse::<$ty>(stringify!($ty))?)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for ArgValue {
+    type Error = ParamError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParamError> {
+        visitor.visit_string(self.value)
+    }
+
+    parse_arg! {
+        deserialize_bool => visit_bool: bool,
+        deserialize_i8 => visit_i8: i8,
+        deserialize_i16 => visit_i16: i16,
+        deserialize_i32 => visit_i32: i32,
+        deserialize_i64 => visit_i64: i64,
+        deserialize_u8 => visit_u8: u8,
+        deserialize_u16 => visit_u16: u16,
+        deserialize_u32 => visit_u32: u32,
+        deserialize_u64 => visit_u64: u64,
+        deserialize_f32 => visit_f32: f32,
+        deserialize_f64 => visit_f64: f64,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParamError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ParamError> {
+        visitor.visit_enum(self.value.into_deserializer())
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ParamError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        char str string bytes byte_buf unit unit_struct seq tuple tuple_struct map
+        struct identifier ignored_any
+    }
+}
+
+/// Deserializer that only records the field list a struct asks for.
+struct FieldProbe<'a> {
+    names: &'a mut Vec<&'static str>,
+}
+
+impl<'de> de::Deserializer<'de> for FieldProbe<'_> {
+    type Error = ParamError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, ParamError> {
+        Err(ParamError("not a struct".to_string()))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, ParamError> {
+        self.names.extend_from_slice(fields);
+        Err(ParamError("probe only".to_string()))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map enum
+        identifier ignored_any
+    }
+}
+
+/// Deserializer that hands a struct only `field` and notes whether its value is asked
+/// for as an `Option`.
+struct OptionProbe<'a> {
+    field: Option<&'static str>,
+    optional: &'a mut bool,
+}
+
+impl<'de> de::Deserializer<'de> for OptionProbe<'_> {
+    type Error = ParamError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, ParamError> {
+        Err(ParamError("not a struct".to_string()))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ParamError> {
+        visitor.visit_map(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map enum
+        identifier ignored_any
+    }
+}
+
+impl<'de> MapAccess<'de> for OptionProbe<'_> {
+    type Error = ParamError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, ParamError> {
+        match self.field.take() {
+            Some(field) => seed.deserialize(field.into_deserializer()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, ParamError> {
+        seed.deserialize(OptionValue {
+            optional: &mut *self.optional,
+        })
+    }
+}
+
+struct OptionValue<'a> {
+    optional: &'a mut bool,
+}
+
+impl<'de> de::Deserializer<'de> for OptionValue<'_> {
+    type Error = ParamError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, ParamError> {
+        Err(ParamError("probe only".to_string()))
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, ParamError> {
+        *self.optional = true;
+        Err(ParamError("probe only".to_string()))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf
+        unit unit_struct newtype_struct seq tuple tuple_struct map struct enum
+        identifier ignored_any
+    }
+}
+
+/// Flattens structured parameters (a JSON array, object or scalar, as sent by remote
+/// clients or built from a Lua table) into positional arguments. Object keys are placed
+/// by `field_order`; unknown keys are an error.
+pub fn structured_to_args(
+    value: &serde_json::Value,
+    field_order: &[String],
+) -> Result<Vec<String>, ParamError> {
+    fn scalar(value: &serde_json::Value) -> Result<String, ParamError> {
+        match value {
+            serde_json::Value::String(s) => Ok(s.clone()),
+            serde_json::Value::Number(n) => Ok(n.to_string()),
+            serde_json::Value::Bool(b) => Ok(b.to_string()),
+            other => Err(ParamError(format!("unsupported parameter value {}", other))),
+        }
+    }
+
+    match value {
+        serde_json::Value::Null => Ok(Vec::new()),
+        serde_json::Value::Array(items) => items.iter().map(scalar).collect(),
+        serde_json::Value::Object(map) => {
+            if let Some(unknown) = map.keys().find(|k| !field_order.contains(k)) {
+                return Err(ParamError(format!("unknown parameter '{}'", unknown)));
+            }
+            let mut args = Vec::new();
+            for field in field_order {
+                match map.get(field) {
+                    Some(value) => args.push(scalar(value)?),
+                    None => break,
+                }
+            }
+            if args.len() < map.len() {
+                return Err(ParamError(
+                    "parameters must be given without gaps, in order".to_string(),
+                ));
+            }
+            Ok(args)
+        }
+        other => Ok(vec![scalar(other)?]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Seek {
+        seconds: f64,
+        relative: Option<bool>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Add {
+        path: String,
+    }
+
+    #[test]
+    fn test_from_args() {
+        let seek: Seek = from_args(&["12.5".to_string()]).unwrap();
+        assert_eq!(
+            seek,
+            Seek {
+                seconds: 12.5,
+                relative: None
+            }
+        );
+
+        let err = from_args::<Seek>(&["soon".to_string()]).unwrap_err();
+        assert_eq!(err.0, "invalid f64 'soon' for <seconds>");
+
+        let add: Add = from_args(&["My".to_string(), "Song.flac".to_string()]).unwrap();
+        assert_eq!(add.path, "My Song.flac");
+        assert_eq!(field_names::<Seek>(), vec!["seconds", "relative"]);
+        assert_eq!(optional_fields::<Seek>(), vec!["relative"]);
+        assert!(optional_fields::<Add>().is_empty());
+    }
+
+    #[test]
+    fn test_structured_to_args() {
+        let order = vec!["seconds".to_string(), "relative".to_string()];
+        let args = structured_to_args(&serde_json::json!({"seconds": 3, "relative": true}), &order)
+            .unwrap();
+        assert_eq!(args, vec!["3", "true"]);
+        assert!(structured_to_args(&serde_json::json!({"relative": true}), &order).is_err());
+        assert_eq!(
+            structured_to_args(&serde_json::json!([1.5]), &order).unwrap(),
+            vec!["1.5"]
+        );
+    }
+}
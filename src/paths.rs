@@ -0,0 +1,214 @@
+use std::path::{Component, Path, PathBuf};
+use tracing::*;
+
+/// The form track paths are stored in: canonical (symlinks resolved) when the file exists,
+/// otherwise made absolute against the working directory with `.`/`..` folded away, so
+/// the same file added from different directories ends up as one entry.
+pub fn normalize_path(path: &str) -> String {
+    if let Ok(canonical) = Path::new(path).canonicalize() {
+        return canonical.to_string_lossy().into_owned();
+    }
+
+    let joined = match std::env::current_dir() {
+        Ok(cwd) => cwd.join(path),
+        Err(_) => PathBuf::from(path),
+    };
+    let mut out = PathBuf::new();
+    for component in joined.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out.to_string_lossy().into_owned()
+}
+
+/// Environment variable that overrides where the database lives.
+pub const DB_PATH_ENV: &str = "EIGENPLAYER_DB";
+
+/// File name of the database inside the data directory.
+const DB_FILE_NAME: &str = "playlists.db";
+
+/// File name of the config script inside the config directory.
+const CONFIG_FILE_NAME: &str = "config.lua";
+
+/// Expands a leading `~/` to the home directory.
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// `$XDG_DATA_HOME/eigenplayer`, falling back to `~/.local/share/eigenplayer`, or the
+/// working directory when neither is known.
+pub fn data_dir() -> PathBuf {
+    let base = std::env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")));
+    match base {
+        Some(base) => base.join("eigenplayer"),
+        None => PathBuf::from("."),
+    }
+}
+
+/// `$XDG_CONFIG_HOME/eigenplayer`, falling back to `~/.config/eigenplayer`, or the
+/// working directory when neither is known.
+pub fn config_dir() -> PathBuf {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    match base {
+        Some(base) => base.join("eigenplayer"),
+        None => PathBuf::from("."),
+    }
+}
+
+/// `$XDG_STATE_HOME/eigenplayer`, falling back to `~/.local/state/eigenplayer`, or the
+/// working directory when neither is known.
+pub fn state_dir() -> PathBuf {
+    let base = std::env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")));
+    match base {
+        Some(base) => base.join("eigenplayer"),
+        None => PathBuf::from("."),
+    }
+}
+
+/// File the REPL keeps typed commands in.
+pub fn input_history_path() -> PathBuf {
+    state_dir().join("history")
+}
+
+/// Directory downloaded podcast episodes are kept in.
+pub fn podcast_dir() -> PathBuf {
+    data_dir().join("podcasts")
+}
+
+/// Directory plugins are loaded from at startup.
+pub fn scripts_dir() -> PathBuf {
+    config_dir().join("scripts")
+}
+
+/// Picks the database file: the command line flag wins, then the `EIGENPLAYER_DB`
+/// environment variable, then `db.path` from the config, then the data directory.
+/// Empty values count as unset. A `playlists.db` in the working directory, where it used
+/// to live, is still used when the data directory has none.
+pub fn resolve_db_path(cli: Option<&Path>, env: Option<&str>, config: Option<&str>) -> PathBuf {
+    if let Some(path) = cli.filter(|p| !p.as_os_str().is_empty()) {
+        return path.to_path_buf();
+    }
+    let env = env.filter(|p| !p.is_empty());
+    if let Some(path) = env.or(config).filter(|p| !p.is_empty()) {
+        return expand_home(path);
+    }
+    let path = data_dir().join(DB_FILE_NAME);
+    let legacy = Path::new(DB_FILE_NAME);
+    if !path.exists() && legacy.exists() {
+        info!(
+            "[Database] Using {} from the working directory; move it to {} to use it everywhere",
+            DB_FILE_NAME,
+            path.display()
+        );
+        return legacy.to_path_buf();
+    }
+    path
+}
+
+/// Where the config script belongs: `config.lua` in the config directory.
+pub fn config_path() -> PathBuf {
+    config_dir().join(CONFIG_FILE_NAME)
+}
+
+/// Picks the config script: the `--config` flag wins, then `config.lua` in the config
+/// directory. A `config.lua` in the working directory, where it used to live, is still
+/// read when the config directory has none.
+pub fn resolve_config_path(cli: Option<&Path>) -> PathBuf {
+    if let Some(path) = cli.filter(|p| !p.as_os_str().is_empty()) {
+        return path.to_path_buf();
+    }
+    let path = config_path();
+    let legacy = Path::new(CONFIG_FILE_NAME);
+    if !path.exists() && legacy.exists() {
+        return legacy.to_path_buf();
+    }
+    path
+}
+
+/// Replaces `old_prefix` at the start of `path` with `new_prefix`, if it's there as a
+/// whole: `/mnt/music` covers `/mnt/music/a.mp3` but not `/mnt/music2/a.mp3`. A trailing
+/// `/` on either prefix makes no difference.
+pub fn relocate_path(path: &str, old_prefix: &str, new_prefix: &str) -> Option<String> {
+    let rest = path.strip_prefix(old_prefix.trim_end_matches('/'))?;
+    if !rest.is_empty() && !rest.starts_with('/') {
+        return None;
+    }
+    Some(format!("{}{}", new_prefix.trim_end_matches('/'), rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_path() {
+        let cwd = std::env::current_dir().unwrap();
+        let expected = cwd.join("music/a.mp3").to_string_lossy().into_owned();
+        assert_eq!(normalize_path("./music/x/../a.mp3"), expected);
+        assert_eq!(
+            normalize_path("/does/not/exist/../a.mp3"),
+            "/does/not/a.mp3"
+        );
+    }
+
+    #[test]
+    fn test_resolve_db_path() {
+        let cli = Path::new("/tmp/cli.db");
+        assert_eq!(
+            resolve_db_path(Some(cli), Some("/env.db"), Some("/config.db")),
+            cli
+        );
+        assert_eq!(
+            resolve_db_path(None, Some("/env.db"), Some("/config.db")),
+            Path::new("/env.db")
+        );
+        assert_eq!(
+            resolve_db_path(None, Some(""), Some("/config.db")),
+            Path::new("/config.db")
+        );
+        let found = resolve_db_path(None, None, None);
+        assert!(found == data_dir().join("playlists.db") || found == Path::new("playlists.db"));
+        if let Some(home) = std::env::var_os("HOME") {
+            assert_eq!(expand_home("~/music.db"), Path::new(&home).join("music.db"));
+        }
+    }
+
+    #[test]
+    fn test_resolve_config_path() {
+        let cli = Path::new("/tmp/mine.lua");
+        assert_eq!(resolve_config_path(Some(cli)), cli);
+        let found = resolve_config_path(Some(Path::new("")));
+        assert!(found == config_dir().join("config.lua") || found == Path::new("config.lua"));
+    }
+
+    #[test]
+    fn test_relocate_path() {
+        assert_eq!(
+            relocate_path("/mnt/old/a.mp3", "/mnt/old", "/media/new").as_deref(),
+            Some("/media/new/a.mp3")
+        );
+        assert_eq!(
+            relocate_path("/mnt/old/a.mp3", "/mnt/old/", "/media/new/").as_deref(),
+            Some("/media/new/a.mp3")
+        );
+        assert_eq!(relocate_path("/other/a.mp3", "/mnt/old", "/x"), None);
+        assert_eq!(relocate_path("/mnt/old2/a.mp3", "/mnt/old", "/x"), None);
+    }
+}
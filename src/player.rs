@@ -0,0 +1,1316 @@
+use crate::audio::AudioBackend;
+use crate::commands::{advance, register_commands};
+use crate::config;
+use crate::core::*;
+use crate::db::{Database, DbEvent, SharedDatabase};
+use crate::db_writer::{DbWrite, DbWriter};
+use crate::input_history::InputHistory;
+use crate::lua::{
+    apply_config_colors, apply_config_settings, bind_config_keys, init_lua, register_db,
+    run_script, with_core,
+};
+use crate::paths::{DB_PATH_ENV, input_history_path, resolve_db_path, scripts_dir};
+use crate::plugin::{PluginHost, plugin_files, plugin_name};
+use crate::property::register_property;
+use crate::repl::{REPL_COMMANDS, Repl};
+use crate::tokenize::quote;
+use crate::watch::{Change, FileWatcher};
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::*;
+
+/// Builds a `Core` with the stock properties and commands, optionally running a Lua
+/// config script on top. Useful on its own for tests and headless embedders; `Player`
+/// uses it for the state half of the setup.
+pub struct CoreBuilder {
+    properties: bool,
+    commands: bool,
+    config: Option<PathBuf>,
+}
+
+impl Default for CoreBuilder {
+    fn default() -> Self {
+        Self {
+            properties: true,
+            commands: true,
+            config: None,
+        }
+    }
+}
+
+impl CoreBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Skips `register_property`, for embedders that define their own property set.
+    pub fn without_default_properties(mut self) -> Self {
+        self.properties = false;
+        self
+    }
+
+    /// Skips `register_commands`.
+    pub fn without_default_commands(mut self) -> Self {
+        self.commands = false;
+        self
+    }
+
+    /// Lua script run once the properties exist. A missing file is not an error.
+    pub fn config(mut self, path: impl AsRef<Path>) -> Self {
+        self.config = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn build(self) -> SharedCore {
+        self.build_with_config_keys().0
+    }
+
+    /// `build`, also returning the keys the config bound so a reload can tell which
+    /// bindings it dropped.
+    pub(crate) fn build_with_config_keys(self) -> (SharedCore, Vec<String>) {
+        let core = Core::new().into_shared();
+
+        if self.properties {
+            register_property(&mut core.write().unwrap());
+        }
+
+        let mut config_keys = Vec::new();
+        if let Some(path) = &self.config {
+            config_keys = load_config(&core, &mut core.write().unwrap(), path).unwrap_or_default();
+        }
+        if self.properties {
+            apply_env_overrides(&mut core.write().unwrap());
+        }
+
+        if self.commands {
+            register_commands(&mut core.write().unwrap());
+        }
+
+        (core, config_keys)
+    }
+}
+
+/// Runs the config script at `path` against `core`, the locked `shared`, so its timers
+/// can't enter Lua halfway. Returns the keys it bound, or `None` when it couldn't run.
+fn load_config(shared: &SharedCore, core: &mut Core, path: &Path) -> Option<Vec<String>> {
+    let script = match std::fs::read_to_string(path) {
+        Ok(script) => script,
+        Err(_) => {
+            warn!(
+                "[Config] {} not found, using default configuration",
+                path.display()
+            );
+            return None;
+        }
+    };
+    let lua = match init_lua(Arc::clone(shared)) {
+        Ok(lua) => lua,
+        Err(e) => {
+            warn!("[Config] Failed to initialize Lua for config: {}", e);
+            return None;
+        }
+    };
+    let result = with_core(&lua, core, || {
+        run_script(&lua, &script)?;
+        let keys = bind_config_keys(&lua)?;
+        apply_config_colors(&lua)?;
+        apply_config_settings(&lua)?;
+        Ok(keys)
+    });
+    // converted even when the script failed halfway: what it did set still counts
+    config::enforce(core);
+    match result {
+        Ok(keys) => {
+            info!(
+                "[Config] Successfully loaded and executed {}",
+                path.display()
+            );
+            Some(keys)
+        }
+        Err(e) => {
+            warn!("[Config] Failed to execute {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Applies `EIGENPLAYER_<SECTION>__<KEY>` environment overrides, see
+/// `config::apply_env_overrides`.
+fn apply_env_overrides(core: &mut Core) {
+    for problem in config::apply_env_overrides(core, std::env::vars()) {
+        warn!("[Config] Ignoring environment override {}", problem);
+    }
+}
+
+/// Keys among `keys` (as `bind_config_keys` returns them) bound to a command line whose
+/// command neither the REPL nor the core knows. Each is logged, so a typo in
+/// `config.keys` shows up when the config loads rather than when the key is pressed.
+fn check_config_keys(core: &Core, keys: &[String]) -> Vec<String> {
+    let mut unknown = Vec::new();
+    for key in keys {
+        let Some(line) = core.keys.lookup(key) else {
+            continue;
+        };
+        let command = line.split_whitespace().next().unwrap_or("");
+        if !REPL_COMMANDS.contains(&command) && !core.commands.contains_key(command) {
+            warn!(
+                "[Config] Key '{}' is bound to unknown command '{}'",
+                key, command
+            );
+            unknown.push(key.clone());
+        }
+    }
+    unknown
+}
+
+/// Reruns the config at `path` over the running player. Keys `config_keys` (the previous
+/// run's bindings) had that this run no longer binds go back to their defaults, a new
+/// `audio.default_volume` is applied to the volume right away, and `CONFIG_RELOADED`
+/// tells everyone else which properties changed. A config that fails leaves the
+/// bindings alone.
+fn reload_config(shared: &SharedCore, path: &Path, config_keys: &mut Vec<String>) {
+    let mut core = shared.write().unwrap();
+    let before: HashMap<String, PropertyValue> = core
+        .properties_in("")
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.clone()))
+        .collect();
+
+    let Some(keys) = load_config(shared, &mut core, path) else {
+        return;
+    };
+    // the environment still wins over whatever the file says now
+    apply_env_overrides(&mut core);
+    for key in config_keys.iter().filter(|key| !keys.contains(key)) {
+        core.keys.unbind(key);
+    }
+    core.keys.bind_defaults();
+    check_config_keys(&core, &keys);
+    *config_keys = keys;
+
+    let changed: Vec<String> = core
+        .properties_in("")
+        .into_iter()
+        .filter(|(name, value)| before.get(*name) != Some(*value))
+        .map(|(name, _)| name.to_string())
+        .collect();
+    if changed.iter().any(|name| name == "audio.default_volume")
+        && let Some(volume) = core.get_property("audio.default_volume").cloned()
+        && let Err(e) = core.set_property("audio.volume", volume)
+    {
+        warn!("[Config] Failed to apply the new default volume: {}", e);
+    }
+    info!(
+        "[Config] Reloaded {}, {} settings changed",
+        path.display(),
+        changed.len()
+    );
+    core.emit_custom(CONFIG_RELOADED, PropertyValue::StringList(changed));
+}
+
+/// A fully wired player: core state, audio output driven by property observers, and the
+/// playlist database.
+///
+/// ```ignore
+/// let player = Player::builder().config("config.lua").build()?;
+/// player.spawn_ticker();
+/// player.run_repl()?;
+/// ```
+pub struct Player {
+    pub core: SharedCore,
+    pub audio: Arc<Mutex<AudioBackend>>,
+    pub db: Database,
+    pub writer: DbWriter,
+    pub plugins: Arc<Mutex<PluginHost>>,
+    config: Option<PathBuf>,
+    /// Keys the config bound, so reloading it can undo bindings it no longer makes.
+    config_keys: Vec<String>,
+    scripts_dir: PathBuf,
+    json_output: bool,
+}
+
+#[derive(Default)]
+pub struct PlayerBuilder {
+    core: CoreBuilder,
+    db_path: Option<PathBuf>,
+    scripts_dir: Option<PathBuf>,
+    json_output: bool,
+}
+
+impl PlayerBuilder {
+    pub fn config(mut self, path: impl AsRef<Path>) -> Self {
+        self.core = self.core.config(path);
+        self
+    }
+
+    /// Database file to use regardless of `EIGENPLAYER_DB` and `db.path`, e.g. from a
+    /// command line flag.
+    pub fn db_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.db_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Directory to load plugins from instead of `~/.config/eigenplayer/scripts`.
+    pub fn scripts_dir(mut self, path: impl AsRef<Path>) -> Self {
+        self.scripts_dir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Makes the REPL answer in JSON from the start, see `Repl::with_json`.
+    pub fn json_output(mut self, json: bool) -> Self {
+        self.json_output = json;
+        self
+    }
+
+    /// Replaces the core setup entirely, e.g. to drop the default commands.
+    pub fn core(mut self, core: CoreBuilder) -> Self {
+        self.core = core;
+        self
+    }
+
+    /// Runs a Lua script against the core and database instead of starting the player,
+    /// and returns once it and the timers and async tasks it started have finished.
+    /// Nothing is wired to an audio device: playback properties change as usual but make
+    /// no sound. The script sees `core`, `db` and its arguments in `arg` (`arg[0]` being
+    /// the script itself); `core:print` goes to stdout, warnings and errors to stderr.
+    pub fn run_script(
+        self,
+        path: &Path,
+        args: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let source = std::fs::read_to_string(path)?;
+        let core = self.core.build();
+        let db_path = prepare_db_path(self.db_path.as_deref(), &core.read().unwrap())?;
+        let db = Database::new(&db_path.to_string_lossy())?;
+        core.write().unwrap().subscribe_event(
+            Some(&format!("custom:{}", SCRIPT_MESSAGE)),
+            Arc::new(|event, _core| {
+                if let EventType::Custom(_, PropertyValue::StringList(message)) = event
+                    && let [level, text] = message.as_slice()
+                {
+                    match level.as_str() {
+                        "info" => println!("{}", text),
+                        _ => eprintln!("{}: {}", level, text),
+                    }
+                }
+            }),
+        );
+
+        let lua = init_lua(Arc::clone(&core))?;
+        register_db(&lua, db.into_shared())?;
+        let arg = lua.create_table()?;
+        arg.set(0, path.to_string_lossy())?;
+        for (i, value) in args.iter().enumerate() {
+            arg.set(i + 1, value.as_str())?;
+        }
+        lua.globals().set("arg", arg)?;
+        with_core(&lua, &mut core.write().unwrap(), || {
+            lua.load(&source)
+                .set_name(format!("@{}", path.display()))
+                .exec()
+        })?;
+
+        // timers, HTTP replies and async tasks the script started need the core driven
+        while core.read().unwrap().has_pending_timers() {
+            std::thread::sleep(TICK_INTERVAL);
+            core.write().unwrap().tick();
+        }
+        Ok(())
+    }
+
+    pub fn build(self) -> Result<Player, Box<dyn std::error::Error>> {
+        let config = self.core.config.clone();
+        let (core, config_keys) = self.core.build_with_config_keys();
+
+        let db_path = prepare_db_path(self.db_path.as_deref(), &core.read().unwrap())?;
+        let mut db = Database::new(&db_path.to_string_lossy())?;
+        // set before connecting so every connection below reports its changes
+        let (events_tx, events_rx) = mpsc::channel();
+        db.set_event_sender(events_tx);
+        info!("[Database] Initialized {}", db_path.display());
+        let trash_days = core.read().unwrap().get_int("db.trash_days").unwrap_or(30);
+        match db.purge_trash(trash_days.max(0) as u32) {
+            Ok(0) => {}
+            Ok(purged) => info!("[Database] Purged {} deleted playlists", purged),
+            Err(e) => warn!("[Database] Failed to purge deleted playlists: {}", e),
+        }
+
+        let audio = Arc::new(Mutex::new(audio_from_config(&core.read().unwrap())?));
+        // the core's callbacks need their own connection for lookups; `db` moves into
+        // the REPL and writes go through the background writer
+        let core_db = db.connect()?.into_shared();
+        let writer = DbWriter::spawn(db.connect()?);
+
+        {
+            let mut core_lock = core.write().unwrap();
+            attach_audio(&mut core_lock, &audio);
+            restore_queue(&mut core_lock, &audio, &db)?;
+            persist_queue(&mut core_lock, writer.clone())?;
+            record_track_stats(&mut core_lock, writer.clone());
+            advance_on_finish(&mut core_lock);
+            remember_playlist_positions(&mut core_lock, writer.clone());
+            apply_eq_presets(&mut core_lock, Arc::clone(&core_db));
+            resume_positions(&mut core_lock, &audio, Arc::clone(&core_db), writer.clone());
+            forward_db_events(&mut core_lock, events_rx);
+            sync_loaded_playlist(&mut core_lock, Arc::clone(&core_db), writer.clone());
+            add_logging(&mut core_lock);
+        }
+
+        // plugins come last so they see the fully wired core
+        let mut plugins = PluginHost::new(Arc::clone(&core))?;
+        plugins.set_db(db.connect()?.into_shared())?;
+        if core.read().unwrap().get_bool("scripts.sandbox") == Some(true) {
+            info!("[Lua] Running plugins sandboxed");
+            plugins.set_sandboxed(true);
+        }
+        let scripts_dir = self.scripts_dir.unwrap_or_else(scripts_dir);
+        let loaded = plugins.load_dir(&scripts_dir);
+        if loaded > 0 {
+            info!(
+                "[Lua] Loaded {} plugins from {}",
+                loaded,
+                scripts_dir.display()
+            );
+        }
+
+        // plugin commands count, so only now
+        check_config_keys(&core.read().unwrap(), &config_keys);
+
+        Ok(Player {
+            core,
+            audio,
+            db,
+            writer,
+            plugins: Arc::new(Mutex::new(plugins)),
+            config,
+            config_keys,
+            scripts_dir,
+            json_output: self.json_output,
+        })
+    }
+}
+
+/// Resolves the database file (see `resolve_db_path`) and makes sure its directory exists.
+fn prepare_db_path(cli: Option<&Path>, core: &Core) -> std::io::Result<PathBuf> {
+    let env = std::env::var(DB_PATH_ENV).ok();
+    let db_path = resolve_db_path(
+        cli,
+        env.as_deref(),
+        core.get_string("db.path").map(|s| s.as_str()),
+    );
+    if let Some(dir) = db_path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    Ok(db_path)
+}
+
+/// Creates the audio backend from the `audio.*` and `eq.*` properties.
+fn audio_from_config(core: &Core) -> Result<AudioBackend, Box<dyn std::error::Error>> {
+    let default_volume = core.get_float("audio.default_volume").unwrap_or(0.5);
+    let ring_buffer_size = core.get_int("audio.ring_buffer_size").unwrap_or(88200) as usize;
+    let enable_eq = core.get_bool("eq.enabled").unwrap_or(false);
+    let eq_bands = core
+        .get_property("eq.bands")
+        .and_then(|v| v.as_eq_band_list())
+        .cloned()
+        .unwrap_or_default();
+    let producer_sleep_time = core.get_int("audio.producer_sleep_time").unwrap_or(100);
+
+    let mut audio = AudioBackend::with_ring_buffer_size(
+        ring_buffer_size,
+        default_volume,
+        enable_eq,
+        eq_bands,
+        producer_sleep_time as u64,
+    )?;
+    // a device that's gone shouldn't keep the player from starting
+    let (backend, device, sample_format) = output_settings(core);
+    if !(backend.is_empty() && device.is_empty() && sample_format.is_empty())
+        && let Err(e) = audio.set_output(&backend, &device, &sample_format)
+    {
+        warn!("[Audio] {}; using the default output", e);
+    }
+    info!(
+        "[Audio] Initialized audio backend with {} prebuffer packets",
+        ring_buffer_size
+    );
+    Ok(audio)
+}
+
+/// `audio.backend`, `audio.device` and `audio.sample_format`, "" where unset.
+fn output_settings(core: &Core) -> (String, String, String) {
+    let get = |name| core.get_string(name).cloned().unwrap_or_default();
+    (
+        get("audio.backend"),
+        get("audio.device"),
+        get("audio.sample_format"),
+    )
+}
+
+/// Subscribes the backend to the properties that drive it: `playlist.current` loads a
+/// track, `audio.playing` starts/pauses, `audio.volume` sets the gain,
+/// `audio.ring_buffer_size` sizes the buffer of the next track and `audio.backend`,
+/// `audio.device` and `audio.sample_format` reopen the output.
+pub fn attach_audio(core: &mut Core, audio: &Arc<Mutex<AudioBackend>>) {
+    let audio_for_track = Arc::clone(audio);
+    if let Some(prop) = core.properties.get_mut("playlist.current") {
+        prop.subscribe(Arc::new(move |value, core| {
+            if let Some(track) = value.as_string()
+                && track != "none"
+            {
+                info!("[Audio] Loading track: {}", track);
+                let start = core
+                    .get_float("audio.start_position")
+                    .unwrap_or(0.0)
+                    .max(0.0);
+                let mut audio = audio_for_track.lock().unwrap();
+                if let Err(e) = audio.load_track_at(track, start as f64) {
+                    warn!("[Audio] Failed to load track: {}", e);
+                }
+                let duration = audio.duration().unwrap_or(0.0) as f32;
+                if start > 0.0 {
+                    let _ = core.set_property("audio.start_position", PropertyValue::Float(0.0));
+                }
+                let _ = core.set_property("audio.finished", PropertyValue::Bool(false));
+                let _ = core.set_property("audio.position", PropertyValue::Float(start));
+                let _ = core.set_property("audio.duration", PropertyValue::Float(duration));
+            }
+        }));
+    }
+
+    // the output callback can't touch the core, so poll for the end of the track
+    let audio_for_end = Arc::clone(audio);
+    core.schedule_repeating(
+        Duration::from_millis(200),
+        Arc::new(move |core| {
+            let (finished, position) = {
+                let mut audio = audio_for_end.lock().unwrap();
+                (audio.take_finished(), audio.position() as f32)
+            };
+            let last = core.get_float("audio.position").unwrap_or(0.0);
+            if (position - last).abs() >= 1.0 || (finished && position != last) {
+                let _ = core.set_property("audio.position", PropertyValue::Float(position));
+            }
+            if !finished {
+                return;
+            }
+            let _ = core.set_property("audio.finished", PropertyValue::Bool(true));
+            if let Some(track) = core.get_string("playlist.current").cloned() {
+                core.emit_custom(TRACK_FINISHED, PropertyValue::String(track));
+            }
+        }),
+    );
+
+    let audio_for_seek = Arc::clone(audio);
+    core.subscribe_event(
+        Some(&format!("custom:{}", SEEK_REQUESTED)),
+        Arc::new(move |event, _core| {
+            if let EventType::Custom(_, PropertyValue::Float(position)) = event
+                && let Err(e) = audio_for_seek.lock().unwrap().seek(*position as f64)
+            {
+                warn!("[Audio] Failed to seek: {}", e);
+            }
+        }),
+    );
+
+    let audio_for_playing = Arc::clone(audio);
+    if let Some(prop) = core.properties.get_mut("audio.playing") {
+        prop.subscribe(Arc::new(move |value, _core| {
+            if let Some(playing) = value.as_bool() {
+                let mut audio = audio_for_playing.lock().unwrap();
+                if playing {
+                    if let Err(e) = audio.play() {
+                        warn!("[Audio] Failed to start playback: {}", e);
+                    }
+                } else {
+                    audio.pause();
+                }
+            }
+        }));
+    }
+
+    let audio_for_volume = Arc::clone(audio);
+    if let Some(prop) = core.properties.get_mut("audio.volume") {
+        prop.subscribe(Arc::new(move |value, _core| {
+            if let Some(vol) = value.as_float() {
+                let mut audio = audio_for_volume.lock().unwrap();
+                audio.set_volume(vol);
+            }
+        }));
+    }
+
+    let audio_for_bands = Arc::clone(audio);
+    if let Some(prop) = core.properties.get_mut("eq.bands") {
+        prop.subscribe(Arc::new(move |value, _core| {
+            if let Some(bands) = value.as_eq_band_list() {
+                audio_for_bands.lock().unwrap().set_eq_bands(bands.clone());
+            }
+        }));
+    }
+
+    let audio_for_eq = Arc::clone(audio);
+    if let Some(prop) = core.properties.get_mut("eq.enabled") {
+        prop.subscribe(Arc::new(move |value, _core| {
+            if let Some(enabled) = value.as_bool() {
+                audio_for_eq.lock().unwrap().set_eq_enabled(enabled);
+            }
+        }));
+    }
+
+    let audio_for_buffer = Arc::clone(audio);
+    if let Some(prop) = core.properties.get_mut("audio.ring_buffer_size") {
+        prop.subscribe(Arc::new(move |value, _core| {
+            if let Some(size) = value.as_int().filter(|size| *size > 0) {
+                audio_for_buffer
+                    .lock()
+                    .unwrap()
+                    .set_ring_buffer_size(size as usize);
+            }
+        }));
+    }
+
+    let audio_for_output = Arc::clone(audio);
+    let reopen_output: PropertyCallback = Arc::new(move |_value, core| {
+        let (backend, device, sample_format) = output_settings(core);
+        let result = audio_for_output
+            .lock()
+            .unwrap()
+            .set_output(&backend, &device, &sample_format);
+        if let Err(e) = result {
+            error!("[Audio] Failed to switch output: {}", e);
+        }
+    });
+    for name in ["audio.backend", "audio.device", "audio.sample_format"] {
+        if let Some(prop) = core.properties.get_mut(name) {
+            prop.subscribe(Arc::clone(&reopen_output));
+        }
+    }
+}
+
+/// Puts back the running order, current track and offset saved by `persist_queue`. Older
+/// databases without a queue fall back to the `default` playlist.
+fn restore_queue(
+    core: &mut Core,
+    audio: &Arc<Mutex<AudioBackend>>,
+    db: &Database,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut tracks = db.load_queue()?;
+    if tracks.is_empty() {
+        tracks = db.get_playlist_tracks("default")?;
+    }
+    if tracks.is_empty() {
+        return Ok(());
+    }
+    info!("[Database] Restored queue with {} tracks", tracks.len());
+    core.set_property("playlist.tracks", PropertyValue::StringList(tracks))?;
+
+    if let Some((track, position)) = db.get_queue_current()? {
+        core.set_property("playlist.current", PropertyValue::String(track))?;
+        if position > 0.0
+            && let Err(e) = audio.lock().unwrap().seek(position)
+        {
+            warn!("[Audio] Failed to restore position: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Mirrors the running order into the queue table so it survives restarts.
+fn persist_queue(core: &mut Core, writer: DbWriter) -> Result<(), CoreError> {
+    let writer_for_tracks = writer.clone();
+    core.subscribe_property(
+        "playlist.tracks",
+        Rate::Immediate,
+        Arc::new(move |value, _core| {
+            if let Some(tracks) = value.as_string_list() {
+                writer_for_tracks.send(DbWrite::SaveQueue(tracks.clone()));
+            }
+        }),
+    )?;
+
+    let writer_for_current = writer.clone();
+    core.subscribe_property(
+        "playlist.current",
+        Rate::Immediate,
+        Arc::new(move |value, _core| {
+            let track = value.as_string().filter(|t| *t != "none").cloned();
+            writer_for_current.send(DbWrite::SetQueueCurrent(track));
+        }),
+    )?;
+
+    core.subscribe_property(
+        "audio.position",
+        Rate::Throttle(Duration::from_secs(5)),
+        Arc::new(move |value, _core| {
+            if let Some(position) = value.as_float() {
+                writer.send(DbWrite::SetQueuePosition(position as f64));
+            }
+        }),
+    )
+}
+
+/// Keeps the `last_track`/`last_position` of the loaded saved playlist up to date, so
+/// `load <name> --resume` can pick up where it was left.
+fn remember_playlist_positions(core: &mut Core, writer: DbWriter) {
+    fn save(core: &Core, writer: &DbWriter, position: f32) {
+        let (Some(playlist), Some(track), Some(tracks)) = (
+            core.get_string("playlist.name"),
+            core.get_string("playlist.current"),
+            core.get_string_list("playlist.tracks"),
+        ) else {
+            return;
+        };
+        // right after a load the previous playlist's track may still be current
+        if playlist.is_empty() || !tracks.contains(track) {
+            return;
+        }
+        writer.send(DbWrite::SavePlaylistPosition {
+            playlist: playlist.clone(),
+            track: track.clone(),
+            position: position as f64,
+        });
+    }
+
+    let writer_for_current = writer.clone();
+    if let Some(prop) = core.properties.get_mut("playlist.current") {
+        prop.subscribe(Arc::new(move |_value, core| {
+            let start = core.get_float("audio.start_position").unwrap_or(0.0);
+            save(core, &writer_for_current, start);
+        }));
+    }
+    if let Some(prop) = core.properties.get_mut("audio.position") {
+        prop.subscribe_with_rate(
+            Arc::new(move |value, core| {
+                save(core, &writer, value.as_float().unwrap_or(0.0));
+            }),
+            Rate::Throttle(Duration::from_secs(5)),
+        );
+    }
+}
+
+/// Counts completed plays and skips in the database from the track events.
+fn record_track_stats(core: &mut Core, writer: DbWriter) {
+    core.subscribe_event(
+        Some("custom:track_*"),
+        Arc::new(move |event, _core| {
+            let EventType::Custom(name, PropertyValue::String(track)) = event else {
+                return;
+            };
+            match name.as_str() {
+                TRACK_FINISHED => {
+                    writer.send(DbWrite::RecordPlayCompletion(track.clone()));
+                    writer.send(DbWrite::MarkEpisodeFinished(track.clone()));
+                }
+                TRACK_SKIPPED => writer.send(DbWrite::RecordSkip(track.clone())),
+                _ => {}
+            }
+        }),
+    );
+}
+
+/// Moves on (or repeats, see `playlist.repeat`) when a track plays to the end.
+fn advance_on_finish(core: &mut Core) {
+    core.subscribe_event(
+        Some(&format!("custom:{}", TRACK_FINISHED)),
+        Arc::new(|_event, core| {
+            if let Err(e) = advance(core) {
+                warn!("[Core] Failed to advance to the next track: {}", e);
+            }
+        }),
+    );
+}
+
+/// Remembers where long tracks (at least `audio.resume_min_duration` seconds) were left
+/// and picks them up there the next time they're played.
+fn resume_positions(
+    core: &mut Core,
+    audio: &Arc<Mutex<AudioBackend>>,
+    db: SharedDatabase,
+    writer: DbWriter,
+) {
+    fn qualifies(core: &Core) -> bool {
+        let min = core.get_float("audio.resume_min_duration").unwrap_or(600.0);
+        let duration = core.get_float("audio.duration").unwrap_or(0.0);
+        min > 0.0 && duration >= min
+    }
+
+    let audio_for_load = Arc::clone(audio);
+    if let Some(prop) = core.properties.get_mut("playlist.current") {
+        prop.subscribe(Arc::new(move |value, core| {
+            let Some(track) = value.as_string() else {
+                return;
+            };
+            // audio.duration is updated through the queue, so ask the backend directly
+            let duration = audio_for_load.lock().unwrap().duration().unwrap_or(0.0);
+            let min = core.get_float("audio.resume_min_duration").unwrap_or(600.0) as f64;
+            if min <= 0.0 || duration < min {
+                return;
+            }
+            let saved = db.lock().unwrap().get_resume_position(track);
+            if let Ok(Some(position)) = saved {
+                info!("[Audio] Resuming {} at {:.0}s", track, position);
+                if let Err(e) = audio_for_load.lock().unwrap().seek(position) {
+                    warn!("[Audio] Failed to resume: {}", e);
+                }
+            }
+        }));
+    }
+
+    let writer_for_save = writer.clone();
+    core.schedule_repeating(
+        Duration::from_secs(5),
+        Arc::new(move |core| {
+            if core.get_bool("audio.playing") != Some(true)
+                || core.get_bool("audio.finished") == Some(true)
+                || !qualifies(core)
+            {
+                return;
+            }
+            if let (Some(track), Some(position)) = (
+                core.get_string("playlist.current"),
+                core.get_float("audio.position"),
+            ) {
+                writer_for_save.send(DbWrite::SaveResumePosition {
+                    track: track.clone(),
+                    position: position as f64,
+                });
+            }
+        }),
+    );
+
+    core.subscribe_event(
+        Some(&format!("custom:{}", TRACK_FINISHED)),
+        Arc::new(move |event, _core| {
+            if let EventType::Custom(_, PropertyValue::String(track)) = event {
+                writer.send(DbWrite::ClearResumePosition(track.clone()));
+            }
+        }),
+    );
+}
+
+/// Switches to the EQ preset linked to the new track (or its album) and back to the
+/// bands that were active before once a track without one comes on.
+fn apply_eq_presets(core: &mut Core, db: SharedDatabase) {
+    // bands to go back to, set while a linked preset is active
+    let saved: Mutex<Option<Vec<[f32; 4]>>> = Mutex::new(None);
+
+    if let Some(prop) = core.properties.get_mut("playlist.current") {
+        prop.subscribe(Arc::new(move |value, core| {
+            let Some(track) = value.as_string() else {
+                return;
+            };
+            let preset = match db.lock().unwrap().preset_for_track(track) {
+                Ok(preset) => preset,
+                Err(e) => {
+                    warn!("[Database] Failed to look up EQ preset: {}", e);
+                    return;
+                }
+            };
+
+            let mut saved = saved.lock().unwrap();
+            let bands = match preset {
+                Some((name, bands)) => {
+                    info!("[Audio] Using EQ preset '{}' for {}", name, track);
+                    if saved.is_none() {
+                        let current = core
+                            .get_property("eq.bands")
+                            .and_then(|v| v.as_eq_band_list());
+                        *saved = Some(current.cloned().unwrap_or_default());
+                    }
+                    bands
+                }
+                None => match saved.take() {
+                    Some(bands) => bands,
+                    None => return,
+                },
+            };
+            let _ = core.set_property("eq.bands", PropertyValue::EqBandList(bands));
+        }));
+    }
+}
+
+/// Publishes the database's `DbEvent`s as core custom events, so frontends and plugins
+/// can subscribe to `custom:playlist_*` and `custom:history_appended` instead of polling.
+fn forward_db_events(core: &mut Core, events: Receiver<DbEvent>) {
+    let events = Mutex::new(events);
+    core.schedule_repeating(
+        TICK_INTERVAL,
+        Arc::new(move |core| {
+            let pending: Vec<DbEvent> = events.lock().unwrap().try_iter().collect();
+            for event in pending {
+                let (name, payload) = match event {
+                    DbEvent::PlaylistModified(name) => {
+                        (PLAYLIST_MODIFIED, PropertyValue::String(name))
+                    }
+                    DbEvent::PlaylistRenamed { old, new } => {
+                        (PLAYLIST_RENAMED, PropertyValue::StringList(vec![old, new]))
+                    }
+                    DbEvent::PlaylistDeleted(name) => {
+                        (PLAYLIST_DELETED, PropertyValue::String(name))
+                    }
+                    DbEvent::HistoryAppended(track) => {
+                        (HISTORY_APPENDED, PropertyValue::String(track))
+                    }
+                };
+                core.emit_custom(name, payload);
+            }
+        }),
+    );
+}
+
+/// Keeps `playlist.tracks` and `playlist.name` in step with the saved playlist they were
+/// loaded from when it is edited, renamed or deleted through the database. The queue is
+/// only replaced while it still matches what was saved; adds, removes and moves that
+/// weren't saved yet are kept. The other way round, a `move` in a queue that matches the
+/// saved playlist is saved right away, whoever ran it.
+fn sync_loaded_playlist(core: &mut Core, db: SharedDatabase, writer: DbWriter) {
+    // the saved contents of the loaded playlist, as of the last load or change
+    let saved: Arc<Mutex<Vec<String>>> = Arc::default();
+
+    let moved = Arc::clone(&saved);
+    core.add_post_command_hook(Arc::new(move |name, params, _elapsed, core| {
+        let (Some(playlist), [from, to]) = (core.get_string("playlist.name"), params) else {
+            return;
+        };
+        let (Ok(from), Ok(to)) = (from.parse::<usize>(), to.parse::<usize>()) else {
+            return;
+        };
+        if name != "move" || playlist.is_empty() || from == to {
+            return;
+        }
+        let mut saved = moved.lock().unwrap();
+        if from == 0 || from > saved.len() || to == 0 || to > saved.len() {
+            return;
+        }
+        let mut expected = saved.clone();
+        let track = expected.remove(from - 1);
+        expected.insert(to - 1, track);
+        // a queue with unsaved edits doesn't line up with the saved entries
+        if core.get_string_list("playlist.tracks") != Some(&expected) {
+            return;
+        }
+        *saved = expected;
+        writer.send(DbWrite::MovePlaylistEntry {
+            playlist: playlist.clone(),
+            from: from - 1,
+            to: to - 1,
+        });
+    }));
+
+    let snapshot = Arc::clone(&saved);
+    let reader = Arc::clone(&db);
+    let _ = core.subscribe_property(
+        "playlist.name",
+        Rate::Immediate,
+        Arc::new(move |value, _core| {
+            let tracks = match value.as_string().filter(|name| !name.is_empty()) {
+                Some(name) => reader
+                    .lock()
+                    .unwrap()
+                    .get_playlist_tracks(name)
+                    .unwrap_or_default(),
+                None => Vec::new(),
+            };
+            *snapshot.lock().unwrap() = tracks;
+        }),
+    );
+
+    core.subscribe_event(
+        Some("custom:playlist_*"),
+        Arc::new(move |event, core| {
+            let EventType::Custom(name, payload) = event else {
+                return;
+            };
+            let loaded = core
+                .get_string("playlist.name")
+                .cloned()
+                .unwrap_or_default();
+            if loaded.is_empty() {
+                return;
+            }
+            match (name.as_str(), payload) {
+                (PLAYLIST_MODIFIED, PropertyValue::String(playlist)) if *playlist == loaded => {
+                    let tracks = match db.lock().unwrap().get_playlist_tracks(playlist) {
+                        Ok(tracks) => tracks,
+                        Err(e) => {
+                            warn!("[Database] Failed to reload playlist '{}': {}", playlist, e);
+                            return;
+                        }
+                    };
+                    let previous = std::mem::replace(&mut *saved.lock().unwrap(), tracks.clone());
+                    let queue = core.get_string_list("playlist.tracks");
+                    if queue == Some(&tracks) {
+                        return;
+                    }
+                    if queue != Some(&previous) {
+                        info!(
+                            "[Database] Playlist '{}' changed; keeping the unsaved queue",
+                            playlist
+                        );
+                        return;
+                    }
+                    let _ = core.set_property("playlist.tracks", PropertyValue::StringList(tracks));
+                }
+                (PLAYLIST_RENAMED, PropertyValue::StringList(names))
+                    if names.len() == 2 && names[0] == loaded =>
+                {
+                    let _ =
+                        core.set_property("playlist.name", PropertyValue::String(names[1].clone()));
+                }
+                // the queue stays, it just isn't tied to a saved playlist any more
+                (PLAYLIST_DELETED, PropertyValue::String(playlist)) if *playlist == loaded => {
+                    let _ =
+                        core.set_property("playlist.name", PropertyValue::String(String::new()));
+                }
+                _ => {}
+            }
+        }),
+    );
+}
+
+fn add_logging(core: &mut Core) {
+    core.add_post_command_hook(Arc::new(|name, params, elapsed, _core| {
+        debug!("[Core] Command '{}' {:?} took {:?}", name, params, elapsed);
+    }));
+
+    core.subscribe_event(
+        None,
+        Arc::new(|event, _core| match event {
+            EventType::PropertyChanged(name) => {
+                if name != "audio.playing" {
+                    info!("[Core] Property '{}' changed", name);
+                }
+            }
+            EventType::CommandExecuted(name) => {
+                info!("[Core] Command '{}' executed", name);
+            }
+            EventType::Custom(name, payload) => {
+                info!("[Core] Event '{}' emitted: {:?}", name, payload);
+            }
+        }),
+    );
+}
+
+impl Player {
+    pub fn builder() -> PlayerBuilder {
+        PlayerBuilder::default()
+    }
+
+    /// A new connection to the player's database for another thread or frontend.
+    pub fn connect_db(&self) -> rusqlite::Result<SharedDatabase> {
+        Ok(self.db.connect()?.into_shared())
+    }
+
+    /// Starts the thread that drives core timers and throttled/debounced observers.
+    pub fn spawn_ticker(&self) -> std::thread::JoinHandle<()> {
+        let core = Arc::clone(&self.core);
+        std::thread::spawn(move || {
+            loop {
+                std::thread::sleep(TICK_INTERVAL);
+                core.write().unwrap().tick();
+            }
+        })
+    }
+
+    /// Starts the thread that reruns `config.lua` when it is saved (see `reload_config`)
+    /// and loads, reloads or unloads plugins as files in the scripts directory come,
+    /// change and go. Checks once a second.
+    pub fn spawn_watcher(&self) -> std::thread::JoinHandle<()> {
+        let core = Arc::clone(&self.core);
+        let plugins = Arc::clone(&self.plugins);
+        let config = self.config.clone();
+        let mut config_keys = self.config_keys.clone();
+        let scripts_dir = self.scripts_dir.clone();
+        let watched_config = config.clone();
+        let watched = move || {
+            let mut files = plugin_files(&scripts_dir);
+            files.extend(watched_config.clone());
+            files
+        };
+
+        let mut watcher = FileWatcher::new();
+        // everything was loaded at startup
+        watcher.poll(watched());
+        std::thread::spawn(move || {
+            loop {
+                std::thread::sleep(Duration::from_secs(1));
+                for change in watcher.poll(watched()) {
+                    match change {
+                        Change::Modified(path) if Some(&path) == config.as_ref() => {
+                            info!("[Config] {} changed, reloading", path.display());
+                            reload_config(&core, &path, &mut config_keys);
+                        }
+                        Change::Modified(path) => {
+                            let result = plugins.lock().unwrap().load_file(&path);
+                            if let Err(e) = result {
+                                warn!("[Lua] Failed to reload plugin {}: {}", path.display(), e);
+                            }
+                        }
+                        Change::Removed(path) if Some(&path) == config.as_ref() => {}
+                        Change::Removed(path) => {
+                            plugins.lock().unwrap().unload(&plugin_name(&path));
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    /// Runs the prompt until the user quits or stdin ends, then unloads the plugins.
+    /// Returns whether every command succeeded, so piped scripts can fail loudly.
+    pub fn run_repl(self) -> std::io::Result<bool> {
+        let lua = self.plugins.lock().unwrap().lua().clone();
+        let mut repl = Repl::new(self.db, self.writer)
+            .with_lua(lua)
+            .with_plugins(Arc::clone(&self.plugins))
+            .with_json(self.json_output);
+        // piped scripts shouldn't fill the history of the person at the terminal
+        if std::io::stdin().is_terminal() {
+            let path = input_history_path();
+            match InputHistory::load(&path) {
+                Ok(history) => repl = repl.with_input_history(history),
+                Err(e) => warn!("[Core] Failed to read {}: {}", path.display(), e),
+            }
+        }
+        let result = repl.run(&self.core);
+        self.plugins.lock().unwrap().unload_all();
+        result.map(|_| repl.failures() == 0)
+    }
+
+    /// Runs `words` as a single REPL command, e.g. `["play", "song.flac"]`, and returns
+    /// whether it went without errors. A command that starts playback plays until the
+    /// player stops (the queue ran out, or something paused it) before this returns, so
+    /// `eigenplayer play song.flac` plays the song. Needs `spawn_ticker` running.
+    ///
+    /// The command always runs in this process: there is no daemon or IPC endpoint to
+    /// hand it to yet, so a player already running elsewhere is not involved.
+    pub fn run_once(self, words: &[String]) -> std::io::Result<bool> {
+        let lua = self.plugins.lock().unwrap().lua().clone();
+        let mut repl = Repl::new(self.db, self.writer)
+            .with_lua(lua)
+            .with_plugins(Arc::clone(&self.plugins))
+            .with_json(self.json_output);
+        let line: Vec<String> = words.iter().map(|word| quote(word)).collect();
+        repl.execute(&self.core, &line.join(" "))?;
+        loop {
+            {
+                let core = self.core.read().unwrap();
+                if core.get_bool("audio.playing") != Some(true)
+                    || core.get_bool("audio.finished") == Some(true)
+                {
+                    break;
+                }
+            }
+            std::thread::sleep(Duration::from_millis(200));
+        }
+        self.plugins.lock().unwrap().unload_all();
+        Ok(repl.failures() == 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_core_builder_defaults() {
+        let core = CoreBuilder::new().build();
+        let mut core = core.write().unwrap();
+        assert_eq!(core.get_float("audio.volume"), Some(1.0));
+        core.execute_command("volume", vec!["0.3".to_string()])
+            .unwrap();
+        assert_eq!(core.get_float("audio.volume"), Some(0.3));
+
+        let bare = CoreBuilder::new()
+            .without_default_properties()
+            .without_default_commands()
+            .build();
+        let bare = bare.read().unwrap();
+        assert!(bare.properties.is_empty());
+        assert!(bare.commands.is_empty());
+    }
+
+    #[test]
+    fn test_run_script() {
+        let dir = std::env::temp_dir().join("eigenplayer_run_script_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("tidy.lua");
+        std::fs::write(
+            &script,
+            r#"
+            assert(arg[0]:find("tidy.lua") and #arg == 2)
+            db:save_playlist(arg[1], { arg[2] })
+            core:execute_command("volume", { 0.25 })
+            assert(core:get_float("audio.volume") == 0.25)
+            async.run(function()
+                async.sleep(30)
+                db:save_playlist("later", { arg[2] })
+            end)
+            "#,
+        )
+        .unwrap();
+
+        let db_path = dir.join("test.db");
+        Player::builder()
+            .db_path(&db_path)
+            .run_script(&script, &["mix".to_string(), "/m/a.mp3".to_string()])
+            .unwrap();
+        let db = Database::new(&db_path.to_string_lossy()).unwrap();
+        assert_eq!(db.get_playlist_tracks("mix").unwrap(), vec!["/m/a.mp3"]);
+        assert_eq!(db.get_playlist_tracks("later").unwrap(), vec!["/m/a.mp3"]);
+
+        std::fs::write(&script, "error('boom')").unwrap();
+        let failed = Player::builder()
+            .db_path(&db_path)
+            .run_script(&script, &[])
+            .unwrap_err();
+        assert!(failed.to_string().contains("boom"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_reload_config() {
+        let dir = std::env::temp_dir().join("eigenplayer_reload_config_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.lua");
+        std::fs::write(
+            &path,
+            r#"
+            core:set_property("audio.default_volume", 0.5)
+            config.keys = { space = "next", x = "prev" }
+            "#,
+        )
+        .unwrap();
+        let (core, mut config_keys) = CoreBuilder::new().config(&path).build_with_config_keys();
+        let reloaded = Arc::new(Mutex::new(None));
+        let seen = Arc::clone(&reloaded);
+        core.write().unwrap().subscribe_event(
+            Some(&format!("custom:{}", CONFIG_RELOADED)),
+            Arc::new(move |event, _core| {
+                if let EventType::Custom(_, PropertyValue::StringList(changed)) = event {
+                    *seen.lock().unwrap() = Some(changed.clone());
+                }
+            }),
+        );
+
+        std::fs::write(
+            &path,
+            r#"
+            core:set_property("audio.default_volume", 0.2)
+            config.keys = { x = "prev" }
+            "#,
+        )
+        .unwrap();
+        reload_config(&core, &path, &mut config_keys);
+
+        let core = core.read().unwrap();
+        assert_eq!(config_keys, vec!["x"]);
+        // dropped from the config, so back to the stock binding
+        assert_eq!(core.keys.lookup("space"), Some("toggle"));
+        assert_eq!(core.keys.lookup("x"), Some("prev"));
+        assert_eq!(core.get_float("audio.volume"), Some(0.2));
+        assert_eq!(
+            *reloaded.lock().unwrap(),
+            Some(vec!["audio.default_volume".to_string()])
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_check_config_keys() {
+        let core = CoreBuilder::new().build();
+        let mut core = core.write().unwrap();
+        let keys: Vec<String> = ["x", "y", "z", "ctrl+s"].map(String::from).into();
+        core.keys.bind("x", "nexxt");
+        core.keys.bind("y", "stats month");
+        core.keys.bind("z", "volume +0.1");
+        core.keys.bind("ctrl+s", ":lua print(1)");
+        assert_eq!(check_config_keys(&core, &keys), vec!["x"]);
+    }
+
+    #[test]
+    fn test_sync_loaded_playlist() {
+        let path = std::env::temp_dir().join("eigenplayer_sync_playlist_test.db");
+        let _ = std::fs::remove_file(&path);
+        let db = Database::new(&path.to_string_lossy()).unwrap();
+        let tracks = |names: &[&str]| names.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        db.replace_playlist("mix", &tracks(&["/a", "/b"])).unwrap();
+        db.set_entry_note("mix", 0, Some("opener")).unwrap();
+        let writer = DbWriter::spawn(db.connect().unwrap());
+        let db = db.into_shared();
+        let mut core = Core::new();
+        register_property(&mut core);
+        register_commands(&mut core);
+        sync_loaded_playlist(&mut core, Arc::clone(&db), writer.clone());
+        core.set_property(
+            "playlist.tracks",
+            PropertyValue::StringList(tracks(&["/a", "/b"])),
+        )
+        .unwrap();
+        core.set_property("playlist.name", PropertyValue::String("mix".into()))
+            .unwrap();
+        let modified = |core: &mut Core| {
+            core.emit_custom(PLAYLIST_MODIFIED, PropertyValue::String("mix".into()))
+        };
+
+        // a move in an unedited queue is saved, note and all
+        core.execute_command("move", vec!["1".into(), "2".into()])
+            .unwrap();
+        writer.flush();
+        let entries = db.lock().unwrap().get_playlist_entries("mix").unwrap();
+        assert_eq!(entries[1].track, "/a");
+        assert_eq!(entries[1].note.as_deref(), Some("opener"));
+        core.execute_command("move", vec!["2".into(), "1".into()])
+            .unwrap();
+        writer.flush();
+
+        // an unedited queue follows the saved playlist
+        db.lock()
+            .unwrap()
+            .replace_playlist("mix", &tracks(&["/a", "/b", "/c"]))
+            .unwrap();
+        modified(&mut core);
+        assert_eq!(
+            core.get_string_list("playlist.tracks"),
+            Some(&tracks(&["/a", "/b", "/c"]))
+        );
+
+        // an unsaved remove survives a move, a note edit and a change to the saved contents
+        core.set_property(
+            "playlist.tracks",
+            PropertyValue::StringList(tracks(&["/a", "/c"])),
+        )
+        .unwrap();
+        core.execute_command("move", vec!["2".into(), "1".into()])
+            .unwrap();
+        writer.flush();
+        assert_eq!(
+            db.lock().unwrap().get_playlist_tracks("mix").unwrap(),
+            tracks(&["/a", "/b", "/c"])
+        );
+        db.lock()
+            .unwrap()
+            .set_entry_note("mix", 0, Some("opener"))
+            .unwrap();
+        modified(&mut core);
+        db.lock()
+            .unwrap()
+            .replace_playlist("mix", &tracks(&["/a", "/b", "/c", "/d"]))
+            .unwrap();
+        modified(&mut core);
+        assert_eq!(
+            core.get_string_list("playlist.tracks"),
+            Some(&tracks(&["/c", "/a"]))
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,533 @@
+use crate::core::SharedCore;
+use crate::db::SharedDatabase;
+use crate::lua::{Registrations, init_lua, register_db, with_core};
+use mlua::{Function, Lua, MultiValue, Table, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::*;
+
+/// Globals a sandboxed plugin can still reach. `os` and `io` are replaced by the cut-down
+/// versions `sandbox` builds, and tables among these (`string`, `eigen`, `config`, ...)
+/// by shallow copies, so one plugin can't patch them for everyone else.
+const SAFE_GLOBALS: &[&str] = &[
+    "_VERSION",
+    "assert",
+    "error",
+    "getmetatable",
+    "ipairs",
+    "next",
+    "pairs",
+    "pcall",
+    "print",
+    "rawequal",
+    "rawget",
+    "rawlen",
+    "rawset",
+    "select",
+    "setmetatable",
+    "tonumber",
+    "tostring",
+    "type",
+    "xpcall",
+    "string",
+    "table",
+    "math",
+    "utf8",
+    "coroutine",
+    "core",
+    "db",
+    "config",
+    "http",
+    "json",
+    "eigen",
+    "require_api",
+    "async",
+];
+
+/// What `require` hands out in the sandbox: libraries without side effects and the
+/// player's own helpers, as the sandbox's copies.
+const SAFE_MODULES: &[&str] = &[
+    "string",
+    "table",
+    "math",
+    "utf8",
+    "coroutine",
+    "http",
+    "json",
+    "async",
+];
+
+/// The clock and date functions of `os`; the rest runs programs or touches files.
+const SAFE_OS: &[&str] = &["clock", "date", "difftime", "time"];
+
+/// A script loaded from the plugin directory.
+pub struct Plugin {
+    /// File name without the `.lua` extension.
+    pub name: String,
+    pub path: PathBuf,
+    /// The `plugin` table the script filled in (or returned).
+    table: Table,
+    /// What it set up while loading, removed again on unload.
+    registrations: Registrations,
+}
+
+impl Plugin {
+    /// What the plugin set up while loading.
+    pub fn registrations(&self) -> &Registrations {
+        &self.registrations
+    }
+}
+
+/// Owns the Lua state plugins run in and the plugins loaded into it.
+///
+/// Every script runs in its own environment: globals it defines stay private, reads fall
+/// through to the shared globals (`core`, `db`, the standard library). It finds a `plugin`
+/// table there with its `name`, and can set `on_load` / `on_unload` functions on it;
+/// both are called with the `core` userdata. A script may also return its own table
+/// instead.
+///
+/// ```lua
+/// plugin.on_load = function(core)
+///     core:set_or_create("scrobble.enabled", true)
+/// end
+/// ```
+///
+/// Sandboxed plugins (`set_sandboxed`) see only a safe subset of the globals instead:
+/// no `os.execute`, files open read-only, and `require` knows just `SAFE_MODULES`.
+pub struct PluginHost {
+    lua: Lua,
+    core: SharedCore,
+    plugins: Vec<Plugin>,
+    sandboxed: bool,
+}
+
+impl PluginHost {
+    pub fn new(core: SharedCore) -> mlua::Result<Self> {
+        Ok(Self {
+            lua: init_lua(core.clone())?,
+            core,
+            plugins: Vec::new(),
+            sandboxed: false,
+        })
+    }
+
+    /// Runs plugins loaded from now on in the restricted environment, see `sandbox`.
+    pub fn set_sandboxed(&mut self, sandboxed: bool) {
+        self.sandboxed = sandboxed;
+    }
+
+    /// Runs `f` against the plugins' Lua state with the core locked, the order every path
+    /// into Lua takes so a script on this thread and a Lua timer or command on another
+    /// can't deadlock.
+    pub fn with_lua<R>(&self, f: impl FnOnce(&Lua) -> mlua::Result<R>) -> mlua::Result<R> {
+        let mut core = self.core.write().unwrap();
+        with_core(&self.lua, &mut core, || f(&self.lua))
+    }
+
+    /// Makes the library available to plugins as the `db` global. Call it before loading
+    /// any.
+    pub fn set_db(&self, db: SharedDatabase) -> mlua::Result<()> {
+        register_db(&self.lua, db)
+    }
+
+    pub fn lua(&self) -> &Lua {
+        &self.lua
+    }
+
+    pub fn plugins(&self) -> &[Plugin] {
+        &self.plugins
+    }
+
+    /// Loads every `*.lua` file in `dir`, in name order, and returns how many loaded. A
+    /// missing directory means no plugins; a script that fails is logged and skipped.
+    pub fn load_dir(&mut self, dir: &Path) -> usize {
+        let mut loaded = 0;
+        for path in plugin_files(dir) {
+            match self.load_file(&path) {
+                Ok(()) => loaded += 1,
+                Err(e) => warn!("[Lua] Failed to load plugin {}: {}", path.display(), e),
+            }
+        }
+        loaded
+    }
+
+    /// Runs one plugin script and its `on_load`. A plugin with the same name is unloaded
+    /// first, unless the new script doesn't even compile; then the old one stays.
+    pub fn load_file(&mut self, path: &Path) -> mlua::Result<()> {
+        let name = plugin_name(path);
+        let source = fs::read_to_string(path).map_err(mlua::Error::external)?;
+
+        let sandboxed = self.sandboxed;
+        let (plugin, chunk) = self.with_lua(|lua| {
+            let env = lua.create_table()?;
+            let meta = lua.create_table()?;
+            if sandboxed {
+                meta.set("__index", sandbox(lua)?)?;
+                // `load` would otherwise run chunks against the real globals
+                let load = lua
+                    .load(SANDBOXED_LOAD)
+                    .call::<Function>((lua.globals().get::<Function>("load")?, &env))?;
+                env.set("load", load)?;
+            } else {
+                meta.set("__index", lua.globals())?;
+            }
+            env.set_metatable(Some(meta))?;
+            let plugin = lua.create_table()?;
+            plugin.set("name", name.as_str())?;
+            env.set("plugin", &plugin)?;
+
+            let chunk = lua
+                .load(&source)
+                .set_name(format!("@{}", path.display()))
+                .set_environment(env)
+                .into_function()?;
+            Ok((plugin, chunk))
+        })?;
+
+        self.unload(&name);
+        self.lua.set_app_data(Registrations::default());
+        let loaded = self.with_lua(|lua| {
+            let table = match chunk.call::<Value>(())? {
+                Value::Table(table) => table,
+                _ => plugin,
+            };
+            call_hook(&table, "on_load", &lua.globals())?;
+            Ok(table)
+        });
+        let registrations = self
+            .lua
+            .remove_app_data::<Registrations>()
+            .unwrap_or_default();
+
+        match loaded {
+            Ok(table) => {
+                info!("[Lua] Loaded plugin '{}'", name);
+                self.plugins.push(Plugin {
+                    name,
+                    path: path.to_path_buf(),
+                    table,
+                    registrations,
+                });
+                Ok(())
+            }
+            Err(e) => {
+                self.release(&registrations);
+                Err(e)
+            }
+        }
+    }
+
+    /// Takes away the timers, commands, key bindings and observers a plugin registered.
+    fn release(&self, registrations: &Registrations) {
+        let mut core = self.core.write().unwrap();
+        for id in &registrations.timers {
+            core.cancel_timer(*id);
+        }
+        for command in &registrations.commands {
+            core.commands.remove(command);
+        }
+        for key in &registrations.keys {
+            core.keys.unbind(key);
+        }
+        for (property, observer) in &registrations.observers {
+            core.unsubscribe_property(property, observer);
+        }
+    }
+
+    /// Calls the plugin's `on_unload` and forgets it. Returns false if no plugin has that
+    /// name.
+    pub fn unload(&mut self, name: &str) -> bool {
+        let Some(index) = self.plugins.iter().position(|p| p.name == name) else {
+            return false;
+        };
+        let plugin = self.plugins.remove(index);
+        let unloaded = self.with_lua(|lua| call_hook(&plugin.table, "on_unload", &lua.globals()));
+        if let Err(e) = unloaded {
+            warn!("[Lua] on_unload of plugin '{}' failed: {}", plugin.name, e);
+        }
+        self.release(&plugin.registrations);
+        info!("[Lua] Unloaded plugin '{}'", plugin.name);
+        true
+    }
+
+    /// Loads a plugin again from the file it came from. None if no plugin has that name.
+    pub fn reload(&mut self, name: &str) -> Option<mlua::Result<()>> {
+        let path = self.plugins.iter().find(|p| p.name == name)?.path.clone();
+        Some(self.load_file(&path))
+    }
+
+    /// Unloads every plugin, most recently loaded first.
+    pub fn unload_all(&mut self) {
+        while let Some(plugin) = self.plugins.last() {
+            let name = plugin.name.clone();
+            self.unload(&name);
+        }
+    }
+}
+
+/// Wraps `load` for a sandboxed plugin: text chunks only, run in the plugin's environment
+/// unless given another. Written in Lua so the environment stays collectable.
+const SANDBOXED_LOAD: &str = r#"
+local load, env = ...
+return function(chunk, name, _, chunk_env)
+    return load(chunk, name, "t", chunk_env or env)
+end
+"#;
+
+/// Wraps the sandbox's `require` so it hands out the sandbox's copies of the modules.
+/// Written in Lua for the same reason as `SANDBOXED_LOAD`.
+const SANDBOXED_REQUIRE: &str = r#"
+local modules, require = ...
+return function(name)
+    local module = modules[name]
+    if module == nil then
+        return require(name)
+    end
+    return module
+end
+"#;
+
+/// `getmetatable` for a sandboxed plugin: the metatable shared by all strings stays
+/// hidden, everything else is as usual.
+const SANDBOXED_GETMETATABLE: &str = r#"
+local getmetatable = ...
+local string_meta = getmetatable("")
+return function(value)
+    local meta = getmetatable(value)
+    if meta == string_meta then
+        return nil
+    end
+    return meta
+end
+"#;
+
+/// The globals a sandboxed plugin reads through to, built from the current ones.
+fn sandbox(lua: &Lua) -> mlua::Result<Table> {
+    let globals = lua.globals();
+    let sandbox = lua.create_table()?;
+    for name in SAFE_GLOBALS {
+        let value = match globals.get::<Value>(*name)? {
+            Value::Table(full) => {
+                let copy = lua.create_table()?;
+                for pair in full.pairs::<Value, Value>() {
+                    let (key, value) = pair?;
+                    copy.set(key, value)?;
+                }
+                Value::Table(copy)
+            }
+            other => other,
+        };
+        sandbox.set(*name, value)?;
+    }
+
+    let modules = lua.create_table()?;
+    for name in SAFE_MODULES {
+        modules.set(*name, sandbox.get::<Value>(*name)?)?;
+    }
+
+    // the string metatable's `__index` is the real `string` table
+    let getmetatable = lua
+        .load(SANDBOXED_GETMETATABLE)
+        .call::<Function>(globals.get::<Function>("getmetatable")?)?;
+    sandbox.set("getmetatable", getmetatable)?;
+
+    let os = lua.create_table()?;
+    let full_os: Table = globals.get("os")?;
+    for name in SAFE_OS {
+        os.set(*name, full_os.get::<Value>(*name)?)?;
+    }
+    sandbox.set("os", os)?;
+
+    let open: Function = globals.get::<Table>("io")?.get("open")?;
+    let io = lua.create_table()?;
+    io.set(
+        "open",
+        lua.create_function(move |_, (path, mode): (String, Option<String>)| {
+            let mode = mode.unwrap_or_else(|| "r".to_string());
+            if mode != "r" && mode != "rb" {
+                return Err(mlua::Error::RuntimeError(format!(
+                    "sandboxed plugins can only open files for reading, not '{}'",
+                    mode
+                )));
+            }
+            open.call::<MultiValue>((path, mode))
+        })?,
+    )?;
+    sandbox.set("io", io)?;
+
+    let unavailable = lua.create_function(|_, name: String| -> mlua::Result<Value> {
+        Err(mlua::Error::RuntimeError(format!(
+            "module '{}' is not available to sandboxed plugins",
+            name
+        )))
+    })?;
+    let require = lua
+        .load(SANDBOXED_REQUIRE)
+        .call::<Function>((modules, unavailable))?;
+    sandbox.set("require", require)?;
+    Ok(sandbox)
+}
+
+/// The `*.lua` files in `dir`, sorted; empty if it doesn't exist.
+pub fn plugin_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lua"))
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths
+}
+
+/// A plugin is named after its file, without the `.lua`.
+pub fn plugin_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn call_hook(plugin: &Table, hook: &str, globals: &Table) -> mlua::Result<()> {
+    match plugin.get::<Option<Function>>(hook)? {
+        Some(hook) => hook.call(globals.get::<Value>("core")?),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Core;
+
+    #[test]
+    fn test_load_plugins_from_dir() {
+        let dir = std::env::temp_dir().join("eigenplayer_plugin_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("a_counter.lua"),
+            r#"
+            local loads = 0
+            plugin.on_load = function(core)
+                loads = loads + 1
+                core:set_or_create("counter.loads", loads)
+            end
+            plugin.on_unload = function(core)
+                core:set_or_create("counter.unloaded", plugin.name)
+            end
+            core:register_command("count", function() end)
+            core:add_interval(1000, function() end)
+            "#,
+        )
+        .unwrap();
+        fs::write(dir.join("b_broken.lua"), "this is not lua").unwrap();
+        fs::write(dir.join("notes.txt"), "ignored").unwrap();
+
+        let core = Core::new().into_shared();
+        let mut host = PluginHost::new(core.clone()).unwrap();
+        assert_eq!(host.load_dir(&dir), 1);
+        assert_eq!(host.plugins()[0].name, "a_counter");
+        assert_eq!(core.read().unwrap().get_int("counter.loads"), Some(1));
+        // plugin globals stay in the plugin's environment
+        assert!(host.lua().globals().get::<Value>("loads").unwrap().is_nil());
+
+        // reloading replaces the old instance's timer instead of adding a second one
+        let timer = host.plugins()[0].registrations.timers[0];
+        host.reload("a_counter").unwrap().unwrap();
+        assert!(host.reload("missing").is_none());
+        assert_eq!(host.plugins().len(), 1);
+        assert!(!core.read().unwrap().timer_pending(timer));
+
+        host.unload_all();
+        assert!(host.plugins().is_empty());
+        assert!(!core.read().unwrap().commands.contains_key("count"));
+        assert_eq!(
+            core.read().unwrap().get_string("counter.unloaded").cloned(),
+            Some("a_counter".to_string())
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sandbox() {
+        let dir = std::env::temp_dir().join("eigenplayer_sandbox_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("probe.lua");
+        fs::write(
+            &script,
+            r#"
+            assert(os.execute == nil and os.time() > 0)
+            assert(not pcall(io.open, "/tmp/eigenplayer_sandbox_escape", "w"))
+            assert(require("string") == string)
+            assert(not pcall(require, "os"))
+            assert(load("return os.execute")() == nil)
+            assert(load(string.dump(function() end)) == nil)
+            core:set_or_create("sandbox.ok", true)
+            "#,
+        )
+        .unwrap();
+
+        let core = Core::new().into_shared();
+        let mut host = PluginHost::new(core.clone()).unwrap();
+        host.set_sandboxed(true);
+        host.load_file(&script).unwrap();
+        assert_eq!(core.read().unwrap().get_bool("sandbox.ok"), Some(true));
+        // the real globals are untouched
+        let os: Table = host.lua().globals().get("os").unwrap();
+        assert!(os.get::<Option<Function>>("execute").unwrap().is_some());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sandbox_libraries_are_copies() {
+        let dir = std::env::temp_dir().join("eigenplayer_sandbox_copy_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("a_patch.lua"),
+            r#"
+            string.format = function() return "patched" end
+            table.insert = nil
+            math.pi = 3
+            assert(getmetatable("") == nil)
+            assert(not pcall(function()
+                getmetatable("").__index.format = function() return "escaped" end
+            end))
+            assert(getmetatable(setmetatable({}, { x = 1 })).x == 1)
+            eigen.patched = true
+            config.patched = true
+            assert(require("string").format() == "patched")
+            "#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("b_probe.lua"),
+            r#"
+            assert(string.format("%d", 1) == "1")
+            assert(table.insert ~= nil and math.pi > 3.14)
+            assert(eigen.patched == nil and config.patched == nil)
+            assert(("%d"):format(3) == "3")
+            core:set_or_create("sandbox.clean", true)
+            "#,
+        )
+        .unwrap();
+
+        let core = Core::new().into_shared();
+        let mut host = PluginHost::new(core.clone()).unwrap();
+        host.set_sandboxed(true);
+        assert_eq!(host.load_dir(&dir), 2);
+        assert_eq!(core.read().unwrap().get_bool("sandbox.clean"), Some(true));
+        let format: String = host
+            .lua()
+            .load(r#"return string.format("%d", 2)"#)
+            .eval()
+            .unwrap();
+        assert_eq!(format, "2");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
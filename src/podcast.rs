@@ -0,0 +1,348 @@
+use crate::core::{Core, CoreError, PropertyValue};
+use crate::db::Database;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use tracing::*;
+
+/// Playlist that refreshed episodes are saved to, besides being queued.
+pub const PODCAST_PLAYLIST: &str = "podcasts";
+
+/// An `<item>` of an RSS feed that has an audio enclosure.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Episode {
+    /// `<guid>`, falling back to the enclosure URL.
+    pub guid: String,
+    pub title: String,
+    pub url: String,
+    pub published: Option<String>,
+}
+
+/// The bits of an RSS feed the player uses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Feed {
+    pub title: String,
+    pub episodes: Vec<Episode>,
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Text content of the first `<tag>` in `xml`, with CDATA unwrapped.
+fn element_text(xml: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}", tag);
+    let mut search = 0;
+    let start = loop {
+        let found = search + xml[search..].find(&open)?;
+        let after = found + open.len();
+        // don't match <titleFoo> when looking for <title>
+        match xml[after..].chars().next() {
+            Some('>') | Some(' ') | Some('\t') | Some('\n') | Some('\r') => break found,
+            _ => search = after,
+        }
+    };
+    let content_start = start + xml[start..].find('>')? + 1;
+    let content_end = content_start + xml[content_start..].find(&format!("</{}>", tag))?;
+    let raw = xml[content_start..content_end].trim();
+
+    let text = match raw
+        .strip_prefix("<![CDATA[")
+        .and_then(|s| s.strip_suffix("]]>"))
+    {
+        Some(cdata) => cdata.to_string(),
+        None => decode_entities(raw),
+    };
+    Some(text.trim().to_string())
+}
+
+/// Value of `attr` on the first `<tag ...>` in `xml`.
+fn element_attr(xml: &str, tag: &str, attr: &str) -> Option<String> {
+    let start = xml.find(&format!("<{} ", tag))?;
+    let end = start + xml[start..].find('>')?;
+    let element = &xml[start..end];
+    for quote in ['"', '\''] {
+        let key = format!("{}={}", attr, quote);
+        if let Some(pos) = element.find(&key) {
+            let value_start = pos + key.len();
+            let value_end = value_start + element[value_start..].find(quote)?;
+            return Some(decode_entities(&element[value_start..value_end]));
+        }
+    }
+    None
+}
+
+/// Minimal RSS 2.0 reader: enough for podcast feeds, not a general XML parser.
+pub fn parse_feed(xml: &str) -> Feed {
+    let channel_head = xml.split("<item").next().unwrap_or(xml);
+    let title = element_text(channel_head, "title").unwrap_or_else(|| "Untitled".to_string());
+
+    let mut episodes = Vec::new();
+    for chunk in xml.split("<item").skip(1) {
+        let item = match chunk.find("</item>") {
+            Some(end) => &chunk[..end],
+            None => chunk,
+        };
+        let Some(url) = element_attr(item, "enclosure", "url") else {
+            continue;
+        };
+        episodes.push(Episode {
+            guid: element_text(item, "guid").unwrap_or_else(|| url.clone()),
+            title: element_text(item, "title").unwrap_or_else(|| url.clone()),
+            url,
+            published: element_text(item, "pubDate"),
+        });
+    }
+
+    Feed { title, episodes }
+}
+
+/// Opens an `http(s)://` URL, or a local file given as a `file://` URL.
+fn open_url(url: &str) -> Result<Box<dyn Read + Send>, Box<dyn std::error::Error>> {
+    match url.strip_prefix("file://") {
+        Some(path) => Ok(Box::new(File::open(path)?)),
+        None => Ok(ureq::get(url).call()?.into_reader()),
+    }
+}
+
+pub fn fetch_feed(url: &str) -> Result<Feed, Box<dyn std::error::Error>> {
+    let mut body = String::new();
+    open_url(url)?.read_to_string(&mut body)?;
+    Ok(parse_feed(&body))
+}
+
+fn sanitize(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || "-_. ".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    cleaned.trim().to_string()
+}
+
+/// Downloads an episode into `dir` and returns the local path. The file is named after
+/// the episode's `id` in the database as well as its title, so episodes that share a
+/// title ("Trailer", "Bonus") don't overwrite each other.
+pub fn download_episode(
+    episode: &Episode,
+    id: i64,
+    dir: &Path,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    fs::create_dir_all(dir)?;
+    let extension = episode
+        .url
+        .split(['?', '#'])
+        .next()
+        .and_then(|path| Path::new(path).extension())
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("mp3");
+    let target = dir.join(format!("{} {}.{}", id, sanitize(&episode.title), extension));
+
+    let mut source = open_url(&episode.url)?;
+    let mut file = File::create(&target)?;
+    io::copy(&mut source, &mut file)?;
+    Ok(target)
+}
+
+/// Subscribes to a feed, fetching it once for its title. The episodes already published
+/// are recorded as known so only the newest one is queued by the first refresh.
+pub fn subscribe(db: &Database, url: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let feed = fetch_feed(url)?;
+    let feed_id = db.add_feed(url, &feed.title)?;
+    for episode in feed.episodes.iter().skip(1) {
+        if let Some(id) = db.add_episode(feed_id, episode)? {
+            db.set_episode_listened(id)?;
+        }
+    }
+    Ok(feed.title)
+}
+
+/// Fetches every subscribed feed, downloads episodes that weren't seen before (or whose
+/// download failed last time) into `cache_dir/<feed title>/` and appends them to the `podcasts` playlist. Returns the
+/// downloaded paths, for `enqueue`. A feed that fails to refresh is logged and skipped.
+/// This blocks on the network, so it should run without the core locked.
+pub fn refresh(db: &Database, cache_dir: &Path) -> rusqlite::Result<Vec<String>> {
+    let mut queued = Vec::new();
+
+    for (feed_id, url, title) in db.get_feeds()? {
+        let feed = match fetch_feed(&url) {
+            Ok(feed) => feed,
+            Err(e) => {
+                warn!("[Podcast] Failed to refresh {}: {}", url, e);
+                continue;
+            }
+        };
+
+        for episode in &feed.episodes {
+            let Some(id) = db.episode_to_download(feed_id, episode)? else {
+                continue;
+            };
+            match download_episode(episode, id, &cache_dir.join(sanitize(&title))) {
+                Ok(path) => {
+                    let path = path.to_string_lossy().into_owned();
+                    db.set_episode_path(id, &path)?;
+                    db.add_track_to_playlist(PODCAST_PLAYLIST, &path)?;
+                    queued.push(path);
+                }
+                Err(e) => warn!("[Podcast] Failed to download {}: {}", episode.url, e),
+            }
+        }
+    }
+
+    Ok(queued)
+}
+
+/// Appends the episodes `refresh` downloaded to the play queue, skipping any that are
+/// already in it. Returns how many were added.
+pub fn enqueue(core: &mut Core, episodes: &[String]) -> Result<usize, CoreError> {
+    let mut tracks = core
+        .get_string_list("playlist.tracks")
+        .cloned()
+        .unwrap_or_default();
+    let before = tracks.len();
+    for episode in episodes {
+        if !tracks.contains(episode) {
+            tracks.push(episode.clone());
+        }
+    }
+    let added = tracks.len() - before;
+    if added > 0 {
+        core.set_property("playlist.tracks", PropertyValue::StringList(tracks))?;
+    }
+    Ok(added)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEED: &str = r#"<?xml version="1.0"?>
+<rss version="2.0"><channel>
+  <title>Tech &amp; Talk</title>
+  <item>
+    <title><![CDATA[Episode 2: <Rust>]]></title>
+    <guid isPermaLink="false">ep-2</guid>
+    <pubDate>Tue, 02 Jan 2024 10:00:00 GMT</pubDate>
+    <enclosure url="https://example.com/ep2.mp3?x=1&amp;y=2" type="audio/mpeg" length="1"/>
+  </item>
+  <item>
+    <title>Episode 1</title>
+    <enclosure url='https://example.com/ep1.mp3' type='audio/mpeg'/>
+  </item>
+  <item><title>Show notes only</title></item>
+</channel></rss>"#;
+
+    #[test]
+    fn test_parse_feed() {
+        let feed = parse_feed(FEED);
+        assert_eq!(feed.title, "Tech & Talk");
+        assert_eq!(feed.episodes.len(), 2);
+
+        let latest = &feed.episodes[0];
+        assert_eq!(latest.title, "Episode 2: <Rust>");
+        assert_eq!(latest.guid, "ep-2");
+        assert_eq!(latest.url, "https://example.com/ep2.mp3?x=1&y=2");
+        assert_eq!(
+            latest.published.as_deref(),
+            Some("Tue, 02 Jan 2024 10:00:00 GMT")
+        );
+
+        // no <guid>, so the enclosure URL identifies it
+        assert_eq!(feed.episodes[1].guid, "https://example.com/ep1.mp3");
+    }
+
+    fn feed_xml(dir: &Path, episodes: &[(&str, &str)]) -> String {
+        let items: String = episodes
+            .iter()
+            .map(|(guid, title)| {
+                format!(
+                    "<item><title>{}</title><guid>{}</guid>\
+                     <enclosure url=\"file://{}\" type=\"audio/mpeg\"/></item>",
+                    title,
+                    guid,
+                    dir.join(format!("{}.mp3", guid)).display()
+                )
+            })
+            .collect();
+        format!(
+            "<rss><channel><title>Local</title>{}</channel></rss>",
+            items
+        )
+    }
+
+    #[test]
+    fn test_refresh_local_feed() {
+        let dir = std::env::temp_dir().join("eigenplayer_podcast_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for guid in ["ep1", "ep2"] {
+            fs::write(dir.join(format!("{}.mp3", guid)), guid).unwrap();
+        }
+        let feed = dir.join("feed.xml");
+        let url = format!("file://{}", feed.display());
+        let cache = dir.join("cache");
+        let db = Database::in_memory().unwrap();
+
+        // newest first; subscribing marks everything but the newest as listened
+        fs::write(&feed, feed_xml(&dir, &[("ep2", "Bonus"), ("ep1", "Pilot")])).unwrap();
+        assert_eq!(subscribe(&db, &url).unwrap(), "Local");
+        let first = refresh(&db, &cache).unwrap();
+        assert_eq!(first.len(), 1);
+        assert_eq!(fs::read_to_string(&first[0]).unwrap(), "ep2");
+        assert_eq!(
+            db.get_feed_summaries().unwrap(),
+            vec![("Local".to_string(), 1)]
+        );
+
+        // a new episode with the same title gets its own file; known ones are skipped
+        fs::write(
+            &feed,
+            feed_xml(
+                &dir,
+                &[("ep3", "Bonus"), ("ep2", "Bonus"), ("ep1", "Pilot")],
+            ),
+        )
+        .unwrap();
+        // its file isn't there yet, so the download fails and is retried next time
+        assert!(refresh(&db, &cache).unwrap().is_empty());
+        fs::write(dir.join("ep3.mp3"), "ep3").unwrap();
+        let second = refresh(&db, &cache).unwrap();
+        assert_eq!(second.len(), 1);
+        assert_ne!(second[0], first[0]);
+        assert_eq!(fs::read_to_string(&second[0]).unwrap(), "ep3");
+        assert_eq!(fs::read_to_string(&first[0]).unwrap(), "ep2");
+        assert!(refresh(&db, &cache).unwrap().is_empty());
+        assert_eq!(
+            db.get_feed_summaries().unwrap(),
+            vec![("Local".to_string(), 2)]
+        );
+        assert_eq!(
+            db.get_playlist_tracks(PODCAST_PLAYLIST).unwrap(),
+            vec![first[0].clone(), second[0].clone()]
+        );
+
+        let mut core = Core::new();
+        crate::property::register_property(&mut core);
+        assert_eq!(enqueue(&mut core, &first).unwrap(), 1);
+        assert_eq!(
+            enqueue(&mut core, &[first[0].clone(), second[0].clone()]).unwrap(),
+            1
+        );
+        assert_eq!(
+            core.get_string_list("playlist.tracks").unwrap(),
+            &vec![first[0].clone(), second[0].clone()]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
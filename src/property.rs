@@ -1,15 +1,81 @@
 use crate::core::*;
+use crate::style::Role;
 
+/// Properties read once while the player starts; changing them later only matters for
+/// the next run.
+pub const RESTART_PROPERTIES: &[&str] = &[
+    "db.path",
+    "db.trash_days",
+    "audio.producer_sleep_time",
+    "audio.exclusive",
+];
+
+/// Registers the built-in properties. Names are namespaced by the subsystem that owns
+/// them (`audio.*`, `playlist.*`, `eq.*`), see `Core::property_names_in`.
 pub fn register_property(core: &mut Core) {
     // Playback properties
-    core.add_property("playing", PropertyValue::Bool(false));
-    core.add_property("current_track", PropertyValue::String("none".to_string()));
-    core.add_property("volume", PropertyValue::Float(1.0));
-    core.add_property("playlist", PropertyValue::StringList(Vec::new()));
-    core.add_property("enable_eq", PropertyValue::Bool(false));
-    // Config properties - these will be set from config.lua
-    core.add_property("ring_buffer_size", PropertyValue::Int(88200));
-    core.add_property("default_volume", PropertyValue::Float(0.5));
-    core.add_property("eq_bands", PropertyValue::EqBandList(Vec::new()));
-    core.add_property("producer_sleep_time", PropertyValue::Int(100));
+    core.add_property("audio.playing", PropertyValue::Bool(false));
+    core.add_property(
+        "playlist.current",
+        PropertyValue::String("none".to_string()),
+    );
+    core.add_property("audio.volume", PropertyValue::Float(1.0));
+    // set by the player when the current track ends, cleared when another one loads
+    core.add_property("audio.finished", PropertyValue::Bool(false));
+    core.history.untrack("audio.finished");
+    // playback position and length of the current track, in seconds
+    core.add_property("audio.position", PropertyValue::Float(0.0));
+    core.add_property("audio.duration", PropertyValue::Float(0.0));
+    core.history.untrack("audio.position");
+    core.history.untrack("audio.duration");
+    core.add_property("playlist.tracks", PropertyValue::StringList(Vec::new()));
+    // next/prev pick tracks in random order instead of playlist order
+    core.add_property("playlist.shuffle", PropertyValue::Bool(false));
+    // what happens when a track ends: "off" moves on and stops after the last track,
+    // "one" plays it again, "all" wraps around to the start
+    core.add_property("playlist.repeat", PropertyValue::String("off".to_string()));
+    // tracks played so far in this shuffle round, in order, so prev can go back
+    core.add_property(
+        "playlist.shuffle_played",
+        PropertyValue::StringList(Vec::new()),
+    );
+    core.history.untrack("playlist.shuffle_played");
+    // saved playlist the queue was last loaded from or saved to, "" if none
+    core.add_property("playlist.name", PropertyValue::String(String::new()));
+    // offset (seconds) the next loaded track starts at; reset to 0 once it's used
+    core.add_property("audio.start_position", PropertyValue::Float(0.0));
+    core.history.untrack("audio.start_position");
+    core.add_property("eq.enabled", PropertyValue::Bool(false));
+    // Config properties - these will be set from config.lua; see RESTART_PROPERTIES for
+    // the ones only read at startup
+    core.add_property("audio.ring_buffer_size", PropertyValue::Int(88200));
+    core.add_property("audio.default_volume", PropertyValue::Float(0.5));
+    core.add_property("eq.bands", PropertyValue::EqBandList(Vec::new()));
+    core.add_property("audio.producer_sleep_time", PropertyValue::Int(100));
+    // audio API (see `devices`), output device by name and the sample format written to
+    // it; "" means the platform's default for each
+    core.add_property("audio.backend", PropertyValue::String(String::new()));
+    core.add_property("audio.device", PropertyValue::String(String::new()));
+    core.add_property("audio.sample_format", PropertyValue::String(String::new()));
+    // ask for the device to ourselves; no backend supports it yet, so only warned about
+    core.add_property("audio.exclusive", PropertyValue::Bool(false));
+    // database file; "" means the data directory (see paths::resolve_db_path)
+    core.add_property("db.path", PropertyValue::String(String::new()));
+    // deleted playlists older than this many days are purged at startup
+    core.add_property("db.trash_days", PropertyValue::Int(30));
+    // tracks at least this long (seconds) resume where they were left; 0 disables
+    core.add_property("audio.resume_min_duration", PropertyValue::Float(600.0));
+    // run plugins without os.execute, file writes or arbitrary require (plugin::SAFE_*)
+    core.add_property("scripts.sandbox", PropertyValue::Bool(false));
+    // REPL colors (see style::Style); output that isn't a terminal stays plain anyway
+    core.add_property("ui.colors", PropertyValue::Bool(true));
+    // REPL prompt; {track}, {pos}, {vol} and {playing_icon} are filled in before each
+    // command
+    core.add_property("ui.prompt", PropertyValue::String("> ".to_string()));
+    for role in Role::ALL {
+        core.add_property(
+            &role.property(),
+            PropertyValue::String(role.default_color().to_string()),
+        );
+    }
 }
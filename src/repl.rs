@@ -1,271 +1,2968 @@
-use crate::core::{Core, PropertyValue};
-use crate::db::Database;
-use std::io::{self, Write};
+use crate::audio::AudioBackend;
+use crate::config;
+use crate::core::{
+    Core, EventType, PropertyCallback, PropertyValue, Rate, SCRIPT_MESSAGE, SharedCore,
+};
+use crate::db::{
+    CopyOutcome, Database, EqTarget, FAVORITES_PLAYLIST, RenameOutcome, StatsPeriod,
+    UndeleteOutcome,
+};
+use crate::db_writer::{DbWrite, DbWriter};
+use crate::eq::{band_type_name, parse_band_type};
+use crate::export;
+use crate::input_history::InputHistory;
+use crate::lua::{self, with_core};
+use crate::metadata::{probe_file, read_metadata};
+use crate::paths::{config_path, expand_home, normalize_path, podcast_dir, relocate_path};
+use crate::plugin::{PluginHost, plugin_name};
+use crate::podcast;
+use crate::property::RESTART_PROPERTIES;
+use crate::scan::scan_directory;
+use crate::style::Style;
+use crate::terminal::{RawMode, read_key};
+use crate::tokenize::tokenize;
+use crate::xspf::export_playlist;
+use mlua::{Lua, MultiValue};
+use std::cell::{Cell, RefCell};
+use std::fmt::Display;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+use tracing::*;
+
+/// `println!` for command output: printed, or collected for the JSON response.
+macro_rules! say {
+    ($repl:expr) => {
+        $repl.say(String::new())
+    };
+    ($repl:expr, $($arg:tt)*) => {
+        $repl.say(format!($($arg)*))
+    };
+}
+
+/// `eprintln!` in the theme's error color, or an error in the JSON response.
+macro_rules! print_error {
+    ($repl:expr, $($arg:tt)*) => {
+        $repl.report_error(format!($($arg)*))
+    };
+}
+
+/// What one command printed, collected in JSON mode.
+#[derive(Default)]
+struct Response {
+    command: String,
+    output: Vec<String>,
+    errors: Vec<String>,
+}
+
+/// Commands `Repl::handle` takes care of itself, aliases included; any other command
+/// line has to name a core command. Keep in step with `handle`.
+pub const REPL_COMMANDS: &[&str] = &[
+    ":lua",
+    "clear",
+    "watch",
+    "keys",
+    "plugin",
+    "quit",
+    "exit",
+    "q",
+    "format",
+    "help",
+    "h",
+    "status",
+    "playlist",
+    "pl",
+    "note",
+    "weight",
+    "playlists",
+    "history",
+    "config",
+    "dump",
+    "log",
+    "play",
+    "pause",
+    "next",
+    "n",
+    "prev",
+    "p",
+    "shuffle",
+    "repeat",
+    "goto",
+    "g",
+    "seek",
+    "undo",
+    "redo",
+    "reset",
+    "search",
+    "add",
+    "a",
+    "move",
+    "mv",
+    "remove",
+    "rm",
+    "like",
+    "unlike",
+    "rate",
+    "info",
+    "podcast",
+    "tag",
+    "missing",
+    "relocate",
+    "folder",
+    "delete",
+    "undelete",
+    "rename",
+    "export",
+    "export-db",
+    "ls",
+    "eq",
+    "recent",
+    "added",
+    "stats",
+    "db",
+    "scan",
+    "volume",
+    "vol",
+    "v",
+    "devices",
+    "device",
+    "load",
+    "save",
+];
 
 pub struct Repl {
     db: Database,
+    writer: DbWriter,
+    lua: Option<Lua>,
+    plugins: Option<Arc<Mutex<PluginHost>>>,
+    /// Tracks the last `search` listed; `#n` picks one of them.
+    results: Vec<String>,
+    /// Colors from the `ui.*` properties, refreshed before each command.
+    style: Style,
+    /// Answer every command with one JSON object per line instead of text.
+    json: Arc<AtomicBool>,
+    /// The command being handled in JSON mode and what it printed so far.
+    response: RefCell<Option<Response>>,
+    /// How many errors commands have reported so far.
+    failures: Cell<usize>,
+    /// The prompt shown while it waits for input, so script messages can redraw it.
+    at_prompt: Arc<Mutex<Option<String>>>,
+    /// Whether a person types the input. Piped input gets no prompts or questions.
+    interactive: bool,
+    /// Set once `run` reads commands, so slow work can finish in the background and
+    /// report back while the prompt stays usable.
+    running: bool,
+    /// Lines typed at the prompt, for `!!` and `!n`.
+    input_history: InputHistory,
 }
 
 impl Repl {
-    pub fn new(db: Database) -> Self {
-        Self { db }
+    /// `db` serves the listings; playlist edits are queued on `writer`.
+    pub fn new(db: Database, writer: DbWriter) -> Self {
+        Self {
+            db,
+            writer,
+            lua: None,
+            plugins: None,
+            results: Vec::new(),
+            style: Style::plain(),
+            json: Arc::new(AtomicBool::new(false)),
+            response: RefCell::new(None),
+            failures: Cell::new(0),
+            at_prompt: Arc::new(Mutex::new(None)),
+            interactive: io::stdin().is_terminal(),
+            running: false,
+            input_history: InputHistory::default(),
+        }
+    }
+
+    /// Interpreter that `:lua` evaluates in, normally the plugins' one so their state can
+    /// be inspected.
+    pub fn with_lua(mut self, lua: Lua) -> Self {
+        self.lua = Some(lua);
+        self
+    }
+
+    /// Plugins the `plugin` command lists, loads and unloads.
+    pub fn with_plugins(mut self, plugins: Arc<Mutex<PluginHost>>) -> Self {
+        self.plugins = Some(plugins);
+        self
+    }
+
+    /// Recalls and records typed lines in `history`, e.g. one loaded from disk.
+    pub fn with_input_history(mut self, history: InputHistory) -> Self {
+        self.input_history = history;
+        self
+    }
+
+    /// Starts in JSON mode (see `format json`), for programs driving the REPL.
+    pub fn with_json(self, json: bool) -> Self {
+        self.json.store(json, Ordering::SeqCst);
+        self
+    }
+
+    /// Number of errors reported since the REPL was created, e.g. to pick an exit code.
+    pub fn failures(&self) -> usize {
+        self.failures.get()
+    }
+
+    /// Prompts and questions are for a person at a terminal, not for programs reading
+    /// JSON or scripts piping commands in.
+    fn prompts(&self) -> bool {
+        self.interactive && !self.json()
+    }
+
+    fn json(&self) -> bool {
+        self.json.load(Ordering::SeqCst)
+    }
+
+    fn say(&self, text: String) {
+        match self.response.borrow_mut().as_mut() {
+            Some(response) => response.output.extend(
+                text.lines()
+                    .filter(|line| !line.is_empty())
+                    .map(String::from),
+            ),
+            None => println!("{}", text),
+        }
+    }
+
+    fn report_error(&self, text: String) {
+        self.failures.set(self.failures.get() + 1);
+        match self.response.borrow_mut().as_mut() {
+            Some(response) => response.errors.push(text),
+            None => eprintln!("{}", self.style.error(text)),
+        }
+    }
+
+    /// In JSON mode, collects what `input` prints until `finish_response`.
+    fn begin_response(&self, input: &str) {
+        if self.json() {
+            *self.response.borrow_mut() = Some(Response {
+                command: input.to_string(),
+                ..Default::default()
+            });
+        }
+    }
+
+    /// Prints the collected response as `{"command", "ok", "output", "errors"}`.
+    fn finish_response(&self) {
+        let Some(response) = self.response.borrow_mut().take() else {
+            return;
+        };
+        let json = serde_json::json!({
+            "command": response.command,
+            "ok": response.errors.is_empty(),
+            "output": response.output,
+            "errors": response.errors,
+        });
+        println!("{}", json);
+    }
+
+    fn refresh_style(&mut self, core: &Core) {
+        self.style = if self.json() {
+            Style::plain()
+        } else {
+            Style::from_core(core)
+        };
     }
 
-    pub fn run(&mut self, core: &mut Core) -> io::Result<()> {
-        println!("EigenPlayer REPL");
-        println!("Type 'help' for available commands, 'quit' to exit\n");
+    /// Reads commands from stdin until `quit` or end of input. The core is only locked
+    /// while a command is being handled, so other threads (audio, Lua, remote frontends)
+    /// can use it between commands. Without a terminal (a pipe, a cron job) the lines are
+    /// read as a script: no prompts, and `clear` needs `--force` instead of asking.
+    /// `failures` tells afterwards whether any went wrong.
+    pub fn run(&mut self, shared: &SharedCore) -> io::Result<()> {
+        if self.prompts() {
+            println!("EigenPlayer REPL");
+            println!("Type 'help' for available commands, 'quit' to exit\n");
+        }
+        {
+            let mut core = shared.write().unwrap();
+            self.refresh_style(&core);
+            self.show_script_messages(&mut core);
+        }
+        self.running = true;
 
         loop {
-            print!("> ");
-            io::stdout().flush()?;
+            let prompt = self
+                .prompts()
+                .then(|| render_prompt(&shared.read().unwrap(), &self.db));
+            if let Some(prompt) = &prompt {
+                print!("{}", prompt);
+                io::stdout().flush()?;
+            }
 
             let mut input = String::new();
-            io::stdin().read_line(&mut input)?;
+            *self.at_prompt.lock().unwrap() = prompt;
+            let read = io::stdin().read_line(&mut input);
+            *self.at_prompt.lock().unwrap() = None;
 
+            if read? == 0 {
+                break;
+            }
             let input = input.trim();
             if input.is_empty() {
                 continue;
             }
+            let input = match self.input_history.expand(input) {
+                Ok(Some(line)) => {
+                    if self.prompts() {
+                        println!("{}", line);
+                    }
+                    line
+                }
+                Ok(None) => input.to_string(),
+                Err(e) => {
+                    self.begin_response(input);
+                    print_error!(self, "{}", e);
+                    self.finish_response();
+                    continue;
+                }
+            };
+            if let Err(e) = self.input_history.push(&input) {
+                warn!("[Core] Failed to save input history: {}", e);
+            }
+            if self.execute(shared, &input)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs one command line as if typed at the prompt (answered in JSON in JSON mode).
+    /// Returns true when it asks to quit.
+    pub fn execute(&mut self, shared: &SharedCore, input: &str) -> io::Result<bool> {
+        self.begin_response(input);
+        let quit = self.handle(shared, input);
+        self.finish_response();
+        quit
+    }
+
+    /// The core is locked only while the command runs.
+    fn handle(&mut self, shared: &SharedCore, input: &str) -> io::Result<bool> {
+        // `:lua` keeps the raw text; without code it reads lines until `:end`, so the
+        // core must not be locked while it waits for input
+        if let Some(code) = input.strip_prefix(":lua") {
+            if code.trim().is_empty() {
+                self.lua_mode(shared)?;
+            } else if code.starts_with(char::is_whitespace) {
+                self.eval_lua(&mut shared.write().unwrap(), code.trim());
+            } else {
+                print_error!(self, "Unknown command: '{}'", input);
+            }
+            return Ok(false);
+        }
+
+        // asking for confirmation waits on stdin, which must not happen with the core
+        // locked
+        if let Some(flag) = input.strip_prefix("clear")
+            && (flag.is_empty() || flag.starts_with(char::is_whitespace))
+        {
+            match flag.trim() {
+                "" => self.clear_playlist(shared, false)?,
+                "--force" | "-f" => self.clear_playlist(shared, true)?,
+                _ => print_error!(self, "Usage: clear [--force]"),
+            }
+            return Ok(false);
+        }
+
+        // watching waits on stdin for the key that ends it
+        if input == "watch" {
+            self.watch(shared)?;
+            return Ok(false);
+        }
+
+        // key mode reads stdin key by key and runs what each key is bound to
+        if input == "keys" {
+            return self.key_mode(shared);
+        }
+
+        let (command, args) = match tokenize(input) {
+            Ok(mut words) if !words.is_empty() => {
+                let command = words.remove(0);
+                (command, words)
+            }
+            Ok(_) => return Ok(false),
+            Err(e) => {
+                print_error!(self, "Can't parse the command: {}", e);
+                return Ok(false);
+            }
+        };
+        let command = command.as_str();
+
+        // refreshing waits on the network; the core is only locked to queue the episodes
+        if command == "podcast" && args == ["refresh"] {
+            self.refresh_podcasts(shared);
+            return Ok(false);
+        }
 
-            let parts: Vec<&str> = input.split_whitespace().collect();
-            let command = parts[0];
-            let args: Vec<String> = parts[1..].iter().map(|s| s.to_string()).collect();
+        // the plugin host locks the core itself while scripts run
+        if command == "plugin" {
+            self.plugin_command(&args);
+            return Ok(false);
+        }
+
+        let mut core_lock = shared.write().unwrap();
+        let core = &mut *core_lock;
+        self.refresh_style(core);
 
-            match command {
-                "quit" | "exit" | "q" => {
-                    println!("Goodbye!");
-                    break;
+        match command {
+            "quit" | "exit" | "q" => {
+                say!(self, "Goodbye!");
+                return Ok(true);
+            }
+            "format" => match args.first().map(|f| f.as_str()) {
+                Some("json") => {
+                    self.json.store(true, Ordering::SeqCst);
+                    self.begin_response(input);
                 }
-                "help" | "h" => {
-                    self.print_help();
+                Some("text") => {
+                    say!(self, "Output format: text");
+                    self.finish_response();
+                    self.json.store(false, Ordering::SeqCst);
                 }
-                "status" => {
-                    self.print_status(core);
+                _ => say!(
+                    self,
+                    "Output format: {}",
+                    if self.json() { "json" } else { "text" }
+                ),
+            },
+            "help" | "h" => match args.first() {
+                None => self.print_help(),
+                Some(name) => match core.command_usage(name) {
+                    Some(usage) => {
+                        self.print_header(name);
+                        for line in usage.help() {
+                            say!(self, "{}", line);
+                        }
+                    }
+                    None => print_error!(
+                        self,
+                        "No detailed help for '{}'; 'help' lists every command",
+                        name
+                    ),
+                },
+            },
+            "status" => {
+                self.print_status(core);
+            }
+            "playlist" | "pl" => match args.split_first() {
+                None => self.show_playlist(core),
+                Some((action, rest)) if action == "delete" && !rest.is_empty() => {
+                    self.delete_playlist(&rest.join(" "))
                 }
-                "playlist" | "pl" => {
-                    self.show_playlist(core);
+                Some((action, [old, new])) if action == "rename" => self.rename_playlist(old, new),
+                Some((action, [src, dst])) if action == "copy" => self.copy_playlist(src, dst),
+                Some((action, rest)) if action == "show" && !rest.is_empty() => {
+                    self.writer.flush();
+                    self.show_saved_playlist(&rest.join(" "));
                 }
-                "playlists" => {
-                    self.show_all_playlists();
+                // anything that isn't a subcommand names a playlist, so one called
+                // "copy" or "rename" can still be viewed
+                Some(_) => {
+                    self.writer.flush();
+                    self.show_saved_playlist(&args.join(" "));
                 }
-                "history" => {
-                    self.show_history();
+            },
+            "note" => match args.get(1).and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) if n > 0 => {
+                    let text = args[2..].join(" ");
+                    let note = (!text.is_empty()).then_some(text.as_str());
+                    self.writer.flush();
+                    match self.db.set_entry_note(&args[0], n - 1, note) {
+                        Ok(true) if note.is_some() => say!(self, "Noted entry {}", n),
+                        Ok(true) => say!(self, "Cleared note on entry {}", n),
+                        Ok(false) => say!(self, "'{}' has no entry {}", args[0], n),
+                        Err(e) => print_error!(self, "Failed to set note: {}", e),
+                    }
                 }
-                "play" => {
-                    if args.is_empty() {
-                        core.set_property("playing", PropertyValue::Bool(true));
-                        println!("Resumed playback");
-                    } else {
-                        let track = args.join(" ");
-                        core.execute_command("play", vec![track]);
+                _ => print_error!(self, "Usage: note <playlist> <n> [text]"),
+            },
+            "weight" => {
+                let index = args.get(1).and_then(|n| n.parse::<usize>().ok());
+                let weight = args.get(2).and_then(|w| w.parse::<i64>().ok());
+                match (index, weight) {
+                    (Some(n), Some(weight)) if n > 0 => {
+                        self.writer.flush();
+                        match self.db.set_entry_weight(&args[0], n - 1, weight) {
+                            Ok(true) => say!(self, "Set weight of entry {} to {}", n, weight),
+                            Ok(false) => say!(self, "'{}' has no entry {}", args[0], n),
+                            Err(e) => print_error!(self, "Failed to set weight: {}", e),
+                        }
                     }
+                    _ => print_error!(self, "Usage: weight <playlist> <n> <weight>"),
                 }
-                "pause" => {
-                    core.execute_command("pause", vec![]);
-                    println!("Paused");
+            }
+            "playlists" => {
+                // reads below should see playlist edits still in the writer queue
+                self.writer.flush();
+                self.show_all_playlists();
+            }
+            "history" => self.history(&args),
+            "config" => self.config(core, &args),
+            "dump" => match serde_json::to_string_pretty(&core.snapshot()) {
+                Ok(json) => say!(self, "{}", json),
+                Err(e) => print_error!(self, "Failed to serialize state: {}", e),
+            },
+            "log" => {
+                let limit = args.first().and_then(|n| n.parse().ok()).unwrap_or(20);
+                self.show_event_log(core, limit);
+            }
+            "play" => {
+                if args.is_empty() {
+                    match core.set_property("audio.playing", PropertyValue::Bool(true)) {
+                        Ok(()) => say!(self, "Resumed playback"),
+                        Err(e) => print_error!(self, "Failed to resume playback: {}", e),
+                    }
+                } else {
+                    match self.track_arg(&args.join(" ")) {
+                        Ok(track) => self.run_command(core, "play", vec![track]),
+                        Err(e) => say!(self, "{}", e),
+                    }
                 }
-                "next" | "n" => {
-                    core.execute_command("next", vec![]);
+            }
+            "pause" => {
+                self.run_command(core, "pause", vec![]);
+                say!(self, "Paused");
+            }
+            "next" | "n" => {
+                self.run_command(core, "next", vec![]);
+            }
+            "prev" | "p" => {
+                self.run_command(core, "prev", vec![]);
+            }
+            "shuffle" => match core.execute_command("shuffle", args) {
+                Ok(()) if core.get_bool("playlist.shuffle") == Some(true) => {
+                    say!(self, "Shuffle on")
                 }
-                "prev" | "p" => {
-                    core.execute_command("prev", vec![]);
+                Ok(()) => say!(self, "Shuffle off"),
+                Err(e) => print_error!(self, "Failed to set shuffle: {}", e),
+            },
+            "repeat" => match core.execute_command("repeat", args) {
+                Ok(()) => say!(
+                    self,
+                    "Repeat: {}",
+                    core.get_string("playlist.repeat")
+                        .map_or("off", |r| r.as_str())
+                ),
+                Err(e) => print_error!(self, "Failed to set repeat: {}", e),
+            },
+            "goto" | "g" => match args.as_slice() {
+                [n] => {
+                    if let Err(e) = core.execute_command("goto", vec![n.clone()]) {
+                        print_error!(self, "Failed to jump: {}", e);
+                    } else if let Some(track) = core.get_string("playlist.current") {
+                        say!(self, "Playing: {}", self.db.display_name(track));
+                    }
                 }
-                "add" | "a" => {
-                    if args.is_empty() {
-                        println!("Usage: add <track_path>");
+                _ => print_error!(self, "Usage: goto <n>"),
+            },
+            "seek" => match args.as_slice() {
+                [position] => {
+                    if let Err(e) = core.execute_command("seek", vec![position.clone()]) {
+                        print_error!(self, "Failed to seek: {}", e);
                     } else {
-                        let track = args.join(" ");
-                        core.execute_command("add", vec![track.clone()]);
-                        if let Err(e) = self.db.add_track_to_playlist("default", &track) {
-                            eprintln!("Failed to add to database: {}", e);
+                        self.print_position(core);
+                    }
+                }
+                _ => print_error!(self, "Usage: seek <1:23 | +10 | -30>"),
+            },
+            "undo" | "redo" => {
+                let available = if command == "undo" {
+                    core.history.can_undo()
+                } else {
+                    core.history.can_redo()
+                };
+                if available {
+                    self.run_command(core, command, vec![]);
+                    say!(
+                        self,
+                        "{} done",
+                        if command == "undo" { "Undo" } else { "Redo" }
+                    );
+                } else {
+                    say!(self, "Nothing to {}", command);
+                }
+            }
+            "reset" => {
+                if args.is_empty() {
+                    print_error!(self, "Usage: reset <property|all>");
+                } else {
+                    self.run_command(core, "reset", args);
+                }
+            }
+            "search" => {
+                if args.is_empty() {
+                    print_error!(self, "Usage: search <terms>");
+                } else {
+                    self.search(core, &args.join(" "));
+                }
+            }
+            "add" | "a" => {
+                if args.is_empty() {
+                    print_error!(self, "Usage: add <track_path>");
+                } else {
+                    match self.track_arg(&args.join(" ")) {
+                        Ok(track) => {
+                            self.run_command(core, "add", vec![track.clone()]);
+                            self.index_track(&track);
+                            say!(self, "Added: {}", self.db.display_name(&track));
                         }
-                        println!("Added: {}", track);
+                        Err(e) => say!(self, "{}", e),
                     }
                 }
-                "remove" | "rm" => {
-                    if args.is_empty() {
-                        println!("Usage: remove <track_path>");
+            }
+            "move" | "mv" => match args.as_slice() {
+                [from, to] => {
+                    if let Err(e) = core.execute_command("move", vec![from.clone(), to.clone()]) {
+                        print_error!(self, "Failed to move: {}", e);
                     } else {
-                        let track = args.join(" ");
-                        core.execute_command("remove", vec![track.clone()]);
-                        if let Err(e) = self.db.remove_track_from_playlist("default", &track) {
-                            eprintln!("Failed to remove from database: {}", e);
+                        self.show_playlist(core);
+                    }
+                }
+                _ => print_error!(self, "Usage: move <from> <to>"),
+            },
+            "remove" | "rm" => {
+                if args.is_empty() {
+                    print_error!(self, "Usage: remove <track_path>");
+                } else {
+                    let track = args.join(" ");
+                    self.run_command(core, "remove", vec![track.clone()]);
+                    say!(self, "Removed: {}", track);
+                }
+            }
+            "like" | "unlike" => {
+                let track = if args.is_empty() {
+                    core.get_string("playlist.current").cloned()
+                } else {
+                    Some(normalize_path(&args.join(" ")))
+                };
+                match track {
+                    Some(track) if track != "none" => {
+                        let liked = command == "like";
+                        let verb = if liked { "Liked" } else { "Unliked" };
+                        say!(self, "{}: {}", verb, self.db.display_name(&track));
+                        self.writer.send(DbWrite::SetFavorite { track, liked });
+                    }
+                    _ => print_error!(self, "Usage: {} [track]", command),
+                }
+            }
+            "rate" => {
+                let rating = args.first().and_then(|r| r.parse::<u8>().ok());
+                let track = if args.len() > 1 {
+                    self.track_arg(&args[1..].join(" "))
+                } else {
+                    core.get_string("playlist.current")
+                        .filter(|t| *t != "none")
+                        .cloned()
+                        .ok_or_else(|| "No track is playing".to_string())
+                };
+                match (rating, track) {
+                    (Some(rating @ 0..=5), Ok(track)) => {
+                        let name = self.db.display_name(&track);
+                        match rating {
+                            0 => say!(self, "Cleared the rating of {}", name),
+                            _ => say!(self, "Rated {} {}", name, stars(rating)),
                         }
-                        println!("Removed: {}", track);
+                        let rating = (rating > 0).then_some(rating);
+                        self.writer.send(DbWrite::SetRating { track, rating });
+                    }
+                    (_, Err(e)) if rating.is_some() => print_error!(self, "{}", e),
+                    _ => print_error!(self, "Usage: rate <1-5, 0 to clear> [track]"),
+                }
+            }
+            "info" => {
+                let arg = args.join(" ");
+                let tracks = core.get_string_list("playlist.tracks");
+                let track = match arg.parse::<usize>() {
+                    _ if arg.is_empty() => core
+                        .get_string("playlist.current")
+                        .filter(|t| *t != "none")
+                        .cloned()
+                        .ok_or_else(|| "No track is playing".to_string()),
+                    Ok(n) => n
+                        .checked_sub(1)
+                        .and_then(|index| tracks?.get(index))
+                        .cloned()
+                        .ok_or_else(|| format!("No playlist entry {}", n)),
+                    Err(_) => self.track_arg(&arg),
+                };
+                match track {
+                    Ok(track) => {
+                        self.writer.flush();
+                        self.show_track_info(&track);
+                    }
+                    Err(e) => print_error!(self, "{} (usage: info [track|n])", e),
+                }
+            }
+            "podcast" => {
+                self.podcast(&args);
+            }
+            "tag" => {
+                self.tag(core, &args);
+            }
+            "missing" => match self.db.missing_tracks() {
+                Ok(paths) if paths.is_empty() => say!(self, "All tracks found"),
+                Ok(paths) => {
+                    self.print_header(format!("Missing Files ({})", paths.len()));
+                    for path in paths {
+                        say!(self, "  {}", path);
+                    }
+                    say!(
+                        self,
+                        "Use 'relocate <old_prefix> <new_prefix>' to fix moved folders\n"
+                    );
+                }
+                Err(e) => print_error!(self, "Failed to check tracks: {}", e),
+            },
+            "relocate" => {
+                if args.len() != 2 {
+                    print_error!(self, "Usage: relocate <old_prefix> <new_prefix>");
+                } else {
+                    self.writer.flush();
+                    self.relocate(core, &args[0], &args[1]);
+                }
+            }
+            "folder" => {
+                if args.is_empty() {
+                    print_error!(self, "Usage: folder <path>");
+                } else {
+                    let path = args.join(" ");
+                    match self.db.create_folder(&path) {
+                        Ok(_) => say!(self, "Created folder '{}'", path),
+                        Err(e) => print_error!(self, "Failed to create folder: {}", e),
                     }
                 }
-                "volume" | "vol" | "v" => {
-                    if args.is_empty() {
-                        if let Some(vol) = core.get_float("volume") {
-                            println!("Volume: {:.0}%", vol * 100.0);
+            }
+            "delete" => {
+                if args.is_empty() {
+                    print_error!(self, "Usage: delete <playlist_name>");
+                } else {
+                    self.delete_playlist(&args.join(" "));
+                }
+            }
+            "undelete" => {
+                if args.is_empty() {
+                    self.show_trash();
+                } else {
+                    let name = args.join(" ");
+                    self.writer.flush();
+                    match self.db.undelete_playlist(&name) {
+                        Ok(UndeleteOutcome::Restored) => say!(self, "Restored '{}'", name),
+                        Ok(UndeleteOutcome::NotFound) => {
+                            say!(self, "No deleted playlist named '{}'", name)
                         }
-                    } else {
-                        core.execute_command("volume", args);
+                        Ok(UndeleteOutcome::NameTaken) => {
+                            say!(self, "A playlist named '{}' exists; rename it first", name)
+                        }
+                        Err(e) => print_error!(self, "Failed to restore playlist: {}", e),
                     }
                 }
-                "load" => {
-                    if args.is_empty() {
-                        println!("Usage: load <playlist_name>");
-                    } else {
-                        let playlist_name = &args[0];
-                        match self.db.get_playlist_tracks(playlist_name) {
-                            Ok(tracks) => {
-                                core.set_property(
-                                    "playlist",
-                                    PropertyValue::StringList(tracks.clone()),
-                                );
-                                println!(
-                                    "Loaded playlist '{}' with {} tracks",
-                                    playlist_name,
-                                    tracks.len()
-                                );
+            }
+            "rename" => {
+                if args.len() != 2 {
+                    print_error!(self, "Usage: rename <old_name> <new_name>");
+                } else {
+                    self.rename_playlist(&args[0], &args[1]);
+                }
+            }
+            "export" => {
+                if args.len() < 2 {
+                    print_error!(self, "Usage: export <playlist_name> <file.xspf>");
+                } else {
+                    let playlist_name = &args[0];
+                    let file = args[1..].join(" ");
+                    self.writer.flush();
+                    match export_playlist(&self.db, playlist_name) {
+                        Ok(xml) => match std::fs::write(&file, xml) {
+                            Ok(()) => say!(self, "Exported '{}' to {}", playlist_name, file),
+                            Err(e) => {
+                                print_error!(self, "Failed to write {}: {}", file, e)
                             }
+                        },
+                        Err(e) => print_error!(self, "Failed to export playlist: {}", e),
+                    }
+                }
+            }
+            "export-db" => {
+                let csv = args.first().is_some_and(|a| a == "--csv");
+                let path = args[usize::from(csv)..].join(" ");
+                if path.is_empty() {
+                    print_error!(self, "Usage: export-db [--csv] <path>");
+                } else {
+                    self.writer.flush();
+                    match export::collect(&self.db) {
+                        Ok(data) => match export::write_export(&data, Path::new(&path), csv) {
+                            Ok(()) => say!(self, "Exported database to {}", path),
                             Err(e) => {
-                                eprintln!("Failed to load playlist: {}", e);
+                                print_error!(self, "Failed to write {}: {}", path, e)
                             }
-                        }
+                        },
+                        Err(e) => print_error!(self, "Failed to read database: {}", e),
                     }
                 }
-                "save" => {
-                    if args.is_empty() {
-                        println!("Usage: save <playlist_name>");
-                    } else {
-                        let playlist_name = &args[0];
-                        if let Some(tracks) = core.get_string_list("playlist") {
-                            if let Err(e) = self.db.create_playlist(playlist_name) {
-                                eprintln!("Failed to create playlist: {}", e);
-                            } else {
-                                for track in tracks {
-                                    if let Err(e) =
-                                        self.db.add_track_to_playlist(playlist_name, track)
-                                    {
-                                        eprintln!("Failed to add track: {}", e);
-                                    }
-                                }
-                                println!(
-                                    "Saved playlist '{}' with {} tracks",
-                                    playlist_name,
-                                    tracks.len()
+            }
+            "ls" => self.browse(&args),
+            "eq" => self.eq(core, &args),
+            "recent" | "added" => {
+                let limit = args.first().and_then(|n| n.parse().ok()).unwrap_or(10);
+                if command == "recent" {
+                    self.show_recently_played(limit);
+                } else {
+                    self.show_recently_added(limit);
+                }
+            }
+            "stats" => {
+                let period = match args.first().map(|s| s.as_str()) {
+                    None | Some("week") => Some(StatsPeriod::Week),
+                    Some("month") => Some(StatsPeriod::Month),
+                    Some("all") => Some(StatsPeriod::AllTime),
+                    Some(_) => None,
+                };
+                match period {
+                    Some(period) => self.show_stats(period),
+                    None => print_error!(self, "Usage: stats [week|month|all]"),
+                }
+            }
+            "db" => {
+                self.writer.flush();
+                match args.first().map(|s| s.as_str()) {
+                    Some("check") => self.check_database(),
+                    Some("vacuum") => match self.db.vacuum() {
+                        Ok(freed) => say!(
+                            self,
+                            "Vacuumed database, reclaimed {:.1} KiB",
+                            freed as f64 / 1024.0
+                        ),
+                        Err(e) => print_error!(self, "Failed to vacuum database: {}", e),
+                    },
+                    _ => print_error!(self, "Usage: db <check|vacuum>"),
+                }
+            }
+            "scan" => {
+                if args.is_empty() {
+                    print_error!(self, "Usage: scan <directory>");
+                } else {
+                    let dir = args.join(" ");
+                    match scan_directory(&self.db, Path::new(&dir)) {
+                        Ok(report) => {
+                            say!(
+                                self,
+                                "Scanned {}: {} tracks added, {} files skipped, {} failed",
+                                dir,
+                                report.added,
+                                report.skipped,
+                                report.failed
+                            );
+                            if report.merged > 0 {
+                                say!(
+                                    self,
+                                    "Carried history over to {} moved or re-encoded tracks",
+                                    report.merged
                                 );
                             }
+                            for (new, existing) in &report.duplicates {
+                                say!(self, "  Duplicate: {} sounds like {}", new, existing);
+                            }
                         }
+                        Err(e) => print_error!(self, "Failed to scan {}: {}", dir, e),
                     }
                 }
-                _ => {
-                    println!(
-                        "Unknown command: '{}'. Type 'help' for available commands.",
-                        command
+            }
+            "volume" | "vol" | "v" => {
+                if args.is_empty() {
+                    if let Some(vol) = core.get_float("audio.volume") {
+                        say!(self, "Volume: {:.0}%", vol * 100.0);
+                    }
+                } else {
+                    self.run_command(core, "volume", args);
+                }
+            }
+            "devices" => self.show_devices(core),
+            // device names often have spaces, so the rest of the line is the name
+            "device" => match args.join(" ").as_str() {
+                "" => match Self::active_device(core) {
+                    Some(device) => say!(self, "Output device: {}", device),
+                    None => print_error!(self, "No output device available"),
+                },
+                "default" => self.switch_device(core, ""),
+                name => self.switch_device(core, name),
+            },
+            "load" => {
+                let resume = args.iter().any(|a| a == "--resume");
+                // unquoted names with spaces still mean one playlist
+                let words: Vec<&str> = args
+                    .iter()
+                    .filter(|a| *a != "--resume")
+                    .map(String::as_str)
+                    .collect();
+                let name = (!words.is_empty()).then(|| words.join(" "));
+                if let Some(playlist_name) = &name {
+                    self.writer.flush();
+                    match self.db.get_playlist_tracks(playlist_name) {
+                        Ok(tracks) => {
+                            if let Err(e) = core.set_property(
+                                "playlist.tracks",
+                                PropertyValue::StringList(tracks.clone()),
+                            ) {
+                                print_error!(self, "Failed to load playlist: {}", e);
+                                return Ok(false);
+                            }
+                            let _ = core.set_property(
+                                "playlist.name",
+                                PropertyValue::String(playlist_name.clone()),
+                            );
+                            say!(
+                                self,
+                                "Loaded playlist '{}' with {} tracks",
+                                playlist_name,
+                                tracks.len()
+                            );
+                            if resume {
+                                self.resume_playlist(core, playlist_name, &tracks);
+                            }
+                        }
+                        Err(e) => {
+                            print_error!(self, "Failed to load playlist: {}", e);
+                        }
+                    }
+                } else {
+                    print_error!(self, "Usage: load <playlist_name> [--resume]");
+                }
+            }
+            "save" => {
+                if args.is_empty() {
+                    print_error!(self, "Usage: save <playlist_name>");
+                } else if args.join(" ") == FAVORITES_PLAYLIST {
+                    say!(
+                        self,
+                        "'{}' always holds the liked tracks; use like/unlike",
+                        FAVORITES_PLAYLIST
                     );
+                } else {
+                    let playlist_name = &args.join(" ");
+                    if let Some(tracks) = core.get_string_list("playlist.tracks").cloned() {
+                        let count = tracks.len();
+                        self.writer.send(DbWrite::ReplacePlaylist {
+                            name: playlist_name.clone(),
+                            tracks,
+                        });
+                        let _ = core.set_property(
+                            "playlist.name",
+                            PropertyValue::String(playlist_name.clone()),
+                        );
+                        say!(
+                            self,
+                            "Saved playlist '{}' with {} tracks",
+                            playlist_name,
+                            count
+                        );
+                    }
                 }
             }
+            // commands registered by plugins
+            _ if core.commands.contains_key(command) => {
+                self.run_command(core, command, args);
+            }
+            _ => {
+                print_error!(
+                    self,
+                    "Unknown command: '{}'. Type 'help' for available commands.",
+                    command
+                );
+            }
         }
-
-        Ok(())
+        Ok(false)
     }
 
-    fn print_help(&self) {
-        println!("\nAvailable commands:");
-        println!("  play [track]      - Play a track or resume playback");
-        println!("  pause             - Pause playback");
-        println!("  stop              - Stop playback");
-        println!("  next (n)          - Play next track");
-        println!("  prev (p)          - Play previous track");
-        println!("  add (a) <track>   - Add track to current playlist");
-        println!("  remove (rm) <tr>  - Remove track from playlist");
-        println!("  volume (v) [0-1]  - Get or set volume");
-        println!("  playlist (pl)     - Show current playlist");
-        println!("  playlists         - Show all saved playlists");
-        println!("  load <name>       - Load a saved playlist");
-        println!("  save <name>       - Save current playlist");
-        println!("  history           - Show play history");
-        println!("  status            - Show player status");
-        println!("  help (h)          - Show this help");
-        println!("  quit (q)          - Exit\n");
+    /// Evaluates `code` and prints what it returns.
+    fn eval_lua(&self, core: &mut Core, code: &str) {
+        let Some(lua) = &self.lua else {
+            say!(self, "No Lua interpreter available");
+            return;
+        };
+        match with_core(lua, core, || lua::eval(lua, code)) {
+            Ok(values) => self.print_lua_values(&values),
+            Err(e) => print_error!(self, "Lua error: {}", e),
+        }
     }
 
-    fn print_status(&self, core: &Core) {
-        println!("\n=== Player Status ===");
+    fn print_lua_values(&self, values: &MultiValue) {
+        if !values.is_empty() {
+            let shown: Vec<String> = values.iter().map(lua::format_value).collect();
+            say!(self, "{}", shown.join("\t"));
+        }
+    }
 
-        if let Some(playing) = core.get_bool("playing") {
-            println!("Playing: {}", if playing { "Yes" } else { "No" });
+    /// Empties the current playlist. Unless `force` is set, asks first when the tracks
+    /// aren't saved as they are under `playlist.name`.
+    fn clear_playlist(&self, shared: &SharedCore, force: bool) -> io::Result<()> {
+        let (tracks, name) = {
+            let core = shared.read().unwrap();
+            (
+                core.get_string_list("playlist.tracks")
+                    .cloned()
+                    .unwrap_or_default(),
+                core.get_string("playlist.name")
+                    .cloned()
+                    .unwrap_or_default(),
+            )
+        };
+        if tracks.is_empty() {
+            say!(self, "Playlist is already empty");
+            return Ok(());
         }
 
-        if let Some(track) = core.get_string("current_track") {
-            println!("Current track: {}", track);
+        self.writer.flush();
+        let saved = !name.is_empty() && self.db.get_playlist_tracks(&name).ok() == Some(tracks);
+        if !force && !saved && !self.prompts() {
+            print_error!(self, "The playlist isn't saved; use 'clear --force'");
+            return Ok(());
+        }
+        if !force && !saved {
+            print!("The playlist isn't saved. Clear it anyway? [y/N] ");
+            io::stdout().flush()?;
+            let mut answer = String::new();
+            io::stdin().read_line(&mut answer)?;
+            if !answer.trim().eq_ignore_ascii_case("y") {
+                say!(self, "Kept the playlist");
+                return Ok(());
+            }
         }
 
-        if let Some(vol) = core.get_float("volume") {
-            println!("Volume: {:.0}%", vol * 100.0);
+        self.run_command(&mut shared.write().unwrap(), "clear", vec![]);
+        say!(self, "Cleared the playlist");
+        Ok(())
+    }
+
+    /// `config [get] [key|prefix]` shows settings, `config set <key> <value>` changes one
+    /// for this session. Settings are properties; config.lua sets the same ones at
+    /// startup. `config init [path]` writes a commented config.lua to start from.
+    fn config(&self, core: &mut Core, args: &[String]) {
+        match args.first().map(|a| a.as_str()) {
+            Some("init") if args.len() <= 2 => {
+                let path = args.get(1).map_or_else(config_path, |p| expand_home(p));
+                match config::write_default_config(&path) {
+                    Ok(()) => say!(self, "Wrote {}", path.display()),
+                    Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                        print_error!(self, "{} already exists", path.display())
+                    }
+                    Err(e) => print_error!(self, "Failed to write {}: {}", path.display(), e),
+                }
+            }
+            None => self.show_config(core, ""),
+            Some("get") if args.len() <= 2 => {
+                self.show_config(core, args.get(1).map_or("", |k| k.as_str()))
+            }
+            Some("set") if args.len() >= 3 => {
+                let key = &args[1];
+                let text = args[2..].join(" ");
+                let Some(current) = core.get_property(key) else {
+                    print_error!(self, "Unknown setting '{}'", key);
+                    return;
+                };
+                let value = match current
+                    .parse_as(&text)
+                    .and_then(|value| config::check(key, &value))
+                {
+                    Ok(value) => value,
+                    Err(e) => {
+                        print_error!(self, "Can't set {}: {}", key, e);
+                        return;
+                    }
+                };
+                if let Err(e) = core.set_property(key, value.clone()) {
+                    print_error!(self, "Failed to set {}: {}", key, e);
+                    return;
+                }
+                say!(self, "{} = {}", key, value.to_json());
+                match key.as_str() {
+                    "audio.ring_buffer_size" => say!(self, "Takes effect from the next track"),
+                    // a new default is what the user wants to hear now
+                    "audio.default_volume" => {
+                        let _ = core.set_property("audio.volume", value);
+                    }
+                    _ if RESTART_PROPERTIES.contains(&key.as_str()) => {
+                        say!(self, "Takes effect after a restart")
+                    }
+                    _ => {}
+                }
+            }
+            _ => print_error!(
+                self,
+                "Usage: config [get] [key|prefix] | config set <key> <value> | config init [path]"
+            ),
         }
+    }
 
-        if let Some(playlist) = core.get_string_list("playlist") {
-            println!("Playlist size: {} tracks", playlist.len());
+    /// Every setting named `key` or under it (`audio` lists `audio.*`).
+    fn show_config(&self, core: &Core, key: &str) {
+        let settings = match core.get_property(key) {
+            Some(value) => vec![(key, value)],
+            None => core.properties_in(key),
+        };
+        if settings.is_empty() {
+            print_error!(self, "Unknown setting '{}'", key);
+            return;
+        }
+        for (name, value) in settings {
+            say!(self, "{} = {}", name, value.to_json());
         }
+    }
 
-        println!();
+    /// The `audio.backend` devices are listed from; "" for the platform default.
+    fn backend(core: &Core) -> &str {
+        core.get_string("audio.backend").map_or("", |b| b.as_str())
     }
 
-    fn show_playlist(&self, core: &Core) {
-        if let Some(playlist) = core.get_string_list("playlist") {
-            if playlist.is_empty() {
-                println!("Playlist is empty");
+    /// Device output goes to: the one `audio.device` names, or the backend's default.
+    fn active_device(core: &Core) -> Option<String> {
+        match core.get_string("audio.device") {
+            Some(device) if !device.is_empty() => Some(device.clone()),
+            _ => AudioBackend::default_device(Self::backend(core)),
+        }
+    }
+
+    fn show_devices(&self, core: &Core) {
+        let devices = match AudioBackend::output_devices(Self::backend(core)) {
+            Ok(devices) => devices,
+            Err(e) => {
+                print_error!(self, "Failed to list output devices: {}", e);
+                return;
+            }
+        };
+        if devices.is_empty() {
+            say!(self, "No output devices found");
+            return;
+        }
+        let active = Self::active_device(core);
+        match Self::backend(core) {
+            "" => self.print_header("Output devices"),
+            backend => self.print_header(format!("Output devices ({})", backend)),
+        }
+        for device in devices {
+            if active.as_ref() == Some(&device) {
+                say!(self, "{}", self.style.current(format!("▶ {}", device)));
             } else {
-                println!("\n=== Current Playlist ({} tracks) ===", playlist.len());
-                for (i, track) in playlist.iter().enumerate() {
-                    let marker = if Some(track) == core.get_string("current_track") {
-                        "▶"
-                    } else {
-                        " "
-                    };
-                    println!("{} {}. {}", marker, i + 1, track);
-                }
-                println!();
+                say!(self, "  {}", device);
             }
         }
+        say!(
+            self,
+            "Backends: {} (set audio.backend to switch)",
+            AudioBackend::backends().join(", ")
+        );
     }
 
-    fn show_all_playlists(&self) {
-        match self.db.get_all_playlists() {
-            Ok(playlists) => {
-                if playlists.is_empty() {
-                    println!("No saved playlists");
-                } else {
-                    println!("\n=== Saved Playlists ===");
-                    for playlist in playlists {
-                        match self.db.get_playlist_tracks(&playlist) {
-                            Ok(tracks) => {
-                                println!("  {} ({} tracks)", playlist, tracks.len());
-                            }
-                            Err(_) => {
-                                println!("  {}", playlist);
-                            }
-                        }
-                    }
-                    println!();
+    /// Moves output to `name`, or back to the host's default when it's empty.
+    fn switch_device(&self, core: &mut Core, name: &str) {
+        if !name.is_empty() {
+            match AudioBackend::output_devices(Self::backend(core)) {
+                Ok(devices) if devices.iter().any(|d| d == name) => {}
+                Ok(_) => {
+                    print_error!(self, "No output device named '{}'; see 'devices'", name);
+                    return;
+                }
+                Err(e) => {
+                    print_error!(self, "Failed to list output devices: {}", e);
+                    return;
                 }
             }
-            Err(e) => {
-                eprintln!("Failed to get playlists: {}", e);
+        }
+        match core.set_property("audio.device", PropertyValue::String(name.to_string())) {
+            Ok(()) => match Self::active_device(core) {
+                Some(device) => say!(self, "Output device: {}", device),
+                None => say!(self, "Output device: default"),
+            },
+            Err(e) => print_error!(self, "Failed to switch output device: {}", e),
+        }
+    }
+
+    /// Moves a saved playlist to the trash.
+    fn delete_playlist(&self, name: &str) {
+        self.writer.flush();
+        match self.db.delete_playlist(name) {
+            Ok(true) => say!(
+                self,
+                "Moved '{}' to the trash; 'undelete {}' brings it back",
+                name,
+                name
+            ),
+            Ok(false) => print_error!(self, "No playlist named '{}'", name),
+            Err(e) => print_error!(self, "Failed to delete playlist: {}", e),
+        }
+    }
+
+    fn rename_playlist(&self, old: &str, new: &str) {
+        self.writer.flush();
+        match self.db.rename_playlist(old, new) {
+            Ok(RenameOutcome::Renamed) => say!(self, "Renamed playlist '{}' to '{}'", old, new),
+            Ok(RenameOutcome::NotFound) => print_error!(self, "No playlist named '{}'", old),
+            Ok(RenameOutcome::NameTaken) => {
+                print_error!(self, "A playlist named '{}' already exists", new)
             }
+            Err(e) => print_error!(self, "Failed to rename playlist: {}", e),
         }
     }
 
-    fn show_history(&self) {
-        match self.db.get_play_history(10) {
-            Ok(history) => {
-                if history.is_empty() {
-                    println!("No play history");
-                } else {
-                    println!("\n=== Play History (last 10) ===");
-                    for (track, timestamp) in history {
-                        println!("  {} - {}", timestamp, track);
-                    }
-                    println!();
-                }
+    fn copy_playlist(&self, src: &str, dst: &str) {
+        self.writer.flush();
+        match self.db.copy_playlist(src, dst) {
+            Ok(CopyOutcome::Copied) => say!(self, "Copied playlist '{}' to '{}'", src, dst),
+            Ok(CopyOutcome::NotFound) => print_error!(self, "No playlist named '{}'", src),
+            Ok(CopyOutcome::NameTaken) => {
+                print_error!(self, "A playlist named '{}' already exists", dst)
             }
+            Err(e) => print_error!(self, "Failed to copy playlist: {}", e),
+        }
+    }
+
+    /// Redraws a one-line now-playing display whenever one of the properties it shows
+    /// changes, until any key is pressed. The terminal is in raw mode meanwhile.
+    fn watch(&self, shared: &SharedCore) -> io::Result<()> {
+        const WATCHED: [&str; 6] = [
+            "playlist.current",
+            "playlist.tracks",
+            "audio.playing",
+            "audio.position",
+            "audio.duration",
+            "audio.volume",
+        ];
+        if !self.prompts() {
+            print_error!(self, "watch needs a terminal");
+            return Ok(());
+        }
+        let raw = match RawMode::enable() {
+            Ok(raw) => raw,
             Err(e) => {
-                eprintln!("Failed to get history: {}", e);
+                print_error!(self, "Can't read single keys here: {}", e);
+                return Ok(());
+            }
+        };
+
+        let changed = Arc::new(AtomicBool::new(true));
+        let flag = Arc::clone(&changed);
+        let callback: PropertyCallback = Arc::new(move |_, _| flag.store(true, Ordering::SeqCst));
+        {
+            let mut core = shared.write().unwrap();
+            for name in WATCHED {
+                let _ = core.subscribe_property(name, Rate::Immediate, Arc::clone(&callback));
+            }
+        }
+
+        let (done, key) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = read_key();
+            let _ = done.send(());
+        });
+
+        println!("Watching; press any key to stop");
+        let mut stdout = io::stdout();
+        while key.try_recv() == Err(TryRecvError::Empty) {
+            if changed.swap(false, Ordering::SeqCst) {
+                let line = self.now_playing(&shared.read().unwrap());
+                print!("\r\x1b[K{}", line);
+                stdout.flush()?;
             }
+            thread::sleep(Duration::from_millis(100));
+        }
+        drop(raw);
+        println!();
+
+        let mut core = shared.write().unwrap();
+        for name in WATCHED {
+            core.unsubscribe_property(name, &callback);
+        }
+        Ok(())
+    }
+
+    /// Runs the command bound to each key pressed (see `Core::keys`) until Esc, or `q`
+    /// when nothing else uses it. Returns true when a binding asked to quit.
+    fn key_mode(&mut self, shared: &SharedCore) -> io::Result<bool> {
+        if !self.prompts() {
+            print_error!(self, "keys needs a terminal");
+            return Ok(false);
         }
+        let raw = match RawMode::enable() {
+            Ok(raw) => raw,
+            Err(e) => {
+                print_error!(self, "Can't read single keys here: {}", e);
+                return Ok(false);
+            }
+        };
+        say!(
+            self,
+            "Key mode: Esc or q returns to the prompt, ? lists the keys"
+        );
+
+        let mut quit = false;
+        while let Some(key) = read_key()? {
+            if key == "esc" {
+                break;
+            }
+            let bound = shared.read().unwrap().keys.lookup(&key).map(str::to_string);
+            match (bound, key.as_str()) {
+                (Some(command), _) => {
+                    quit = self.execute(shared, &command)?;
+                    if quit {
+                        break;
+                    }
+                }
+                (None, "q" | "ctrl+c") => break,
+                (None, "?") => self.show_keys(&shared.read().unwrap()),
+                (None, _) => {}
+            }
+        }
+        drop(raw);
+        if !quit {
+            say!(self, "Back to typed commands");
+        }
+        Ok(quit)
+    }
+
+    fn show_keys(&self, core: &Core) {
+        self.print_header("Keys");
+        for (key, command) in core.keys.iter() {
+            say!(self, "  {:<12} {}", key, command);
+        }
+        say!(self, "  {:<12} {}", "esc", "back to the prompt");
+    }
+
+    /// The last `limit` typed lines, numbered for `!n`.
+    fn show_input_history(&self, limit: usize) {
+        let entries = self.input_history.entries();
+        if entries.is_empty() {
+            say!(self, "No commands typed yet");
+            return;
+        }
+        let start = entries.len().saturating_sub(limit);
+        for (i, line) in entries.iter().enumerate().skip(start) {
+            say!(self, "{:>5}  {}", i + 1, line);
+        }
+    }
+
+    /// `▶ Artist – Title  1:23 / 4:56  vol 80%  3/12`, the line `watch` shows.
+    fn now_playing(&self, core: &Core) -> String {
+        let tracks = core
+            .get_string_list("playlist.tracks")
+            .map(Vec::as_slice)
+            .unwrap_or(&[]);
+        let current = core.get_string("playlist.current").filter(|t| *t != "none");
+        let Some(track) = current else {
+            return format!("■ Nothing playing  queue {}", tracks.len());
+        };
+
+        let icon = if core.get_bool("audio.playing") == Some(true) {
+            "▶"
+        } else {
+            "⏸"
+        };
+        let position = core.get_float("audio.position").unwrap_or(0.0);
+        let time = match core.get_float("audio.duration").filter(|d| *d > 0.0) {
+            Some(duration) => format!("{} / {}", format_time(position), format_time(duration)),
+            None => format_time(position),
+        };
+        let volume = core.get_float("audio.volume").unwrap_or(1.0);
+        let queue = match tracks.iter().position(|t| t == track) {
+            Some(index) => format!("{}/{}", index + 1, tracks.len()),
+            None => format!("queue {}", tracks.len()),
+        };
+        format!(
+            "{}  {}  vol {:.0}%  {}",
+            self.style
+                .current(format!("{} {}", icon, self.db.display_name(track))),
+            time,
+            volume * 100.0,
+            queue
+        )
+    }
+
+    /// `plugin list|load|unload|reload`. A script that fails is reported and only that
+    /// plugin is affected; the others and the Lua state keep running.
+    fn plugin_command(&self, args: &[String]) {
+        let Some(plugins) = &self.plugins else {
+            say!(self, "Plugins are not available");
+            return;
+        };
+        let mut plugins = plugins.lock().unwrap();
+        let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        match args.as_slice() {
+            [] | ["list"] => {
+                if plugins.plugins().is_empty() {
+                    say!(self, "No plugins loaded");
+                }
+                for plugin in plugins.plugins() {
+                    let registrations = plugin.registrations();
+                    say!(
+                        self,
+                        "{} - {} ({} commands, {} timers)",
+                        plugin.name,
+                        plugin.path.display(),
+                        registrations.commands.len(),
+                        registrations.timers.len()
+                    );
+                }
+            }
+            ["load", path] => {
+                let path = expand_home(path);
+                match plugins.load_file(&path) {
+                    Ok(()) => say!(self, "Loaded plugin '{}'", plugin_name(&path)),
+                    Err(e) => print_error!(self, "Failed to load plugin {}: {}", path.display(), e),
+                }
+            }
+            ["unload", name] => {
+                if plugins.unload(name) {
+                    say!(self, "Unloaded plugin '{}'", name);
+                } else {
+                    say!(self, "No plugin named '{}'", name);
+                }
+            }
+            ["reload", name] => match plugins.reload(name) {
+                Some(Ok(())) => say!(self, "Reloaded plugin '{}'", name),
+                Some(Err(e)) => {
+                    print_error!(self, "Failed to reload plugin '{}': {}", name, e)
+                }
+                None => say!(self, "No plugin named '{}'", name),
+            },
+            _ => print_error!(
+                self,
+                "Usage: plugin [list | load <path> | unload <name> | reload <name>]"
+            ),
+        }
+    }
+
+    /// Reads Lua chunks until `:end`. A chunk that isn't finished yet (an open
+    /// `function`, `do`, ...) continues on the next line.
+    fn lua_mode(&self, shared: &SharedCore) -> io::Result<()> {
+        let Some(lua) = &self.lua else {
+            say!(self, "No Lua interpreter available");
+            return Ok(());
+        };
+        say!(self, "Lua mode; ':end' returns to the player prompt");
+
+        let mut chunk = String::new();
+        loop {
+            if self.prompts() {
+                print!("{}", if chunk.is_empty() { "lua> " } else { "lua>> " });
+                io::stdout().flush()?;
+            }
+
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line)? == 0 || line.trim() == ":end" {
+                return Ok(());
+            }
+            chunk.push_str(&line);
+            if chunk.trim().is_empty() {
+                chunk.clear();
+                continue;
+            }
+
+            let mut core = shared.write().unwrap();
+            match with_core(lua, &mut core, || lua::eval(lua, &chunk)) {
+                Ok(values) => self.print_lua_values(&values),
+                Err(e) if lua::is_incomplete(&e) => continue,
+                Err(e) => print_error!(self, "Lua error: {}", e),
+            }
+            chunk.clear();
+        }
+    }
+
+    /// Prints `core:print`/`core:notify` messages as they come. One that arrives while the
+    /// prompt is waiting (from a timer, say) replaces the prompt line and redraws it.
+    fn show_script_messages(&self, core: &mut Core) {
+        let at_prompt = Arc::clone(&self.at_prompt);
+        let json = Arc::clone(&self.json);
+        core.subscribe_event(
+            Some(&format!("custom:{}", SCRIPT_MESSAGE)),
+            Arc::new(move |event, core| {
+                let EventType::Custom(_, PropertyValue::StringList(message)) = event else {
+                    return;
+                };
+                let [level, text] = message.as_slice() else {
+                    return;
+                };
+                if json.load(Ordering::SeqCst) {
+                    let message =
+                        serde_json::json!({"event": SCRIPT_MESSAGE, "level": level, "text": text});
+                    println!("{}", message);
+                    return;
+                }
+                let waiting = at_prompt.lock().unwrap().clone();
+                if waiting.is_some() {
+                    print!("\r\x1b[K");
+                }
+                let style = Style::from_core(core);
+                match level.as_str() {
+                    "info" => println!("{}", text),
+                    "warn" => println!("{}", style.warning(format!("warn: {}", text))),
+                    _ => println!("{}", style.error(format!("{}: {}", level, text))),
+                }
+                if let Some(prompt) = waiting {
+                    print!("{}", prompt);
+                }
+                let _ = io::stdout().flush();
+            }),
+        );
+    }
+
+    fn print_help(&self) {
+        say!(
+            self,
+            "\nAvailable commands (quote words with spaces: add \"My Song.flac\"):"
+        );
+        say!(
+            self,
+            "  play [track]      - Play a track (#n search result, @n history entry) or resume"
+        );
+        say!(self, "  pause             - Pause playback");
+        say!(self, "  stop              - Stop playback");
+        say!(self, "  next (n)          - Play next track");
+        say!(self, "  prev (p)          - Play previous track");
+        say!(self, "  goto (g) <n>      - Play entry n of the playlist");
+        say!(self, "  add (a) <track>   - Add track to current playlist");
+        say!(
+            self,
+            "  search <terms>    - Find tracks; 'play #n' / 'add #n' pick a result"
+        );
+        say!(self, "  remove (rm) <tr>  - Remove track from playlist");
+        say!(
+            self,
+            "  move (mv) <a> <b> - Move playlist entry a to position b"
+        );
+        say!(
+            self,
+            "  clear [--force]   - Empty the playlist (asks first if it isn't saved)"
+        );
+        say!(
+            self,
+            "  shuffle [on|off]  - Play the playlist in random order (toggles)"
+        );
+        say!(
+            self,
+            "  repeat [mode]     - Repeat off, one or all (cycles without a mode)"
+        );
+        say!(
+            self,
+            "  seek <pos>        - Jump to 1:23, or +10/-30 seconds from here"
+        );
+        say!(self, "  volume (v) [0-1]  - Get or set volume");
+        say!(
+            self,
+            "  devices           - List output devices, the active one marked"
+        );
+        say!(
+            self,
+            "  device [name|default] - Show or switch the output device"
+        );
+        say!(
+            self,
+            "  undo / redo       - Revert or re-apply the last state change"
+        );
+        say!(
+            self,
+            "  reset <prop|all>  - Restore properties to their defaults"
+        );
+        say!(self, "  playlist (pl)     - Show current playlist");
+        say!(
+            self,
+            "  playlist <name>   - Show a saved playlist with its notes (also playlist show <name>)"
+        );
+        say!(
+            self,
+            "  playlist delete <name> | rename <old> <new> | copy <src> <dst>"
+        );
+        say!(
+            self,
+            "  note <pl> <n> [t] - Annotate entry n of a saved playlist (no text clears)"
+        );
+        say!(
+            self,
+            "  weight <pl> <n> w - Set the sort weight of entry n (lower sorts first)"
+        );
+        say!(self, "  playlists         - Show all saved playlists");
+        say!(
+            self,
+            "  load <name>       - Load a saved playlist (--resume to continue where it was left)"
+        );
+        say!(self, "  save <name>       - Save current playlist");
+        say!(
+            self,
+            "  folder <path>     - Create a playlist folder (use folder/name paths)"
+        );
+        say!(self, "  rename <old> <to> - Rename a saved playlist");
+        say!(
+            self,
+            "  delete <name>     - Move a saved playlist to the trash"
+        );
+        say!(
+            self,
+            "  undelete [name]   - Restore a deleted playlist, or list the trash"
+        );
+        say!(
+            self,
+            "  export <name> <f> - Export a saved playlist to XSPF"
+        );
+        say!(
+            self,
+            "  export-db <path>  - Dump the database as JSON (--csv first for CSV files)"
+        );
+        say!(
+            self,
+            "  missing           - List tracks whose files have moved"
+        );
+        say!(
+            self,
+            "  relocate <a> <b>  - Rewrite paths starting with a to start with b"
+        );
+        say!(
+            self,
+            "  db <check|vacuum> - Check the database for problems or compact it"
+        );
+        say!(
+            self,
+            "  eq [show|on|off]  - Show the equalizer or switch it on/off"
+        );
+        say!(
+            self,
+            "  eq set <n> <dB> [hz [q [type]]] / eq remove <n> - Edit band n (n+1 adds one)"
+        );
+        say!(
+            self,
+            "  eq preset <name>  - Load a saved preset into the equalizer"
+        );
+        say!(
+            self,
+            "  eq <action>       - EQ presets: presets, save <n>, assign <n> [album], unassign"
+        );
+        say!(
+            self,
+            "  ls [what]         - Browse artists, albums [artist], genres, album <n>, genre <n>"
+        );
+        say!(
+            self,
+            "  scan <dir>        - Add a folder's audio files to the library"
+        );
+        say!(
+            self,
+            "  history [n]       - Show play history (--by-track or --by-day to group)"
+        );
+        say!(
+            self,
+            "  history [n] --page <p> --search <text> - Page through or filter play history"
+        );
+        say!(
+            self,
+            "  history --commands [n] - Show typed commands; !! and !n run them again"
+        );
+        say!(self, "  recent [n]        - Show recently played tracks");
+        say!(self, "  added [n]         - Show recently added tracks");
+        say!(self, "  log [n]           - Show the last n core events");
+        say!(
+            self,
+            "  :lua [code]       - Evaluate Lua, or enter Lua mode until ':end'"
+        );
+        say!(
+            self,
+            "  plugin [action]   - Plugins: list, load <path>, unload <name>, reload <name>"
+        );
+        say!(self, "  dump              - Print all properties as JSON");
+        say!(
+            self,
+            "  config [get] [key] / config set <key> <value> - Show or change settings live"
+        );
+        say!(
+            self,
+            "  config init [path] - Write a commented default config.lua"
+        );
+        say!(
+            self,
+            "  stats [period]    - Library and listening stats; top lists for week, month or all"
+        );
+        say!(
+            self,
+            "  podcast [action]  - List, add <url> or refresh podcast feeds"
+        );
+        say!(
+            self,
+            "  tag <action> [t]  - Tag the current track: add, remove, list, load"
+        );
+        say!(
+            self,
+            "  info [track|n]    - Show tags, stream details and play counts (n: playlist entry)"
+        );
+        say!(
+            self,
+            "  like [track]      - Add a track to the 'favorites' playlist"
+        );
+        say!(
+            self,
+            "  unlike [track]    - Remove a track from 'favorites'"
+        );
+        say!(
+            self,
+            "  rate <1-5> [track] - Rate the current track (0 clears); shown as stars"
+        );
+        say!(self, "  status            - Show player status");
+        say!(
+            self,
+            "  watch             - Keep a live now-playing line until a key is pressed"
+        );
+        say!(
+            self,
+            "  keys              - Single-key mode: space, n/p, arrows (Esc leaves)"
+        );
+        say!(
+            self,
+            "  format json|text  - Answer each command with a JSON object, or text"
+        );
+        say!(
+            self,
+            "  help (h) [cmd]    - Show this help, or usage and examples for one command"
+        );
+        say!(self, "  quit (q)          - Exit\n");
+    }
+
+    fn print_status(&self, core: &Core) {
+        self.print_header("Player Status");
+
+        if let Some(playing) = core.get_bool("audio.playing") {
+            say!(self, "Playing: {}", if playing { "Yes" } else { "No" });
+        }
+
+        if let Some(track) = core.get_string("playlist.current") {
+            say!(self, "Current track: {}", self.db.display_name(track));
+        }
+
+        if let Some(track) = core.get_string("playlist.current").filter(|t| *t != "none") {
+            self.print_position(core);
+            if let Ok((meta, info)) = probe_file(track) {
+                let mut format = Vec::new();
+                format.extend(meta.codec.clone());
+                format.extend(
+                    info.sample_rate
+                        .map(|rate| format!("{:.1} kHz", rate as f64 / 1000.0)),
+                );
+                format.extend(info.channels.map(|channels| format!("{} ch", channels)));
+                format.extend(
+                    info.bitrate(meta.duration)
+                        .map(|kbps| format!("{:.0} kbps", kbps)),
+                );
+                if !format.is_empty() {
+                    say!(self, "Format: {}", format.join(", "));
+                }
+            }
+        }
+
+        if let Some(vol) = core.get_float("audio.volume") {
+            say!(self, "Volume: {:.0}%", vol * 100.0);
+        }
+
+        if let Some(playlist) = core.get_string_list("playlist.tracks") {
+            say!(self, "Playlist size: {} tracks", playlist.len());
+        }
+
+        say!(self);
+    }
+
+    fn run_command(&self, core: &mut Core, name: &str, params: Vec<String>) {
+        if let Err(e) = core.execute_command(name, params) {
+            print_error!(self, "Command '{}' failed: {}", name, e);
+        }
+    }
+
+    /// `=== title ===` after a blank line, in the theme's header color.
+    fn print_header(&self, title: impl Display) {
+        say!(
+            self,
+            "\n{}",
+            self.style.header(format!("=== {} ===", title))
+        );
+    }
+
+    /// `#n` is entry n of the last `search`, `@n` the nth most recent play in `history`;
+    /// anything else is a track path.
+    fn track_arg(&self, arg: &str) -> Result<String, String> {
+        if let Some(index) = arg.strip_prefix('@').and_then(|n| n.parse::<usize>().ok()) {
+            self.writer.flush();
+            return match self.db.play_history_entry(index) {
+                Ok(Some(track)) => Ok(track),
+                Ok(None) => Err(format!("No history entry {}", arg)),
+                Err(e) => Err(format!("Failed to read history: {}", e)),
+            };
+        }
+        match arg.strip_prefix('#') {
+            Some(n) => n
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| self.results.get(n.checked_sub(1)?))
+                .cloned()
+                .ok_or_else(|| format!("No search result {}", arg)),
+            None => Ok(normalize_path(arg)),
+        }
+    }
+
+    /// Lists library tracks matching `query`, or when the library has none, playlist
+    /// entries that fuzzily do. The listing is numbered for `play #n` / `add #n`.
+    fn search(&mut self, core: &Core, query: &str) {
+        let mut found: Vec<(String, String)> = match self.db.search_tracks(query, 50) {
+            Ok(tracks) => tracks
+                .into_iter()
+                .map(|track| (track.display_name(), track.path))
+                .collect(),
+            Err(e) => {
+                print_error!(self, "Failed to search: {}", e);
+                return;
+            }
+        };
+        let source = if found.is_empty() {
+            found = core
+                .get_string_list("playlist.tracks")
+                .into_iter()
+                .flatten()
+                .map(|track| (self.db.display_name(track), track.clone()))
+                .filter(|(name, track)| fuzzy_match(query, name) || fuzzy_match(query, track))
+                .collect();
+            "playlist"
+        } else {
+            "library"
+        };
+
+        if found.is_empty() {
+            say!(self, "No matches for '{}'", query);
+            return;
+        }
+        self.print_header(format!("{} matches in {}", found.len(), source));
+        for (i, (name, _)) in found.iter().enumerate() {
+            say!(self, "  #{:<3} {}", i + 1, name);
+        }
+        say!(self, "Use 'play #n' or 'add #n'\n");
+        self.results = found.into_iter().map(|(_, track)| track).collect();
+    }
+
+    /// `Position: [======>-------] 1:23 / 4:05`, just the time when the length isn't known.
+    fn print_position(&self, core: &Core) {
+        let position = core.get_float("audio.position").unwrap_or(0.0);
+        match core.get_float("audio.duration").filter(|d| *d > 0.0) {
+            Some(duration) => say!(
+                self,
+                "Position: {} {} / {}",
+                progress_bar(position / duration, 30),
+                format_time(position),
+                format_time(duration)
+            ),
+            None => say!(self, "Position: {}", format_time(position)),
+        }
+    }
+
+    fn show_playlist(&self, core: &Core) {
+        if let Some(playlist) = core.get_string_list("playlist.tracks") {
+            if playlist.is_empty() {
+                say!(self, "Playlist is empty");
+            } else {
+                // ratings may still be queued
+                self.writer.flush();
+                self.print_header(format!("Current Playlist ({} tracks)", playlist.len()));
+                for (i, track) in playlist.iter().enumerate() {
+                    let line = format!(
+                        "{}. {}{}",
+                        i + 1,
+                        self.db.display_name(track),
+                        self.rating_suffix(track)
+                    );
+                    if Some(track) == core.get_string("playlist.current") {
+                        say!(self, "{}", self.style.current(format!("▶ {}", line)));
+                    } else {
+                        say!(self, "  {}", line);
+                    }
+                }
+                say!(self);
+            }
+        }
+    }
+
+    /// Jumps to the track and offset the playlist was last left at.
+    fn resume_playlist(&self, core: &mut Core, name: &str, tracks: &[String]) {
+        let (track, position) = match self.db.get_playlist_position(name) {
+            Ok(Some(saved)) if tracks.contains(&saved.0) => saved,
+            Ok(_) => {
+                say!(self, "No saved position, starting from the top");
+                return;
+            }
+            Err(e) => {
+                print_error!(self, "Failed to read saved position: {}", e);
+                return;
+            }
+        };
+        let _ = core.set_property(
+            "audio.start_position",
+            PropertyValue::Float(position as f32),
+        );
+        if let Err(e) = core.set_property("playlist.current", PropertyValue::String(track.clone()))
+        {
+            print_error!(self, "Failed to resume: {}", e);
+            return;
+        }
+        say!(
+            self,
+            "Resuming at {} ({}:{:02})",
+            self.db.display_name(&track),
+            position as u64 / 60,
+            position as u64 % 60
+        );
+    }
+
+    /// `  ★★★☆☆` after a rated track's name in listings, nothing for unrated ones.
+    fn rating_suffix(&self, track: &str) -> String {
+        match self.db.get_rating(track) {
+            Ok(Some(rating)) => format!("  {}", stars(rating)),
+            _ => String::new(),
+        }
+    }
+
+    fn show_saved_playlist(&self, name: &str) {
+        match self.db.get_playlist_entries(name) {
+            Ok(entries) if entries.is_empty() => say!(self, "Playlist '{}' is empty", name),
+            Ok(entries) => {
+                self.print_header(format!("{} ({} tracks)", name, entries.len()));
+                for (i, entry) in entries.iter().enumerate() {
+                    let weight = if entry.sort_weight != 0 {
+                        format!(" [{}]", entry.sort_weight)
+                    } else {
+                        String::new()
+                    };
+                    say!(
+                        self,
+                        "  {}. {}{}{}",
+                        i + 1,
+                        self.db.display_name(&entry.track),
+                        self.rating_suffix(&entry.track),
+                        weight
+                    );
+                    if let Some(note) = &entry.note {
+                        say!(self, "       {}", note);
+                    }
+                }
+                say!(self);
+            }
+            Err(e) => print_error!(self, "Failed to read playlist: {}", e),
+        }
+    }
+
+    /// Reads the file's tags into the library so listings can show them.
+    fn index_track(&self, path: &str) {
+        match read_metadata(path) {
+            Ok(meta) => {
+                if let Err(e) = self.db.upsert_track(&meta) {
+                    print_error!(self, "Failed to store track metadata: {}", e);
+                }
+            }
+            Err(e) => print_error!(self, "Could not read metadata for {}: {}", path, e),
+        }
+    }
+
+    /// `tag add|remove <tag>` label the current track, `tag list` shows all tags (or the
+    /// current track's with `tag list current`), `tag load <tag>` queues tagged tracks.
+    fn tag(&self, core: &mut Core, args: &[String]) {
+        let usage = "Usage: tag add|remove|load <tag> | tag list [current]";
+        let Some((action, rest)) = args.split_first() else {
+            print_error!(self, "{}", usage);
+            return;
+        };
+        let tag = rest.join(" ");
+        let current = core
+            .get_string("playlist.current")
+            .filter(|t| *t != "none")
+            .cloned();
+
+        match (action.as_str(), current) {
+            ("list", _) if tag.is_empty() => match self.db.get_all_tags() {
+                Ok(tags) if tags.is_empty() => say!(self, "No tags"),
+                Ok(tags) => {
+                    for (tag, count) in tags {
+                        say!(self, "  {} ({} tracks)", tag, count);
+                    }
+                }
+                Err(e) => print_error!(self, "Failed to list tags: {}", e),
+            },
+            ("list", Some(track)) if tag == "current" => match self.db.get_track_tags(&track) {
+                Ok(tags) if tags.is_empty() => say!(self, "No tags"),
+                Ok(tags) => say!(self, "  {}", tags.join(", ")),
+                Err(e) => print_error!(self, "Failed to list tags: {}", e),
+            },
+            ("add" | "remove" | "list", None) if !tag.is_empty() => {
+                say!(self, "No track is playing")
+            }
+            ("load", _) if !tag.is_empty() => match self.db.get_tracks_with_tag(&tag) {
+                Ok(tracks) if tracks.is_empty() => say!(self, "No tracks tagged '{}'", tag),
+                Ok(tracks) => {
+                    let count = tracks.len();
+                    match core.set_property("playlist.tracks", PropertyValue::StringList(tracks)) {
+                        Ok(()) => say!(self, "Loaded {} tracks tagged '{}'", count, tag),
+                        Err(e) => print_error!(self, "Failed to load tagged tracks: {}", e),
+                    }
+                }
+                Err(e) => print_error!(self, "Failed to load tagged tracks: {}", e),
+            },
+            ("add", Some(track)) if !tag.is_empty() => match self.db.add_tag(&track, &tag) {
+                Ok(()) => say!(
+                    self,
+                    "Tagged '{}' with '{}'",
+                    self.db.display_name(&track),
+                    tag
+                ),
+                Err(e) => print_error!(self, "Failed to add tag: {}", e),
+            },
+            ("remove", Some(track)) if !tag.is_empty() => match self.db.remove_tag(&track, &tag) {
+                Ok(true) => say!(self, "Removed tag '{}'", tag),
+                Ok(false) => say!(self, "Track is not tagged '{}'", tag),
+                Err(e) => print_error!(self, "Failed to remove tag: {}", e),
+            },
+            _ => print_error!(self, "{}", usage),
+        }
+    }
+
+    fn podcast(&self, args: &[String]) {
+        match args.first().map(|s| s.as_str()) {
+            Some("add") if args.len() == 2 => match podcast::subscribe(&self.db, &args[1]) {
+                Ok(title) => say!(self, "Subscribed to '{}'", title),
+                Err(e) => print_error!(self, "Failed to subscribe: {}", e),
+            },
+            Some("list") | None => match self.db.get_feed_summaries() {
+                Ok(feeds) if feeds.is_empty() => say!(self, "No podcast subscriptions"),
+                Ok(feeds) => {
+                    self.print_header("Podcasts");
+                    for (title, unlistened) in feeds {
+                        say!(self, "  {} ({} unlistened)", title, unlistened);
+                    }
+                    say!(self);
+                }
+                Err(e) => print_error!(self, "Failed to list podcasts: {}", e),
+            },
+            _ => print_error!(
+                self,
+                "Usage: podcast [list] | podcast add <feed_url> | podcast refresh"
+            ),
+        }
+    }
+
+    /// Fetches the feeds and downloads new episodes on a thread of its own when the prompt
+    /// is up, reporting back like `core:print` does; otherwise (scripts, one-shot
+    /// commands, JSON) it waits for the result. Either way the core is only locked to
+    /// queue the episodes.
+    fn refresh_podcasts(&self, shared: &SharedCore) {
+        self.writer.flush();
+        if !(self.running && self.prompts()) {
+            match refresh_and_queue(&self.db, shared) {
+                Ok(message) => say!(self, "{}", message),
+                Err(e) => print_error!(self, "{}", e),
+            }
+            return;
+        }
+
+        let db = match self.db.connect() {
+            Ok(db) => db,
+            Err(e) => {
+                print_error!(self, "Failed to open the database: {}", e);
+                return;
+            }
+        };
+        let shared = Arc::clone(shared);
+        thread::spawn(move || {
+            let (level, text) = match refresh_and_queue(&db, &shared) {
+                Ok(message) => ("info", message),
+                Err(e) => ("error", e),
+            };
+            let message = vec![level.to_string(), text];
+            shared
+                .write()
+                .unwrap()
+                .emit_custom(SCRIPT_MESSAGE, PropertyValue::StringList(message));
+        });
+        say!(self, "Refreshing podcasts in the background");
+    }
+
+    fn relocate(&self, core: &mut Core, old_prefix: &str, new_prefix: &str) {
+        match self.db.relocate(old_prefix, new_prefix) {
+            Ok(changed) => say!(self, "Updated {} database entries", changed),
+            Err(e) => {
+                print_error!(self, "Failed to relocate: {}", e);
+                return;
+            }
+        }
+
+        // the loaded queue still has the old paths
+        if let Some(tracks) = core.get_string_list("playlist.tracks") {
+            let moved: Vec<String> = tracks
+                .iter()
+                .map(|t| relocate_path(t, old_prefix, new_prefix).unwrap_or_else(|| t.clone()))
+                .collect();
+            if moved != *tracks
+                && let Err(e) =
+                    core.set_property("playlist.tracks", PropertyValue::StringList(moved))
+            {
+                print_error!(self, "Failed to update the queue: {}", e);
+            }
+        }
+    }
+
+    fn check_database(&self) {
+        match self.db.check() {
+            Ok(report) => {
+                if report.problems.is_empty() {
+                    say!(self, "Integrity check passed");
+                } else {
+                    say!(
+                        self,
+                        "Integrity check found {} problems:",
+                        report.problems.len()
+                    );
+                    for problem in &report.problems {
+                        say!(self, "  {}", problem);
+                    }
+                }
+                say!(
+                    self,
+                    "Removed {} orphaned playlist entries",
+                    report.orphans_removed
+                );
+            }
+            Err(e) => print_error!(self, "Failed to check database: {}", e),
+        }
+    }
+
+    /// Tags and stream details read from the file itself; when it can't be read (moved,
+    /// unplugged drive) whatever the library stored about it.
+    fn show_track_info(&self, path: &str) {
+        self.print_header(self.db.display_name(path));
+        say!(self, "Path: {}", path);
+        let (meta, info) = match probe_file(path) {
+            Ok((meta, info)) => (Some(meta), Some(info)),
+            Err(e) => {
+                say!(
+                    self,
+                    "{}",
+                    self.style.warning(format!("Can't read the file: {}", e))
+                );
+                (self.db.get_track(path).ok().flatten(), None)
+            }
+        };
+        if let Some(meta) = &meta {
+            if let Some(title) = &meta.title {
+                say!(self, "Title: {}", title);
+            }
+            if let Some(artist) = &meta.artist {
+                say!(self, "Artist: {}", artist);
+            }
+            if let Some(album) = &meta.album {
+                say!(self, "Album: {}", album);
+            }
+            if let Some(number) = meta.track_number {
+                say!(self, "Track: {}", number);
+            }
+            if let Some(duration) = meta.duration {
+                say!(self, "Duration: {}", format_time(duration as f32));
+            }
+            if let Some(genre) = &meta.genre {
+                say!(self, "Genre: {}", genre);
+            }
+            if let Some(codec) = &meta.codec {
+                say!(self, "Codec: {}", codec);
+            }
+        }
+        if let Some(info) = &info {
+            if let Some(rate) = info.sample_rate {
+                say!(self, "Sample rate: {:.1} kHz", rate as f64 / 1000.0);
+            }
+            if let Some(channels) = info.channels {
+                say!(self, "Channels: {}", channels);
+            }
+            if let Some(bits) = info.bits_per_sample {
+                say!(self, "Bit depth: {} bit", bits);
+            }
+            if let Some(kbps) = info.bitrate(meta.as_ref().and_then(|m| m.duration)) {
+                say!(self, "Bitrate: {:.0} kbps", kbps);
+            }
+            say!(self, "File size: {}", format_size(info.file_size));
+        }
+        if self.db.is_favorite(path).unwrap_or(false) {
+            say!(self, "Liked: yes");
+        }
+        if let Ok(Some(rating)) = self.db.get_rating(path) {
+            say!(self, "Rating: {}", stars(rating));
+        }
+        match self.db.get_track_stats(path) {
+            Ok((plays, skips)) => say!(self, "Plays: {}  Skips: {}", plays, skips),
+            Err(e) => print_error!(self, "Failed to get track stats: {}", e),
+        }
+        say!(self);
+    }
+
+    fn show_stats(&self, period: StatsPeriod) {
+        self.writer.flush();
+        let title = match period {
+            StatsPeriod::Week => "this week",
+            StatsPeriod::Month => "this month",
+            StatsPeriod::AllTime => "all time",
+        };
+        self.print_header("Stats");
+
+        match self.db.library_summary() {
+            Ok((tracks, secs)) => say!(self, "Library: {} tracks, {}", tracks, format_hours(secs)),
+            Err(e) => print_error!(self, "Failed to get library size: {}", e),
+        }
+
+        let week = self.db.total_listening_time(StatsPeriod::Week);
+        let month = self.db.total_listening_time(StatsPeriod::Month);
+        match (week, month) {
+            (Ok(week), Ok(month)) => say!(
+                self,
+                "Listening time: {} this week, {} this month",
+                format_hours(week),
+                format_hours(month)
+            ),
+            (Err(e), _) | (_, Err(e)) => {
+                print_error!(self, "Failed to get listening time: {}", e)
+            }
+        }
+        if period == StatsPeriod::AllTime
+            && let Ok(secs) = self.db.total_listening_time(period)
+        {
+            say!(self, "Listening time: {} all time", format_hours(secs));
+        }
+
+        if let Ok(artists) = self.db.top_artists(period, 5)
+            && !artists.is_empty()
+        {
+            say!(self, "Top artists ({}):", title);
+            for (artist, plays) in artists {
+                say!(self, "  {} ({} plays)", artist, plays);
+            }
+        }
+
+        if let Ok(tracks) = self.db.top_tracks(period, 5)
+            && !tracks.is_empty()
+        {
+            say!(self, "Most played ({}):", title);
+            for (track, plays) in tracks {
+                say!(self, "  {} ({} plays)", self.db.display_name(&track), plays);
+            }
+        }
+
+        if let Ok(skipped) = self.db.most_skipped(5)
+            && !skipped.is_empty()
+        {
+            // skips are only counted, not timestamped, so this can't follow `period`
+            say!(self, "Most skipped (all time):");
+            for (track, skips) in skipped {
+                say!(self, "  {} ({} skips)", self.db.display_name(&track), skips);
+            }
+        }
+        say!(self);
+    }
+
+    /// `eq presets | save <name> | assign <preset> [album] | unassign [album]`
+    /// `eq [show] | on | off | set <n> <gain> [freq [q [type]]] | remove <n> |
+    /// preset <name>` change the live equalizer; `presets`, `save`, `assign` and
+    /// `unassign` manage saved presets.
+    fn eq(&self, core: &mut Core, args: &[String]) {
+        let action = args.first().map(|s| s.as_str()).unwrap_or("show");
+        let mut bands = core
+            .get_property("eq.bands")
+            .and_then(|v| v.as_eq_band_list())
+            .cloned()
+            .unwrap_or_default();
+        match action {
+            "show" => {
+                self.show_eq(core);
+                return;
+            }
+            "on" | "off" => {
+                let enabled = action == "on";
+                match core.set_property("eq.enabled", PropertyValue::Bool(enabled)) {
+                    Ok(()) => say!(self, "EQ {}", action),
+                    Err(e) => print_error!(self, "Failed to switch EQ {}: {}", action, e),
+                }
+                return;
+            }
+            "set" => {
+                let usage = "Usage: eq set <n> <gain_db> [freq_hz [q [lowshelf|peak|highshelf]]]";
+                let n = args.get(1).and_then(|n| n.parse::<usize>().ok());
+                let Some(index) = n
+                    .and_then(|n| n.checked_sub(1))
+                    .filter(|i| *i <= bands.len())
+                else {
+                    print_error!(self, "{} (n is 1 to {})", usage, bands.len() + 1);
+                    return;
+                };
+                let numbers: Result<Vec<f32>, _> = args
+                    .iter()
+                    .skip(2)
+                    .take(3)
+                    .map(|a| a.parse::<f32>())
+                    .collect();
+                let band_type = match args.get(5) {
+                    Some(name) => parse_band_type(name),
+                    None => Some(bands.get(index).map_or(1.0, |band| band[3])),
+                };
+                let (Ok(numbers), Some(band_type)) = (numbers, band_type) else {
+                    print_error!(self, "{}", usage);
+                    return;
+                };
+                // a new band needs at least a frequency; Q defaults to 1
+                let band = match (bands.get(index), numbers.as_slice()) {
+                    (Some(old), [gain]) => [old[0], old[1], *gain, band_type],
+                    (Some(old), [gain, freq]) => [*freq, old[1], *gain, band_type],
+                    (None, [gain, freq]) => [*freq, 1.0, *gain, band_type],
+                    (_, [gain, freq, q]) => [*freq, *q, *gain, band_type],
+                    _ => {
+                        print_error!(self, "{}", usage);
+                        return;
+                    }
+                };
+                if index == bands.len() {
+                    bands.push(band);
+                } else {
+                    bands[index] = band;
+                }
+            }
+            "remove" => match args.get(1).and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) if (1..=bands.len()).contains(&n) => {
+                    bands.remove(n - 1);
+                }
+                _ => {
+                    print_error!(self, "Usage: eq remove <n> (n is 1 to {})", bands.len());
+                    return;
+                }
+            },
+            "preset" => {
+                let name = args[1..].join(" ");
+                if name.is_empty() {
+                    print_error!(self, "Usage: eq preset <name>");
+                    return;
+                }
+                match self.db.get_eq_preset(&name) {
+                    Ok(Some(preset)) => bands = preset,
+                    Ok(None) => {
+                        print_error!(self, "No EQ preset named '{}'", name);
+                        return;
+                    }
+                    Err(e) => {
+                        print_error!(self, "Failed to read preset: {}", e);
+                        return;
+                    }
+                }
+                if let Err(e) = core.set_property("eq.enabled", PropertyValue::Bool(true)) {
+                    print_error!(self, "Failed to switch EQ on: {}", e);
+                }
+            }
+            _ => {
+                self.eq_presets(core, args);
+                return;
+            }
+        }
+
+        match core.set_property("eq.bands", PropertyValue::EqBandList(bands)) {
+            Ok(()) => self.show_eq(core),
+            Err(e) => print_error!(self, "Failed to update EQ: {}", e),
+        }
+    }
+
+    fn show_eq(&self, core: &Core) {
+        let enabled = core.get_bool("eq.enabled") == Some(true);
+        self.print_header(format!(
+            "Equalizer ({})",
+            if enabled { "on" } else { "off" }
+        ));
+        match core
+            .get_property("eq.bands")
+            .and_then(|v| v.as_eq_band_list())
+        {
+            Some(bands) if !bands.is_empty() => {
+                for (i, [freq, q, gain, band_type]) in bands.iter().enumerate() {
+                    say!(
+                        self,
+                        "  {}. {:>7.0} Hz  Q {:<4.2}  {:+5.1} dB  {}",
+                        i + 1,
+                        freq,
+                        q,
+                        gain,
+                        band_type_name(*band_type)
+                    );
+                }
+            }
+            _ => say!(
+                self,
+                "  No bands; add one with 'eq set 1 <gain_db> <freq_hz>'"
+            ),
+        }
+        say!(self);
+    }
+
+    fn eq_presets(&self, core: &Core, args: &[String]) {
+        let action = args.first().map(|s| s.as_str()).unwrap_or("");
+        let album = args.last().is_some_and(|a| a == "album");
+        // the current track's path, or its album with `album`
+        let key = || {
+            let track = core
+                .get_string("playlist.current")
+                .filter(|t| *t != "none")?;
+            if album {
+                self.db.get_track(track).ok()??.album
+            } else {
+                Some(track.clone())
+            }
+        };
+
+        match (action, args.get(1)) {
+            ("presets", _) => match self.db.eq_presets() {
+                Ok(names) if names.is_empty() => say!(self, "No EQ presets saved"),
+                Ok(names) => say!(self, "EQ presets: {}", names.join(", ")),
+                Err(e) => print_error!(self, "Failed to list presets: {}", e),
+            },
+            ("save", Some(name)) => {
+                let bands = core
+                    .get_property("eq.bands")
+                    .and_then(|v| v.as_eq_band_list())
+                    .cloned()
+                    .unwrap_or_default();
+                match self.db.save_eq_preset(name, &bands) {
+                    Ok(()) => say!(self, "Saved {} bands as preset '{}'", bands.len(), name),
+                    Err(e) => print_error!(self, "Failed to save preset: {}", e),
+                }
+            }
+            ("assign", Some(_)) | ("unassign", _) => {
+                let preset = (action == "assign").then(|| args[1].as_str());
+                if let Some(name) = preset
+                    && !matches!(self.db.get_eq_preset(name), Ok(Some(_)))
+                {
+                    say!(self, "No EQ preset named '{}'", name);
+                    return;
+                }
+                let Some(key) = key() else {
+                    say!(
+                        self,
+                        "Nothing playing{}",
+                        if album { " with an album tag" } else { "" }
+                    );
+                    return;
+                };
+                let target = if album {
+                    EqTarget::Album(&key)
+                } else {
+                    EqTarget::Track(&key)
+                };
+                match self.db.assign_eq_preset(target, preset) {
+                    Ok(()) => match preset {
+                        Some(name) => say!(self, "'{}' now uses preset '{}'", key, name),
+                        None => say!(self, "Removed the EQ preset of '{}'", key),
+                    },
+                    Err(e) => print_error!(self, "Failed to update EQ preset: {}", e),
+                }
+            }
+            _ => print_error!(
+                self,
+                "Usage: eq show|on|off|set|remove|preset|presets|save|assign|unassign (see 'help')"
+            ),
+        }
+    }
+
+    /// `ls artists|albums [artist]|genres|album <name>|genre <name>`
+    fn browse(&self, args: &[String]) {
+        let rest = args.get(1..).unwrap_or_default().join(" ");
+        let groups = match args.first().map(|s| s.as_str()).unwrap_or("artists") {
+            "artists" => Some(("Artists", self.db.artists())),
+            "albums" if rest.is_empty() => Some(("Albums", self.db.albums(None))),
+            "albums" => Some(("Albums", self.db.albums(Some(&rest)))),
+            "genres" => Some(("Genres", self.db.genres())),
+            "album" | "genre" if !rest.is_empty() => None,
+            _ => {
+                print_error!(
+                    self,
+                    "Usage: ls artists | albums [artist] | genres | album <name> | genre <name>"
+                );
+                return;
+            }
+        };
+
+        if let Some((title, result)) = groups {
+            match result {
+                Ok(rows) if rows.is_empty() => say!(self, "Nothing tagged yet; try 'scan <dir>'"),
+                Ok(rows) => {
+                    self.print_header(title);
+                    for (name, count) in rows {
+                        say!(self, "  {} ({} tracks)", name, count);
+                    }
+                    say!(self);
+                }
+                Err(e) => print_error!(self, "Failed to browse library: {}", e),
+            }
+            return;
+        }
+
+        let tracks = if args[0] == "album" {
+            self.db.tracks(&rest)
+        } else {
+            self.db.tracks_with_genre(&rest)
+        };
+        match tracks {
+            Ok(tracks) if tracks.is_empty() => say!(self, "No tracks in {} '{}'", args[0], rest),
+            Ok(tracks) => {
+                self.print_header(rest);
+                for track in tracks {
+                    match track.track_number {
+                        Some(n) => say!(self, "  {:>2}. {}", n, track.display_name()),
+                        None => say!(self, "      {}", track.display_name()),
+                    }
+                    say!(self, "      {}", track.path);
+                }
+                say!(self);
+            }
+            Err(e) => print_error!(self, "Failed to browse library: {}", e),
+        }
+    }
+
+    fn show_recently_added(&self, limit: usize) {
+        match self.db.recently_added(limit) {
+            Ok(tracks) if tracks.is_empty() => say!(self, "Library is empty"),
+            Ok(tracks) => {
+                self.print_header("Recently Added");
+                for (i, track) in tracks.iter().enumerate() {
+                    say!(self, "  {}. {}", i + 1, self.db.display_name(track));
+                }
+                say!(self);
+            }
+            Err(e) => print_error!(self, "Failed to get recently added tracks: {}", e),
+        }
+    }
+
+    fn show_recently_played(&self, limit: usize) {
+        match self.db.recently_played(limit) {
+            Ok(tracks) if tracks.is_empty() => say!(self, "No play history"),
+            Ok(tracks) => {
+                self.print_header("Recently Played");
+                for (i, (track, played_at)) in tracks.iter().enumerate() {
+                    say!(
+                        self,
+                        "  {}. {} ({})",
+                        i + 1,
+                        self.db.display_name(track),
+                        played_at
+                    );
+                }
+                say!(self);
+            }
+            Err(e) => print_error!(self, "Failed to get recently played tracks: {}", e),
+        }
+    }
+
+    fn show_trash(&self) {
+        match self.db.trashed_playlists() {
+            Ok(rows) if rows.is_empty() => say!(self, "The trash is empty"),
+            Ok(rows) => {
+                self.print_header("Deleted Playlists");
+                for (name, deleted_at, count) in rows {
+                    say!(
+                        self,
+                        "  {} ({} tracks, deleted {})",
+                        name,
+                        count,
+                        deleted_at
+                    );
+                }
+                say!(self, "Use 'undelete <name>' to restore one\n");
+            }
+            Err(e) => print_error!(self, "Failed to read the trash: {}", e),
+        }
+    }
+
+    fn show_all_playlists(&self) {
+        match self.db.playlist_tree() {
+            Ok(entries) => {
+                let liked = self.db.favorites().map(|f| f.len()).unwrap_or(0);
+                if entries.is_empty() && liked == 0 {
+                    say!(self, "No saved playlists");
+                } else {
+                    self.print_header("Saved Playlists");
+                    say!(self, "  {} ({} tracks)", FAVORITES_PLAYLIST, liked);
+                    for entry in entries {
+                        let indent = "  ".repeat(entry.depth + 1);
+                        if entry.is_folder {
+                            say!(self, "{}{}/", indent, entry.name);
+                            continue;
+                        }
+                        match self.db.get_playlist_tracks(&entry.path) {
+                            Ok(tracks) => {
+                                say!(self, "{}{} ({} tracks)", indent, entry.name, tracks.len());
+                            }
+                            Err(_) => {
+                                say!(self, "{}{}", indent, entry.name);
+                            }
+                        }
+                    }
+                    say!(self);
+                }
+            }
+            Err(e) => {
+                print_error!(self, "Failed to get playlists: {}", e);
+            }
+        }
+    }
+
+    fn show_event_log(&self, core: &Core, limit: usize) {
+        let entries = core.recent_events(limit);
+        if entries.is_empty() {
+            say!(self, "Event log is empty");
+            return;
+        }
+
+        self.print_header(format!("Event Log (last {})", entries.len()));
+        for entry in entries {
+            let secs = entry
+                .at
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+                % 86400;
+            let (kind, name) = match &entry.event {
+                EventType::PropertyChanged(name) => ("property", name),
+                EventType::CommandExecuted(name) => ("command", name),
+                EventType::Custom(name, _) => ("custom", name),
+            };
+            say!(
+                self,
+                "  {:02}:{:02}:{:02} UTC  {:<8} {} {}",
+                secs / 3600,
+                secs / 60 % 60,
+                secs % 60,
+                kind,
+                name,
+                entry.detail
+            );
+        }
+        say!(self);
+    }
+
+    fn show_history_by_track(&self, limit: usize) {
+        match self.db.history_by_track(limit) {
+            Ok(rows) if rows.is_empty() => say!(self, "No play history"),
+            Ok(rows) => {
+                self.print_header("Plays by Track");
+                for (track, plays, last) in rows {
+                    say!(
+                        self,
+                        "  {:>4}  {}  (last {})",
+                        plays,
+                        self.db.display_name(&track),
+                        last
+                    );
+                }
+                say!(self);
+            }
+            Err(e) => print_error!(self, "Failed to get history: {}", e),
+        }
+    }
+
+    fn show_history_by_day(&self, limit: usize) {
+        match self.db.history_by_day(limit) {
+            Ok(rows) if rows.is_empty() => say!(self, "No play history"),
+            Ok(rows) => {
+                self.print_header("Plays by Day");
+                for (day, plays) in rows {
+                    say!(self, "  {}  {:>4}", day, plays);
+                }
+                say!(self);
+            }
+            Err(e) => print_error!(self, "Failed to get history: {}", e),
+        }
+    }
+
+    /// `history [n] [--page p] [--search text]` pages through plays;
+    /// `--by-track`, `--by-day` and `--commands` show the other views.
+    fn history(&self, args: &[String]) {
+        const USAGE: &str = "Usage: history [n] [--page p] [--search text] | history --by-track|--by-day|--commands [n]";
+        let mut mode = None;
+        let mut limit = 10;
+        let mut page = 1;
+        let mut search = None;
+        let mut words = args.iter();
+        while let Some(word) = words.next() {
+            let parsed = match word.as_str() {
+                "--by-track" | "--by-day" | "--commands" => {
+                    mode = Some(word.as_str());
+                    true
+                }
+                "--page" => words
+                    .next()
+                    .and_then(|p| p.parse().ok())
+                    .map(|p| page = p)
+                    .is_some(),
+                "--search" => words
+                    .next()
+                    .map(|text| search = Some(text.as_str()))
+                    .is_some(),
+                n => n.parse().ok().map(|n| limit = n).is_some(),
+            };
+            if !parsed || page == 0 || limit == 0 {
+                print_error!(self, "{}", USAGE);
+                return;
+            }
+        }
+        match mode {
+            None => self.show_history(limit, page, search),
+            Some(_) if page != 1 || search.is_some() => {
+                print_error!(self, "--page and --search only apply to the play history")
+            }
+            Some("--by-track") => self.show_history_by_track(limit),
+            Some("--by-day") => self.show_history_by_day(limit),
+            _ => self.show_input_history(limit),
+        }
+    }
+
+    fn show_history(&self, limit: usize, page: usize, search: Option<&str>) {
+        self.writer.flush();
+        let rows = match self.db.play_history_page(search, (page - 1) * limit, limit) {
+            Ok(rows) => rows,
+            Err(e) => {
+                print_error!(self, "Failed to get history: {}", e);
+                return;
+            }
+        };
+        if rows.is_empty() {
+            match search {
+                _ if page > 1 => say!(self, "No more play history"),
+                Some(text) => say!(self, "No plays matching '{}'", text),
+                None => say!(self, "No play history"),
+            }
+            return;
+        }
+
+        let mut title = "Play History".to_string();
+        if let Some(text) = search {
+            title += &format!(" matching '{}'", text);
+        }
+        if page > 1 {
+            title += &format!(" (page {})", page);
+        }
+        self.print_header(title);
+        let full = rows.len() == limit;
+        for (index, track, timestamp) in rows {
+            say!(
+                self,
+                "{:>5}  {} - {}",
+                index,
+                timestamp,
+                self.db.display_name(&track)
+            );
+        }
+        if full {
+            say!(
+                self,
+                "'play @n' plays an entry again; '--page {}' shows older plays",
+                page + 1
+            );
+        }
+        say!(self);
+    }
+}
+
+/// Whether the characters of `query` (spaces aside) appear in `text` in order, ignoring
+/// case: `dsotm` matches "Dark Side of the Moon".
+fn fuzzy_match(query: &str, text: &str) -> bool {
+    let mut text = text.chars().flat_map(char::to_lowercase);
+    query
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .all(|q| text.any(|c| c == q))
+}
+
+/// `[=====>----]` filled to `fraction` (0 to 1), `width` characters between the brackets.
+fn progress_bar(fraction: f32, width: usize) -> String {
+    let filled = ((fraction.clamp(0.0, 1.0) * width as f32) as usize).min(width);
+    let mut bar = "=".repeat(filled);
+    if filled < width {
+        bar.push('>');
+        bar.push_str(&"-".repeat(width - filled - 1));
+    }
+    format!("[{}]", bar)
+}
+
+/// Refreshes the podcast feeds into the data directory and appends what was downloaded
+/// to the play queue. Returns the message for the user.
+fn refresh_and_queue(db: &Database, shared: &SharedCore) -> Result<String, String> {
+    let downloaded = podcast::refresh(db, &podcast_dir())
+        .map_err(|e| format!("Failed to refresh podcasts: {}", e))?;
+    if downloaded.is_empty() {
+        return Ok("No new episodes".to_string());
+    }
+    let queued = podcast::enqueue(&mut shared.write().unwrap(), &downloaded)
+        .map_err(|e| format!("Failed to queue new episodes: {}", e))?;
+    Ok(format!(
+        "Queued {} new episodes, also saved in playlist '{}'",
+        queued,
+        podcast::PODCAST_PLAYLIST
+    ))
+}
+
+/// Fills the `ui.prompt` template: `{track}` (display name), `{pos}` (`1:23/4:56`),
+/// `{vol}` (`80%`) and `{playing_icon}` (▶, ⏸ or ■ when nothing is loaded). Other text,
+/// unknown placeholders included, is kept as written.
+fn render_prompt(core: &Core, db: &Database) -> String {
+    let template = core.get_string("ui.prompt").map_or("> ", |t| t.as_str());
+    if !template.contains('{') {
+        return template.to_string();
+    }
+    let current = core.get_string("playlist.current").filter(|t| *t != "none");
+    let track = current.map_or("nothing".to_string(), |t| db.display_name(t));
+    let position = core.get_float("audio.position").unwrap_or(0.0);
+    let pos = match core.get_float("audio.duration").filter(|d| *d > 0.0) {
+        Some(duration) => format!("{}/{}", format_time(position), format_time(duration)),
+        None => format_time(position),
+    };
+    let vol = format!(
+        "{:.0}%",
+        core.get_float("audio.volume").unwrap_or(1.0) * 100.0
+    );
+    let icon = match (current, core.get_bool("audio.playing")) {
+        (None, _) => "■",
+        (Some(_), Some(true)) => "▶",
+        (Some(_), _) => "⏸",
+    };
+    template
+        .replace("{track}", &track)
+        .replace("{pos}", &pos)
+        .replace("{vol}", &vol)
+        .replace("{playing_icon}", icon)
+}
+
+/// `★★★☆☆` for a rating of 3.
+fn stars(rating: u8) -> String {
+    let filled = usize::from(rating.min(5));
+    "★".repeat(filled) + &"☆".repeat(5 - filled)
+}
+
+/// `512 B`, `3.2 KB`, `8.4 MB`, in powers of 1024.
+fn format_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "KB", "MB"] {
+        if size < 1024.0 {
+            return match unit {
+                "B" => format!("{} B", bytes),
+                _ => format!("{:.1} {}", size, unit),
+            };
+        }
+        size /= 1024.0;
+    }
+    format!("{:.1} GB", size)
+}
+
+/// `3h 05m`, rounded to the minute.
+fn format_hours(seconds: f64) -> String {
+    let mins = (seconds.max(0.0) / 60.0).round() as u64;
+    format!("{}h {:02}m", mins / 60, mins % 60)
+}
+
+/// `m:ss`, or `h:mm:ss` from an hour on.
+fn format_time(seconds: f32) -> String {
+    let secs = seconds.max(0.0) as u64;
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formatting() {
+        assert_eq!(format_time(83.6), "1:23");
+        assert_eq!(format_hours(11_100.0), "3h 05m");
+        assert_eq!(format_time(3723.0), "1:02:03");
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(stars(3), "★★★☆☆");
+        assert_eq!(format_size(8_808_038), "8.4 MB");
+        assert_eq!(format_size(3 << 30), "3.0 GB");
+        assert_eq!(progress_bar(0.0, 5), "[>----]");
+        assert_eq!(progress_bar(0.5, 4), "[==>-]");
+        assert_eq!(progress_bar(1.5, 4), "[====]");
+        assert!(fuzzy_match("dsotm", "Dark Side of the Moon"));
+        assert!(fuzzy_match("pink floyd", "PinkFloyd – Time"));
+        assert!(!fuzzy_match("moon dark", "Dark Side of the Moon"));
+    }
+
+    #[test]
+    fn test_json_response() {
+        let db = Database::in_memory().unwrap();
+        let writer = DbWriter::spawn(Database::in_memory().unwrap());
+        let repl = Repl::new(db, writer).with_json(true);
+
+        repl.begin_response("status");
+        say!(repl, "\n=== Player Status ===");
+        say!(repl);
+        print_error!(repl, "Failed to {}", "look");
+        let response = repl.response.borrow_mut().take().unwrap();
+        assert_eq!(response.command, "status");
+        assert_eq!(response.output, vec!["=== Player Status ==="]);
+        assert_eq!(response.errors, vec!["Failed to look"]);
+    }
+
+    #[test]
+    fn test_now_playing() {
+        let db = Database::in_memory().unwrap();
+        let writer = DbWriter::spawn(Database::in_memory().unwrap());
+        let repl = Repl::new(db, writer);
+        let mut core = Core::new();
+        crate::property::register_property(&mut core);
+        assert_eq!(repl.now_playing(&core), "■ Nothing playing  queue 0");
+
+        let tracks = vec!["/a.mp3".to_string(), "/b.mp3".to_string()];
+        core.set_property("playlist.tracks", PropertyValue::StringList(tracks))
+            .unwrap();
+        core.set_property("playlist.current", PropertyValue::String("/b.mp3".into()))
+            .unwrap();
+        core.set_property("audio.position", PropertyValue::Float(83.0))
+            .unwrap();
+        core.set_property("audio.duration", PropertyValue::Float(200.0))
+            .unwrap();
+        core.set_property("audio.volume", PropertyValue::Float(0.8))
+            .unwrap();
+        assert_eq!(
+            repl.now_playing(&core),
+            "⏸ /b.mp3  1:23 / 3:20  vol 80%  2/2"
+        );
+    }
+
+    #[test]
+    fn test_render_prompt() {
+        let db = Database::in_memory().unwrap();
+        let mut core = Core::new();
+        crate::property::register_property(&mut core);
+        assert_eq!(render_prompt(&core, &db), "> ");
+
+        core.set_property(
+            "ui.prompt",
+            PropertyValue::String("{playing_icon} {track} {pos} {vol} {x}> ".into()),
+        )
+        .unwrap();
+        assert_eq!(render_prompt(&core, &db), "■ nothing 0:00 100% {x}> ");
+
+        core.set_property("playlist.current", PropertyValue::String("/a.mp3".into()))
+            .unwrap();
+        core.set_property("audio.playing", PropertyValue::Bool(true))
+            .unwrap();
+        core.set_property("audio.position", PropertyValue::Float(61.0))
+            .unwrap();
+        core.set_property("audio.duration", PropertyValue::Float(120.0))
+            .unwrap();
+        assert_eq!(render_prompt(&core, &db), "▶ /a.mp3 1:01/2:00 100% {x}> ");
     }
 }
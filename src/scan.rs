@@ -0,0 +1,173 @@
+use crate::db::Database;
+use crate::fingerprint::{MATCH_THRESHOLD, fingerprint, is_distinctive, similarity};
+use crate::metadata::read_metadata;
+use std::fs;
+use std::io;
+use std::path::Path;
+use tracing::*;
+
+/// Outcome of a library scan.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ScanReport {
+    /// Files symphonia could probe; these were written to the `tracks` table.
+    pub added: usize,
+    /// Files that are not audio (or that symphonia can't read), and subdirectories that
+    /// couldn't be listed.
+    pub skipped: usize,
+    /// Audio files whose metadata could not be stored.
+    pub failed: usize,
+    /// New files recognised as a recording whose old file is gone; its history moved
+    /// over to the new path.
+    pub merged: usize,
+    /// `(new, existing)` pairs of files that sound like the same recording.
+    pub duplicates: Vec<(String, String)>,
+}
+
+/// Recursively walks `dir`, probes every file and stores the metadata of the ones that
+/// turn out to be audio. Symlinked directories are not followed, so links back up the
+/// tree can't loop. A subdirectory that can't be read is logged and skipped; only `dir`
+/// itself failing is an error.
+///
+/// Files without a fingerprint get one. A new file that matches a known recording
+/// takes over its play history when the old file no longer exists (moved or
+/// re-encoded), and is reported as a duplicate otherwise.
+pub fn scan_directory(db: &Database, dir: &Path) -> io::Result<ScanReport> {
+    let mut report = ScanReport::default();
+    let mut known = db.fingerprints().unwrap_or_default();
+    let root = dir.canonicalize()?;
+    let mut pending = vec![root.clone()];
+
+    while let Some(current) = pending.pop() {
+        let listing = match fs::read_dir(&current) {
+            Ok(listing) => listing,
+            Err(e) if current == root => return Err(e),
+            Err(e) => {
+                warn!("[Scan] Skipping {}: {}", current.display(), e);
+                report.skipped += 1;
+                continue;
+            }
+        };
+        let mut entries = listing.filter_map(|entry| entry.ok()).collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let path = entry.path();
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(_) => continue,
+            };
+
+            if file_type.is_dir() {
+                pending.push(path);
+                continue;
+            }
+
+            let Some(path_str) = path.to_str() else {
+                report.skipped += 1;
+                continue;
+            };
+
+            let meta = match read_metadata(path_str) {
+                Ok(meta) => meta,
+                Err(_) => {
+                    report.skipped += 1;
+                    continue;
+                }
+            };
+            let is_new = matches!(db.get_track(path_str), Ok(None));
+            if db.upsert_track(&meta).is_err() {
+                report.failed += 1;
+                continue;
+            }
+            report.added += 1;
+
+            if known.iter().all(|(known_path, _)| known_path != path_str) {
+                match_fingerprint(db, path_str, is_new, &mut known, &mut report);
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+fn match_fingerprint(
+    db: &Database,
+    path: &str,
+    is_new: bool,
+    known: &mut Vec<(String, Vec<u32>)>,
+    report: &mut ScanReport,
+) {
+    let Ok(fp) = fingerprint(path) else {
+        return;
+    };
+
+    if is_new && is_distinctive(&fp) {
+        let best = known
+            .iter()
+            .filter(|(_, other)| is_distinctive(other))
+            .map(|(other_path, other)| (other_path, similarity(&fp, other)))
+            .filter(|(_, score)| *score >= MATCH_THRESHOLD)
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(other_path, _)| other_path.clone());
+
+        if let Some(other) = best {
+            if Path::new(&other).exists() {
+                report.duplicates.push((path.to_string(), other));
+            } else if db.merge_track(&other, path).is_ok() {
+                known.retain(|(known_path, _)| *known_path != other);
+                report.merged += 1;
+            }
+        }
+    }
+
+    if db.set_fingerprint(path, &fp).is_ok() {
+        known.push((path.to_string(), fp));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_directory() {
+        let root = std::env::temp_dir().join("eigenplayer_scan_test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("album")).unwrap();
+
+        crate::metadata::write_wav(&root.join("album").join("one.wav"), &[0; 100]);
+        fs::write(root.join("notes.txt"), "not audio").unwrap();
+
+        let db = Database::in_memory().unwrap();
+        let report = scan_directory(&db, &root).unwrap();
+        assert_eq!(report.added, 1);
+        assert_eq!(report.skipped, 1);
+
+        let track = root.join("album").join("one.wav");
+        assert!(db.get_track(track.to_str().unwrap()).unwrap().is_some());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scan_unreadable_directory() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = std::env::temp_dir().join("eigenplayer_scan_unreadable_test");
+        let _ = fs::remove_dir_all(&root);
+        let locked = root.join("locked");
+        fs::create_dir_all(&locked).unwrap();
+        fs::write(root.join("notes.txt"), "not audio").unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+
+        let db = Database::in_memory().unwrap();
+        let report = scan_directory(&db, &root).unwrap();
+        // root reads everything, so the directory only counts where permissions apply
+        let unreadable = fs::read_dir(&locked).is_err();
+        assert_eq!(report.skipped, 1 + unreadable as usize);
+
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::remove_dir_all(root).unwrap();
+    }
+}
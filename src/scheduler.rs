@@ -0,0 +1,92 @@
+use crate::core::Core;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub type TimerCallback = Arc<dyn Fn(&mut Core) + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimerId(u64);
+
+impl TimerId {
+    /// The id as a plain number, for handing to scripts.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    pub fn from_u64(id: u64) -> Self {
+        Self(id)
+    }
+}
+
+struct Timer {
+    id: TimerId,
+    due: Instant,
+    interval: Option<Duration>,
+    callback: TimerCallback,
+}
+
+/// One-shot and repeating timers fired from `Core::tick`, so features like sleep timers,
+/// position polling and Lua timeouts share one clock instead of spawning threads.
+#[derive(Default)]
+pub struct Scheduler {
+    timers: Vec<Timer>,
+    next_id: u64,
+}
+
+impl Scheduler {
+    pub fn add(
+        &mut self,
+        after: Duration,
+        interval: Option<Duration>,
+        callback: TimerCallback,
+    ) -> TimerId {
+        self.next_id += 1;
+        let id = TimerId(self.next_id);
+        self.timers.push(Timer {
+            id,
+            due: Instant::now() + after,
+            interval,
+            callback,
+        });
+        id
+    }
+
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        let before = self.timers.len();
+        self.timers.retain(|t| t.id != id);
+        self.timers.len() != before
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    pub fn is_pending(&self, id: TimerId) -> bool {
+        self.timers.iter().any(|t| t.id == id)
+    }
+
+    /// Removes fired one-shot timers, reschedules repeating ones and returns the callbacks
+    /// that are due, in the order they were due.
+    pub(crate) fn take_due(&mut self, now: Instant) -> Vec<TimerCallback> {
+        let mut due: Vec<(Instant, TimerCallback)> = Vec::new();
+        self.timers.retain_mut(|timer| {
+            if timer.due > now {
+                return true;
+            }
+            due.push((timer.due, Arc::clone(&timer.callback)));
+            match timer.interval {
+                Some(interval) => {
+                    timer.due += interval;
+                    // don't try to catch up on ticks missed while the core was busy
+                    if timer.due <= now {
+                        timer.due = now + interval;
+                    }
+                    true
+                }
+                None => false,
+            }
+        });
+        due.sort_by_key(|(at, _)| *at);
+        due.into_iter().map(|(_, cb)| cb).collect()
+    }
+}
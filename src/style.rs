@@ -0,0 +1,179 @@
+use crate::core::Core;
+use std::fmt::Display;
+use std::io::{self, IsTerminal};
+
+/// What a piece of REPL output is, so the theme can pick its color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Header,
+    Current,
+    Error,
+    Warning,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Header, Role::Current, Role::Error, Role::Warning];
+
+    /// Name in the theme table and the `ui.color.*` properties.
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Header => "header",
+            Role::Current => "current",
+            Role::Error => "error",
+            Role::Warning => "warning",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Role> {
+        Role::ALL.into_iter().find(|role| role.name() == name)
+    }
+
+    /// Property holding the role's color, e.g. `ui.color.error`.
+    pub fn property(self) -> String {
+        format!("ui.color.{}", self.name())
+    }
+
+    pub fn default_color(self) -> &'static str {
+        match self {
+            Role::Header => "bold",
+            Role::Current => "bold green",
+            Role::Error => "red",
+            Role::Warning => "yellow",
+        }
+    }
+}
+
+/// Turns a color like `bold red` or `bright_cyan` into its escape sequence. Words are
+/// attributes (`bold`, `dim`, `italic`, `underline`) and colors, plain or `bright_`;
+/// `none` (or nothing) means no styling. None if a word isn't one of those.
+pub fn escape_code(color: &str) -> Option<String> {
+    const COLORS: [&str; 8] = [
+        "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    ];
+    let mut codes = Vec::new();
+    for word in color.split_whitespace() {
+        let word = word.to_lowercase();
+        let code = match word.as_str() {
+            "none" => continue,
+            "bold" => 1,
+            "dim" => 2,
+            "italic" => 3,
+            "underline" => 4,
+            _ => match word.strip_prefix("bright_") {
+                Some(name) => 90 + COLORS.iter().position(|c| *c == name)?,
+                None => 30 + COLORS.iter().position(|c| *c == word)?,
+            },
+        };
+        codes.push(code.to_string());
+    }
+    if codes.is_empty() {
+        return Some(String::new());
+    }
+    Some(format!("\x1b[{}m", codes.join(";")))
+}
+
+/// Colors REPL output by role. Without colors every method returns the text as is.
+#[derive(Debug, Clone, Default)]
+pub struct Style {
+    /// Escape sequence per role, in `Role::ALL` order; None when colors are off.
+    codes: Option<Vec<String>>,
+}
+
+impl Style {
+    pub fn plain() -> Self {
+        Self::default()
+    }
+
+    /// The theme in the `ui.color.*` properties. Plain when `ui.colors` is off,
+    /// `NO_COLOR` is set, or stdout or stderr isn't a terminal, so piped output stays
+    /// free of escape codes.
+    pub fn from_core(core: &Core) -> Self {
+        let enabled = core.get_bool("ui.colors") != Some(false)
+            && std::env::var_os("NO_COLOR").is_none()
+            && io::stdout().is_terminal()
+            && io::stderr().is_terminal();
+        if enabled {
+            Self::themed(core)
+        } else {
+            Self::plain()
+        }
+    }
+
+    /// The theme regardless of where output goes. Unknown colors fall back to the
+    /// role's default.
+    pub fn themed(core: &Core) -> Self {
+        let codes = Role::ALL
+            .iter()
+            .map(|role| {
+                core.get_string(&role.property())
+                    .and_then(|color| escape_code(color))
+                    .or_else(|| escape_code(role.default_color()))
+                    .unwrap_or_default()
+            })
+            .collect();
+        Self { codes: Some(codes) }
+    }
+
+    pub fn paint(&self, role: Role, text: impl Display) -> String {
+        let code = self.codes.as_ref().and_then(|codes| {
+            let index = Role::ALL.iter().position(|r| *r == role)?;
+            codes.get(index).filter(|code| !code.is_empty())
+        });
+        match code {
+            Some(code) => format!("{}{}\x1b[0m", code, text),
+            None => text.to_string(),
+        }
+    }
+
+    pub fn header(&self, text: impl Display) -> String {
+        self.paint(Role::Header, text)
+    }
+
+    pub fn current(&self, text: impl Display) -> String {
+        self.paint(Role::Current, text)
+    }
+
+    pub fn error(&self, text: impl Display) -> String {
+        self.paint(Role::Error, text)
+    }
+
+    pub fn warning(&self, text: impl Display) -> String {
+        self.paint(Role::Warning, text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::PropertyValue;
+    use crate::property::register_property;
+
+    #[test]
+    fn test_escape_code() {
+        assert_eq!(escape_code("red").as_deref(), Some("\x1b[31m"));
+        assert_eq!(
+            escape_code("Bold bright_cyan").as_deref(),
+            Some("\x1b[1;96m")
+        );
+        assert_eq!(escape_code("none").as_deref(), Some(""));
+        assert_eq!(escape_code("bold purple"), None);
+    }
+
+    #[test]
+    fn test_style() {
+        let mut core = Core::new();
+        register_property(&mut core);
+        core.set_property("ui.color.error", PropertyValue::String("magenta".into()))
+            .unwrap();
+        core.set_property("ui.color.warning", PropertyValue::String("sparkly".into()))
+            .unwrap();
+        core.set_property("ui.color.header", PropertyValue::String("none".into()))
+            .unwrap();
+
+        let style = Style::themed(&core);
+        assert_eq!(style.error("oops"), "\x1b[35moops\x1b[0m");
+        assert_eq!(style.warning("hm"), "\x1b[33mhm\x1b[0m");
+        assert_eq!(style.header("=== A ==="), "=== A ===");
+        assert_eq!(Style::plain().error("oops"), "oops");
+    }
+}
@@ -0,0 +1,141 @@
+use std::io::{self, Read};
+use std::process::{Command, Stdio};
+
+/// Puts the terminal on stdin into character-at-a-time mode without echo, and back into
+/// the mode it was in when dropped. Uses `stty`, so it works wherever that exists
+/// without pulling in a terminal library.
+pub struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    pub fn enable() -> io::Result<Self> {
+        let saved = stty(&["-g"])?;
+        // -isig: Ctrl+C arrives as a key instead of killing the player mid-mode
+        stty(&["-icanon", "-echo", "-isig", "min", "1", "time", "0"])?;
+        Ok(Self {
+            saved: saved.trim().to_string(),
+        })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Waits for the next key press on stdin, in raw mode. None at end of input.
+pub fn read_key() -> io::Result<Option<String>> {
+    // an escape sequence arrives in one read, so one buffer holds a whole key
+    let mut buf = [0u8; 16];
+    loop {
+        let n = io::stdin().read(&mut buf)?;
+        if n == 0 {
+            return Ok(None);
+        }
+        if let Some(key) = decode_key(&buf[..n]) {
+            return Ok(Some(key));
+        }
+    }
+}
+
+/// Names the key a terminal sent as `bytes`, in the form `keys::normalize_key` accepts:
+/// `space`, `ctrl+n`, `shift+up`, `alt+x`, `q`. None for sequences it doesn't know.
+pub fn decode_key(bytes: &[u8]) -> Option<String> {
+    let named = |name: &str| Some(name.to_string());
+    match bytes {
+        [] => None,
+        [0x1b] => named("esc"),
+        [b' '] => named("space"),
+        [b'\r'] | [b'\n'] => named("enter"),
+        [b'\t'] => named("tab"),
+        [0x7f] | [0x08] => named("backspace"),
+        [c @ 0x01..=0x1a] => Some(format!("ctrl+{}", (b'a' + c - 1) as char)),
+        [0x1b, b'[' | b'O', rest @ ..] => decode_csi(rest),
+        [0x1b, rest @ ..] => {
+            let key = decode_key(rest)?;
+            Some(format!("alt+{}", key))
+        }
+        _ => {
+            let text = std::str::from_utf8(bytes).ok()?;
+            let mut chars = text.chars();
+            let c = chars.next()?;
+            chars.next().is_none().then(|| c.to_string())
+        }
+    }
+}
+
+/// The part of an escape sequence after `ESC [`: `A` is up, `1;5A` is ctrl+up, `5~` is
+/// page up.
+fn decode_csi(rest: &[u8]) -> Option<String> {
+    let text = std::str::from_utf8(rest).ok()?;
+    let (params, last) = text.split_at(text.len().checked_sub(1)?);
+    let mut params = params.split(';');
+    let number = params.next().unwrap_or("");
+    // xterm encodes modifiers as 1 + shift(1) + alt(2) + ctrl(4)
+    let modifiers = params
+        .next()
+        .and_then(|m| m.parse::<u8>().ok())
+        .map_or(0, |m| m.saturating_sub(1));
+
+    let key = match (last, number) {
+        ("A", _) => "up",
+        ("B", _) => "down",
+        ("C", _) => "right",
+        ("D", _) => "left",
+        ("H", _) => "home",
+        ("F", _) => "end",
+        ("~", "1" | "7") => "home",
+        ("~", "2") => "insert",
+        ("~", "3") => "delete",
+        ("~", "4" | "8") => "end",
+        ("~", "5") => "pgup",
+        ("~", "6") => "pgdown",
+        _ => return None,
+    };
+
+    let mut name = String::new();
+    for (bit, modifier) in [(4, "ctrl+"), (2, "alt+"), (1, "shift+")] {
+        if modifiers & bit != 0 {
+            name.push_str(modifier);
+        }
+    }
+    name.push_str(key);
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_key() {
+        assert_eq!(decode_key(b" ").as_deref(), Some("space"));
+        assert_eq!(decode_key(b"n").as_deref(), Some("n"));
+        assert_eq!(decode_key(b"N").as_deref(), Some("N"));
+        assert_eq!(decode_key(&[0x0e]).as_deref(), Some("ctrl+n"));
+        assert_eq!(decode_key(b"\x1b").as_deref(), Some("esc"));
+        assert_eq!(decode_key(b"\x1b[A").as_deref(), Some("up"));
+        assert_eq!(decode_key(b"\x1bOD").as_deref(), Some("left"));
+        assert_eq!(decode_key(b"\x1b[1;5C").as_deref(), Some("ctrl+right"));
+        assert_eq!(decode_key(b"\x1b[5~").as_deref(), Some("pgup"));
+        assert_eq!(decode_key(b"\x1bx").as_deref(), Some("alt+x"));
+        assert_eq!(decode_key("é".as_bytes()).as_deref(), Some("é"));
+        assert_eq!(decode_key(b"\x1b[Z9"), None);
+        assert_eq!(decode_key(b"ab"), None);
+    }
+}
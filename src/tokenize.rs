@@ -0,0 +1,92 @@
+/// Splits a command line into words the way a shell would, minus expansions: words are
+/// separated by whitespace, `"..."` and `'...'` keep spaces in a word, and a backslash
+/// takes the next character literally (except inside single quotes), so
+/// `add "My Song (live).flac"` and `add My\ Song.flac` each give one path.
+pub fn tokenize(input: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    // a word exists once any part of it was seen, so `""` is an empty word
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+    let mut chars = input.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"') | None, '\\') => match chars.next() {
+                Some(next) => {
+                    word.push(next);
+                    in_word = true;
+                }
+                None => return Err("nothing to escape after the trailing '\\'".to_string()),
+            },
+            (Some(_), c) => word.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+
+    if let Some(q) = quote {
+        return Err(format!("unclosed {} quote", q));
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Quotes `word` so `tokenize` gives it back unchanged.
+pub fn quote(word: &str) -> String {
+    let plain = !word.is_empty()
+        && !word
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\'));
+    if plain {
+        return word.to_string();
+    }
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize(r#"add "My Song (live).flac""#).unwrap(),
+            vec!["add", "My Song (live).flac"]
+        );
+        assert_eq!(
+            tokenize(r#"save road\ trip  'it''s' """#).unwrap(),
+            vec!["save", "road trip", "its", ""]
+        );
+        assert_eq!(
+            tokenize(r#"note "say \"hi\"" 'a\b'"#).unwrap(),
+            vec!["note", r#"say "hi""#, r"a\b"]
+        );
+        assert!(tokenize(r#"load "road trip"#).is_err());
+        assert!(tokenize(r"play \").is_err());
+        assert!(tokenize("   ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_quote() {
+        for word in ["plain", "two words", "it's", r"back\slash", ""] {
+            assert_eq!(tokenize(&quote(word)).unwrap(), vec![word]);
+        }
+        assert_eq!(quote("plain"), "plain");
+    }
+}
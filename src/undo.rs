@@ -0,0 +1,93 @@
+use crate::core::PropertyValue;
+use std::collections::HashSet;
+
+const MAX_UNDO_GROUPS: usize = 100;
+
+/// A single property change, enough to apply it in either direction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mutation {
+    pub name: String,
+    pub old: PropertyValue,
+    pub new: PropertyValue,
+}
+
+/// Undo/redo stacks of property mutations. Changes made while a command runs are grouped
+/// so that one `undo` reverts the whole command (e.g. a `remove` that rewrote the playlist
+/// and moved the current track).
+#[derive(Default)]
+pub struct UndoHistory {
+    undo: Vec<Vec<Mutation>>,
+    redo: Vec<Vec<Mutation>>,
+    open_group: Option<Vec<Mutation>>,
+    depth: usize,
+    untracked: HashSet<String>,
+    pub(crate) replaying: bool,
+}
+
+impl UndoHistory {
+    /// Excludes a property from the history, for fast-changing state like playback
+    /// position that should never be undone.
+    pub fn untrack(&mut self, name: &str) {
+        self.untracked.insert(name.to_string());
+    }
+
+    pub fn begin_group(&mut self) {
+        self.depth += 1;
+        if self.depth == 1 {
+            self.open_group = Some(Vec::new());
+        }
+    }
+
+    pub fn end_group(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+        if self.depth == 0
+            && let Some(group) = self.open_group.take()
+            && !group.is_empty()
+        {
+            self.push(group);
+        }
+    }
+
+    pub fn record(&mut self, mutation: Mutation) {
+        if self.replaying || mutation.old == mutation.new || self.untracked.contains(&mutation.name)
+        {
+            return;
+        }
+        match &mut self.open_group {
+            Some(group) => group.push(mutation),
+            None => self.push(vec![mutation]),
+        }
+    }
+
+    fn push(&mut self, group: Vec<Mutation>) {
+        self.undo.push(group);
+        if self.undo.len() > MAX_UNDO_GROUPS {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub(crate) fn pop_undo(&mut self) -> Option<Vec<Mutation>> {
+        self.undo.pop()
+    }
+
+    pub(crate) fn pop_redo(&mut self) -> Option<Vec<Mutation>> {
+        self.redo.pop()
+    }
+
+    pub(crate) fn push_undone(&mut self, group: Vec<Mutation>) {
+        self.redo.push(group);
+    }
+
+    pub(crate) fn push_redone(&mut self, group: Vec<Mutation>) {
+        self.undo.push(group);
+    }
+}
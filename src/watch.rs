@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// What `FileWatcher::poll` noticed about a file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// The file is new or its modification time moved.
+    Modified(PathBuf),
+    Removed(PathBuf),
+}
+
+/// Notices edits by comparing modification times between polls; std has no portable
+/// change notification and a poll a second is plenty for config files.
+#[derive(Debug, Default)]
+pub struct FileWatcher {
+    known: HashMap<PathBuf, SystemTime>,
+}
+
+impl FileWatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks `paths`, the files that should be watched right now, against the last
+    /// poll. The first poll reports every existing file as modified; files that were
+    /// seen before but are gone (or no longer listed) are reported as removed.
+    pub fn poll(&mut self, paths: impl IntoIterator<Item = PathBuf>) -> Vec<Change> {
+        let mut seen = HashMap::new();
+        let mut changes = Vec::new();
+        for path in paths {
+            let Ok(modified) = fs::metadata(&path).and_then(|meta| meta.modified()) else {
+                continue;
+            };
+            if self.known.get(&path) != Some(&modified) {
+                changes.push(Change::Modified(path.clone()));
+            }
+            seen.insert(path, modified);
+        }
+
+        let mut removed: Vec<PathBuf> = self
+            .known
+            .keys()
+            .filter(|path| !seen.contains_key(*path))
+            .cloned()
+            .collect();
+        removed.sort();
+        changes.extend(removed.into_iter().map(Change::Removed));
+
+        self.known = seen;
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_poll_reports_changes() {
+        let dir = std::env::temp_dir().join("eigenplayer_watch_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("config.lua");
+        let script = dir.join("plugin.lua");
+        fs::write(&config, "-- v1").unwrap();
+        fs::write(&script, "-- v1").unwrap();
+        let paths = || vec![config.clone(), script.clone()];
+
+        let mut watcher = FileWatcher::new();
+        assert_eq!(watcher.poll(paths()).len(), 2);
+        assert!(watcher.poll(paths()).is_empty());
+
+        let later = SystemTime::now() + Duration::from_secs(10);
+        fs::File::options()
+            .write(true)
+            .open(&config)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        fs::remove_file(&script).unwrap();
+        assert_eq!(
+            watcher.poll(paths()),
+            vec![
+                Change::Modified(config.clone()),
+                Change::Removed(script.clone())
+            ]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+use crate::db::Database;
+use std::fmt::Write;
+use std::path::Path;
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Turns a filesystem path into a `file://` URI, percent-encoding everything but the
+/// unreserved characters and `/`.
+fn file_uri(path: &str) -> String {
+    let absolute = Path::new(path)
+        .canonicalize()
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| path.to_string());
+
+    let mut uri = String::from("file://");
+    for byte in absolute.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(byte as char)
+            }
+            _ => {
+                let _ = write!(uri, "%{:02X}", byte);
+            }
+        }
+    }
+    uri
+}
+
+/// Renders a saved playlist as an XSPF document. Title, creator, album and duration
+/// come from the `tracks` table when the track has been indexed.
+pub fn export_playlist(db: &Database, playlist: &str) -> rusqlite::Result<String> {
+    let tracks = db.get_playlist_tracks(playlist)?;
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+    let _ = writeln!(xml, "  <title>{}</title>", escape(playlist));
+    xml.push_str("  <trackList>\n");
+
+    for path in tracks {
+        xml.push_str("    <track>\n");
+        let _ = writeln!(
+            xml,
+            "      <location>{}</location>",
+            escape(&file_uri(&path))
+        );
+        if let Some(meta) = db.get_track(&path)? {
+            if let Some(title) = &meta.title {
+                let _ = writeln!(xml, "      <title>{}</title>", escape(title));
+            }
+            if let Some(artist) = &meta.artist {
+                let _ = writeln!(xml, "      <creator>{}</creator>", escape(artist));
+            }
+            if let Some(album) = &meta.album {
+                let _ = writeln!(xml, "      <album>{}</album>", escape(album));
+            }
+            if let Some(number) = meta.track_number {
+                let _ = writeln!(xml, "      <trackNum>{}</trackNum>", number);
+            }
+            if let Some(duration) = meta.duration {
+                // XSPF durations are in milliseconds
+                let _ = writeln!(
+                    xml,
+                    "      <duration>{}</duration>",
+                    (duration * 1000.0).round() as u64
+                );
+            }
+        }
+        xml.push_str("    </track>\n");
+    }
+
+    xml.push_str("  </trackList>\n");
+    xml.push_str("</playlist>\n");
+    Ok(xml)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::TrackMetadata;
+
+    #[test]
+    fn test_export_playlist() {
+        let db = Database::in_memory().unwrap();
+        db.add_track_to_playlist("mix", "/music/a b.mp3").unwrap();
+        db.add_track_to_playlist("mix", "/music/c.mp3").unwrap();
+        db.upsert_track(&TrackMetadata {
+            path: "/music/a b.mp3".to_string(),
+            title: Some("Rock & Roll".to_string()),
+            artist: Some("Band".to_string()),
+            duration: Some(61.5),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let xml = export_playlist(&db, "mix").unwrap();
+        assert!(xml.contains("<title>mix</title>"));
+        assert!(xml.contains("<location>file:///music/a%20b.mp3</location>"));
+        assert!(xml.contains("<title>Rock &amp; Roll</title>"));
+        assert!(xml.contains("<creator>Band</creator>"));
+        assert!(xml.contains("<duration>61500</duration>"));
+        assert_eq!(xml.matches("<track>").count(), 2);
+    }
+}